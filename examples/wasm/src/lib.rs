@@ -0,0 +1,16 @@
+use gfp::pak_reader::implements::new_pak;
+use wasm_bindgen::prelude::*;
+
+/// List the entry paths of a pak held in memory.
+///
+/// `data` is the content of the pak, e.g. `new Uint8Array(arrayBuffer)`.
+#[wasm_bindgen]
+pub fn list_entries(data: &[u8], varient: i32) -> Result<Vec<String>, JsError> {
+    let mut pak = new_pak(data, varient)?;
+
+    let mut paths = Vec::new();
+    for entry_id in 0..pak.entries_count()? {
+        paths.push(pak.get_entry_path(entry_id)?);
+    }
+    Ok(paths)
+}
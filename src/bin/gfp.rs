@@ -1,11 +1,51 @@
 use clap::{Parser, Subcommand};
 use gfp::error::PakError;
-use gfp::pak_reader::implements::open_paks_by_glob;
-use gfp::utils::cli;
+use gfp::pak_reader::block_map::{BlockMapReport, check_block_map};
+use gfp::pak_reader::cancel::CancellationToken;
+use gfp::pak_reader::checksum_cache::ChecksumCache;
+use gfp::pak_reader::detect::is_gfp_pak;
+use gfp::pak_reader::diff::{Change, DiffBasis, DiffOptions, EntryDiff, diff_paks};
+use gfp::pak_reader::dupes::{DupeAnalyzer, DupeKey, DupeReport};
+use gfp::pak_reader::entry_filter;
+use gfp::pak_reader::entry_reader::DEFAULT_BUFFER_CAP;
+use gfp::pak_reader::ext::PakReaderExt;
+use gfp::pak_reader::extract::{
+    ExtractEvent, ExtractOptions, ExtractTotals, Manifest, ManifestRow, MtimePolicy, OutputLayout,
+    PathRewrite, Provenance, ReadStrategy, extract_pak,
+};
+use gfp::pak_reader::implements::{
+    DiscoveryOptions, PakOpenResult, open_detected_pak, open_pak, open_paks_by_pattern,
+    open_paks_in_dir,
+};
+use gfp::pak_reader::io_stats::IoStats;
+use gfp::pak_reader::metadata::{BuildSource, PakMetadata};
+use gfp::pak_reader::path_match::PathMatch;
+use gfp::pak_reader::plan::{ExtractPlan, PlannedPak, apply_planned_pak};
+use gfp::pak_reader::run_report::{PakRunReport, ReportFile, UnpackReport};
+use gfp::pak_reader::salvage::{SalvageReport, detect_extension, salvage_scan};
+use gfp::pak_reader::{EntryLayout, EntryPayload, PakReader};
+use gfp::utils::cli::{EntryFilter, EntryFormat};
+use gfp::utils::pak_version::sort_paks_by_version;
+use gfp::utils::read_at::ReadAt;
+use gfp::utils::{cli, hex, human_size};
+use glob::{MatchOptions, PatternError};
 use pathdiff::diff_paths;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// --pak-version 的取值
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum PakVersion {
+    #[value(name = "7")]
+    V7,
+    #[value(name = "10")]
+    V10,
+    Auto,
+}
 
 /// 和平精英解包工具
 #[derive(Parser)]
@@ -14,13 +54,83 @@ struct CliArgs {
     #[clap(subcommand)]
     subcommand: Command,
 
-    /// 处理版本号为 10 的 pak，用于 ShadowTrackerExtra/Saved/ 中的大多数 pak （默认值）
-    #[arg(long, group = "pak_version", default_value = "true")]
+    /// pak 的格式：10 用于 ShadowTrackerExtra/Saved/ 中的大多数 pak；7 用于 ShadowTrackerExtra/Saved/Paks/avatarpaks/
+    /// 中的 pak；auto 根据文件尾识别每个 pak，包含被改了扩展名的 pak 并跳过其他游戏的 pak。默认为 auto
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "VERSION",
+        default_value_t = PakVersion::Auto,
+        hide_default_value = true
+    )]
+    pak_version: PakVersion,
+
+    /// 旧写法，同 --pak-version 10
+    #[arg(
+        long,
+        global = true,
+        hide = true,
+        group = "legacy_pak_version",
+        conflicts_with = "pak_version"
+    )]
     v10: bool,
 
-    /// 处理版本号为 7 的 pak，用于 ShadowTrackerExtra/Saved/Paks/avatarpaks/ 中的 pak
-    #[arg(long, group = "pak_version")]
+    /// 旧写法，同 --pak-version 7
+    #[arg(
+        long,
+        global = true,
+        hide = true,
+        group = "legacy_pak_version",
+        conflicts_with = "pak_version"
+    )]
     v7: bool,
+
+    /// 旧写法，同 --pak-version auto
+    #[arg(
+        long,
+        global = true,
+        hide = true,
+        group = "legacy_pak_version",
+        conflicts_with = "pak_version"
+    )]
+    detect: bool,
+
+    /// 进入符号链接指向的目录，每个目录只进入一次，不会因循环链接而无限遍历
+    #[arg(long, global = true, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// 不进入符号链接指向的目录（默认值）
+    #[arg(long, global = true, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+
+    /// 最多向下查找的层数，1 表示只查找目录中直接包含的 pak；对路径模板从其第一个通配符之前的目录开始计算
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// 匹配路径模板时不区分大小写，例如 *.PAK 也能匹配 a.pak
+    #[arg(long, global = true)]
+    case_insensitive: bool,
+
+    /// 按发现顺序逐个处理 pak，不再先收集全部路径、去重并排序，处理大量 pak 时能更快开始输出
+    #[arg(long, global = true)]
+    no_sort: bool,
+
+    /// 读取 pak 时绕过系统页缓存（Linux 的 O_DIRECT、Windows 的 FILE_FLAG_NO_BUFFERING），
+    /// 解包大量数据时不挤占其它程序的缓存；文件系统不支持时退回普通读取
+    #[arg(long, global = true)]
+    direct: bool,
+
+    /// 为 ls、unpack、apply 和 verify 输出便于脚本处理的稳定格式：每行若干个以制表符分隔的字段，
+    /// 第一个字段表示行的类型，字段中的 %、制表符和换行写为 %25、%09、%0A、%0D，格式见 README；
+    /// 其它提示不再输出到 stdout，错误仍输出到 stderr
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    /// 显示日志，-v 显示调试日志，-vv 显示全部日志，包括每个 pak 处理完后的 I/O 统计；设置环境变量 GFP_LOG 时以其为准
+    #[cfg(feature = "tracing")]
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
 }
 
 #[derive(Subcommand)]
@@ -40,11 +150,39 @@ enum Command {
     /// game_patch_1.32.11.13800.pak
     ///     IsEncrypted: false
     ///     Version: 10
+    ///     ParserVersion: 10
+    ///     FileSize: 23820 (23.3 KiB)
+    ///     Build: 1.32.11.13846 (file name)
+    ///     MountPoint: ShadowTrackerExtra/Content/
+    ///     EntryCount: 7
+    ///     IndexOffset: 22333
+    ///     IndexSize: 1442 (1.4 KiB)
+    ///     FooterHash: 3024aaa2f876b073f8454ab0a939b5daf2b137b2
+    ///     EncryptedEntries: 0
+    ///     PlainEntries: 7
+    ///     CompressionMethods: 0=2, 1=5
     /// ```
+    ///
+    /// Version 为 pak 文件末尾记录的版本号，ParserVersion 为解析时使用的格式（--pak-version 指定或 auto 识别的结果），
+    /// 两者不一致时 ParserVersion 后会标出 (mismatch)，说明很可能选错了格式
+    ///
+    /// Build 为 pak 所属的客户端版本，取自 pak 中的版本清单条目（manifest），没有时取自文件名，
+    /// 都没有时不显示；--fast 时只取自文件名
+    ///
+    /// CompressionMethods 为每种压缩方式的条目数，0 表示未压缩
     #[command(verbatim_doc_comment)]
     Info {
         #[arg(default_value = "**/*.pak")]
         file_pattern: String,
+
+        /// 每个 pak 输出一行 JSON，例如 {"path":"a.pak","encrypted":false,"version":10,"parser_version":10,"file_size":23820,...}；
+        /// 错误同样以每行一个 {"error":"...","pak":"a.pak"} 输出到 stderr，与 pak 无关的错误中 pak 为 null
+        #[arg(long)]
+        json: bool,
+
+        /// 只读取文件末尾的 pak 信息，不读取索引，只显示是否加密、版本和文件大小，适合 pak 很多的目录
+        #[arg(long)]
+        fast: bool,
     },
 
     /// 列出每个 pak 中的文件
@@ -54,15 +192,105 @@ enum Command {
     /// ```sh
     /// gfp ls **/*.pak
     /// ```
-    #[command(verbatim_doc_comment)]
+    ///
+    /// --paths-only、--ids-only 和 -0 只输出条目本身，不显示 pak 路径等其他行，便于交给其他工具：
+    ///
+    /// ```sh
+    /// gfp ls **/*.pak --paths-only --with-pak -0 | xargs -0 -n2 echo
+    /// ```
+    #[command(
+        verbatim_doc_comment,
+        group(clap::ArgGroup::new("minimal").multiple(true).args(["paths_only", "ids_only", "print0"]))
+    )]
     Ls {
         /// 路径模板，例如 **/*.pak
         #[arg(required = true)]
         file_pattern: String,
 
-        /// 是否显示条目路径
+        /// 显示 pak 路径，并在每个条目后显示压缩率（在 pak 中占用的字节数 / 解压后的字节数），
+        /// 接近 100% 说明内容本身已经压缩过，空条目显示为 -
         #[arg(short = 'n', long)]
         show_entry_path: bool,
+
+        /// 只列出版本号最新的 pak，版本号取自文件名，例如 game_patch_1.32.11.13846.pak
+        #[arg(long)]
+        latest: bool,
+
+        /// 只显示每个 pak 的条目数，只读取索引开头，不解析条目
+        #[arg(long, conflicts_with = "layout")]
+        count: bool,
+
+        /// 在每个条目下显示其数据在 pak 中的位置、压缩方式和是否加密，用于调试
+        #[arg(long)]
+        layout: bool,
+
+        /// 重新计算每个条目的 SHA-1 并与索引中记录的哈希比较，在条目后显示 OK、MISMATCH
+        /// 或 NOHASH（未记录哈希）；有不匹配的条目时以退出码 1 退出
+        #[arg(long, conflicts_with = "count")]
+        verify_hashes: bool,
+
+        /// 只列出哈希不匹配的条目
+        #[arg(long, requires = "verify_hashes")]
+        only_mismatches: bool,
+
+        /// 校验哈希使用的线程数，每个线程各自打开一次 pak；为 auto 时使用 CPU 核心数
+        #[arg(
+            short,
+            long,
+            visible_alias = "threads",
+            value_name = "N",
+            default_value = "1",
+            value_parser = cli::parse_threads,
+            requires = "verify_hashes"
+        )]
+        jobs: usize,
+
+        /// 只列出满足条件的条目，可重复指定，条件之间为“且”；支持 compression、encrypted 和 size，
+        /// 运算符为 = != < <= > >=，size 可带 K/M/G 后缀，例如 --where compression=0 --where "size>10M"
+        #[arg(long = "where", value_name = "EXPR", conflicts_with = "count")]
+        where_filters: Vec<EntryFilter>,
+
+        /// 按模板输出每个条目，代替默认的 [id] path；可用 {id}、{path}、{size}（解压后的字节数）、
+        /// {compressed}（在 pak 中占用的字节数）、{method}（0 未压缩，1 zlib）和 {enc}（是否加密），
+        /// {{ 和 }} 表示花括号，\t 和 \n 表示制表符和换行，例如 --format '{id}\t{size}\t{path}'
+        #[arg(
+            long,
+            value_name = "TEMPLATE",
+            conflicts_with_all = ["count", "layout", "show_entry_path", "verify_hashes", "porcelain"]
+        )]
+        format: Option<EntryFormat>,
+
+        /// 按 pak 所属的客户端版本分组列出，每组前显示 Build 版本号，版本取法同 info 的 Build，
+        /// 无法确定版本的 pak 排在最后
+        #[arg(long, conflicts_with_all = ["latest", "format", "porcelain"])]
+        group_by_build: bool,
+
+        /// 每行只输出一个条目路径，不显示 pak 路径和条目 id
+        #[arg(
+            long,
+            conflicts_with_all = ["count", "layout", "show_entry_path", "format", "porcelain", "group_by_build", "ids_only"]
+        )]
+        paths_only: bool,
+
+        /// 每行只输出一个条目 id，不显示 pak 路径和条目路径
+        #[arg(
+            long,
+            conflicts_with_all = ["count", "layout", "show_entry_path", "format", "porcelain", "group_by_build"]
+        )]
+        ids_only: bool,
+
+        /// 每条记录以 NUL 而不是换行结尾，用于把含空格或换行的路径交给 xargs -0；
+        /// 不与 --ids-only 一起使用时只输出条目路径，同 --paths-only
+        #[arg(
+            short = '0',
+            long,
+            conflicts_with_all = ["count", "layout", "show_entry_path", "format", "porcelain", "group_by_build"]
+        )]
+        print0: bool,
+
+        /// 在 --paths-only、--ids-only 或 -0 输出的每条记录前加上 pak 路径和制表符
+        #[arg(long, requires = "minimal")]
+        with_pak: bool,
     },
 
     /// 将每个 pak 解包到指定路径
@@ -72,19 +300,92 @@ enum Command {
     /// ```sh
     /// gfp unapck **/*.pak --output_dir "D:\gfp_output"
     /// ```
+    ///
+    /// 不指定输出目录时解包到当前目录下的 gfp_out
+    ///
+    /// 按 Ctrl-C 会在当前块处理完后停止，删除未写完的文件，并以退出码 130 退出；再按一次立即退出
+    ///
+    /// 输出目录中会写入 provenance.tsv，记录每个解包出的文件来自哪个 pak 的哪个条目及其哈希
     #[command(verbatim_doc_comment)]
     Unpack {
         /// 路径模板
         #[arg(required = true)]
         file_pattern: String,
 
-        /// 输出目录
+        /// 输出目录，不存在时自动创建
+        #[arg(default_value = DEFAULT_OUTPUT_DIR)]
+        output_dir: String,
+
+        /// 是否在终端显示条目名
+        #[arg(short = 'n', long)]
+        show_entry_path: bool,
+
+        /// 边解包边将每个条目的结果写入 PATH，每行为以制表符分隔的 pak 路径、条目 id、条目路径、
+        /// 输出路径、写入的字节数、写入内容的 SHA-1、状态（ok、resumed、skipped 或 failed）和原因，
+        /// 中断时已写入的行仍然有效
+        #[arg(long, value_name = "PATH")]
+        manifest: Option<PathBuf>,
+
+        /// 不在最后输出解包、跳过和失败的条目数、写入的字节数及耗时
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// 将本次解包的报告写入 PATH：gfp 版本、命令行、开始时间、生效的解包选项、--include-from 和 --exclude-from 列表，
+        /// 以及每个 pak 解包、跳过和失败的条目数、写入的字节数、耗时和错误，为 JSON 格式。每个 pak 完成后更新，中断时仍包含已完成的 pak
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+
+        /// 将报告作为一行追加到 --report 指定的文件末尾而不是覆盖它，文件为每行一次解包的 JSON Lines 历史记录
+        #[arg(long, requires = "report")]
+        report_append: bool,
+
+        #[command(flatten)]
+        extract: ExtractArgs,
+    },
+    /// 列出 unpack 将写入的每个文件，写成 JSON 格式的计划，供审阅后用 apply 执行
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp plan "*.pak" --include-from lua.txt --strip-components 2 --out plan.json
+    /// gfp apply plan.json D:\gfp_output
+    /// ```
+    ///
+    /// 计划中记录每个 pak 的路径和文件尾中的索引哈希，以及每个文件的条目 id、条目路径、
+    /// 相对于输出目录的输出路径、大小和索引中的哈希；因路径层级不足而会被跳过的条目不在计划中
+    #[command(verbatim_doc_comment)]
+    Plan {
+        /// 路径模板
         #[arg(required = true)]
+        file_pattern: String,
+
+        /// 将计划写入 PATH，不指定时输出到 stdout
+        #[arg(short, long, value_name = "PATH")]
+        out: Option<PathBuf>,
+
+        #[command(flatten)]
+        extract: ExtractArgs,
+    },
+    /// 执行 plan 生成的计划，将其中的文件解包到输出目录
+    ///
+    /// 写入任何文件前先检查计划中的每个 pak，文件尾中的索引哈希与计划不同时拒绝执行；
+    /// 使用与 plan 时相同的 --pak-version 打开 pak。Ctrl-C 和 provenance.tsv 同 unpack
+    #[command(verbatim_doc_comment)]
+    Apply {
+        /// plan 生成的计划文件
+        plan: PathBuf,
+
+        /// 输出目录，不存在时自动创建
+        #[arg(default_value = DEFAULT_OUTPUT_DIR)]
         output_dir: String,
 
         /// 是否在终端显示条目名
         #[arg(short = 'n', long)]
         show_entry_path: bool,
+
+        /// 不在最后输出解包、跳过和失败的条目数、写入的字节数及耗时
+        #[arg(short, long)]
+        quiet: bool,
     },
     /// 读取 pak 的索引信息，写入到目标目录中对应路径下
     #[command(verbatim_doc_comment)]
@@ -105,122 +406,4099 @@ enum Command {
         #[arg(short = 'i', long)]
         print_index: bool,
     },
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = CliArgs::parse();
+    /// 查找包含指定条目的 pak
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp search **/*.pak ShadowTrackerExtra/Content/Lua/common/lua_object.lua
+    /// gfp search **/*.pak lua_object --contains --all
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// <pak>: <条目 id> <条目路径>
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Search {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
 
-    let varient = if args.v7 {
-        7
-    } else if args.v10 {
-        10
-    } else {
-        panic!("Never")
-    };
+        /// 条目路径
+        #[arg(required = true)]
+        query: String,
 
-    match args.subcommand {
-        Command::Info { file_pattern } => {
-            for (pak_path, mut pak) in open_paks_by_glob(&file_pattern, varient)? {
-                println!("{}", pak_path.to_string_lossy());
-                println!("    IsEncrypted: {}", pak.encrypted()?);
-                println!("    Version: {}", pak.version()?);
-            }
-        }
-        Command::Ls {
-            file_pattern,
-            show_entry_path,
-        } => {
-            let file_pattern = cli::prepare_file_pattern(file_pattern);
+        /// 匹配路径中包含 query 的条目，而不是完全相同的条目
+        #[arg(short = 'c', long)]
+        contains: bool,
 
-            for (pak_path, mut pak) in open_paks_by_glob(&file_pattern, varient)? {
-                if show_entry_path {
-                    println!("[{}]", pak_path.to_string_lossy());
-                }
+        /// 显示每个 pak 中的所有匹配项，而不是只显示第一个
+        #[arg(short = 'a', long)]
+        all: bool,
 
-                for entry_id in 0..pak.entries_count()? {
-                    let entry_path = pak.get_entry_path(entry_id)?;
-                    println!("[{}] {}", entry_id, entry_path);
-                }
-            }
-        }
-        Command::Unpack {
-            file_pattern,
-            output_dir,
-            show_entry_path,
-        } => {
-            let file_pattern = cli::prepare_file_pattern(file_pattern);
-            let output_dir = PathBuf::from(output_dir);
+        /// 比较路径时不区分大小写
+        #[arg(short = 'i', long)]
+        ignore_case: bool,
 
-            for (pak_path, mut pak) in open_paks_by_glob(&file_pattern, varient)? {
-                println!("[{}]", pak_path.to_string_lossy());
+        /// 比较路径时不区分大小写，把 \ 视为 /，并忽略 pak 的挂载点，例如 ui\hud\Foo.png 也能找到
+        /// ShadowTrackerExtra/Content/UI/HUD/foo.png；多个条目同时匹配时报错
+        #[arg(long)]
+        normalize: bool,
 
-                if let Err(e) = (|| -> Result<(), PakError> {
-                    for entry_id in 0..pak.entries_count()? {
-                        let entry_path = pak.get_entry_path(entry_id)?;
-                        if show_entry_path {
-                            println!("[{}] {}", entry_id, entry_path);
-                        }
+        /// 只显示满足条件的条目，格式同 ls --where
+        #[arg(long = "where", value_name = "EXPR")]
+        where_filters: Vec<EntryFilter>,
 
-                        let output_path = output_dir.join(&entry_path);
-                        if let Some(parent) = output_path.parent() {
-                            std::fs::create_dir_all(parent)?;
-                        }
-                        let mut output_file = File::create(&output_path)?;
-                        pak.extract_entry_to_file(entry_id, &mut output_file)?;
-                    }
-                    Ok(())
-                })() {
-                    eprintln!("Error unpacking {}: {}", pak_path.to_string_lossy(), e);
-                }
-            }
-        }
-        Command::Index {
-            file_pattern,
-            output_dir,
-            base_dir,
-            print_index,
-        } => {
-            let file_pattern = cli::prepare_file_pattern(file_pattern);
-            let base_dir = PathBuf::from(base_dir);
-            let output_dir = PathBuf::from(output_dir);
+        /// 只输出匹配的条目路径，每个路径以 NUL 结尾，同 ls --print0
+        #[arg(short = '0', long)]
+        print0: bool,
+    },
 
-            for (pak_path, mut pak) in open_paks_by_glob(&file_pattern, varient)? {
-                let relative_pak_path = diff_paths(&pak_path, &base_dir).unwrap();
-                println!("{}", relative_pak_path.to_string_lossy());
+    /// 将条目的内容输出到 stdout，多个 pak 包含该条目时取最后一个
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp cat **/*.pak ShadowTrackerExtra/Content/Lua/common/lua_object.lua --range 0:100
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Cat {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
 
-                let output_path = output_dir.join(&relative_pak_path);
-                if let Some(parent) = output_path.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
+        /// 条目路径
+        #[arg(required = true)]
+        entry_path: String,
 
-                let mut output_file = File::create(&output_path)?;
+        /// 只输出解压后内容中从第 START 个字节开始的 LEN 个字节，超出条目的部分被忽略；
+        /// 只读取覆盖这些字节的压缩块
+        #[arg(long, value_name = "START:LEN", value_parser = parse_range)]
+        range: Option<(u64, u64)>,
+    },
 
-                if let Err(e) = (|| -> Result<(), PakError> {
-                    if print_index {
-                        println!("{:>12} {}", "size", "path");
-                    }
+    /// 列出同时存在于多个 pak 中的条目路径，以及包含它们的 pak
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp overrides **/*.pak
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// ShadowTrackerExtra/Content/Lua/common/lua_object.lua
+    ///     Paks/game_patch_1.32.11.13846.pak
+    ///     Paks/game_patch_1.32.11.13992.pak
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Overrides {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
+    },
 
-                    for entry_id in 0..pak.entries_count()? {
-                        let path = pak.get_entry_path(entry_id)?;
+    /// 找出内容相同的条目，按浪费的空间从大到小列出
+    ///
+    /// 按索引中记录的哈希比较，哈希为全零的条目改用解压后的大小和前 64 KiB 的哈希比较。
+    /// 浪费的空间为除一个以外所有相同条目在 pak 中占用的字节数
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp dupes **/*.pak --across-paks-only
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// Wasted: 2434 (2.4 KiB) in 1 groups
+    /// 2 x 2434 (2.4 KiB), wasted 2434 (2.4 KiB)
+    ///     Paks/game_patch_1.32.11.13846.pak: [3] ShadowTrackerExtra/Content/Lua/common/lua_object.lua
+    ///     Paks/game_patch_1.32.11.13992.pak: [136] ShadowTrackerExtra/Content/Lua/common/lua_object.lua
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Dupes {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
 
-                        if print_index {
-                            println!("{}", path);
-                        }
+        /// 不显示只出现在同一个 pak 中的重复条目
+        #[arg(long)]
+        across_paks_only: bool,
 
-                        output_file.write(format!("{}\n", path).as_bytes())?;
-                    }
+        /// 以一行 JSON 输出，例如 {"wasted":2434,"groups":[{"hash":"...","stored_size":2434,"wasted":2434,"members":[{"pak":"a.pak","entry_id":3,"path":"..."}]}]}；
+        /// 哈希为全零的条目以 "file_size" 和 "sample_hash" 代替 "hash"
+        #[arg(long)]
+        json: bool,
+    },
 
-                    Ok(())
-                })() {
-                    eprintln!(
-                        "Error processing index for {}: {}",
-                        pak_path.to_string_lossy(),
-                        e
-                    );
-                }
-            }
-        }
-    }
+    /// 按路径比较两组 pak 的条目，列出新增（A）、删除（D）和修改（M）的条目
+    ///
+    /// 同一组中多个 pak 包含同一路径时，以最后一个 pak 中的为准。默认按索引中记录的哈希比较，
+    /// 哈希为全零的条目改用 pak 中数据的哈希。每个结果后注明依据：path 表示只在一侧存在，
+    /// hash 为按哈希比较，fingerprint 为 --quick 的启发式比较
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp diff old/Paks new/Paks --quick --confirm
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// Comparing by fingerprint (heuristic), changed entries confirmed by hash
+    /// A ShadowTrackerExtra/Content/Lua/client/logic/login/logic_pakversion.lua [path]
+    /// M ShadowTrackerExtra/Content/Lua/common/lua_object.lua [hash]
+    /// 1 added, 0 removed, 1 changed, 5 unchanged (5 by fingerprint)
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Diff {
+        /// 旧 pak 的路径模板
+        old_pattern: String,
 
-    Ok(())
+        /// 新 pak 的路径模板
+        new_pattern: String,
+
+        /// 只读取索引，按大小、压缩后大小、块数和第一个块的大小比较，不计算哈希，
+        /// 哈希被清零时也能使用；结果是启发式的，大小相同的修改会被当作未修改
+        #[arg(long)]
+        quick: bool,
+
+        /// 与 --quick 一起使用，对判断为修改的条目再计算 pak 中数据的哈希确认
+        #[arg(long, requires = "quick")]
+        confirm: bool,
+    },
+
+    /// 校验每个条目的 SHA-1，存在不匹配的条目时以非零状态码退出
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp verify **/*.pak --cache
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// game_patch_1.32.11.13800.pak
+    ///     [3] ShadowTrackerExtra/Content/Lua/common/lua_object.lua: MISMATCH
+    ///     OK: 6, Mismatched: 1, Unhashed: 0
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Verify {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
+
+        /// 将计算出的哈希缓存到 pak 旁的 .gfpsum 文件中，pak 未修改时直接复用
+        #[arg(long)]
+        cache: bool,
+
+        /// 同时检查条目数据在 pak 中的位置：列出块不按顺序排列的条目和多个条目共用的块，
+        /// 这两种情况都能正常解包；不同条目的数据部分重叠时视为校验失败
+        #[arg(long)]
+        deep: bool,
+    },
+
+    /// 测量解包速度：将每个条目解包到空输出，不写入磁盘
+    ///
+    /// 先只读取每个条目在 pak 中的数据，再完整解包（读取、解密和解压）一次，
+    /// 两者的差距大致为解密和解压所用的时间；第二次读取通常来自系统缓存
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp bench game_patch_1.32.11.13992.pak
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// game_patch_1.32.11.13992.pak
+    ///     Entries: 247
+    ///     Read: 32.6 MiB in 0.006 s, 5731.1 MiB/s
+    ///     Extract: 73.2 MiB in 0.323 s, 226.6 MiB/s
+    /// ```
+    ///
+    /// 速度按解包后的大小计算，Read 按在 pak 中占用的大小计算
+    #[command(verbatim_doc_comment)]
+    Bench {
+        /// 路径模板
+        #[arg(required = true)]
+        file_pattern: String,
+    },
+
+    /// 将 pak 的索引（已解密，压缩的索引已解压）写入文件，供外部分析
+    ///
+    /// 同时在 <OUT>.json 中记录索引在 pak 中的位置和大小、导出的字节数、是否加密和 pak 版本
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp dump-index game_patch_1.32.11.13846.pak --out index.bin
+    /// ```
+    ///
+    /// index.bin.json：
+    ///
+    /// ```
+    /// {"path":"game_patch_1.32.11.13846.pak","offset":22333,"size":1442,"length":1442,"encrypted":false,"version":10}
+    /// ```
+    #[command(verbatim_doc_comment)]
+    DumpIndex {
+        /// pak 路径
+        #[arg(required = true)]
+        pak_path: PathBuf,
+
+        /// 输出文件
+        #[arg(short, long, required = true)]
+        out: PathBuf,
+    },
+
+    /// 从索引已损坏或丢失的 pak 中尽量找回文件，例如下载中断、末尾被截断的 pak
+    ///
+    /// 打包时每个条目的数据前都有一份与索引中相同的记录，salvage 在整个文件中查找这样的记录，
+    /// 未压缩的条目须与记录的哈希一致，压缩的条目须能解压第一个块，才会被找回。
+    /// 条目路径随索引一起丢失，找回的文件写入输出目录下的 salvaged/<偏移>_<哈希前 8 位>.<扩展名>，
+    /// 扩展名根据文件开头识别，无法识别时为 bin；空条目无法与填充区分，不会被找回
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp salvage game_patch_1.32.11.13846.pak D:\gfp_output
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// Salvaged 7 entries (141.5 KiB), 0 don't match their hash
+    /// Rejected 1 candidate records:
+    ///     payload past the end of the file: 1
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Salvage {
+        /// pak 路径
+        #[arg(required = true)]
+        pak_path: PathBuf,
+
+        /// 输出目录，不存在时自动创建
+        #[arg(default_value = DEFAULT_OUTPUT_DIR)]
+        output_dir: String,
+
+        /// 是否在终端显示找回的文件名
+        #[arg(short = 'n', long)]
+        show_entry_path: bool,
+    },
+
+    /// 对单个 pak 进行一系列只读检查并输出报告，用于排查无法解包的 pak
+    ///
+    /// 依次检查 footer、magic 与版本、加密标志、索引大小、条目数量、抽样条目路径能否查找，
+    /// 以及第一个条目能否解包；无法继续的错误记为失败并停止后续检查。任一检查失败时退出码为 1
+    ///
+    /// 示例：
+    ///
+    /// ```sh
+    /// gfp doctor game_patch_1.32.11.13846.pak
+    /// ```
+    ///
+    /// 输出：
+    ///
+    /// ```
+    /// [ OK ] footer: V10 footer
+    /// [ OK ] magic: 0xFF67FF70
+    /// [ OK ] version: declared 10, read as 10
+    /// [ OK ] encrypted: index is plain
+    /// [ OK ] index size: 1442 bytes at 22333
+    /// [ OK ] entries: 7 entries, payloads end at 22333
+    /// [ OK ] paths: 7 of 7 sampled paths resolve
+    /// [ OK ] extract: entry 0 (49.6 KiB) matches its hash
+    /// ```
+    #[command(verbatim_doc_comment)]
+    Doctor {
+        /// pak 路径
+        #[arg(required = true)]
+        pak_path: PathBuf,
+    },
+}
+
+/// unpack 中决定如何解包每个 pak 的选项，由 [`ExtractArgs::extract_options`] 统一转换为 [`ExtractOptions`]，
+/// 与库的语义相同
+#[derive(clap::Args, Debug)]
+struct ExtractArgs {
+    /// 从 TOML 文件读取解包选项，键名为不含 -- 的选项名，例如 strip-components = 2、mtime = "pak"，
+    /// 只解包部分条目时为 entry-ids = [0, 3]；命令行中指定的选项优先于文件中的设置
+    #[arg(long, value_name = "FILE")]
+    options_file: Option<PathBuf>,
+
+    /// 将每个 pak 解包到输出目录下以 pak 文件名（不含扩展名）命名的子目录中，而不是合并到一起
+    #[arg(long)]
+    per_pak: bool,
+
+    /// 继续被中断的解包：在每个 pak 的输出目录中用 <pak 文件名>.gfpresume 记录已解包的条目，重新运行时跳过它们
+    #[arg(long)]
+    resume: bool,
+
+    /// 类似 tar --strip-components，去掉条目路径开头的 N 级目录，例如为 2 时
+    /// ShadowTrackerExtra/Content/Lua/a.lua 解包到 <输出目录>/Lua/a.lua；路径不足 N+1 级的条目会被跳过
+    #[arg(long, value_name = "N")]
+    strip_components: Option<usize>,
+
+    /// 解包出的文件的修改时间：now 为解包时的时间；pak 为来源 pak 的修改时间；
+    /// fixed:<RFC 3339 时间> 为固定的时间，例如 fixed:2024-05-01T12:00:00+08:00，便于得到可复现的结果。默认为 now
+    #[arg(long, value_name = "MODE")]
+    mtime: Option<MtimePolicy>,
+
+    /// 只解包 id 为 ID 的条目（即 ls 显示的条目 id），可以指定多次；
+    /// 不需要查找路径，是最快的选择方式
+    #[arg(long = "id", value_name = "ID")]
+    ids: Vec<u64>,
+
+    /// 将路径中不能用作 Windows 文件名的部分替换为 _：字符 <>:"|?*\ 和控制字符、
+    /// 结尾的点和空格，并在 CON、AUX、COM1 等设备名前加 _；在 Windows 上总是开启
+    #[arg(long)]
+    sanitize_names: bool,
+
+    /// 读取 pak 的方式：default 由系统决定预读；prefetch 在处理当前条目时提示系统预读下一个条目
+    /// （Linux 的 posix_fadvise），机械硬盘和网络存储上更快；mmap 将 pak 映射到内存中读取并同样预读，
+    /// 仅支持 Linux，其它平台退回普通读取，解包期间 pak 不能被修改；同时指定 --direct 时不映射。默认为 default
+    #[arg(long, value_name = "STRATEGY")]
+    read_strategy: Option<ReadStrategy>,
+
+    /// 只解包路径匹配 FILE 中任一行的条目，可以指定多次。每行为一个条目路径，含 *、? 或 [ 时为 glob 模式，
+    /// 以 re: 开头时为正则表达式（匹配路径的任意部分）；忽略空行和以 # 开头的行。
+    /// 结束时显示每个列表匹配的条目数
+    #[arg(long, value_name = "FILE")]
+    include_from: Vec<PathBuf>,
+
+    /// 不解包路径匹配 FILE 中任一行的条目，格式同 --include-from，优先于 --include-from
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+
+    /// 将以 FROM 开头的条目路径中的 FROM 替换为 TO，可以指定多次，只使用第一个匹配的规则，
+    /// 例如 --rewrite ShadowTrackerExtra/Content/=Content/；在 --sanitize-names 和 --strip-components 之前进行。
+    /// 按字符串比较，FROM 应以 / 结尾以免匹配到同名前缀的目录；多个条目被改写为同一路径时后解包的覆盖先解包的
+    #[arg(long = "rewrite", value_name = "FROM=TO")]
+    rewrites: Vec<PathRewrite>,
+
+    /// 有条目不匹配任何 --rewrite 规则时报错，而不是保留其原路径
+    #[arg(long)]
+    rewrite_strict: bool,
+
+    /// 不在解包每个条目前检查 pak 的大小和末尾的 pak 信息是否变化；默认发现 pak 在解包中途被修改
+    /// （例如游戏正在更新）时停止解包该 pak 并报错，以免按旧索引写出错误的内容
+    #[arg(long)]
+    ignore_changes: bool,
+
+    /// 解包后检查每个文件是否本身是完整的 zlib 数据（例如以 78 9C 开头的配置文件），是则替换为解压后的内容。
+    /// 只有 zlib 头有效、能完整解压且校验和一致、解压后不超过 256 MiB 时才解压，其它文件保持原样。plan 不支持此选项
+    #[arg(long)]
+    inflate_inner: bool,
+
+    /// 与 --inflate-inner 一起使用，保留解压前的文件，文件名后加 .z
+    #[arg(long)]
+    keep_raw: bool,
+}
+
+impl ExtractArgs {
+    /// 读取 --include-from 和 --exclude-from 列表
+    fn entry_lists(&self) -> Result<Vec<EntryList>, PakError> {
+        self.include_from
+            .iter()
+            .map(|path| EntryList::load("include", path.clone()))
+            .chain(
+                self.exclude_from
+                    .iter()
+                    .map(|path| EntryList::load("exclude", path.clone())),
+            )
+            .collect()
+    }
+
+    /// 先读取 --options-file，再用命令行中指定的选项覆盖，lists 为已读取的 --include-from 和 --exclude-from 列表
+    fn extract_options(&self, lists: &[EntryList]) -> Result<ExtractOptions, PakError> {
+        let mut options = match &self.options_file {
+            Some(path) => ExtractOptions::from_toml_file(path)?,
+            None => ExtractOptions::default(),
+        };
+        if self.per_pak {
+            options.layout = OutputLayout::PerPak;
+        }
+        options.resume |= self.resume;
+        if let Some(count) = self.strip_components {
+            options.strip_components = count;
+        }
+        if let Some(mtime) = self.mtime {
+            options.mtime = mtime;
+        }
+        options.sanitize_names |= self.sanitize_names || cfg!(windows);
+        if !self.ids.is_empty() {
+            options.entry_ids = Some(self.ids.clone());
+        }
+        if !lists.is_empty() {
+            options.filter = entry_lists_filter(lists);
+        }
+        if let Some(read_strategy) = self.read_strategy {
+            options.read_strategy = read_strategy;
+        }
+        if !self.rewrites.is_empty() {
+            options.path_rewrites = self.rewrites.clone();
+        }
+        options.rewrite_strict |= self.rewrite_strict;
+        options.ignore_changes |= self.ignore_changes;
+        options.inflate_inner |= self.inflate_inner;
+        options.keep_raw |= self.keep_raw;
+        Ok(options)
+    }
+}
+
+/// 安装输出到 stderr 的日志订阅者
+#[cfg(feature = "tracing")]
+fn init_tracing(verbose: u8) {
+    use tracing_subscriber::EnvFilter;
+
+    let filter = EnvFilter::try_from_env("GFP_LOG").unwrap_or_else(|_| {
+        EnvFilter::new(match verbose {
+            0 => "warn",
+            1 => "debug",
+            _ => "trace",
+        })
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// 查找和打开 pak 的方式
+struct OpenOptions {
+    varient: i32,
+    discovery: DiscoveryOptions,
+    /// 以 JSON 输出错误，见 [`format_error`]
+    json_errors: bool,
+}
+
+/// 打开每个匹配的 pak，无法打开的 pak 会输出错误信息后跳过
+///
+/// `file_pattern` 是已存在的目录时遍历该目录，否则作为路径模板处理
+fn open_paks(
+    file_pattern: &str,
+    options: &OpenOptions,
+) -> Result<impl Iterator<Item = (PathBuf, Box<dyn PakReader>)>, PatternError> {
+    let paks: Box<dyn Iterator<Item = (PathBuf, PakOpenResult)>> =
+        if Path::new(file_pattern).is_dir() {
+            Box::new(open_paks_in_dir(
+                file_pattern,
+                options.varient,
+                options.discovery.clone(),
+            ))
+        } else {
+            let file_pattern = if !options.discovery.detect {
+                cli::prepare_file_pattern(file_pattern)
+            } else if Path::new(file_pattern).exists() {
+                // 不限制扩展名，已存在的文件按字面匹配
+                glob::Pattern::escape(file_pattern)
+            } else {
+                file_pattern.to_string()
+            };
+            Box::new(open_paks_by_pattern(
+                &file_pattern,
+                options.varient,
+                options.discovery.clone(),
+            )?)
+        };
+
+    Ok(paks.filter_map(|(pak_path, pak)| match pak {
+        Ok(pak) => Some((pak_path, pak)),
+        Err(e) => {
+            eprintln!(
+                "{}",
+                format_error(
+                    options.json_errors,
+                    Some(&pak_path),
+                    "Error opening pak file",
+                    &e
+                )
+            );
+            None
+        }
+    }))
+}
+
+/// 打开计划中的一个 pak，--pak-version auto 时按文件尾识别版本
+fn open_planned_pak(
+    pak_path: &Path,
+    options: &OpenOptions,
+) -> Result<Box<dyn PakReader>, PakError> {
+    if options.discovery.detect {
+        open_detected_pak(pak_path)?.ok_or_else(|| PakError::invalid_data("Not a gfp pak"))
+    } else {
+        open_pak(pak_path, options.varient)
+    }
+}
+
+/// 格式化一条错误信息
+///
+/// `json` 时为一行 {"error":"...","pak":"..."}，`pak_path` 为空时 pak 为 null，
+/// 否则为 "{context} {pak_path}: {error}" 形式的文本
+fn format_error(
+    json: bool,
+    pak_path: Option<&Path>,
+    context: &str,
+    error: &dyn std::fmt::Display,
+) -> String {
+    match (json, pak_path) {
+        (true, pak_path) => to_json(&ErrorJson {
+            error: error.to_string(),
+            pak: pak_path.map(|pak_path| pak_path.to_string_lossy()),
+        }),
+        (false, Some(pak_path)) => {
+            format!("{} {}: {}", context, pak_path.to_string_lossy(), error)
+        }
+        (false, None) => format!("{}: {}", context, error),
+    }
+}
+
+/// `--json` 时的一条错误信息
+#[derive(serde::Serialize)]
+struct ErrorJson<'a> {
+    error: String,
+    pak: Option<Cow<'a, str>>,
+}
+
+/// 将 value 编码为一行 JSON
+fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).expect("JSON outputs are plain values")
+}
+
+/// `info --json` 输出的一行 JSON，`fast` 时只包含文件末尾 pak 信息中的字段
+fn info_json(pak_path: &Path, pak: &mut dyn PakReader, fast: bool) -> Result<String, PakError> {
+    let mut info = InfoJson {
+        path: pak_path.to_string_lossy(),
+        encrypted: pak.encrypted()?,
+        version: pak.declared_version()?,
+        parser_version: pak.parser_version(),
+        file_size: pak.pak_size()?,
+        index: None,
+        build: None,
+    };
+    if !fast {
+        let stats = pak.index_stats()?;
+        info.index = Some(IndexInfoJson {
+            footer_hash: hex::encode(stats.footer_hash),
+            mount_point: stats.mount_point,
+            entries_count: stats.entries_count,
+            index_offset: stats.index_offset,
+            index_size: stats.index_size,
+            encrypted_entries: stats.encrypted_entries,
+            plain_entries: stats.plain_entries,
+            compression_methods: stats.compression_methods,
+        });
+    }
+    let metadata = pak_metadata(pak, fast)?;
+    info.build = metadata.build().map(|(build, source)| BuildJson {
+        build: build.to_string(),
+        build_source: build_source_name(source),
+    });
+    Ok(to_json(&info))
+}
+
+/// `info --json` 输出的 JSON
+#[derive(serde::Serialize)]
+struct InfoJson<'a> {
+    path: Cow<'a, str>,
+    encrypted: bool,
+    version: u32,
+    parser_version: u32,
+    file_size: u64,
+    /// 不带 --fast 时读取索引得到的字段
+    #[serde(flatten)]
+    index: Option<IndexInfoJson>,
+    #[serde(flatten)]
+    build: Option<BuildJson>,
+}
+
+/// `info --json` 中来自索引的字段
+#[derive(serde::Serialize)]
+struct IndexInfoJson {
+    mount_point: String,
+    entries_count: u64,
+    index_offset: u64,
+    index_size: u64,
+    footer_hash: String,
+    encrypted_entries: u64,
+    plain_entries: u64,
+    compression_methods: BTreeMap<u32, u64>,
+}
+
+/// `info --json` 中 pak 所属的版本
+#[derive(serde::Serialize)]
+struct BuildJson {
+    build: String,
+    build_source: &'static str,
+}
+
+/// pak 的元数据，`fast` 时不查找版本清单条目，不读取索引
+fn pak_metadata(pak: &mut dyn PakReader, fast: bool) -> Result<PakMetadata, PakError> {
+    if fast {
+        pak.pak_metadata_with(&[])
+    } else {
+        pak.pak_metadata()
+    }
+}
+
+/// `info --json` 中的 build_source
+fn build_source_name(source: BuildSource) -> &'static str {
+    match source {
+        BuildSource::Manifest => "manifest",
+        BuildSource::FileName => "file_name",
+    }
+}
+
+/// 将 pak 所属的版本格式化为 `1.32.11.13846 (file name)`，取自版本清单时标出清单条目的路径
+fn format_build(metadata: &PakMetadata) -> Option<String> {
+    let (build, source) = metadata.build()?;
+    Some(match (&metadata.manifest, source) {
+        (Some((manifest_path, _)), BuildSource::Manifest) => {
+            format!("{} (manifest {})", build, manifest_path)
+        }
+        _ => format!("{} (file name)", build),
+    })
+}
+
+/// 打开的 pak 及其路径
+type OpenedPak = (PathBuf, Box<dyn PakReader>);
+
+/// `ls --group-by-build` 的分组：按 pak 所属的版本排序，无法确定版本的排在最后，同一版本的 pak
+/// 保持原有顺序；返回排序后的 pak，以及每组第一个 pak 之前输出的标题
+fn group_paks_by_build(
+    paks: impl Iterator<Item = OpenedPak>,
+) -> Result<(Vec<OpenedPak>, HashMap<PathBuf, String>), PakError> {
+    let mut builds = vec![];
+    for (pak_path, mut pak) in paks {
+        let build = pak.pak_metadata()?.build().map(|(build, _)| build.clone());
+        builds.push((build, pak_path, pak));
+    }
+    builds.sort_by(|(a, ..), (b, ..)| (a.is_none(), a).cmp(&(b.is_none(), b)));
+
+    let mut headings = HashMap::new();
+    let mut last = None;
+    for (build, pak_path, _) in &builds {
+        if last != Some(build) {
+            let heading = match build {
+                Some(build) => format!("Build {}:", build),
+                None => "Build unknown:".to_string(),
+            };
+            headings.insert(pak_path.clone(), heading);
+            last = Some(build);
+        }
+    }
+    let paks = builds
+        .into_iter()
+        .map(|(_, pak_path, pak)| (pak_path, pak))
+        .collect();
+    Ok((paks, headings))
+}
+
+/// 找出版本号最新的 pak，见 [`sort_paks_by_version`]
+fn latest_pak<T>(paks: impl Iterator<Item = (PathBuf, T)>) -> Option<(PathBuf, T)> {
+    let mut paks: BTreeMap<PathBuf, T> = paks.collect();
+    let mut pak_paths: Vec<PathBuf> = paks.keys().cloned().collect();
+    sort_paks_by_version(&mut pak_paths);
+    let pak_path = pak_paths.pop()?;
+    let pak = paks.remove(&pak_path)?;
+    Some((pak_path, pak))
+}
+
+/// 将条目布局格式化为一行，例如
+/// `method=1 encrypted=false size=70000 blocks=[0x4A+0x1F00->65536, 0x1F4A+0x200->4464]`
+fn format_layout(layout: &EntryLayout) -> String {
+    let payload = match &layout.payload {
+        EntryPayload::Stored { offset, length } => format!("stored={:#X}+{:#X}", offset, length),
+        EntryPayload::Blocks(blocks) => {
+            let blocks: Vec<_> = blocks
+                .iter()
+                .map(|block| {
+                    format!(
+                        "{:#X}+{:#X}->{}",
+                        block.offset, block.compressed_length, block.decompressed_length
+                    )
+                })
+                .collect();
+            format!("blocks=[{}]", blocks.join(", "))
+        }
+    };
+    format!(
+        "method={} encrypted={} size={} {}",
+        layout.compression_method, layout.encrypted, layout.file_size, payload
+    )
+}
+
+/// 将压缩率显示为百分比，空条目显示为 -
+fn format_ratio(ratio: Option<f64>) -> String {
+    match ratio {
+        Some(ratio) => format!("{:.1}%", ratio * 100.0),
+        None => "-".to_string(),
+    }
+}
+
+/// `ls --verify-hashes` 中条目的校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashStatus {
+    Ok,
+    Mismatch,
+    /// 索引中的哈希全为 0
+    NoHash,
+}
+
+impl HashStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            HashStatus::Ok => "OK",
+            HashStatus::Mismatch => "MISMATCH",
+            HashStatus::NoHash => "NOHASH",
+        }
+    }
+}
+
+fn entry_hash_status(pak: &mut dyn PakReader, entry_id: u64) -> Result<HashStatus, PakError> {
+    Ok(if pak.entry_hash(entry_id)? == [0; 20] {
+        HashStatus::NoHash
+    } else if pak.verify_entry(entry_id)? {
+        HashStatus::Ok
+    } else {
+        HashStatus::Mismatch
+    })
+}
+
+/// 按条目 id 顺序返回每个条目的校验结果。`jobs` 大于 1 时将条目分为连续的若干段，
+/// 每段由一个线程用重新打开的 pak 校验
+fn entry_hash_statuses(
+    pak_path: &Path,
+    pak: &mut dyn PakReader,
+    jobs: usize,
+) -> Result<Vec<HashStatus>, PakError> {
+    let entries = pak.entries_count()?;
+    let jobs = jobs.clamp(1, entries.max(1) as usize) as u64;
+    if jobs == 1 {
+        return (0..entries)
+            .map(|entry_id| entry_hash_status(pak, entry_id))
+            .collect();
+    }
+
+    let version = pak.parser_version() as i32;
+    let chunk = entries.div_ceil(jobs);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|job| {
+                let ids = job * chunk..((job + 1) * chunk).min(entries);
+                scope.spawn(move || {
+                    let mut pak = open_pak(pak_path, version)?;
+                    ids.map(|entry_id| entry_hash_status(pak.as_mut(), entry_id))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+            })
+            .collect();
+
+        let mut statuses = Vec::with_capacity(entries as usize);
+        for worker in workers {
+            statuses.extend(worker.join().expect("hash worker panicked")?);
+        }
+        Ok(statuses)
+    })
+}
+
+/// `ls --verify-hashes --porcelain` 输出的行：`HASH  <pak>  <id>  <OK|MISMATCH|NOHASH>  <path>`
+fn hash_status_porcelain(
+    pak_path: &Path,
+    entry_id: u64,
+    status: HashStatus,
+    entry_path: &str,
+) -> String {
+    porcelain_line(&[
+        "HASH",
+        &pak_path.to_string_lossy(),
+        &entry_id.to_string(),
+        status.as_str(),
+        entry_path,
+    ])
+}
+
+/// 将字段转义后以制表符连接为 `--porcelain` 的一行
+fn porcelain_line(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            field
+                .replace('%', "%25")
+                .replace('\t', "%09")
+                .replace('\n', "%0A")
+                .replace('\r', "%0D")
+        })
+        .collect();
+    fields.join("\t")
+}
+
+/// `ls --porcelain` 输出的行：`ENTRY  <pak>  <id>  <size>  <path>`，size 为解压后的大小
+fn ls_porcelain(
+    pak_path: &Path,
+    pak: &mut dyn PakReader,
+    filters: &[EntryFilter],
+) -> Result<Vec<String>, PakError> {
+    let pak_path = pak_path.to_string_lossy();
+    let mut lines = vec![];
+    for entry_id in 0..pak.entries_count()? {
+        if !passes_filters(pak, entry_id, filters)? {
+            continue;
+        }
+        lines.push(porcelain_line(&[
+            "ENTRY",
+            &pak_path,
+            &entry_id.to_string(),
+            &pak.entry_layout(entry_id)?.file_size.to_string(),
+            &pak.get_entry_path(entry_id)?,
+        ]));
+    }
+    Ok(lines)
+}
+
+/// `ls --format` 输出的行，每个满足 filters 的条目一行
+fn ls_formatted(
+    pak: &mut dyn PakReader,
+    format: &EntryFormat,
+    filters: &[EntryFilter],
+) -> Result<Vec<String>, PakError> {
+    let mut lines = vec![];
+    for entry_id in 0..pak.entries_count()? {
+        let layout = pak.entry_layout(entry_id)?;
+        if EntryFilter::matches_all(filters, &layout) {
+            lines.push(format.render(entry_id, &pak.get_entry_path(entry_id)?, &layout));
+        }
+    }
+    Ok(lines)
+}
+
+/// `ls --paths-only`、`--ids-only` 和 `--print0` 的输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MinimalFormat {
+    /// 输出条目 id 而不是条目路径
+    ids: bool,
+    /// 每条记录前加上 pak 路径和制表符
+    with_pak: bool,
+    /// 以 NUL 而不是换行结尾
+    print0: bool,
+}
+
+/// `ls` 的精简输出：每个要列出的条目只写入一条记录
+fn ls_minimal(
+    pak_path: &Path,
+    pak: &mut dyn PakReader,
+    format: MinimalFormat,
+    is_listed: impl Fn(u64) -> bool,
+    filters: &[EntryFilter],
+    output: &mut dyn Write,
+) -> Result<(), PakError> {
+    let terminator = if format.print0 { '\0' } else { '\n' };
+    for entry_id in 0..pak.entries_count()? {
+        if !is_listed(entry_id) || !passes_filters(pak, entry_id, filters)? {
+            continue;
+        }
+        if format.with_pak {
+            write!(output, "{}\t", pak_path.to_string_lossy()).map_err(PakError::output)?;
+        }
+        if format.ids {
+            write!(output, "{}{}", entry_id, terminator).map_err(PakError::output)?;
+        } else {
+            let entry_path = pak.get_entry_path(entry_id)?;
+            write!(output, "{}{}", entry_path, terminator).map_err(PakError::output)?;
+        }
+    }
+    Ok(())
+}
+
+/// `ls` 的默认输出：每个要列出的条目一行 `[id] path`，按需在后面加上压缩率和哈希校验结果，
+/// 并在下一行显示布局
+fn ls_rows(
+    pak: &mut dyn PakReader,
+    show_ratio: bool,
+    layout: bool,
+    statuses: Option<&[HashStatus]>,
+    is_listed: impl Fn(u64) -> bool,
+    filters: &[EntryFilter],
+    output: &mut dyn Write,
+) -> Result<(), PakError> {
+    for entry_id in 0..pak.entries_count()? {
+        if !is_listed(entry_id) || !passes_filters(pak, entry_id, filters)? {
+            continue;
+        }
+        let mut line = format!("[{}] {}", entry_id, pak.get_entry_path(entry_id)?);
+        if show_ratio {
+            let ratio = format_ratio(pak.entry_layout(entry_id)?.compression_ratio());
+            line = format!("{} {}", line, ratio);
+        }
+        if let Some(statuses) = statuses {
+            line = format!("{} {}", line, statuses[entry_id as usize].as_str());
+        }
+        writeln!(output, "{}", line).map_err(PakError::output)?;
+        if layout {
+            writeln!(
+                output,
+                "    {}",
+                format_layout(&pak.entry_layout(entry_id)?)
+            )
+            .map_err(PakError::output)?;
+        }
+    }
+    Ok(())
+}
+
+/// 条目是否满足所有 --where 条件，没有条件时不读取条目布局
+fn passes_filters(
+    pak: &mut dyn PakReader,
+    entry_id: u64,
+    filters: &[EntryFilter],
+) -> Result<bool, PakError> {
+    Ok(filters.is_empty() || EntryFilter::matches_all(filters, &pak.entry_layout(entry_id)?))
+}
+
+/// `unpack --porcelain` 输出的行：`OK|SKIP|FAIL  <pak>  <id>  <path>  <reason>`，
+/// OK 和 SKIP 的 path 为相对于输出目录的路径，FAIL 的 path 为条目路径
+fn extract_event_porcelain(pak_path: &Path, event: &ExtractEvent) -> String {
+    let pak_path = pak_path.to_string_lossy();
+    match event {
+        ExtractEvent::Extracted(file) => porcelain_line(&[
+            if file.resumed { "SKIP" } else { "OK" },
+            &pak_path,
+            &file.entry_id.to_string(),
+            &file.path.to_string_lossy(),
+            if file.resumed { "resumed" } else { "" },
+        ]),
+        ExtractEvent::Skipped {
+            entry_id,
+            entry_path,
+            reason,
+        } => porcelain_line(&["SKIP", &pak_path, &entry_id.to_string(), entry_path, reason]),
+        ExtractEvent::Failed {
+            entry_id,
+            entry_path,
+            error,
+        } => porcelain_line(&[
+            "FAIL",
+            &pak_path,
+            &entry_id.to_string(),
+            entry_path,
+            &error.to_string(),
+        ]),
+    }
+}
+
+/// unpack 的一个 --include-from 或 --exclude-from 列表
+struct EntryList {
+    /// include 或 exclude
+    kind: &'static str,
+    path: PathBuf,
+    filter: entry_filter::EntryFilter,
+    /// 所有 pak 中匹配列表的条目数
+    matched: u64,
+}
+
+impl EntryList {
+    fn load(kind: &'static str, path: PathBuf) -> Result<Self, PakError> {
+        Ok(Self {
+            kind,
+            filter: entry_filter::EntryFilter::from_list_file(&path)?,
+            path,
+            matched: 0,
+        })
+    }
+}
+
+/// 匹配任一 include 列表（没有时为所有条目）且不匹配任何 exclude 列表的条目
+fn entry_lists_filter(lists: &[EntryList]) -> entry_filter::EntryFilter {
+    use entry_filter::EntryFilter;
+    let of_kind = |kind| {
+        lists
+            .iter()
+            .filter(|list| list.kind == kind)
+            .map(|list| list.filter.clone())
+            .collect::<Vec<_>>()
+    };
+    let mut filters = vec![];
+    let (includes, excludes) = (of_kind("include"), of_kind("exclude"));
+    if !includes.is_empty() {
+        filters.push(EntryFilter::Or(includes));
+    }
+    if !excludes.is_empty() {
+        filters.push(EntryFilter::Not(Box::new(EntryFilter::Or(excludes))));
+    }
+    match filters.len() {
+        0 => EntryFilter::All,
+        _ => EntryFilter::And(filters),
+    }
+}
+
+/// 每个列表匹配的条目数，便于发现列表中的错误；`--porcelain` 时为
+/// `LIST  include|exclude  <列表路径>  <条目数>`
+fn entry_list_lines(lists: &[EntryList], porcelain: bool) -> Vec<String> {
+    lists
+        .iter()
+        .map(|list| {
+            let path = list.path.to_string_lossy();
+            if porcelain {
+                porcelain_line(&["LIST", list.kind, &path, &list.matched.to_string()])
+            } else {
+                let kind = if list.kind == "include" {
+                    "Include"
+                } else {
+                    "Exclude"
+                };
+                format!("{} list {} matched {} entries", kind, path, list.matched)
+            }
+        })
+        .collect()
+}
+
+/// 写入 --report，失败时报告错误并不再写入
+fn write_report(report_file: &mut Option<ReportFile>, report: &UnpackReport) {
+    if let Some(file) = report_file
+        && let Err(e) = file.write(report)
+    {
+        eprintln!(
+            "Error writing report {}: {}",
+            file.path().to_string_lossy(),
+            e
+        );
+        *report_file = None;
+    }
+}
+
+/// unpack 最后的汇总行；`--porcelain` 时为
+/// `SUMMARY  <解包数>  <已解包数>  <跳过数>  <失败数>  <失败的 pak 数>  <写入字节数>  <耗时毫秒>`
+fn summary_line(
+    totals: &ExtractTotals,
+    failed_paks: u64,
+    elapsed: Duration,
+    porcelain: bool,
+) -> String {
+    if porcelain {
+        return porcelain_line(&[
+            "SUMMARY",
+            &totals.extracted.to_string(),
+            &totals.resumed.to_string(),
+            &totals.skipped.to_string(),
+            &totals.failed.to_string(),
+            &failed_paks.to_string(),
+            &totals.bytes_written.to_string(),
+            &elapsed.as_millis().to_string(),
+        ]);
+    }
+    let mut line = format!(
+        "Unpacked {} entries ({}), {} resumed, {} skipped, {} failed",
+        totals.extracted,
+        human_size(totals.bytes_written),
+        totals.resumed,
+        totals.skipped,
+        totals.failed
+    );
+    if failed_paks > 0 {
+        line = format!("{}, {} paks failed", line, failed_paks);
+    }
+    format!("{} in {:.2}s", line, elapsed.as_secs_f64())
+}
+
+/// 第一次按 Ctrl-C 时取消返回的令牌，第二次立即以 [`EXIT_CANCELLED`] 退出
+fn cancel_on_ctrlc() -> Result<CancellationToken, ctrlc::Error> {
+    let cancel = CancellationToken::new();
+    let handler_cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(EXIT_CANCELLED);
+        }
+        handler_cancel.cancel();
+    })?;
+    Ok(cancel)
+}
+
+/// 解包被 Ctrl-C 中断时的退出码
+const EXIT_CANCELLED: i32 = 130;
+
+/// 未指定输出目录时 unpack 解包到的目录
+const DEFAULT_OUTPUT_DIR: &str = "gfp_out";
+
+/// 在解包前创建输出目录，并确认可以在其中写入文件，避免解包到一半才失败
+fn prepare_output_dir(output_dir: &Path) -> Result<(), String> {
+    if output_dir.exists() && !output_dir.is_dir() {
+        return Err(format!(
+            "Output directory {} exists and is not a directory",
+            output_dir.to_string_lossy()
+        ));
+    }
+    std::fs::create_dir_all(output_dir).map_err(|e| {
+        format!(
+            "Can't create output directory {}: {}",
+            output_dir.to_string_lossy(),
+            e
+        )
+    })?;
+
+    let probe = output_dir.join(format!(".gfp_write_test_{}", std::process::id()));
+    File::create_new(&probe)
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| {
+            format!(
+                "Output directory {} is not writable: {}",
+                output_dir.to_string_lossy(),
+                e
+            )
+        })
+}
+
+/// 收集所有匹配的 pak 中的条目路径，返回存在于多个 pak 中的路径及包含它们的 pak
+fn find_overrides(
+    file_pattern: &str,
+    options: &OpenOptions,
+) -> Result<BTreeMap<String, Vec<PathBuf>>, Box<dyn std::error::Error>> {
+    let mut paks_by_path: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+
+    for (pak_path, mut pak) in open_paks(file_pattern, options)? {
+        if let Err(e) = (|| -> Result<(), PakError> {
+            for entry_id in 0..pak.entries_count()? {
+                let paks = paks_by_path
+                    .entry(pak.get_entry_path(entry_id)?)
+                    .or_default();
+                if !paks.contains(&pak_path) {
+                    paks.push(pak_path.clone());
+                }
+            }
+            Ok(())
+        })() {
+            eprintln!("Error reading {}: {}", pak_path.to_string_lossy(), e);
+        }
+    }
+
+    paks_by_path.retain(|_, paks| paks.len() > 1);
+    for paks in paks_by_path.values_mut() {
+        sort_paks_by_version(paks);
+    }
+    Ok(paks_by_path)
+}
+
+/// 分析所有匹配的 pak 中的重复条目，无法读取的 pak 会被跳过
+fn find_dupes(file_pattern: &str, options: &OpenOptions) -> Result<DupeReport, PatternError> {
+    let mut analyzer = DupeAnalyzer::new();
+    for (pak_path, mut pak) in open_paks(file_pattern, options)? {
+        if let Err(e) = analyzer.add_pak(&pak_path, pak.as_mut()) {
+            eprintln!("Error reading {}: {}", pak_path.to_string_lossy(), e);
+        }
+    }
+    Ok(analyzer.finish())
+}
+
+/// diff 的输出：比较方式、每个有变化的条目及其依据，最后是各类结果的数量
+fn format_diff(report: &[EntryDiff], options: &DiffOptions) -> String {
+    let mut output = String::from(match (options.quick, options.confirm) {
+        (false, _) => "Comparing by hash\n",
+        (true, false) => "Comparing by fingerprint (heuristic)\n",
+        (true, true) => "Comparing by fingerprint (heuristic), changed entries confirmed by hash\n",
+    });
+    let mut counts = [0usize; 4];
+    let mut by_fingerprint = 0;
+    for diff in report {
+        let (index, letter) = match diff.change {
+            Change::Added => (0, "A"),
+            Change::Removed => (1, "D"),
+            Change::Changed => (2, "M"),
+            Change::Unchanged => (3, ""),
+        };
+        counts[index] += 1;
+        if diff.basis == DiffBasis::Fingerprint {
+            by_fingerprint += 1;
+        }
+        if diff.change != Change::Unchanged {
+            output.push_str(&format!(
+                "{} {} [{}]\n",
+                letter,
+                diff.path,
+                diff.basis.name()
+            ));
+        }
+    }
+    output.push_str(&format!(
+        "{} added, {} removed, {} changed, {} unchanged",
+        counts[0], counts[1], counts[2], counts[3]
+    ));
+    if by_fingerprint > 0 {
+        output.push_str(&format!(" ({} by fingerprint)", by_fingerprint));
+    }
+    output.push('\n');
+    output
+}
+
+/// `dupes --json` 输出的 JSON
+fn dupes_json(report: &DupeReport) -> String {
+    let groups = report
+        .groups
+        .iter()
+        .map(|group| DupeGroupJson {
+            key: match group.key {
+                DupeKey::Hash(hash) => DupeKeyJson::Hash {
+                    hash: hex::encode(hash),
+                },
+                DupeKey::Sample { file_size, hash } => DupeKeyJson::Sample {
+                    file_size,
+                    sample_hash: hex::encode(hash),
+                },
+            },
+            stored_size: group.stored_size,
+            wasted: group.wasted_bytes(),
+            members: group
+                .members
+                .iter()
+                .map(|member| DupeMemberJson {
+                    pak: member.pak_path.to_string_lossy(),
+                    entry_id: member.entry_id,
+                    path: &member.path,
+                })
+                .collect(),
+        })
+        .collect();
+    to_json(&DupesJson {
+        wasted: report.wasted_bytes(),
+        groups,
+    })
+}
+
+/// `dupes --json` 输出的 JSON
+#[derive(serde::Serialize)]
+struct DupesJson<'a> {
+    wasted: u64,
+    groups: Vec<DupeGroupJson<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct DupeGroupJson<'a> {
+    #[serde(flatten)]
+    key: DupeKeyJson,
+    stored_size: u64,
+    wasted: u64,
+    members: Vec<DupeMemberJson<'a>>,
+}
+
+/// 分组依据的字段：索引中的哈希，或哈希为零时的大小和开头部分的哈希
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum DupeKeyJson {
+    Hash { hash: String },
+    Sample { file_size: u64, sample_hash: String },
+}
+
+#[derive(serde::Serialize)]
+struct DupeMemberJson<'a> {
+    pak: Cow<'a, str>,
+    entry_id: u64,
+    path: &'a str,
+}
+
+/// search 的匹配方式
+#[derive(Default)]
+struct SearchOptions<'a> {
+    /// 匹配包含 query 的路径，而不是完全相同的路径
+    contains: bool,
+    /// 显示每个 pak 中的所有匹配项
+    all: bool,
+    path_match: PathMatch,
+    /// --where 条件
+    filters: &'a [EntryFilter],
+}
+
+/// 在每个匹配的 pak 中查找条目，对每个匹配项调用 `on_hit`
+fn search_paks(
+    file_pattern: &str,
+    query: &str,
+    search: &SearchOptions,
+    options: &OpenOptions,
+    mut on_hit: impl FnMut(&Path, u64, &str),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let SearchOptions {
+        contains,
+        all,
+        path_match,
+        filters,
+    } = *search;
+    // 包含匹配不考虑挂载点
+    let query_key = path_match.key(query, "");
+    for (pak_path, mut pak) in open_paks(file_pattern, options)? {
+        if let Err(e) = (|| -> Result<(), PakError> {
+            if !contains {
+                if let Some(entry_id) = pak.find_entry_by_path_with(query, path_match)?
+                    && passes_filters(pak.as_mut(), entry_id, filters)?
+                {
+                    on_hit(&pak_path, entry_id, &pak.get_entry_path(entry_id)?);
+                }
+                return Ok(());
+            }
+
+            for entry_id in 0..pak.entries_count()? {
+                let entry_path = pak.get_entry_path(entry_id)?;
+                if path_match.key(&entry_path, "").contains(&query_key)
+                    && passes_filters(pak.as_mut(), entry_id, filters)?
+                {
+                    on_hit(&pak_path, entry_id, &entry_path);
+                    if !all {
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })() {
+            eprintln!("Error searching {}: {}", pak_path.to_string_lossy(), e);
+        }
+    }
+    Ok(())
+}
+
+/// 解析 --range 的 START:LEN
+fn parse_range(range: &str) -> Result<(u64, u64), String> {
+    let (start, length) = range
+        .split_once(':')
+        .ok_or_else(|| format!("Expected START:LEN, got '{}'", range))?;
+    Ok((
+        start.parse().map_err(|e| format!("Invalid START: {}", e))?,
+        length.parse().map_err(|e| format!("Invalid LEN: {}", e))?,
+    ))
+}
+
+/// 将最后一个包含 entry_path 的 pak 中该条目的内容写入 output，没有找到时返回 false
+///
+/// output 的读取端提前关闭（例如管道到 head）时视为成功
+fn cat_entry(
+    file_pattern: &str,
+    entry_path: &str,
+    range: Option<(u64, u64)>,
+    options: &OpenOptions,
+    output: &mut dyn Write,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut found = None;
+    for (pak_path, mut pak) in open_paks(file_pattern, options)? {
+        match pak.find_entry_by_path(entry_path) {
+            Ok(Some(entry_id)) => found = Some((pak_path, pak, entry_id)),
+            Ok(None) => {}
+            Err(e) => eprintln!("Error searching {}: {}", pak_path.to_string_lossy(), e),
+        }
+    }
+
+    let Some((pak_path, mut pak, entry_id)) = found else {
+        return Ok(false);
+    };
+    let result = match range {
+        Some((start, length)) => pak
+            .extract_entry_range(entry_id, start, length, output)
+            .map(|_| ()),
+        None => pak.extract_entry_to_writer(entry_id, output),
+    };
+    match result {
+        Err(e) if !matches!(e.root_cause(), PakError::OutputClosed) => Err(format!(
+            "Error extracting from {}: {}",
+            pak_path.to_string_lossy(),
+            e
+        )
+        .into()),
+        _ => Ok(true),
+    }
+}
+
+/// 将 pak 的索引写入 out，并将描述它的 JSON 写入 out 后加 .json 的文件，返回该 JSON
+fn dump_index(
+    pak_path: &Path,
+    pak: &mut dyn PakReader,
+    out: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let stats = pak.index_stats()?;
+    let (encrypted, version) = (pak.encrypted()?, pak.declared_version()?);
+    let index = pak.raw_index()?;
+    let header = to_json(&IndexDumpJson {
+        path: pak_path.to_string_lossy(),
+        offset: stats.index_offset,
+        size: stats.index_size,
+        length: index.len(),
+        encrypted,
+        version,
+    });
+    std::fs::write(out, index)?;
+    let mut header_path = out.as_os_str().to_os_string();
+    header_path.push(".json");
+    std::fs::write(header_path, format!("{}\n", header))?;
+    Ok(header)
+}
+
+/// dump-index 写入 out 后加 .json 的文件的 JSON
+#[derive(serde::Serialize)]
+struct IndexDumpJson<'a> {
+    path: Cow<'a, str>,
+    offset: u64,
+    size: u64,
+    /// 解密、解压后的索引长度
+    length: usize,
+    encrypted: bool,
+    version: u32,
+}
+
+/// 将 [`salvage_scan`] 找到的条目写入 `output_dir/salvaged/`
+fn salvage_pak(
+    pak_path: &Path,
+    output_dir: &Path,
+    show_entry_path: bool,
+) -> Result<SalvageReport, Box<dyn std::error::Error>> {
+    let file = File::open(pak_path)?;
+    let report = salvage_scan(&file)?;
+    let salvaged_dir = output_dir.join("salvaged");
+    std::fs::create_dir_all(&salvaged_dir)?;
+    for entry in &report.entries {
+        let mut head = vec![];
+        entry
+            .reader(&file, DEFAULT_BUFFER_CAP)
+            .take(16)
+            .read_to_end(&mut head)?;
+        let file_name = entry.file_name(detect_extension(&head));
+        let mut output = std::io::BufWriter::new(File::create(salvaged_dir.join(&file_name))?);
+        entry
+            .reader(&file, DEFAULT_BUFFER_CAP)
+            .copy_to(&mut output)?;
+        output.flush()?;
+        if show_entry_path {
+            println!("{}", file_name);
+        }
+    }
+    Ok(report)
+}
+
+/// salvage 最后输出的找回和拒绝的条目数，拒绝的记录按原因分别计数
+fn salvage_report_lines(report: &SalvageReport) -> Vec<String> {
+    let salvaged_size: u64 = report
+        .entries
+        .iter()
+        .map(|entry| entry.layout.file_size)
+        .sum();
+    let mismatched = report
+        .entries
+        .iter()
+        .filter(|entry| entry.hash_matches == Some(false))
+        .count();
+    let mut lines = vec![format!(
+        "Salvaged {} entries ({}), {} don't match their hash",
+        report.entries.len(),
+        human_size(salvaged_size),
+        mismatched
+    )];
+    if !report.rejected.is_empty() {
+        let mut reasons = BTreeMap::new();
+        for (_, rejection) in &report.rejected {
+            *reasons.entry(*rejection).or_insert(0u64) += 1;
+        }
+        lines.push(format!(
+            "Rejected {} candidate records:",
+            report.rejected.len()
+        ));
+        for (rejection, count) in reasons {
+            lines.push(format!("    {}: {}", rejection, count));
+        }
+    }
+    lines
+}
+
+/// doctor 的一项检查
+#[derive(Debug, Clone, PartialEq)]
+struct DoctorCheck {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+impl DoctorCheck {
+    fn new(name: &'static str, passed: bool, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.passed { " OK " } else { "FAIL" };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)
+    }
+}
+
+/// doctor 抽样查找路径的条目数
+const DOCTOR_PATH_SAMPLES: u64 = 10;
+
+/// doctor 依次进行的检查，某项检查出错时记为失败，不再进行后续检查
+fn doctor_checks(pak_path: &Path, options: &OpenOptions) -> Vec<DoctorCheck> {
+    let mut checks = vec![];
+    if let Err((name, e)) = run_doctor_checks(pak_path, options, &mut checks) {
+        checks.push(DoctorCheck::new(name, false, e.to_string()));
+    }
+    checks
+}
+
+fn run_doctor_checks(
+    pak_path: &Path,
+    options: &OpenOptions,
+    checks: &mut Vec<DoctorCheck>,
+) -> Result<(), (&'static str, Box<dyn std::error::Error>)> {
+    use gfp::pak_reader::{FOOTER_MAGIC, MAX_INDEX_SIZE, MIN_ENTRY_SIZE};
+
+    let detected = is_gfp_pak(pak_path).map_err(|e| ("footer", e.into()))?;
+    checks.push(match detected {
+        Some(variant) => DoctorCheck::new("footer", true, format!("{:?} footer", variant)),
+        None => DoctorCheck::new("footer", false, "no gfp footer at the end of the file"),
+    });
+
+    let mut pak = open_planned_pak(pak_path, options).map_err(|e| ("open", e.into()))?;
+    // Parsing that stops short of the end of the index fails the entries check
+    pak.set_strict(true);
+
+    let footer = pak.footer_bytes().map_err(|e| ("magic", e.into()))?;
+    let magic = u32::from_le_bytes([footer[1], footer[2], footer[3], footer[4]]);
+    checks.push(DoctorCheck::new(
+        "magic",
+        magic == FOOTER_MAGIC,
+        format!("{:#010X}", magic),
+    ));
+
+    let declared_version = pak.declared_version().map_err(|e| ("version", e.into()))?;
+    checks.push(DoctorCheck::new(
+        "version",
+        declared_version == pak.parser_version(),
+        format!(
+            "declared {}, read as {}",
+            declared_version,
+            pak.parser_version()
+        ),
+    ));
+
+    // The footer check already rejects flags other than 0 and 1
+    let encrypted = pak.encrypted().map_err(|e| ("encrypted", e.into()))?;
+    checks.push(DoctorCheck::new(
+        "encrypted",
+        true,
+        if encrypted {
+            "index is encrypted"
+        } else {
+            "index is plain"
+        },
+    ));
+
+    let pak_size = pak.pak_size().map_err(|e| ("index size", e.into()))?;
+    let stats = pak.index_stats().map_err(|e| ("index size", e.into()))?;
+    checks.push(DoctorCheck::new(
+        "index size",
+        stats.index_size <= MAX_INDEX_SIZE
+            && stats.index_offset.saturating_add(stats.index_size) <= pak_size,
+        format!("{} bytes at {}", stats.index_size, stats.index_offset),
+    ));
+
+    let entries_count = pak.entries_count().map_err(|e| ("entries", e.into()))?;
+    let entries_count_fast = pak
+        .entries_count_fast()
+        .map_err(|e| ("entries", e.into()))?;
+    let mut payloads_end = 0;
+    for entry_id in 0..entries_count {
+        let layout = pak
+            .entry_layout(entry_id)
+            .map_err(|e| ("entries", e.into()))?;
+        let end = match &layout.payload {
+            EntryPayload::Stored { offset, length } => offset.saturating_add(*length),
+            EntryPayload::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| block.offset.saturating_add(block.compressed_length))
+                .max()
+                .unwrap_or(0),
+        };
+        payloads_end = payloads_end.max(end);
+    }
+    checks.push(DoctorCheck::new(
+        "entries",
+        entries_count == entries_count_fast
+            && entries_count.saturating_mul(MIN_ENTRY_SIZE as u64) <= stats.index_size
+            && payloads_end <= stats.index_offset,
+        format!(
+            "{} entries, payloads end at {}",
+            entries_count, payloads_end
+        ),
+    ));
+
+    let step = (entries_count / DOCTOR_PATH_SAMPLES).max(1);
+    let mut sampled = 0;
+    let mut resolved = 0;
+    for entry_id in (0..entries_count)
+        .step_by(step as usize)
+        .take(DOCTOR_PATH_SAMPLES as usize)
+    {
+        sampled += 1;
+        let entry_path = pak
+            .get_entry_path(entry_id)
+            .map_err(|e| ("paths", e.into()))?;
+        let found = pak
+            .find_entry_by_path(&entry_path)
+            .map_err(|e| ("paths", e.into()))?;
+        // Paths the pak holds twice resolve to one of the entries
+        let resolves = match found {
+            Some(found) => {
+                pak.get_entry_path(found).map_err(|e| ("paths", e.into()))? == entry_path
+            }
+            None => false,
+        };
+        if !entry_path.is_empty() && resolves {
+            resolved += 1;
+        }
+    }
+    checks.push(DoctorCheck::new(
+        "paths",
+        resolved == sampled,
+        format!("{} of {} sampled paths resolve", resolved, sampled),
+    ));
+
+    if entries_count == 0 {
+        checks.push(DoctorCheck::new("extract", true, "no entries"));
+        return Ok(());
+    }
+    let layout = pak.entry_layout(0).map_err(|e| ("extract", e.into()))?;
+    let data = pak
+        .extract_entry_to_vec(0)
+        .map_err(|e| ("extract", e.into()))?;
+    let hash = pak.entry_hash(0).map_err(|e| ("extract", e.into()))?;
+    let check = if data.len() as u64 != layout.file_size {
+        DoctorCheck::new(
+            "extract",
+            false,
+            format!(
+                "entry 0 extracted to {} bytes instead of {}",
+                data.len(),
+                layout.file_size
+            ),
+        )
+    } else if hash == [0; 20] {
+        DoctorCheck::new(
+            "extract",
+            true,
+            format!("entry 0 ({}) has no hash", human_size(layout.file_size)),
+        )
+    } else if pak.verify_entry(0).map_err(|e| ("extract", e.into()))? {
+        DoctorCheck::new(
+            "extract",
+            true,
+            format!(
+                "entry 0 ({}) matches its hash",
+                human_size(layout.file_size)
+            ),
+        )
+    } else {
+        DoctorCheck::new("extract", false, "entry 0 doesn't match its hash")
+    };
+    checks.push(check);
+    Ok(())
+}
+
+/// 校验结果统计
+#[derive(Debug, Clone, Default, PartialEq)]
+struct VerifySummary {
+    ok: u64,
+    mismatched: u64,
+    unhashed: u64,
+}
+
+/// `verify --porcelain` 中每个不匹配的条目输出的行：`MISMATCH  <pak>  <id>  <path>`
+fn verify_mismatch_porcelain(pak_path: &Path, entry_id: u64, entry_path: &str) -> String {
+    porcelain_line(&[
+        "MISMATCH",
+        &pak_path.to_string_lossy(),
+        &entry_id.to_string(),
+        entry_path,
+    ])
+}
+
+/// `verify --deep` 输出的块布局检查结果，返回是否没有重叠的数据
+fn print_block_map(pak_path: &Path, pak: &mut dyn PakReader, porcelain: bool) -> bool {
+    match check_block_map(pak) {
+        Ok(report) => {
+            for line in block_map_lines(pak_path, &report, porcelain) {
+                println!("{}", line);
+            }
+            report.overlapping.is_empty()
+        }
+        Err(e) => {
+            eprintln!(
+                "Error checking blocks of {}: {}",
+                pak_path.to_string_lossy(),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// `verify --deep` 输出的行。`--porcelain` 时为
+/// `BLOCKS  <pak>  ORDER|SHARED|OVERLAP  <以逗号分隔的条目 id>  <偏移>  <长度>`，
+/// 只有 SHARED 有偏移和长度
+fn block_map_lines(pak_path: &Path, report: &BlockMapReport, porcelain: bool) -> Vec<String> {
+    let ids = |entry_ids: &[u64]| -> Vec<String> {
+        entry_ids
+            .iter()
+            .map(|entry_id| entry_id.to_string())
+            .collect()
+    };
+    let pak_path = pak_path.to_string_lossy();
+    let mut lines = vec![];
+    if porcelain {
+        let line = |kind: &str, entry_ids: &[u64], offset: &str, length: &str| {
+            porcelain_line(&[
+                "BLOCKS",
+                &pak_path,
+                kind,
+                &ids(entry_ids).join(","),
+                offset,
+                length,
+            ])
+        };
+        for &entry_id in &report.out_of_order {
+            lines.push(line("ORDER", &[entry_id], "", ""));
+        }
+        for piece in &report.shared {
+            lines.push(line(
+                "SHARED",
+                &piece.entry_ids,
+                &piece.offset.to_string(),
+                &piece.length.to_string(),
+            ));
+        }
+        for &(first, second) in &report.overlapping {
+            lines.push(line("OVERLAP", &[first, second], "", ""));
+        }
+        return lines;
+    }
+
+    let bracketed = |entry_ids: &[u64]| -> String {
+        ids(entry_ids)
+            .iter()
+            .map(|entry_id| format!("[{}]", entry_id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if !report.out_of_order.is_empty() {
+        lines.push(format!(
+            "    Blocks out of order: {}",
+            bracketed(&report.out_of_order)
+        ));
+    }
+    for piece in &report.shared {
+        lines.push(format!(
+            "    Shared {:#X}+{:#X}: {}",
+            piece.offset,
+            piece.length,
+            bracketed(&piece.entry_ids)
+        ));
+    }
+    for &(first, second) in &report.overlapping {
+        lines.push(format!(
+            "    WARNING: payloads of [{}] and [{}] overlap",
+            first, second
+        ));
+    }
+    if report.is_plain() {
+        lines.push("    Blocks: in order, none shared".to_string());
+    }
+    lines
+}
+
+/// `verify --porcelain` 中每个 pak 最后输出的行：`PAKOK|PAKFAIL  <pak>  <ok>  <mismatched>  <unhashed>  <reason>`，
+/// 无法读取 pak 时三个计数为空
+fn verify_porcelain(pak_path: &Path, result: &Result<VerifySummary, PakError>) -> String {
+    let pak_path = pak_path.to_string_lossy();
+    match result {
+        Ok(summary) => porcelain_line(&[
+            if summary.mismatched == 0 {
+                "PAKOK"
+            } else {
+                "PAKFAIL"
+            },
+            &pak_path,
+            &summary.ok.to_string(),
+            &summary.mismatched.to_string(),
+            &summary.unhashed.to_string(),
+            if summary.mismatched == 0 {
+                ""
+            } else {
+                "mismatched entries"
+            },
+        ]),
+        Err(e) => porcelain_line(&["PAKFAIL", &pak_path, "", "", "", &e.to_string()]),
+    }
+}
+
+/// 校验 pak 中的每个条目，对每个不匹配的条目调用 `on_mismatch`
+fn verify_pak(
+    pak_path: &Path,
+    pak: &mut dyn PakReader,
+    use_cache: bool,
+    mut on_mismatch: impl FnMut(u64, &str),
+) -> Result<VerifySummary, PakError> {
+    let mut cache = if use_cache {
+        Some(ChecksumCache::load(pak_path)?)
+    } else {
+        None
+    };
+    let mut summary = VerifySummary::default();
+
+    for entry_id in 0..pak.entries_count()? {
+        if pak.entry_hash(entry_id)? == [0; 20] {
+            summary.unhashed += 1;
+            continue;
+        }
+
+        let is_ok = match &mut cache {
+            Some(cache) => cache.verify_entry(pak, entry_id)?,
+            None => pak.verify_entry(entry_id)?,
+        };
+        if is_ok {
+            summary.ok += 1;
+        } else {
+            summary.mismatched += 1;
+            on_mismatch(entry_id, &pak.get_entry_path(entry_id)?);
+        }
+    }
+
+    if let Some(cache) = &mut cache {
+        cache.save()?;
+    }
+    Ok(summary)
+}
+
+/// 一次计时的字节数和用时
+struct Throughput {
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl std::fmt::Display for Throughput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mib_per_second =
+            self.bytes as f64 / 1024.0 / 1024.0 / self.elapsed.as_secs_f64().max(1e-9);
+        write!(
+            f,
+            "{} in {:.3} s, {:.1} MiB/s",
+            human_size(self.bytes),
+            self.elapsed.as_secs_f64(),
+            mib_per_second
+        )
+    }
+}
+
+/// 基准测试结果
+struct BenchResult {
+    entries: u64,
+    /// 只读取条目在 pak 中的数据
+    read: Throughput,
+    /// 完整解包条目
+    extract: Throughput,
+    /// 完整解包时读取器的 I/O
+    extract_io: IoStats,
+}
+
+/// I/O 统计的一行，例如 `12 reads, 1.5 MiB read, 4.0 MiB decompressed`
+fn format_io_stats(stats: &IoStats) -> String {
+    format!(
+        "{} reads, {} read, {} decompressed",
+        stats.reads,
+        human_size(stats.bytes_read),
+        human_size(stats.bytes_decompressed)
+    )
+}
+
+/// 处理完一个 pak 后记录读取器的 I/O 统计，-vv 时显示
+fn log_io_stats(pak_path: &Path, pak: &dyn PakReader) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        pak = %pak_path.display(),
+        io = %format_io_stats(&pak.stats()),
+        "done with pak"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (pak_path, pak);
+}
+
+/// 只统计写入字节数的空输出
+struct CountingSink(u64);
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn bench_pak(pak_path: &Path, pak: &mut dyn PakReader) -> Result<BenchResult, PakError> {
+    let entries = pak.entries_count()?;
+    let mut layouts = Vec::new();
+    for entry_id in 0..entries {
+        layouts.push(pak.entry_layout(entry_id)?);
+    }
+
+    let file = File::open(pak_path)?;
+    let mut buffer = Vec::new();
+    let mut read_bytes = 0;
+    let start = Instant::now();
+    for layout in &layouts {
+        let ranges: Vec<(u64, u64)> = match &layout.payload {
+            EntryPayload::Stored { offset, length } => vec![(*offset, *length)],
+            EntryPayload::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| (block.offset, block.compressed_length))
+                .collect(),
+        };
+        for (offset, length) in ranges {
+            buffer.resize(gfp::utils::to_usize(length)?, 0);
+            read_bytes += file.read_at(&mut buffer, offset)? as u64;
+        }
+    }
+    let read = Throughput {
+        bytes: read_bytes,
+        elapsed: start.elapsed(),
+    };
+
+    let mut output = CountingSink(0);
+    pak.reset_stats();
+    let start = Instant::now();
+    for entry_id in 0..entries {
+        pak.extract_entry_to_writer(entry_id, &mut output)?;
+    }
+    let extract = Throughput {
+        bytes: output.0,
+        elapsed: start.elapsed(),
+    };
+
+    Ok(BenchResult {
+        entries,
+        read,
+        extract,
+        extract_io: pak.stats(),
+    })
+}
+
+/// --pak-version 及其旧写法 --v10、--v7、--detect 指定的格式
+fn pak_version(args: &CliArgs) -> PakVersion {
+    if args.v7 {
+        PakVersion::V7
+    } else if args.v10 {
+        PakVersion::V10
+    } else if args.detect {
+        PakVersion::Auto
+    } else {
+        args.pak_version
+    }
+}
+
+/// 根据命令行参数生成打开 pak 的选项
+fn open_options(args: &CliArgs) -> OpenOptions {
+    let pak_version = pak_version(args);
+    OpenOptions {
+        // auto 时按文件尾识别，不使用 varient
+        varient: if pak_version == PakVersion::V7 { 7 } else { 10 },
+        discovery: DiscoveryOptions {
+            follow_symlinks: args.follow_symlinks,
+            max_depth: args.max_depth,
+            extensions: vec!["pak".to_string()],
+            sort: !args.no_sort,
+            detect: pak_version == PakVersion::Auto,
+            direct: args.direct,
+            // unpack 读取 --read-strategy 后设置
+            mmap: false,
+            match_options: MatchOptions {
+                case_sensitive: !args.case_insensitive,
+                ..MatchOptions::new()
+            },
+        },
+        json_errors: matches!(args.subcommand, Command::Info { json: true, .. }),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = CliArgs::parse();
+
+    #[cfg(feature = "tracing")]
+    init_tracing(args.verbose);
+
+    let mut options = open_options(&args);
+
+    match args.subcommand {
+        Command::Info {
+            file_pattern,
+            json,
+            fast,
+        } => {
+            let paks = match open_paks(&file_pattern, &options) {
+                Ok(paks) => paks,
+                Err(e) if json => {
+                    eprintln!("{}", format_error(true, None, "Invalid pattern", &e));
+                    std::process::exit(1);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let mut stdout = std::io::stdout().lock();
+            for (pak_path, mut pak) in paks {
+                if let Err(e) = (|| -> Result<(), PakError> {
+                    if json {
+                        writeln!(stdout, "{}", info_json(&pak_path, pak.as_mut(), fast)?)
+                            .map_err(PakError::output)?;
+                        return Ok(());
+                    }
+
+                    let file_size = pak.pak_size()?;
+                    writeln!(stdout, "{}", pak_path.to_string_lossy()).map_err(PakError::output)?;
+                    writeln!(stdout, "    IsEncrypted: {}", pak.encrypted()?)
+                        .map_err(PakError::output)?;
+                    let declared_version = pak.declared_version()?;
+                    writeln!(stdout, "    Version: {}", declared_version)
+                        .map_err(PakError::output)?;
+                    writeln!(
+                        stdout,
+                        "    ParserVersion: {}{}",
+                        pak.parser_version(),
+                        if declared_version != pak.parser_version() {
+                            " (mismatch)"
+                        } else {
+                            ""
+                        }
+                    )
+                    .map_err(PakError::output)?;
+                    writeln!(
+                        stdout,
+                        "    FileSize: {} ({})",
+                        file_size,
+                        human_size(file_size)
+                    )
+                    .map_err(PakError::output)?;
+                    if let Some(build) = format_build(&pak_metadata(pak.as_mut(), fast)?) {
+                        writeln!(stdout, "    Build: {}", build).map_err(PakError::output)?;
+                    }
+                    if fast {
+                        return Ok(());
+                    }
+
+                    let stats = pak.index_stats()?;
+                    writeln!(stdout, "    MountPoint: {}", stats.mount_point)
+                        .map_err(PakError::output)?;
+                    writeln!(stdout, "    EntryCount: {}", stats.entries_count)
+                        .map_err(PakError::output)?;
+                    writeln!(stdout, "    IndexOffset: {}", stats.index_offset)
+                        .map_err(PakError::output)?;
+                    writeln!(
+                        stdout,
+                        "    IndexSize: {} ({})",
+                        stats.index_size,
+                        human_size(stats.index_size)
+                    )
+                    .map_err(PakError::output)?;
+                    writeln!(stdout, "    FooterHash: {}", hex::encode(stats.footer_hash))
+                        .map_err(PakError::output)?;
+                    writeln!(stdout, "    EncryptedEntries: {}", stats.encrypted_entries)
+                        .map_err(PakError::output)?;
+                    writeln!(stdout, "    PlainEntries: {}", stats.plain_entries)
+                        .map_err(PakError::output)?;
+                    let compression_methods: Vec<String> = stats
+                        .compression_methods
+                        .iter()
+                        .map(|(method, count)| format!("{}={}", method, count))
+                        .collect();
+                    writeln!(
+                        stdout,
+                        "    CompressionMethods: {}",
+                        compression_methods.join(", ")
+                    )
+                    .map_err(PakError::output)?;
+                    Ok(())
+                })() {
+                    // 输出已被关闭（例如接到 head 之后），不必再显示其余的 pak
+                    if matches!(e.root_cause(), PakError::OutputClosed) {
+                        break;
+                    }
+                    eprintln!(
+                        "{}",
+                        format_error(json, Some(&pak_path), "Error reading", &e)
+                    );
+                }
+            }
+        }
+        Command::Ls {
+            file_pattern,
+            show_entry_path,
+            latest,
+            count,
+            layout,
+            verify_hashes,
+            only_mismatches,
+            jobs,
+            where_filters,
+            format,
+            group_by_build,
+            paths_only,
+            ids_only,
+            print0,
+            with_pak,
+        } => {
+            let mut headings = HashMap::new();
+            let paks: Box<dyn Iterator<Item = _>> = if latest {
+                Box::new(latest_pak(open_paks(&file_pattern, &options)?).into_iter())
+            } else if group_by_build {
+                let (paks, group_headings) =
+                    group_paks_by_build(open_paks(&file_pattern, &options)?)?;
+                headings = group_headings;
+                Box::new(paks.into_iter())
+            } else {
+                Box::new(open_paks(&file_pattern, &options)?)
+            };
+            let mut has_mismatch = false;
+            let mut stdout = std::io::stdout().lock();
+
+            for (pak_path, mut pak) in paks {
+                if let Err(e) = (|| -> Result<(), PakError> {
+                    if let Some(heading) = headings.remove(&pak_path) {
+                        writeln!(stdout, "{}", heading).map_err(PakError::output)?;
+                    }
+                    let statuses = if verify_hashes {
+                        let statuses = entry_hash_statuses(&pak_path, pak.as_mut(), jobs)?;
+                        has_mismatch |= statuses.contains(&HashStatus::Mismatch);
+                        Some(statuses)
+                    } else {
+                        None
+                    };
+                    let is_listed = |entry_id: u64| match &statuses {
+                        Some(statuses) if only_mismatches => {
+                            statuses[entry_id as usize] == HashStatus::Mismatch
+                        }
+                        _ => true,
+                    };
+
+                    if let (true, Some(statuses)) = (args.porcelain, &statuses) {
+                        for (entry_id, status) in statuses.iter().enumerate() {
+                            let entry_id = entry_id as u64;
+                            if is_listed(entry_id)
+                                && passes_filters(pak.as_mut(), entry_id, &where_filters)?
+                            {
+                                let entry_path = pak.get_entry_path(entry_id)?;
+                                writeln!(
+                                    stdout,
+                                    "{}",
+                                    hash_status_porcelain(
+                                        &pak_path,
+                                        entry_id,
+                                        *status,
+                                        &entry_path
+                                    )
+                                )
+                                .map_err(PakError::output)?;
+                            }
+                        }
+                        return Ok(());
+                    }
+                    if args.porcelain {
+                        if count {
+                            let count = pak.entries_count_fast()?.to_string();
+                            writeln!(
+                                stdout,
+                                "{}",
+                                porcelain_line(&["COUNT", &pak_path.to_string_lossy(), &count])
+                            )
+                            .map_err(PakError::output)?;
+                        } else {
+                            for line in ls_porcelain(&pak_path, pak.as_mut(), &where_filters)? {
+                                writeln!(stdout, "{}", line).map_err(PakError::output)?;
+                            }
+                        }
+                        return Ok(());
+                    }
+                    if count {
+                        writeln!(
+                            stdout,
+                            "{}: {}",
+                            pak_path.to_string_lossy(),
+                            pak.entries_count_fast()?
+                        )
+                        .map_err(PakError::output)?;
+                        return Ok(());
+                    }
+                    if let Some(format) = &format {
+                        for line in ls_formatted(pak.as_mut(), format, &where_filters)? {
+                            writeln!(stdout, "{}", line).map_err(PakError::output)?;
+                        }
+                        return Ok(());
+                    }
+                    if paths_only || ids_only || print0 {
+                        let format = MinimalFormat {
+                            ids: ids_only,
+                            with_pak,
+                            print0,
+                        };
+                        ls_minimal(
+                            &pak_path,
+                            pak.as_mut(),
+                            format,
+                            is_listed,
+                            &where_filters,
+                            &mut stdout,
+                        )?;
+                        return Ok(());
+                    }
+                    if show_entry_path {
+                        writeln!(stdout, "[{}]", pak_path.to_string_lossy())
+                            .map_err(PakError::output)?;
+                    }
+                    // 空 pak 没有可列出的行，说明一下以免看起来像出错
+                    if pak.entries_count()? == 0 {
+                        eprintln!("{}: 0 entries", pak_path.to_string_lossy());
+                    }
+
+                    ls_rows(
+                        pak.as_mut(),
+                        show_entry_path,
+                        layout,
+                        statuses.as_deref(),
+                        is_listed,
+                        &where_filters,
+                        &mut stdout,
+                    )
+                })() {
+                    // 输出已被关闭（例如接到 head 之后），不必再列出其余的 pak
+                    if matches!(e.root_cause(), PakError::OutputClosed) {
+                        break;
+                    }
+                    eprintln!(
+                        "{}",
+                        format_error(options.json_errors, Some(&pak_path), "Error reading", &e)
+                    );
+                }
+            }
+
+            if has_mismatch {
+                std::process::exit(1);
+            }
+        }
+        Command::Unpack {
+            file_pattern,
+            output_dir,
+            show_entry_path,
+            manifest,
+            quiet,
+            report: report_path,
+            report_append,
+            extract,
+        } => {
+            let start = Instant::now();
+            let mut lists = extract.entry_lists().unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+            let extract_options = extract.extract_options(&lists).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+            options.discovery.mmap = extract_options.read_strategy == ReadStrategy::Mmap;
+            let output_dir = PathBuf::from(output_dir);
+            if let Err(e) = prepare_output_dir(&output_dir) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            if !args.porcelain {
+                let absolute = std::path::absolute(&output_dir).unwrap_or(output_dir.clone());
+                println!("Unpacking to {}", absolute.to_string_lossy());
+            }
+            let mut provenance = Provenance::default();
+            let mut manifest = manifest.map(Manifest::create).transpose()?;
+            let mut report_file = report_path
+                .map(|path| {
+                    if report_append {
+                        ReportFile::append(path)
+                    } else {
+                        ReportFile::create(path)
+                    }
+                })
+                .transpose()?;
+            let mut report = UnpackReport::new(
+                std::env::args_os()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect(),
+                extract_options.clone(),
+            );
+            report.include_from = extract.include_from.clone();
+            report.exclude_from = extract.exclude_from.clone();
+            write_report(&mut report_file, &report);
+            let mut totals = ExtractTotals::default();
+
+            let cancel = cancel_on_ctrlc()?;
+
+            for (pak_path, mut pak) in open_paks(&file_pattern, &options)? {
+                if !args.porcelain {
+                    println!("[{}]", pak_path.to_string_lossy());
+                    if let Ok(0) = pak.entries_count() {
+                        println!("    0 entries");
+                    }
+                }
+
+                // 无法读取的 pak 由 extract_pak 报告
+                if !lists.is_empty()
+                    && let Ok(paths) = pak.collect_paths()
+                {
+                    for list in &mut lists {
+                        list.matched += paths
+                            .iter()
+                            .filter(|path| list.filter.matches(path))
+                            .count() as u64;
+                    }
+                }
+
+                let pak_start = Instant::now();
+                let mut pak_totals = ExtractTotals::default();
+                let mut has_failed_entry = false;
+                let result = extract_pak(
+                    pak.as_mut(),
+                    &pak_path,
+                    &output_dir,
+                    &extract_options,
+                    &cancel,
+                    |event| {
+                        let is_manifest_failed = manifest.as_mut().is_some_and(|manifest| {
+                            manifest
+                                .append(&ManifestRow::from_event(&pak_path, &event))
+                                .map_err(|e| eprintln!("Error writing manifest: {}", e))
+                                .is_err()
+                        });
+                        if is_manifest_failed {
+                            manifest = None;
+                        }
+                        totals.record(&event);
+                        pak_totals.record(&event);
+                        has_failed_entry |= matches!(event, ExtractEvent::Failed { .. });
+
+                        match event {
+                            _ if args.porcelain => {
+                                println!("{}", extract_event_porcelain(&pak_path, &event));
+                                if let ExtractEvent::Extracted(file) = event {
+                                    provenance.record(file);
+                                }
+                            }
+                            ExtractEvent::Extracted(file) => {
+                                if file.renamed && !file.resumed {
+                                    eprintln!(
+                                        "Renamed [{}] {} -> {}",
+                                        file.entry_id,
+                                        file.entry_path,
+                                        file.path.to_string_lossy()
+                                    );
+                                }
+                                if show_entry_path && !file.resumed {
+                                    println!("[{}] {}", file.entry_id, file.path.to_string_lossy());
+                                }
+                                provenance.record(file);
+                            }
+                            ExtractEvent::Skipped {
+                                entry_id,
+                                entry_path,
+                                reason,
+                            } => eprintln!("Skipped [{}] {}: {}", entry_id, entry_path, reason),
+                            ExtractEvent::Failed { .. } => {}
+                        }
+                    },
+                );
+                // 取消时不记录这个未完成的 pak
+                if !matches!(result, Err(PakError::Cancelled)) {
+                    report.add_pak(PakRunReport {
+                        pak_path: pak_path.clone(),
+                        totals: pak_totals,
+                        duration_ms: pak_start.elapsed().as_millis() as u64,
+                        error: result.as_ref().err().map(|e| e.to_string()),
+                    });
+                    report.duration_ms = start.elapsed().as_millis() as u64;
+                    write_report(&mut report_file, &report);
+                }
+                if let Err(e) = result {
+                    if let PakError::Cancelled = e {
+                        eprintln!("Cancelled");
+                        // 已解包的文件仍记录来源
+                        provenance.save(&output_dir)?;
+                        std::process::exit(EXIT_CANCELLED);
+                    }
+                    if args.porcelain && !has_failed_entry {
+                        let pak_path = pak_path.to_string_lossy();
+                        println!(
+                            "{}",
+                            porcelain_line(&["FAIL", &pak_path, "", "", &e.to_string()])
+                        );
+                    }
+                    eprintln!("Error unpacking {}: {}", pak_path.to_string_lossy(), e);
+                    if let PakError::PakChanged { .. } = e {
+                        eprintln!(
+                            "Run again once the pak stops changing, or pass --ignore-changes to unpack it anyway"
+                        );
+                    }
+                }
+                log_io_stats(&pak_path, pak.as_ref());
+            }
+            for line in entry_list_lines(&lists, args.porcelain) {
+                println!("{}", line);
+            }
+            if !quiet {
+                println!(
+                    "{}",
+                    summary_line(&totals, report.failed_paks, start.elapsed(), args.porcelain)
+                );
+            }
+            provenance.save(&output_dir)?;
+            report.finish(start.elapsed());
+            write_report(&mut report_file, &report);
+        }
+        Command::Plan {
+            file_pattern,
+            out,
+            extract,
+        } => {
+            let extract_options = extract
+                .entry_lists()
+                .and_then(|lists| extract.extract_options(&lists))
+                .unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+            if extract_options.resume {
+                eprintln!("Error: --resume only applies to unpack");
+                std::process::exit(1);
+            }
+
+            let mut plan = ExtractPlan {
+                mtime: extract_options.mtime,
+                paks: vec![],
+            };
+            let mut has_error = false;
+            for (pak_path, mut pak) in open_paks(&file_pattern, &options)? {
+                match PlannedPak::plan(pak.as_mut(), &pak_path, &extract_options) {
+                    Ok(planned) => plan.paks.push(planned),
+                    Err(e) => {
+                        eprintln!(
+                            "{}",
+                            format_error(
+                                options.json_errors,
+                                Some(&pak_path),
+                                "Error planning",
+                                &e
+                            )
+                        );
+                        has_error = true;
+                    }
+                }
+            }
+            // 不完整的计划不写出
+            if has_error {
+                std::process::exit(1);
+            }
+            match out {
+                Some(out) => {
+                    std::fs::write(&out, plan.to_json() + "\n")?;
+                    println!(
+                        "Planned {} files from {} paks into {}",
+                        plan.entries().count(),
+                        plan.paks.len(),
+                        out.to_string_lossy()
+                    );
+                }
+                None => println!("{}", plan.to_json()),
+            }
+        }
+        Command::Apply {
+            plan,
+            output_dir,
+            show_entry_path,
+            quiet,
+        } => {
+            let start = Instant::now();
+            let plan = ExtractPlan::from_json_file(&plan).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+
+            // 写入任何文件前先确认每个 pak 都与计划时相同
+            let mut paks = Vec::with_capacity(plan.paks.len());
+            for planned in &plan.paks {
+                match open_planned_pak(&planned.pak_path, &options).and_then(|mut pak| {
+                    planned.check(pak.as_mut())?;
+                    Ok(pak)
+                }) {
+                    Ok(pak) => paks.push(pak),
+                    Err(e) => eprintln!(
+                        "{}",
+                        format_error(
+                            options.json_errors,
+                            Some(&planned.pak_path),
+                            "Error checking",
+                            &e
+                        )
+                    ),
+                }
+            }
+            if paks.len() < plan.paks.len() {
+                eprintln!("Error: the plan no longer matches its paks, nothing was written");
+                std::process::exit(1);
+            }
+
+            let output_dir = PathBuf::from(output_dir);
+            if let Err(e) = prepare_output_dir(&output_dir) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            let mut provenance = Provenance::default();
+            let mut totals = ExtractTotals::default();
+            let mut failed_paks = 0;
+            let cancel = cancel_on_ctrlc()?;
+            for (planned, mut pak) in plan.paks.iter().zip(paks) {
+                let pak_path = &planned.pak_path;
+                let mut has_failed_entry = false;
+                if let Err(e) = apply_planned_pak(
+                    pak.as_mut(),
+                    planned,
+                    &output_dir,
+                    &plan.mtime,
+                    &cancel,
+                    |event| {
+                        totals.record(&event);
+                        has_failed_entry |= matches!(event, ExtractEvent::Failed { .. });
+                        if args.porcelain {
+                            println!("{}", extract_event_porcelain(pak_path, &event));
+                        }
+                        if let ExtractEvent::Extracted(file) = event {
+                            if show_entry_path && !args.porcelain {
+                                println!("[{}] {}", file.entry_id, file.path.to_string_lossy());
+                            }
+                            provenance.record(file);
+                        }
+                    },
+                ) {
+                    if let PakError::Cancelled = e {
+                        eprintln!("Cancelled");
+                        provenance.save(&output_dir)?;
+                        std::process::exit(EXIT_CANCELLED);
+                    }
+                    if !has_failed_entry {
+                        failed_paks += 1;
+                    }
+                    if args.porcelain && !has_failed_entry {
+                        let pak_path = pak_path.to_string_lossy();
+                        println!(
+                            "{}",
+                            porcelain_line(&["FAIL", &pak_path, "", "", &e.to_string()])
+                        );
+                    }
+                    eprintln!("Error applying {}: {}", pak_path.to_string_lossy(), e);
+                }
+            }
+            if !quiet {
+                println!(
+                    "{}",
+                    summary_line(&totals, failed_paks, start.elapsed(), args.porcelain)
+                );
+            }
+            provenance.save(&output_dir)?;
+        }
+        Command::Index {
+            file_pattern,
+            output_dir,
+            base_dir,
+            print_index,
+        } => {
+            let base_dir = PathBuf::from(base_dir);
+            let output_dir = PathBuf::from(output_dir);
+
+            for (pak_path, mut pak) in open_paks(&file_pattern, &options)? {
+                let relative_pak_path = diff_paths(&pak_path, &base_dir).unwrap();
+                println!("{}", relative_pak_path.to_string_lossy());
+
+                let output_path = output_dir.join(&relative_pak_path);
+                if let Some(parent) = output_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                let mut output_file = File::create(&output_path)?;
+
+                if let Err(e) = (|| -> Result<(), PakError> {
+                    if print_index {
+                        println!("{:>12} path", "size");
+                    }
+
+                    for entry_id in 0..pak.entries_count()? {
+                        let path = pak.get_entry_path(entry_id)?;
+
+                        if print_index {
+                            println!("{}", path);
+                        }
+
+                        output_file.write_all(format!("{}\n", path).as_bytes())?;
+                    }
+
+                    Ok(())
+                })() {
+                    eprintln!(
+                        "Error processing index for {}: {}",
+                        pak_path.to_string_lossy(),
+                        e
+                    );
+                }
+            }
+        }
+        Command::Search {
+            file_pattern,
+            query,
+            contains,
+            all,
+            ignore_case,
+            normalize,
+            where_filters,
+            print0,
+        } => {
+            let path_match = if normalize {
+                PathMatch::Normalized
+            } else if ignore_case {
+                PathMatch::CaseInsensitive
+            } else {
+                PathMatch::Exact
+            };
+            search_paks(
+                &file_pattern,
+                &query,
+                &SearchOptions {
+                    contains,
+                    all,
+                    path_match,
+                    filters: &where_filters,
+                },
+                &options,
+                |pak_path, entry_id, entry_path| {
+                    if print0 {
+                        print!("{}\0", entry_path);
+                    } else {
+                        println!(
+                            "{}: {} {}",
+                            pak_path.to_string_lossy(),
+                            entry_id,
+                            entry_path
+                        );
+                    }
+                },
+            )?;
+        }
+        Command::Cat {
+            file_pattern,
+            entry_path,
+            range,
+        } => {
+            if !cat_entry(
+                &file_pattern,
+                &entry_path,
+                range,
+                &options,
+                &mut std::io::stdout().lock(),
+            )? {
+                eprintln!("Entry not found: {}", entry_path);
+                std::process::exit(1);
+            }
+        }
+        Command::Overrides { file_pattern } => {
+            for (entry_path, paks) in find_overrides(&file_pattern, &options)? {
+                println!("{}", entry_path);
+                for pak_path in paks {
+                    println!("    {}", pak_path.to_string_lossy());
+                }
+            }
+        }
+        Command::Dupes {
+            file_pattern,
+            across_paks_only,
+            json,
+        } => {
+            let mut report = find_dupes(&file_pattern, &options)?;
+            if across_paks_only {
+                report = report.across_paks_only();
+            }
+
+            if json {
+                println!("{}", dupes_json(&report));
+                return Ok(());
+            }
+            println!(
+                "Wasted: {} ({}) in {} groups",
+                report.wasted_bytes(),
+                human_size(report.wasted_bytes()),
+                report.groups.len()
+            );
+            for group in &report.groups {
+                println!(
+                    "{} x {} ({}), wasted {} ({})",
+                    group.members.len(),
+                    group.stored_size,
+                    human_size(group.stored_size),
+                    group.wasted_bytes(),
+                    human_size(group.wasted_bytes())
+                );
+                for member in &group.members {
+                    println!(
+                        "    {}: [{}] {}",
+                        member.pak_path.to_string_lossy(),
+                        member.entry_id,
+                        member.path
+                    );
+                }
+            }
+        }
+        Command::Diff {
+            old_pattern,
+            new_pattern,
+            quick,
+            confirm,
+        } => {
+            let mut old: Vec<_> = open_paks(&old_pattern, &options)?.collect();
+            let mut new: Vec<_> = open_paks(&new_pattern, &options)?.collect();
+            let diff_options = DiffOptions { quick, confirm };
+            let report = diff_paks(&mut old, &mut new, &diff_options)?;
+            print!("{}", format_diff(&report, &diff_options));
+        }
+        Command::Verify {
+            file_pattern,
+            cache,
+            deep,
+        } => {
+            let mut is_all_ok = true;
+
+            for (pak_path, mut pak) in open_paks(&file_pattern, &options)? {
+                if args.porcelain {
+                    let result =
+                        verify_pak(&pak_path, pak.as_mut(), cache, |entry_id, entry_path| {
+                            println!(
+                                "{}",
+                                verify_mismatch_porcelain(&pak_path, entry_id, entry_path)
+                            );
+                        });
+                    if let Err(e) = &result {
+                        eprintln!("Error verifying {}: {}", pak_path.to_string_lossy(), e);
+                    }
+                    if deep && result.is_ok() {
+                        is_all_ok &= print_block_map(&pak_path, pak.as_mut(), true);
+                    }
+                    println!("{}", verify_porcelain(&pak_path, &result));
+                    is_all_ok &= matches!(result, Ok(summary) if summary.mismatched == 0);
+                    log_io_stats(&pak_path, pak.as_ref());
+                    continue;
+                }
+                println!("{}", pak_path.to_string_lossy());
+
+                match verify_pak(&pak_path, pak.as_mut(), cache, |entry_id, entry_path| {
+                    println!("    [{}] {}: MISMATCH", entry_id, entry_path);
+                }) {
+                    Ok(summary) => {
+                        println!(
+                            "    OK: {}, Mismatched: {}, Unhashed: {}",
+                            summary.ok, summary.mismatched, summary.unhashed
+                        );
+                        is_all_ok &= summary.mismatched == 0;
+                        if deep {
+                            is_all_ok &= print_block_map(&pak_path, pak.as_mut(), false);
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error verifying {}: {}", pak_path.to_string_lossy(), e);
+                        is_all_ok = false;
+                    }
+                }
+                log_io_stats(&pak_path, pak.as_ref());
+            }
+
+            if !is_all_ok {
+                std::process::exit(1);
+            }
+        }
+        Command::Bench { file_pattern } => {
+            for (pak_path, mut pak) in open_paks(&file_pattern, &options)? {
+                match bench_pak(&pak_path, pak.as_mut()) {
+                    Ok(result) => {
+                        println!("{}", pak_path.to_string_lossy());
+                        println!("    Entries: {}", result.entries);
+                        println!("    Read: {}", result.read);
+                        println!("    Extract: {}", result.extract);
+                        println!("    Extract I/O: {}", format_io_stats(&result.extract_io));
+                    }
+                    Err(e) => eprintln!("Error benchmarking {}: {}", pak_path.to_string_lossy(), e),
+                }
+            }
+        }
+        Command::DumpIndex { pak_path, out } => {
+            let result = open_planned_pak(&pak_path, &options)
+                .map_err(|e| e.into())
+                .and_then(|mut pak| dump_index(&pak_path, pak.as_mut(), &out));
+            match result {
+                Ok(header) => println!("{}", header),
+                Err(e) => {
+                    eprintln!(
+                        "Error dumping the index of {}: {}",
+                        pak_path.to_string_lossy(),
+                        e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Salvage {
+            pak_path,
+            output_dir,
+            show_entry_path,
+        } => match salvage_pak(&pak_path, Path::new(&output_dir), show_entry_path) {
+            Ok(report) => {
+                for line in salvage_report_lines(&report) {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error salvaging {}: {}", pak_path.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+        },
+        Command::Doctor { pak_path } => {
+            let checks = doctor_checks(&pak_path, &options);
+            for check in &checks {
+                println!("{}", check);
+            }
+            if checks.iter().any(|check| !check.passed) {
+                std::process::exit(1);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfp::pak_reader::block_map::SharedPiece;
+
+    fn v10() -> OpenOptions {
+        OpenOptions {
+            varient: 10,
+            discovery: DiscoveryOptions::default(),
+            json_errors: false,
+        }
+    }
+
+    #[test]
+    fn test_search_paks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut hits = vec![];
+        search_paks(
+            "test/normal/*.pak",
+            "lua_object",
+            &SearchOptions {
+                contains: true,
+                ..Default::default()
+            },
+            &v10(),
+            |pak_path, entry_id, entry_path| {
+                hits.push((pak_path.to_path_buf(), entry_id, entry_path.to_string()))
+            },
+        )?;
+        assert!(!hits.is_empty());
+
+        let (pak_path, entry_id, entry_path) = &hits[0];
+        let mut exact_hits = vec![];
+        search_paks(
+            &pak_path.to_string_lossy(),
+            entry_path,
+            &SearchOptions {
+                ..Default::default()
+            },
+            &v10(),
+            |_, entry_id, _| exact_hits.push(entry_id),
+        )?;
+        assert_eq!(exact_hits, vec![*entry_id]);
+
+        let mut normalized_hits = vec![];
+        search_paks(
+            &pak_path.to_string_lossy(),
+            &entry_path
+                .trim_start_matches("ShadowTrackerExtra/Content/")
+                .to_uppercase()
+                .replace('/', "\\"),
+            &SearchOptions {
+                path_match: PathMatch::Normalized,
+                ..Default::default()
+            },
+            &v10(),
+            |_, entry_id, found_path| normalized_hits.push((entry_id, found_path.to_string())),
+        )?;
+        assert_eq!(normalized_hits, vec![(*entry_id, entry_path.clone())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_format() -> Result<(), Box<dyn std::error::Error>> {
+        let args = CliArgs::try_parse_from([
+            "gfp",
+            "ls",
+            "test/normal/game_patch_1.32.11.13992.pak",
+            "--format",
+            "{size} {path}",
+        ])?;
+        let Command::Ls {
+            file_pattern,
+            format: Some(format),
+            ..
+        } = args.subcommand
+        else {
+            panic!("Expected ls with a format");
+        };
+
+        let mut pak = open_pak(&file_pattern, 10)?;
+        let lines = ls_formatted(pak.as_mut(), &format, &[])?;
+        assert_eq!(lines.len() as u64, pak.entries_count()?);
+        for (entry_id, line) in lines.iter().enumerate() {
+            let (size, path) = line.split_once(' ').unwrap();
+            assert_eq!(
+                size.parse::<u64>()?,
+                pak.entry_layout(entry_id as u64)?.file_size
+            );
+            assert_eq!(path, pak.get_entry_path(entry_id as u64)?);
+        }
+
+        assert!(CliArgs::try_parse_from(["gfp", "ls", ".", "--format", "{size"]).is_err());
+        assert!(
+            CliArgs::try_parse_from(["gfp", "ls", ".", "--format", "{id}", "--porcelain"]).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_args() -> Result<(), Box<dyn std::error::Error>> {
+        use clap::Parser;
+
+        let extract_args = |argv: &[&str]| {
+            let args = CliArgs::try_parse_from([&["gfp", "unpack", "a.pak"], argv].concat())?;
+            let Command::Unpack { extract, .. } = args.subcommand else {
+                panic!("not unpack");
+            };
+            Ok::<_, clap::Error>(extract)
+        };
+
+        let options = extract_args(&[])?.extract_options(&[])?;
+        assert_eq!(options.to_toml(), ExtractOptions::default().to_toml());
+        let options = extract_args(&["--inflate-inner", "--keep-raw"])?.extract_options(&[])?;
+        assert!(options.inflate_inner && options.keep_raw);
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let options_path = temp_dir.path().join("extract.toml");
+        std::fs::write(
+            &options_path,
+            "layout = \"per-pak\"\nstrip-components = 2\nmtime = \"pak\"\nentry-ids = [1]\n",
+        )?;
+        let options_file = options_path.to_str().unwrap();
+
+        let options = extract_args(&["--options-file", options_file])?.extract_options(&[])?;
+        assert_eq!(options.layout, OutputLayout::PerPak);
+        assert_eq!(options.strip_components, 2);
+        assert_eq!(options.mtime, MtimePolicy::Pak);
+        assert_eq!(options.entry_ids, Some(vec![1]));
+
+        // Flags given on the command line win
+        let options = extract_args(&[
+            "--options-file",
+            options_file,
+            "--strip-components",
+            "0",
+            "--id",
+            "3",
+            "--read-strategy",
+            "prefetch",
+        ])?
+        .extract_options(&[])?;
+        assert_eq!(options.layout, OutputLayout::PerPak);
+        assert_eq!(options.strip_components, 0);
+        assert_eq!(options.mtime, MtimePolicy::Pak);
+        assert_eq!(options.entry_ids, Some(vec![3]));
+        assert_eq!(options.read_strategy, ReadStrategy::Prefetch);
+
+        let options = extract_args(&[
+            "--rewrite",
+            "ShadowTrackerExtra/Content/Lua/=lua/",
+            "--rewrite",
+            "ShadowTrackerExtra/Content/=",
+            "--rewrite-strict",
+        ])?
+        .extract_options(&[])?;
+        assert_eq!(
+            options
+                .path_rewrites
+                .iter()
+                .map(|rewrite| rewrite.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "ShadowTrackerExtra/Content/Lua/=lua/",
+                "ShadowTrackerExtra/Content/="
+            ]
+        );
+        assert!(options.rewrite_strict);
+        assert!(extract_args(&["--rewrite", "Content/"]).is_err());
+
+        std::fs::write(&options_path, "per-pak = true\n")?;
+        assert!(
+            extract_args(&["--options-file", options_file])?
+                .extract_options(&[])
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_lists() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let include_path = temp_dir.path().join("include.txt");
+        let exclude_path = temp_dir.path().join("exclude.txt");
+        std::fs::write(&include_path, "# Lua\nre:\\.lua$\n")?;
+        std::fs::write(&exclude_path, "*/logic/*\nno/such/entry.lua\n")?;
+        let mut lists = vec![
+            EntryList::load("include", include_path.clone())?,
+            EntryList::load("exclude", exclude_path.clone())?,
+        ];
+        let filter = entry_lists_filter(&lists);
+        assert!(filter.matches("Lua/common/lua_object.lua"));
+        assert!(!filter.matches("Lua/client/logic/logic_pakversion.lua"));
+        assert!(!filter.matches("UI/HUD/foo.png"));
+        assert!(entry_lists_filter(&lists[1..]).matches("UI/HUD/foo.png"));
+        assert!(entry_lists_filter(&[]).is_all());
+
+        lists[0].matched = 2;
+        assert_eq!(
+            entry_list_lines(&lists, false),
+            [
+                format!("Include list {} matched 2 entries", include_path.display()),
+                format!("Exclude list {} matched 0 entries", exclude_path.display()),
+            ]
+        );
+        assert_eq!(
+            entry_list_lines(&lists[..1], true),
+            [format!("LIST\tinclude\t{}\t2", include_path.display())]
+        );
+        assert!(EntryList::load("include", temp_dir.path().join("missing.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_map_lines() {
+        let pak_path = Path::new("a.pak");
+        let report = BlockMapReport {
+            out_of_order: vec![0],
+            shared: vec![SharedPiece {
+                offset: 0x100,
+                length: 0x20,
+                entry_ids: vec![0, 3],
+            }],
+            overlapping: vec![(1, 2)],
+        };
+        assert_eq!(
+            block_map_lines(pak_path, &report, true),
+            [
+                "BLOCKS\ta.pak\tORDER\t0\t\t",
+                "BLOCKS\ta.pak\tSHARED\t0,3\t256\t32",
+                "BLOCKS\ta.pak\tOVERLAP\t1,2\t\t",
+            ]
+        );
+        assert_eq!(
+            block_map_lines(pak_path, &report, false),
+            [
+                "    Blocks out of order: [0]",
+                "    Shared 0x100+0x20: [0], [3]",
+                "    WARNING: payloads of [1] and [2] overlap",
+            ]
+        );
+        assert_eq!(
+            block_map_lines(pak_path, &BlockMapReport::default(), false),
+            ["    Blocks: in order, none shared"]
+        );
+    }
+
+    #[test]
+    fn test_format_diff() {
+        let diff = |path: &str, change, basis| EntryDiff {
+            path: path.to_string(),
+            change,
+            basis,
+        };
+        let report = [
+            diff("a.lua", Change::Added, DiffBasis::Path),
+            diff("b.lua", Change::Unchanged, DiffBasis::Fingerprint),
+            diff("c.lua", Change::Changed, DiffBasis::Hash),
+            diff("d.lua", Change::Removed, DiffBasis::Path),
+        ];
+        assert_eq!(
+            format_diff(
+                &report,
+                &DiffOptions {
+                    quick: true,
+                    confirm: true
+                }
+            ),
+            "Comparing by fingerprint (heuristic), changed entries confirmed by hash\n\
+             A a.lua [path]\n\
+             M c.lua [hash]\n\
+             D d.lua [path]\n\
+             1 added, 1 removed, 1 changed, 1 unchanged (1 by fingerprint)\n"
+        );
+        assert_eq!(
+            format_diff(&[], &DiffOptions::default()),
+            "Comparing by hash\n0 added, 0 removed, 0 changed, 0 unchanged\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_index() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        for (pak_path, version) in [
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+        ] {
+            let out = temp_dir.path().join("index.bin");
+            let mut pak = open_pak(pak_path, version)?;
+            let header = dump_index(Path::new(pak_path), pak.as_mut(), &out)?;
+
+            // The index straight from the pak file, decrypted by hand
+            let stats = pak.index_stats()?;
+            let data = std::fs::read(pak_path)?;
+            let start = stats.index_offset as usize;
+            let mut expected = data[start..start + stats.index_size as usize].to_vec();
+            if pak.encrypted()? {
+                expected.iter_mut().for_each(|byte| *byte ^= 0x79);
+            }
+            assert!(std::fs::read(&out)? == expected, "{}", pak_path);
+
+            assert_eq!(
+                header,
+                format!(
+                    "{{\"path\":\"{}\",\"offset\":{},\"size\":{},\"length\":{},\"encrypted\":{},\"version\":{}}}",
+                    pak_path,
+                    stats.index_offset,
+                    stats.index_size,
+                    expected.len(),
+                    pak.encrypted()?,
+                    pak.declared_version()?
+                )
+            );
+            assert_eq!(
+                std::fs::read_to_string(temp_dir.path().join("index.bin.json"))?,
+                header + "\n"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_index_default_pak_version() -> Result<(), Box<dyn std::error::Error>> {
+        use clap::Parser;
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let out = temp_dir.path().join("index.bin");
+        let pak_path = "test/avatar/onreadypak_405399.pak";
+        let args = CliArgs::try_parse_from([
+            "gfp",
+            "dump-index",
+            pak_path,
+            "--out",
+            out.to_str().unwrap(),
+        ])?;
+        let options = open_options(&args);
+        let mut pak = open_planned_pak(Path::new(pak_path), &options)?;
+        assert_eq!(pak.parser_version(), 7);
+        dump_index(Path::new(pak_path), pak.as_mut(), &out)?;
+        assert_eq!(
+            std::fs::read(&out)?.len() as u64,
+            pak.index_stats()?.index_size
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_filters() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = "test/normal/game_patch_1.32.11.13992.pak";
+        let filters: Vec<EntryFilter> = vec!["compression=1".parse()?, "size>4K".parse()?];
+        let mut hits = vec![];
+        search_paks(
+            pak_path,
+            "",
+            &SearchOptions {
+                contains: true,
+                all: true,
+                path_match: PathMatch::Exact,
+                filters: &filters,
+            },
+            &v10(),
+            |_, entry_id, _| hits.push(entry_id),
+        )?;
+
+        let mut pak = open_pak(pak_path, 10)?;
+        let expected: Vec<u64> = (0..pak.entries_count()?)
+            .filter(|&entry_id| {
+                let layout = pak.entry_layout(entry_id).unwrap();
+                layout.compression_method == 1 && layout.file_size > 4096
+            })
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(hits, expected);
+        assert_eq!(
+            ls_porcelain(Path::new(pak_path), pak.as_mut(), &filters)?.len(),
+            expected.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_overrides() -> Result<(), Box<dyn std::error::Error>> {
+        let overrides = find_overrides("test/normal/*.pak", &v10())?;
+
+        let paks = overrides
+            .get("ShadowTrackerExtra/Content/Lua/common/lua_object.lua")
+            .expect("lua_object.lua is in both sample paks");
+        assert_eq!(
+            paks,
+            &vec![
+                PathBuf::from("test/normal/game_patch_1.32.11.13846.pak"),
+                PathBuf::from("test/normal/game_patch_1.32.11.13992.pak"),
+            ]
+        );
+        assert!(overrides.values().all(|paks| paks.len() > 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_error_json() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_path = temp_dir.path().join("broken \"1\".pak");
+        std::fs::write(&pak_path, [0u8; 16])?;
+
+        let pattern = glob::Pattern::escape(&pak_path.to_string_lossy());
+        let (opened_path, result) =
+            open_paks_by_pattern(&pattern, 10, DiscoveryOptions::default())?
+                .next()
+                .unwrap();
+        let Err(error) = result else {
+            panic!("{} should fail to open", pak_path.to_string_lossy());
+        };
+
+        let line = format_error(true, Some(&opened_path), "Error opening pak file", &error);
+        let fields = line
+            .strip_prefix("{\"error\":")
+            .and_then(|line| line.strip_suffix('}'))
+            .and_then(|line| line.rsplit_once(",\"pak\":"))
+            .unwrap();
+        assert_eq!(fields.0, serde_json::to_string(&error.to_string())?);
+        assert_eq!(
+            fields.1,
+            serde_json::to_string(&pak_path.to_string_lossy())?
+        );
+        assert!(!line.contains('\n'));
+
+        assert_eq!(
+            format_error(true, None, "Invalid pattern", &"bad"),
+            "{\"error\":\"bad\",\"pak\":null}"
+        );
+        assert_eq!(
+            format_error(false, Some(Path::new("a.pak")), "Error reading", &"bad"),
+            "Error reading a.pak: bad"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_info_json() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+
+        assert_eq!(
+            info_json(pak_path, pak.as_mut(), true)?,
+            "{\"path\":\"test/normal/game_patch_1.32.11.13846.pak\",\"encrypted\":false,\"version\":10,\"parser_version\":10,\"file_size\":23820,\
+            \"build\":\"1.32.11.13846\",\"build_source\":\"file_name\"}"
+        );
+        assert_eq!(
+            info_json(pak_path, pak.as_mut(), false)?,
+            "{\"path\":\"test/normal/game_patch_1.32.11.13846.pak\",\"encrypted\":false,\"version\":10,\"parser_version\":10,\"file_size\":23820,\
+            \"mount_point\":\"ShadowTrackerExtra/Content/\",\"entries_count\":7,\"index_offset\":22333,\"index_size\":1442,\
+            \"footer_hash\":\"3024aaa2f876b073f8454ab0a939b5daf2b137b2\",\"encrypted_entries\":0,\"plain_entries\":7,\
+            \"compression_methods\":{\"0\":2,\"1\":5},\"build\":\"1.32.11.13846\",\"build_source\":\"file_name\"}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_rows() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let mut output = vec![];
+        ls_rows(
+            pak.as_mut(),
+            false,
+            false,
+            None,
+            |entry_id| entry_id == 3,
+            &[],
+            &mut output,
+        )?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "[3] ShadowTrackerExtra/Content/Lua/common/lua_object.lua\n"
+        );
+
+        let statuses = vec![HashStatus::Ok; 7];
+        let mut output = vec![];
+        ls_rows(
+            pak.as_mut(),
+            true,
+            true,
+            Some(&statuses),
+            |_| true,
+            &[],
+            &mut output,
+        )?;
+        let output = String::from_utf8(output)?;
+        assert_eq!(output.lines().count(), 14);
+        assert!(
+            output
+                .lines()
+                .all(|line| line.starts_with('[') || line.starts_with("    "))
+        );
+        assert!(output.starts_with("[0] ") && output.lines().next().unwrap().ends_with(" OK"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_closed_pipe() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let format = MinimalFormat {
+            ids: false,
+            with_pak: false,
+            print0: false,
+        };
+        // The reader is gone before the first row, as when `head` has already exited
+        let (reader, mut writer) = std::io::pipe()?;
+        drop(reader);
+
+        let error =
+            ls_rows(pak.as_mut(), false, false, None, |_| true, &[], &mut writer).unwrap_err();
+        assert!(matches!(error, PakError::OutputClosed));
+        let error =
+            ls_minimal(pak_path, pak.as_mut(), format, |_| true, &[], &mut writer).unwrap_err();
+        assert!(matches!(error, PakError::OutputClosed));
+        let error = ls_minimal(
+            pak_path,
+            pak.as_mut(),
+            format,
+            |_| true,
+            &[],
+            &mut ClosedOutput,
+        )
+        .unwrap_err();
+        assert!(matches!(error, PakError::OutputClosed));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_minimal() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let mut ls = |ids, with_pak, print0, is_listed: fn(u64) -> bool| {
+            let format = MinimalFormat {
+                ids,
+                with_pak,
+                print0,
+            };
+            let mut output = vec![];
+            ls_minimal(pak_path, pak.as_mut(), format, is_listed, &[], &mut output)?;
+            Ok::<_, PakError>(String::from_utf8(output).unwrap())
+        };
+
+        let odd: fn(u64) -> bool = |entry_id| entry_id % 2 == 1;
+        assert_eq!(
+            ls(false, false, false, odd)?,
+            "ShadowTrackerExtra/Content/AR/PF/WScene/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.umap\n\
+             ShadowTrackerExtra/Content/Lua/common/lua_object.lua\n\
+             ShadowTrackerExtra/Content/Platform/WR/Arts_Scenes/_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uexp\n"
+        );
+        assert_eq!(ls(true, false, false, odd)?, "1\n3\n5\n");
+        assert_eq!(ls(true, false, true, odd)?, "1\x003\x005\0");
+        assert_eq!(
+            ls(true, true, false, odd)?,
+            "test/normal/game_patch_1.32.11.13846.pak\t1\n\
+             test/normal/game_patch_1.32.11.13846.pak\t3\n\
+             test/normal/game_patch_1.32.11.13846.pak\t5\n"
+        );
+        assert_eq!(
+            ls(false, true, true, |entry_id| entry_id == 3)?,
+            "test/normal/game_patch_1.32.11.13846.pak\t\
+             ShadowTrackerExtra/Content/Lua/common/lua_object.lua\0"
+        );
+
+        assert!(CliArgs::try_parse_from(["gfp", "ls", ".", "--with-pak"]).is_err());
+        assert!(CliArgs::try_parse_from(["gfp", "ls", ".", "--paths-only", "--ids-only"]).is_err());
+        assert!(
+            CliArgs::try_parse_from(["gfp", "ls", ".", "--ids-only", "-0", "--with-pak"]).is_ok()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_print0() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let print0 = MinimalFormat {
+            ids: false,
+            with_pak: false,
+            print0: true,
+        };
+        let mut output = vec![];
+        ls_minimal(pak_path, pak.as_mut(), print0, |_| true, &[], &mut output)?;
+        assert_eq!(
+            output.iter().filter(|&&byte| byte == 0).count() as u64,
+            pak.entries_count()?
+        );
+        assert!(!output.contains(&b'\n'));
+        assert!(output.starts_with(pak.get_entry_path(0)?.as_bytes()));
+
+        let mut output = vec![];
+        ls_minimal(
+            pak_path,
+            pak.as_mut(),
+            print0,
+            |entry_id| entry_id == 3,
+            &[],
+            &mut output,
+        )?;
+        assert_eq!(
+            output,
+            b"ShadowTrackerExtra/Content/Lua/common/lua_object.lua\0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_paks_by_build() -> Result<(), Box<dyn std::error::Error>> {
+        let open = gfp::pak_reader::implements::open_pak;
+        let paks = [
+            ("test/avatar/onreadypak_405399.pak", 7),
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+        ]
+        .into_iter()
+        .map(|(pak_path, varient)| Ok((PathBuf::from(pak_path), open(pak_path, varient)?)))
+        .collect::<Result<Vec<_>, PakError>>()?;
+
+        let (paks, headings) = group_paks_by_build(paks.into_iter())?;
+        let order: Vec<_> = paks
+            .iter()
+            .map(|(pak_path, _)| {
+                (
+                    pak_path.to_str().unwrap(),
+                    headings.get(pak_path).map(String::as_str),
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (
+                    "test/normal/game_patch_1.32.11.13846.pak",
+                    Some("Build 1.32.11.13846:")
+                ),
+                (
+                    "test/normal/game_patch_1.32.11.13992.pak",
+                    Some("Build 1.32.11.13992:")
+                ),
+                ("test/avatar/onreadypak_405399.pak", Some("Build unknown:")),
+            ]
+        );
+
+        let mut metadata = PakMetadata {
+            footer_version: 10,
+            name_version: gfp::utils::pak_version::parse("1.2.3"),
+            manifest: None,
+        };
+        assert_eq!(format_build(&metadata).unwrap(), "1.2.3 (file name)");
+        metadata.manifest = Some((
+            "Version/version.txt".to_string(),
+            gfp::utils::pak_version::parse("1.2.4").unwrap(),
+        ));
+        assert_eq!(
+            format_build(&metadata).unwrap(),
+            "1.2.4 (manifest Version/version.txt)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dupes_json() -> Result<(), Box<dyn std::error::Error>> {
+        let report = find_dupes("test/normal/*.pak", &v10())?.across_paks_only();
+        let json = dupes_json(&report);
+        assert!(json.starts_with("{\"wasted\":2434,\"groups\":[{\"hash\":\""));
+        assert!(json.contains(
+            "\"stored_size\":2434,\"wasted\":2434,\"members\":[\
+            {\"pak\":\"test/normal/game_patch_1.32.11.13846.pak\",\"entry_id\":3,\"path\":\"ShadowTrackerExtra/Content/Lua/common/lua_object.lua\"},\
+            {\"pak\":\"test/normal/game_patch_1.32.11.13992.pak\",\"entry_id\":136,\"path\":\"ShadowTrackerExtra/Content/Lua/common/lua_object.lua\"}]}]}"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_range() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse_range("10:5"), Ok((10, 5)));
+        assert!(parse_range("10").is_err());
+        assert!(parse_range("-1:5").is_err());
+
+        let entry_path = "ShadowTrackerExtra/Content/Lua/common/lua_object.lua";
+        let mut full = vec![];
+        assert!(cat_entry(
+            "test/normal",
+            entry_path,
+            None,
+            &v10(),
+            &mut full
+        )?);
+        let mut range = vec![];
+        cat_entry(
+            "test/normal",
+            entry_path,
+            Some((100, 50)),
+            &v10(),
+            &mut range,
+        )?;
+        assert_eq!(range, full[100..150]);
+        assert!(!cat_entry(
+            "test/normal",
+            "missing.lua",
+            None,
+            &v10(),
+            &mut vec![]
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_closed_pipe() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = "test/normal/game_patch_1.32.11.13992.pak";
+        let mut pak = open_pak(pak_path, 10)?;
+        let mut largest = (0, 0);
+        for entry_id in 0..pak.entries_count()? {
+            largest = largest.max((pak.entry_layout(entry_id)?.file_size, entry_id));
+        }
+        // Much more than a pipe holds, so the writer is still going when the reader leaves
+        assert!(largest.0 > 1 << 20);
+        let entry_path = pak.get_entry_path(largest.1)?;
+
+        let (mut reader, mut writer) = std::io::pipe()?;
+        let cat = std::thread::spawn(move || {
+            cat_entry(pak_path, &entry_path, None, &v10(), &mut writer).map_err(|e| e.to_string())
+        });
+        let mut head = [0u8; 1024];
+        std::io::Read::read_exact(&mut reader, &mut head)?;
+        drop(reader);
+        assert_eq!(cat.join().unwrap(), Ok(true));
+
+        let mut expected = vec![];
+        pak.extract_entry_range(largest.1, 0, 1024, &mut expected)?;
+        assert_eq!(head.as_slice(), expected);
+
+        let error = pak
+            .extract_entry_to_writer(largest.1, &mut ClosedOutput)
+            .unwrap_err();
+        assert!(matches!(error, PakError::OutputClosed));
+        Ok(())
+    }
+
+    /// 每次写入都返回 BrokenPipe
+    struct ClosedOutput;
+
+    impl Write for ClosedOutput {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_bench_pak() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let result = bench_pak(pak_path, pak.as_mut())?;
+        assert_eq!(result.entries, 7);
+        assert_eq!(
+            result.extract.bytes,
+            [50813, 17220, 181, 4853, 3601, 65205, 2974]
+                .iter()
+                .sum::<u64>()
+        );
+        assert!(result.read.bytes > 0 && result.read.bytes < result.extract.bytes);
+
+        let line = result.extract.to_string();
+        let mib_per_second: f64 = line
+            .strip_suffix(" MiB/s")
+            .and_then(|line| line.rsplit(' ').next())
+            .unwrap()
+            .parse()?;
+        assert!(mib_per_second > 0.0, "{}", line);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_ratio() {
+        assert_eq!(format_ratio(Some(0.4567)), "45.7%");
+        assert_eq!(format_ratio(Some(1.0)), "100.0%");
+        assert_eq!(format_ratio(None), "-");
+    }
+
+    #[test]
+    fn test_porcelain_line() {
+        assert_eq!(
+            porcelain_line(&["OK", "a b.pak", "1", "dir\tname\n100%.lua", ""]),
+            "OK\ta b.pak\t1\tdir%09name%0A100%25.lua\t"
+        );
+    }
+
+    #[test]
+    fn test_entry_hash_statuses() -> Result<(), Box<dyn std::error::Error>> {
+        let source = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_path = temp_dir.path().join("tampered.pak");
+        let mut data = std::fs::read(source)?;
+        let offset = match open_pak(source, 10)?.entry_layout(3)?.payload {
+            EntryPayload::Stored { offset, .. } => offset,
+            EntryPayload::Blocks(blocks) => blocks[0].offset,
+        };
+        data[offset as usize] ^= 0xFF;
+        std::fs::write(&pak_path, data)?;
+
+        let mut expected = vec![HashStatus::Ok; 7];
+        expected[3] = HashStatus::Mismatch;
+        for jobs in [1, 3, 16] {
+            let mut pak = open_pak(&pak_path, 10)?;
+            assert_eq!(
+                entry_hash_statuses(&pak_path, pak.as_mut(), jobs)?,
+                expected
+            );
+        }
+        assert_eq!(
+            hash_status_porcelain(&pak_path, 3, HashStatus::Mismatch, "a\tb"),
+            format!("HASH\t{}\t3\tMISMATCH\ta%09b", pak_path.to_string_lossy())
+        );
+        Ok(())
+    }
+
+    /// 以下快照固定了 --porcelain 的格式，改动格式会使测试失败
+    #[test]
+    fn test_ls_porcelain_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        assert_eq!(
+            ls_porcelain(pak_path, pak.as_mut(), &[])?.join("\n"),
+            "\
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t0\t50813\tShadowTrackerExtra/Content/AR/PF/WScene/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.uexp
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t1\t17220\tShadowTrackerExtra/Content/AR/PF/WScene/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.umap
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t2\t181\tShadowTrackerExtra/Content/Lua/client/logic/login/logic_pakversion.lua
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t3\t4853\tShadowTrackerExtra/Content/Lua/common/lua_object.lua
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t4\t3601\tShadowTrackerExtra/Content/Platform/WR/Arts_Scenes/_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uasset
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t5\t65205\tShadowTrackerExtra/Content/Platform/WR/Arts_Scenes/_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uexp
+ENTRY\ttest/normal/game_patch_1.32.11.13846.pak\t6\t2974\tShadowTrackerExtra/Content/ShaderMaps/2FEC6CAD4916C29E73026D9DD243B1F8.nt"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_line() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = open_pak(pak_path, 10)?;
+        let mut totals = ExtractTotals::default();
+        extract_pak(
+            pak.as_mut(),
+            pak_path,
+            temp_dir.path(),
+            &ExtractOptions::default(),
+            &CancellationToken::new(),
+            |event| totals.record(&event),
+        )?;
+        let elapsed = Duration::from_millis(1500);
+        assert_eq!(
+            summary_line(&totals, 0, elapsed, false),
+            "Unpacked 7 entries (141.5 KiB), 0 resumed, 0 skipped, 0 failed in 1.50s"
+        );
+        assert_eq!(
+            summary_line(&totals, 2, elapsed, true),
+            "SUMMARY\t7\t0\t0\t0\t2\t144847\t1500"
+        );
+
+        totals.failed = 1;
+        assert_eq!(
+            summary_line(&totals, 2, elapsed, false),
+            "Unpacked 7 entries (141.5 KiB), 0 resumed, 0 skipped, 1 failed, 2 paks failed in 1.50s"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_salvage_pak() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let data = std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?;
+        // Without the index and the footer, as a download cut short leaves it
+        let pak_path = temp_dir.path().join("truncated.pak");
+        std::fs::write(&pak_path, &data[..22500])?;
+        let output_dir = temp_dir.path().join("out");
+        let report = salvage_pak(&pak_path, &output_dir, false)?;
+        assert_eq!(
+            salvage_report_lines(&report),
+            [
+                "Salvaged 7 entries (141.5 KiB), 0 don't match their hash",
+                "Rejected 1 candidate records:",
+                "    payload past the end of the file: 1",
+            ]
+        );
+        assert_eq!(
+            std::fs::read(output_dir.join("salvaged/000000262c_85deed02.lua"))?.len(),
+            181
+        );
+        assert_eq!(std::fs::read_dir(output_dir.join("salvaged"))?.count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pak_version() -> Result<(), Box<dyn std::error::Error>> {
+        use clap::Parser;
+
+        let pak_path = Path::new("test/avatar/onreadypak_405399.pak");
+        let args = CliArgs::try_parse_from(["gfp", "--pak-version", "7", "info", "x"])?;
+        assert_eq!(pak_version(&args), PakVersion::V7);
+        let options = open_options(&args);
+        assert!(!options.discovery.detect);
+        assert_eq!(open_planned_pak(pak_path, &options)?.parser_version(), 7);
+
+        // 旧写法仍然可用，默认为 auto
+        for (argv, expected) in [
+            (&["--v7"][..], PakVersion::V7),
+            (&["--v10"], PakVersion::V10),
+            (&["--detect"], PakVersion::Auto),
+            (&[], PakVersion::Auto),
+        ] {
+            let args = CliArgs::try_parse_from([&["gfp"], argv, &["info", "x"]].concat())?;
+            assert_eq!(pak_version(&args), expected, "{argv:?}");
+            let args = CliArgs::try_parse_from([&["gfp", "info", "x"], argv].concat())?;
+            assert_eq!(pak_version(&args), expected, "{argv:?}");
+        }
+        let args = CliArgs::try_parse_from(["gfp", "info", "x"])?;
+        let options = open_options(&args);
+        assert!(options.discovery.detect);
+        assert_eq!(open_planned_pak(pak_path, &options)?.parser_version(), 7);
+
+        assert!(CliArgs::try_parse_from(["gfp", "--v7", "--v10", "info", "x"]).is_err());
+        assert!(
+            CliArgs::try_parse_from(["gfp", "--v7", "--pak-version", "10", "info", "x"]).is_err()
+        );
+        assert!(CliArgs::try_parse_from(["gfp", "--pak-version", "9", "info", "x"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_doctor_checks() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let checks = doctor_checks(pak_path, &v10());
+        let lines: Vec<_> = checks.iter().map(|check| check.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "[ OK ] footer: V10 footer",
+                "[ OK ] magic: 0xFF67FF70",
+                "[ OK ] version: declared 10, read as 10",
+                "[ OK ] encrypted: index is plain",
+                "[ OK ] index size: 1442 bytes at 22333",
+                "[ OK ] entries: 7 entries, payloads end at 22333",
+                "[ OK ] paths: 7 of 7 sampled paths resolve",
+                "[ OK ] extract: entry 0 (49.6 KiB) matches its hash",
+            ]
+        );
+
+        // A truncated pak fails the first check, then reading its footer
+        let temp_dir = tempfile::TempDir::new()?;
+        let truncated = temp_dir.path().join("truncated.pak");
+        std::fs::write(&truncated, &std::fs::read(pak_path)?[..22500])?;
+        let checks = doctor_checks(&truncated, &v10());
+        assert_eq!(
+            checks
+                .iter()
+                .map(|check| (check.name, check.passed))
+                .collect::<Vec<_>>(),
+            [("footer", false), ("magic", false)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_report_args() -> Result<(), Box<dyn std::error::Error>> {
+        use clap::Parser;
+
+        let args = CliArgs::try_parse_from([
+            "gfp",
+            "unpack",
+            "a.pak",
+            "--report",
+            "runs.jsonl",
+            "--report-append",
+        ])?;
+        let Command::Unpack {
+            report,
+            report_append,
+            ..
+        } = args.subcommand
+        else {
+            panic!("not unpack");
+        };
+        assert_eq!(report, Some(PathBuf::from("runs.jsonl")));
+        assert!(report_append);
+        assert!(CliArgs::try_parse_from(["gfp", "unpack", "a.pak", "--report-append"]).is_err());
+
+        // 写入失败后不再写入
+        let temp_dir = tempfile::TempDir::new()?;
+        let mut report_file = Some(ReportFile::create(temp_dir.path().join("report.json"))?);
+        std::fs::create_dir(temp_dir.path().join("report.json"))?;
+        write_report(
+            &mut report_file,
+            &UnpackReport::new(vec![], ExtractOptions::default()),
+        );
+        assert!(report_file.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_default_output_dir() -> Result<(), Box<dyn std::error::Error>> {
+        use clap::Parser;
+
+        let args = CliArgs::try_parse_from(["gfp", "unpack", "test/normal/*.pak"])?;
+        let Command::Unpack { output_dir, .. } = args.subcommand else {
+            panic!("not unpack");
+        };
+        assert_eq!(output_dir, DEFAULT_OUTPUT_DIR);
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let output_dir = temp_dir.path().join(output_dir);
+        prepare_output_dir(&output_dir)?;
+        assert!(output_dir.is_dir());
+        assert_eq!(std::fs::read_dir(&output_dir)?.count(), 0);
+
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        extract_pak(
+            pak.as_mut(),
+            pak_path,
+            &output_dir,
+            &ExtractOptions::default(),
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+        assert!(
+            output_dir
+                .join("ShadowTrackerExtra/Content/Lua/common/lua_object.lua")
+                .is_file()
+        );
+
+        let error = prepare_output_dir(pak_path).unwrap_err();
+        assert!(error.contains("is not a directory"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_porcelain_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = gfp::pak_reader::implements::open_pak(pak_path, 10)?;
+        let temp_dir = tempfile::TempDir::new()?;
+        let options = ExtractOptions {
+            strip_components: 5,
+            ..Default::default()
+        };
+
+        let mut lines = vec![];
+        extract_pak(
+            pak.as_mut(),
+            pak_path,
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |event| lines.push(extract_event_porcelain(pak_path, &event)),
+        )?;
+        assert_eq!(
+            lines.join("\n"),
+            "\
+OK\ttest/normal/game_patch_1.32.11.13846.pak\t0\tMaps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.uexp\t
+OK\ttest/normal/game_patch_1.32.11.13846.pak\t1\tMaps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.umap\t
+OK\ttest/normal/game_patch_1.32.11.13846.pak\t2\tlogin/logic_pakversion.lua\t
+SKIP\ttest/normal/game_patch_1.32.11.13846.pak\t3\tShadowTrackerExtra/Content/Lua/common/lua_object.lua\tNot enough path components to strip
+OK\ttest/normal/game_patch_1.32.11.13846.pak\t4\t_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uasset\t
+OK\ttest/normal/game_patch_1.32.11.13846.pak\t5\t_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uexp\t
+SKIP\ttest/normal/game_patch_1.32.11.13846.pak\t6\tShadowTrackerExtra/Content/ShaderMaps/2FEC6CAD4916C29E73026D9DD243B1F8.nt\tNot enough path components to strip"
+        );
+
+        let error = PakError::Cancelled;
+        let event = ExtractEvent::Failed {
+            entry_id: 3,
+            entry_path: "a/b.lua",
+            error: &error,
+        };
+        assert_eq!(
+            extract_event_porcelain(pak_path, &event),
+            "FAIL\ttest/normal/game_patch_1.32.11.13846.pak\t3\ta/b.lua\tCancelled"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_porcelain_snapshot() {
+        let pak_path = Path::new("a.pak");
+        let summary = VerifySummary {
+            ok: 6,
+            mismatched: 1,
+            unhashed: 0,
+        };
+        assert_eq!(
+            verify_mismatch_porcelain(pak_path, 3, "a/b.lua"),
+            "MISMATCH\ta.pak\t3\ta/b.lua"
+        );
+        assert_eq!(
+            verify_porcelain(pak_path, &Ok(summary.clone())),
+            "PAKFAIL\ta.pak\t6\t1\t0\tmismatched entries"
+        );
+        assert_eq!(
+            verify_porcelain(
+                pak_path,
+                &Ok(VerifySummary {
+                    mismatched: 0,
+                    ..summary
+                })
+            ),
+            "PAKOK\ta.pak\t6\t0\t0\t"
+        );
+        assert_eq!(
+            verify_porcelain(pak_path, &Err(PakError::invalid_data("bad"))),
+            "PAKFAIL\ta.pak\t\t\t\tInvalid data: bad"
+        );
+    }
+
+    #[test]
+    fn test_format_error_escapes() {
+        assert_eq!(
+            format_error(
+                true,
+                Some(Path::new("C:\\Paks\\\"a\"\n.pak")),
+                "",
+                &"bad\tpak"
+            ),
+            "{\"error\":\"bad\\tpak\",\"pak\":\"C:\\\\Paks\\\\\\\"a\\\"\\n.pak\"}"
+        );
+    }
+
+    #[test]
+    fn test_latest_pak() -> Result<(), Box<dyn std::error::Error>> {
+        let (pak_path, mut pak) = latest_pak(open_paks("test/normal", &v10())?).unwrap();
+        assert_eq!(
+            pak_path,
+            PathBuf::from("test/normal/game_patch_1.32.11.13992.pak")
+        );
+        assert_eq!(pak.declared_version()?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_pak() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", &pak_path)?;
+
+        let (_, mut pak) = open_paks(&pak_path.to_string_lossy(), &v10())?
+            .next()
+            .unwrap();
+        let entries_count = pak.entries_count()?;
+        let summary = verify_pak(&pak_path, pak.as_mut(), true, |_, _| {
+            panic!("sample pak is intact")
+        })?;
+        assert_eq!(summary.ok + summary.unhashed, entries_count);
+
+        let cached = verify_pak(&pak_path, pak.as_mut(), true, |_, _| {})?;
+        assert_eq!(cached, summary);
+
+        let mut empty = open_pak("test/empty/empty.pak", 10)?;
+        let summary = verify_pak(
+            Path::new("test/empty/empty.pak"),
+            empty.as_mut(),
+            false,
+            |_, _| panic!("the pak has no entries"),
+        )?;
+        assert_eq!(summary, VerifySummary::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_in_bracketed_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_dir = temp_dir.path().join("和平精英PC模拟器[2002291]");
+        std::fs::create_dir(&pak_dir)?;
+        let pak_path = pak_dir.join("game_patch_1.32.11.13846.pak");
+        std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", &pak_path)?;
+
+        let opened: Vec<_> = open_paks(&pak_dir.to_string_lossy(), &v10())?
+            .map(|(pak_path, _)| pak_path)
+            .collect();
+        assert_eq!(opened, vec![pak_path.clone()]);
+
+        let opened: Vec<_> = open_paks(&pak_path.to_string_lossy(), &v10())?
+            .map(|(pak_path, _)| pak_path)
+            .collect();
+        assert_eq!(opened, vec![pak_path]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_max_depth() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let root = temp_dir.path();
+        for name in ["a.pak", "sub/b.pak", "sub/deeper/c.pak"] {
+            let pak_path = root.join(name);
+            std::fs::create_dir_all(pak_path.parent().unwrap())?;
+            std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", pak_path)?;
+        }
+
+        let mut options = v10();
+        options.discovery.max_depth = Some(1);
+        for file_pattern in [
+            root.to_string_lossy().to_string(),
+            root.join("**/*.pak").to_string_lossy().to_string(),
+        ] {
+            let opened: Vec<_> = open_paks(&file_pattern, &options)?
+                .map(|(pak_path, _)| pak_path)
+                .collect();
+            assert_eq!(opened, vec![root.join("a.pak")]);
+        }
+        Ok(())
+    }
 }
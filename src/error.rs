@@ -13,6 +13,71 @@ pub enum PakError {
     #[error("IO error: {:?}", .0)]
     Io(std::io::Error),
 
+    /// A compressed block couldn't be decompressed.
+    #[error("Decompression failed{}: {source}", location(.entry_id, .block))]
+    Decompress {
+        entry_id: Option<u64>,
+        block: Option<u32>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// Several entries match a path looked up with an inexact
+    /// [`PathMatch`](crate::pak_reader::path_match::PathMatch).
+    #[error("Path {path} matches several entries: {entry_ids:?}")]
+    AmbiguousPath { path: String, entry_ids: Vec<u64> },
+
+    /// The operation was stopped through a
+    /// [`CancellationToken`](crate::pak_reader::cancel::CancellationToken).
+    #[error("Cancelled")]
+    Cancelled,
+
+    /// Writing failed with [`BrokenPipe`](std::io::ErrorKind::BrokenPipe):
+    /// whatever read the output, like `head` at the end of a pipe, closed
+    /// it before everything was written. Usually not worth reporting.
+    #[error("Output closed by the reader")]
+    OutputClosed,
+
+    /// Text in the pak couldn't be decoded; `offset` is where the bad
+    /// sequence starts.
+    #[error("{what} at offset {offset:#X}")]
+    Encoding { offset: usize, what: &'static str },
+
+    /// A count or length read from the index at `offset` needs more than the
+    /// `remaining` bytes after it, so the index can't be right.
+    #[error("{what} {value} at index offset {offset:#X} doesn't fit in the {remaining} bytes left")]
+    IndexOverrun {
+        what: &'static str,
+        value: u64,
+        offset: usize,
+        remaining: usize,
+    },
+
+    /// The index looks like garbage both decrypted and as is, e.g. because
+    /// of a different key. Each field says what was wrong with that attempt.
+    #[error("Undecodable index: decrypted, {decrypted}; as is, {plain}")]
+    IndexUndecodable { decrypted: String, plain: String },
+
+    /// The pak couldn't be opened because another program, usually the game,
+    /// holds it without sharing it.
+    #[error("{} is locked by another program, close the game and try again", .path.display())]
+    FileLocked { path: std::path::PathBuf },
+
+    /// The pak on disk isn't the one the reader parsed anymore, e.g. because
+    /// the game updated it; `reason` says what differs.
+    #[error("{} changed since it was opened ({reason}), run again", .path.display())]
+    PakChanged {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+
+    /// Another error with what was being done when it happened, see
+    /// [`PakError::context`].
+    #[error("while {context}: {source}")]
+    Contextual {
+        source: Box<PakError>,
+        context: String,
+    },
+
     #[error("Other: {}", .0)]
     Other(String),
 }
@@ -33,8 +98,63 @@ impl From<IntoStringError> for PakError {
     }
 }
 
+fn location(entry_id: &Option<u64>, block: &Option<u32>) -> String {
+    match (entry_id, block) {
+        (Some(entry_id), Some(block)) => format!(" for entry {}, block {}", entry_id, block),
+        (Some(entry_id), None) => format!(" for entry {}", entry_id),
+        (None, Some(block)) => format!(" for block {}", block),
+        (None, None) => String::new(),
+    }
+}
+
 impl PakError {
     pub fn invalid_data(message: impl AsRef<str>) -> PakError {
         PakError::InvalidData(message.as_ref().to_string())
     }
+
+    /// An error writing the output of an extraction: [`PakError::OutputClosed`]
+    /// for a broken pipe, [`PakError::Io`] otherwise. Reading the pak goes
+    /// through `From<io::Error>` instead, so a broken pipe there stays an
+    /// error worth reporting.
+    pub fn output(error: std::io::Error) -> PakError {
+        match error.kind() {
+            std::io::ErrorKind::BrokenPipe => PakError::OutputClosed,
+            _ => PakError::Io(error),
+        }
+    }
+
+    /// Wrap the error with what was being done, e.g. `parsing entry 4217`,
+    /// which its `Display` puts in front: `while parsing entry 4217: ...`.
+    ///
+    /// [`PakError::Cancelled`] and [`PakError::OutputClosed`] are returned
+    /// as is, so callers can keep matching on them.
+    ///
+    /// ```rust
+    /// use gfp::error::PakError;
+    ///
+    /// let error = PakError::invalid_data("Entry path too long: 9000").context("parsing entry 3");
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "while parsing entry 3: Invalid data: Entry path too long: 9000"
+    /// );
+    /// assert!(matches!(error.root_cause(), PakError::InvalidData(_)));
+    /// assert!(matches!(PakError::Cancelled.context("extracting"), PakError::Cancelled));
+    /// ```
+    pub fn context(self, context: impl Into<String>) -> PakError {
+        match self {
+            PakError::Cancelled | PakError::OutputClosed => self,
+            _ => PakError::Contextual {
+                source: Box::new(self),
+                context: context.into(),
+            },
+        }
+    }
+
+    /// The error without any context added by [`PakError::context`].
+    pub fn root_cause(&self) -> &PakError {
+        match self {
+            PakError::Contextual { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
 }
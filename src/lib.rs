@@ -1,5 +1,25 @@
-#[cfg(not(target_pointer_width = "64"))]
-compile_error!("This crate only supports 64-bit platforms");
+//! Read the pak files of Game for Peace: list their entries and extract
+//! them, see [`pak_reader::PakReader`].
+//!
+//! The quickest way through a pak is [`iter_contents`], which extracts one
+//! entry at a time:
+//!
+//! ```rust
+//! use gfp::pak_reader::ext::PakReaderExt;
+//! use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+//!
+//! let mut pak = GfpPakReaderV10::new(std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?);
+//! for content in pak.iter_contents()? {
+//!     let (path, data) = content?;
+//!     println!("{}: {} bytes", path, data.len());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`iter_contents`]: pak_reader::ext::PakReaderExt::iter_contents
+
+#[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
+compile_error!("This crate only supports 32-bit and 64-bit platforms");
 
 pub mod error;
 pub mod pak_reader;
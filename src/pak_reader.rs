@@ -1,36 +1,248 @@
+pub mod block_map;
+pub mod cancel;
+#[cfg(feature = "std-fs")]
+pub mod checksum_cache;
+pub mod detect;
+pub mod diff;
+pub mod dupes;
+#[cfg(feature = "std-fs")]
+pub mod entry_filter;
+pub mod entry_reader;
+pub mod ext;
+#[cfg(feature = "std-fs")]
+pub mod extract;
+pub mod extraction_cache;
 pub mod gfp_v10;
 pub mod gfp_v7;
+pub mod io_stats;
+pub mod metadata;
+pub mod path_match;
+#[cfg(feature = "std-fs")]
+pub mod plan;
+#[cfg(feature = "std-fs")]
+pub mod pool;
+#[cfg(feature = "std-fs")]
+pub mod run_report;
+pub mod salvage;
 
 use crate::error::PakError;
-use std::fs::File;
-use std::path::Path;
+use crate::pak_reader::cancel::CancellationToken;
+use crate::pak_reader::extraction_cache::{ExtractionCache, ReaderCache};
+use crate::pak_reader::io_stats::IoStats;
+use crate::pak_reader::path_match::PathMatch;
+use crate::utils::file_reader::VecCursor;
+use crate::utils::read_at::ReadAt;
+use crate::utils::{check_zlib_header, xor_each_byte};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Arc;
 
 pub trait PakReader {
-    // Stages
-    fn new(file: File) -> Self
-    where
-        Self: Sized;
-    fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error>
-    where
-        Self: Sized + 'static,
-    {
-        Ok(Box::new(Self::new(File::open(path)?)))
-    }
-
     // pak info
     /// [`Self::load_pak_info`]
     fn encrypted(&mut self) -> Result<bool, PakError>;
+    /// The version field of the pak info at the end of the pak.
+    ///
+    /// This is what the pak says about itself, which may differ from
+    /// [`Self::parser_version`] when a pak is opened with the wrong reader.
+    ///
     /// [`Self::load_pak_info`]
-    fn version(&mut self) -> Result<u32, PakError>;
+    fn declared_version(&mut self) -> Result<u32, PakError>;
+
+    /// The pak version this reader parses, i.e. the format `--pak-version` chose
+    /// or detected for it, regardless of what the pak declares.
+    fn parser_version(&self) -> u32;
+
+    /// Fail on signs of a format the reader may not fully understand,
+    /// instead of only logging a warning with the `tracing` feature. So far
+    /// that's an index whose parsing stops short of its end, see
+    /// [`INDEX_END_SLACK`]. Off by default; takes effect on the next parse.
+    fn set_strict(&mut self, strict: bool);
+
+    fn is_strict(&self) -> bool;
+
+    #[deprecated(note = "use `declared_version`, or `parser_version` for the reader's variant")]
+    fn version(&mut self) -> Result<u32, PakError> {
+        self.declared_version()
+    }
+
+    /// The reads and decompression the reader did so far, for tuning and
+    /// debugging. Counting doesn't change what the reader does.
+    fn stats(&self) -> IoStats;
+
+    /// Count [`Self::stats`] from zero again.
+    fn reset_stats(&mut self);
+
+    /// Size of the whole pak in bytes, which doesn't need any stage loaded.
+    fn pak_size(&mut self) -> Result<u64, PakError>;
 
     /// [`Self::load_entries`]
     fn entries_count(&mut self) -> Result<u64, PakError>;
 
+    /// [`Self::entries_count`] read from the header at the start of the
+    /// index, without parsing any entry, so counting the entries of many paks
+    /// only reads about a KiB of each. Compressed indexes still have to be
+    /// loaded whole, see [`Self::load_entries`].
+    ///
+    /// [`Self::load_pak_info`]
+    fn entries_count_fast(&mut self) -> Result<u64, PakError> {
+        self.entries_count()
+    }
+
+    /// The footer serialized back from what was parsed, obfuscation
+    /// included, which is the pak's own footer byte for byte: fields readers
+    /// don't use are kept as they were read.
+    ///
+    /// [`Self::load_pak_info`]
+    fn footer_bytes(&mut self) -> Result<[u8; gfp_v10::FOOTER_SIZE], PakError>;
+
+    /// Figures about the pak read from its footer and index, without touching
+    /// any entry data.
+    ///
+    /// [`Self::load_entries`]
+    fn index_stats(&mut self) -> Result<IndexStats, PakError>;
+
+    /// The index as the reader decoded it: decrypted and, if it was
+    /// compressed, inflated. It holds the mount point, the entry records and
+    /// everything after them that isn't parsed, e.g. the directory records,
+    /// for tools that want to study it. [`Self::index_stats`] tells where
+    /// it's stored in the pak.
+    ///
+    /// [`Self::load_entries`]
+    fn raw_index(&mut self) -> Result<&[u8], PakError>;
+
+    /// Where the entry's bytes are in the pak and how to undo what was done
+    /// to them, for tools that read the payload themselves.
+    ///
+    /// [`Self::load_entries`]
+    fn entry_layout(&mut self, entry_id: u64) -> Result<EntryLayout, PakError>;
+
+    /// Hint the OS to read the entry's payload ahead, so extracting it
+    /// doesn't wait on a seek per block. Does nothing where the pak's source
+    /// can't prefetch, see [`ReadAt::prefetch`].
+    ///
     /// [`Self::load_entries`]
-    fn extract_entry_to_file(&mut self, entry_id: u64, output: &mut File) -> Result<(), PakError>;
+    fn prefetch_entry(&mut self, entry_id: u64) -> Result<(), PakError>;
 
+    /// Write the entry's content to `output`, which is only ever written to
+    /// and flushed at the end: it isn't truncated or sought, so the content
+    /// lands wherever `output` is, and a pipe works as well as a file. Fails
+    /// with [`PakError::OutputClosed`] if the reading end of `output` goes
+    /// away first.
+    ///
     /// [`Self::load_entries`]
-    fn extract_entry_to_path<P: AsRef<Path>>(
+    fn extract_entry_to_writer(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+    ) -> Result<(), PakError>;
+
+    /// Write bytes `start..start + length` of the entry's content to
+    /// `output` and return how many were written. The range is clamped to
+    /// the entry, and only the compressed blocks covering it are read.
+    /// `output` is flushed like with [`Self::extract_entry_to_writer`].
+    ///
+    /// [`Self::load_entries`]
+    fn extract_entry_range(
+        &mut self,
+        entry_id: u64,
+        start: u64,
+        length: u64,
+        output: &mut dyn Write,
+    ) -> Result<u64, PakError>;
+
+    /// Check that each compressed block of the entry starts with a plausible
+    /// zlib header, reading only its first two bytes.
+    ///
+    /// This is a heuristic for scanning many entries quickly: it catches
+    /// blocks that are overwritten or misplaced, but a block passing it can
+    /// still fail to decompress. Stored entries always pass, blocks in raw
+    /// deflate always fail although they extract. A bad block is reported as
+    /// [`PakError::Decompress`].
+    ///
+    /// [`Self::load_entries`]
+    fn validate_entry_headers(&mut self, entry_id: u64) -> Result<(), PakError>;
+
+    /// [`Self::extract_entry_to_writer`] that stops with
+    /// [`PakError::Cancelled`] once `cancel` is tripped. The readers check it
+    /// before each compressed block or stored chunk, so what was written so
+    /// far is incomplete.
+    ///
+    /// [`Self::load_entries`]
+    fn extract_entry_to_writer_cancellable(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+        cancel: &CancellationToken,
+    ) -> Result<(), PakError> {
+        cancel.check()?;
+        self.extract_entry_to_writer(entry_id, output)
+    }
+
+    /// Have [`PakReaderExt::open_entry`](ext::PakReaderExt::open_entry) and
+    /// [`PakReaderExt::extract_entry_to_vec`](ext::PakReaderExt::extract_entry_to_vec)
+    /// go through `cache`, or stop using one with `None`.
+    fn set_extraction_cache(&mut self, cache: Option<Arc<ExtractionCache>>);
+
+    /// The cache set with [`Self::set_extraction_cache`].
+    fn extraction_cache(&self) -> Option<&ReaderCache>;
+
+    /// Start over from the pak at `path`, read from a new source opened the
+    /// way the current one was, see [`ReadAt::reopen`]. Everything parsed
+    /// so far is dropped and parsed again from the new source when needed,
+    /// so a pak replaced on disk is read as it is now. Stats are kept, and
+    /// so is the [`ExtractionCache`], whose contents are keyed by hash.
+    ///
+    /// Fails, leaving the reader as it was, if the source can't be
+    /// reopened, e.g. for readers of a slice.
+    #[cfg(feature = "std-fs")]
+    fn reopen(&mut self, path: &std::path::Path) -> Result<(), PakError>;
+
+    /// The file the reader was opened from, for readers opened by path like
+    /// [`implements::open_pak`]'s.
+    #[cfg(feature = "std-fs")]
+    fn path(&self) -> Option<&std::path::Path>;
+
+    /// Close the pak file until [`Self::resume`], keeping everything parsed
+    /// so far, so readers kept around for long don't hold a file handle
+    /// each. Reads fail in between. Readers not opened by path, see
+    /// [`Self::path`], keep their source as it is.
+    #[cfg(feature = "std-fs")]
+    fn suspend(&mut self);
+
+    /// Open the pak file closed by [`Self::suspend`] again from its path.
+    /// Does nothing if it isn't closed.
+    ///
+    /// Fails with [`PakError::PakChanged`], staying suspended, if the file
+    /// doesn't have the size and footer the reader parsed anymore, since what
+    /// was parsed may not match it; [`Self::reopen`] starts over from it.
+    #[cfg(feature = "std-fs")]
+    fn resume(&mut self) -> Result<(), PakError>;
+
+    #[cfg(feature = "std-fs")]
+    fn is_suspended(&self) -> bool;
+
+    /// Check that the pak file still has the size and footer the reader
+    /// parsed, failing with [`PakError::PakChanged`] otherwise: entries of a
+    /// pak the game updated meanwhile would be read from the wrong places.
+    /// Costs a `stat` and a read of the footer. Does nothing before the pak
+    /// info is loaded, or while suspended since [`Self::resume`] checks.
+    #[cfg(feature = "std-fs")]
+    fn check_unchanged(&self) -> Result<(), PakError>;
+
+    /// [`Self::load_entries`]
+    #[cfg(feature = "std-fs")]
+    fn extract_entry_to_file(
+        &mut self,
+        entry_id: u64,
+        output: &mut std::fs::File,
+    ) -> Result<(), PakError> {
+        self.extract_entry_to_writer(entry_id, output)
+    }
+
+    /// [`Self::load_entries`]
+    #[cfg(feature = "std-fs")]
+    fn extract_entry_to_path<P: AsRef<std::path::Path>>(
         &mut self,
         entry_id: u64,
         output: P,
@@ -38,45 +250,1924 @@ pub trait PakReader {
     where
         Self: Sized,
     {
-        self.extract_entry_to_file(entry_id, &mut File::create(output)?)
+        self.extract_entry_to_file(entry_id, &mut std::fs::File::create(output)?)
     }
     /// [`Self::load_entry_paths`]
     fn get_entry_path(&mut self, entry_id: u64) -> Result<String, PakError>;
+
+    /// Decode every entry path up front, for callers about to go through all
+    /// of them. Readers that hold all paths anyway do nothing.
+    ///
+    /// [`Self::load_entry_paths`]
+    fn preload_entry_paths(&mut self) -> Result<(), PakError> {
+        Ok(())
+    }
+
+    /// The SHA-1 recorded for the entry in the index.
+    ///
+    /// [`Self::load_entries`]
+    fn entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError>;
+
+    /// Compute the SHA-1 of the entry's bytes as stored in the pak, i.e. before
+    /// decryption and decompression, which is what [`Self::entry_hash`] covers.
+    ///
+    /// [`Self::load_entries`]
+    fn compute_entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError>;
+
+    /// Find the id of the entry whose path is exactly `path`.
+    ///
+    /// The lookup table is built on first use and reused afterwards.
+    fn find_entry_by_path(&mut self, path: &str) -> Result<Option<u64>, PakError>;
+
+    /// Whether an entry's path is exactly `path`.
+    ///
+    /// Unless [`Self::find_entry_by_path`] already built its lookup table,
+    /// the entries are gone through until one matches, without building
+    /// the table nor keeping decoded paths. That's cheaper for a single
+    /// check on a large pak, but each call goes through the entries again:
+    /// several lookups in the same pak are faster with
+    /// [`Self::find_entry_by_path`].
+    fn contains_path(&mut self, path: &str) -> Result<bool, PakError> {
+        Ok(self.find_entry_by_path(path)?.is_some())
+    }
+
+    /// Find the id of the entry whose path matches `path` under `path_match`.
+    ///
+    /// Fails with [`PakError::AmbiguousPath`] if several entries match
+    /// instead of picking one of them. The lookup table of each option is
+    /// built on first use and reused afterwards.
+    fn find_entry_by_path_with(
+        &mut self,
+        path: &str,
+        path_match: PathMatch,
+    ) -> Result<Option<u64>, PakError>;
+}
+
+/// Largest mount point length in a plausible index, including the NUL.
+const MAX_MOUNT_POINT_LENGTH: usize = 1024;
+/// Bytes of the smallest entry record, which bounds the entry count of a
+/// plausible index.
+pub const MIN_ENTRY_SIZE: usize = 74;
+/// Largest index the readers load, compressed or not.
+pub const MAX_INDEX_SIZE: u64 = 50 << 20;
+/// The magic field of the footer of every known pak, v7 and v10 alike. The
+/// readers don't check it.
+pub const FOOTER_MAGIC: u32 = 0xFF67FF70;
+/// Bytes at the start of an index that hold its header, enough for the
+/// longest plausible mount point and the entry count.
+pub(crate) const MAX_INDEX_HEADER_SIZE: usize = 4 + MAX_MOUNT_POINT_LENGTH + 4;
+
+/// Size of the header in front of a stored entry's data when it can't be
+/// read: the entry's record without a block list.
+pub(crate) const INLINE_HEADER_SIZE: u64 = MIN_ENTRY_SIZE as u64;
+
+/// Size of the header in front of the data of a stored entry.
+///
+/// The header is a copy of the entry's record in the index, with the offset
+/// zeroed and, in v7 paks, without the path. Its size is worked out from the
+/// compression method it records, which adds a block list, and the header is
+/// checked against the record: same hash and sizes, and the data has to fit
+/// in the source after it. A header that doesn't check out falls back to
+/// [`INLINE_HEADER_SIZE`].
+pub(crate) fn inline_header_size<R: ReadAt + ?Sized>(
+    source: &R,
+    file_offset: u64,
+    file_hash: &[u8; 20],
+    file_size: u64,
+    compressed_length: u64,
+) -> Result<u64, PakError> {
+    let mut header = [0u8; MIN_ENTRY_SIZE];
+    let read = match source.read_at(&mut header, file_offset) {
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => 0,
+        read => read?,
+    };
+    if read == MIN_ENTRY_SIZE {
+        let mut cursor = VecCursor::new(&header);
+        let hash = *cursor.read::<20>()?;
+        cursor.skip(8)?;
+        let size = u64::from_le_bytes(*cursor.read::<8>()?);
+        let compression_method = u32::from_le_bytes(*cursor.read::<4>()?);
+        let compressed = u64::from_le_bytes(*cursor.read::<8>()?);
+        cursor.skip(21)?;
+        let header_size = match compression_method {
+            0 => INLINE_HEADER_SIZE,
+            _ => {
+                let num_of_blocks = u32::from_le_bytes(*cursor.read::<4>()?);
+                INLINE_HEADER_SIZE + 4 + u64::from(num_of_blocks) * 16
+            }
+        };
+        let source_size = source.size()?;
+        let fits = file_offset
+            .checked_add(header_size)
+            .and_then(|offset| offset.checked_add(file_size))
+            .is_some_and(|data_end| data_end <= source_size);
+        if &hash == file_hash && size == file_size && compressed == compressed_length && fits {
+            return Ok(header_size);
+        }
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        offset = file_offset,
+        "entry header doesn't match the index, assuming {} bytes",
+        INLINE_HEADER_SIZE
+    );
+    Ok(INLINE_HEADER_SIZE)
+}
+
+/// How many bytes may be left after the last record of an index, for
+/// packers padding it.
+pub const INDEX_END_SLACK: usize = 16;
+
+/// Check that parsing the index stopped at its end, `end` bytes into the
+/// `index_size` bytes of index data. Anything left past the slack means the
+/// parser missed part of the index, e.g. fields added by a new game version.
+pub(crate) fn check_index_end(end: usize, index_size: usize, strict: bool) -> Result<(), PakError> {
+    if index_size.saturating_sub(end) < INDEX_END_SLACK {
+        return Ok(());
+    }
+    if strict {
+        return Err(PakError::invalid_data(format!(
+            "Index parsed up to {:#X} of its {:#X} bytes",
+            end, index_size
+        )));
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        end,
+        index_size,
+        "index parsed short of its end, the pak format may have changed"
+    );
+    Ok(())
+}
+
+/// The ranges of a compressed entry's payload that make up its hash, from
+/// the `(offset, length)` of its blocks in order.
+///
+/// Packers write the blocks of an entry back to back, possibly padded, and
+/// hash the `compressed_length` bytes from the first one. Blocks out of
+/// ascending order, shared with other entries or with other data between
+/// them can't be hashed that way, so they are hashed one after the other.
+pub(crate) fn payload_hash_ranges(
+    blocks: &[(u64, u64)],
+    compressed_length: u64,
+) -> Vec<(u64, u64)> {
+    let (Some(&(first, _)), Some(&(last, last_length))) = (blocks.first(), blocks.last()) else {
+        return vec![];
+    };
+    let ascending = blocks
+        .windows(2)
+        .all(|pair| pair[0].0.saturating_add(pair[0].1) <= pair[1].0);
+    let span = last.saturating_add(last_length).saturating_sub(first);
+    if ascending && span <= compressed_length {
+        vec![(first, compressed_length)]
+    } else {
+        blocks.to_vec()
+    }
+}
+
+/// Check that the pak at `path`, read from `source`, still is `size` bytes
+/// long and ends with `footer`, see [`PakReader::resume`].
+#[cfg(feature = "std-fs")]
+pub(crate) fn check_footer<R: ReadAt + ?Sized>(
+    source: &R,
+    path: &std::path::Path,
+    size: u64,
+    footer: &[u8],
+) -> Result<(), PakError> {
+    let changed = |reason: String| PakError::PakChanged {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let current_size = source.size()?;
+    if current_size != size {
+        return Err(changed(format!(
+            "{} bytes instead of {}",
+            current_size, size
+        )));
+    }
+    let mut current = vec![0u8; footer.len()];
+    let read = source.read_at(&mut current, size.saturating_sub(footer.len() as u64))?;
+    if read != footer.len() || current != footer {
+        return Err(changed("different footer".to_string()));
+    }
+    Ok(())
+}
+
+/// Check that `count` records of at least `min_size` bytes each fit in what
+/// is left of the index after `index_cursor`, which they're read from, so
+/// that a garbage count fails before it sizes an allocation or a loop.
+pub(crate) fn check_count(
+    what: &'static str,
+    count: u64,
+    min_size: usize,
+    index_cursor: &VecCursor<u8>,
+) -> Result<usize, PakError> {
+    let remaining = index_cursor.remaining();
+    if count > (remaining / min_size) as u64 {
+        return Err(PakError::IndexOverrun {
+            what,
+            value: count,
+            offset: index_cursor.offset,
+            remaining,
+        });
+    }
+    Ok(count as usize)
+}
+
+/// Bytes taken by a NUL-terminated name whose size field is `size`: the
+/// length of a UTF-8 name, or the negated number of UTF-16 code units. The
+/// name comes next in `index_cursor` and has to fit in what is left.
+pub(crate) fn name_length(size: i32, index_cursor: &VecCursor<u8>) -> Result<usize, PakError> {
+    // i32::MIN negated doesn't fit in an i32, its absolute value does in u64
+    let length = if size > 0 {
+        u64::from(size.unsigned_abs())
+    } else {
+        u64::from(size.unsigned_abs()) * 2
+    };
+    let remaining = index_cursor.remaining();
+    if length > remaining as u64 {
+        return Err(PakError::IndexOverrun {
+            what: "Name length",
+            value: length,
+            offset: index_cursor.offset,
+            remaining,
+        });
+    }
+    Ok(length as usize)
+}
+
+/// Read the mount point at the start of an index: its length, counting the
+/// NUL, then the NUL-terminated string. The string is returned as
+/// [`normalize_mount_point`] leaves it.
+pub(crate) fn read_mount_point(index_cursor: &mut VecCursor<u8>) -> Result<String, PakError> {
+    let mount_point_length = u32::from_le_bytes(*index_cursor.read::<4>()?) as usize;
+    let mount_point_data = index_cursor.read_dyn(mount_point_length)?;
+    let mount_point = std::ffi::CString::from_vec_with_nul(mount_point_data)?.into_string()?;
+    Ok(normalize_mount_point(&mount_point).to_string())
+}
+
+/// The mount point without its leading run of `../`.
+///
+/// Paks record mount points relative to the engine's binaries directory,
+/// e.g. `../../../ShadowTrackerExtra/Content/`, which isn't useful outside
+/// the game, so readers report them from the game root.
+///
+/// ```rust
+/// use gfp::pak_reader::normalize_mount_point;
+///
+/// assert_eq!(
+///     normalize_mount_point("../../../ShadowTrackerExtra/Content/"),
+///     "ShadowTrackerExtra/Content/"
+/// );
+/// assert_eq!(normalize_mount_point("../Game/"), "Game/");
+/// assert_eq!(normalize_mount_point("/"), "/");
+/// assert_eq!(normalize_mount_point(""), "");
+/// ```
+pub fn normalize_mount_point(mount_point: &str) -> &str {
+    let mut mount_point = mount_point;
+    while let Some(rest) = mount_point.strip_prefix("../") {
+        mount_point = rest;
+    }
+    mount_point
+}
+
+/// Why `index_data` can't be a decoded index, judging by its header: the
+/// mount point length, the mount point and the entry count.
+fn check_index_header(index_data: &[u8]) -> Result<(), String> {
+    index_header_entry_count(index_data, index_data.len()).map(|_| ())
+}
+
+/// The entry count in `header`, the start of a decoded index of
+/// `index_size` bytes, or why it can't be one, see [`check_index_header`].
+fn index_header_entry_count(header: &[u8], index_size: usize) -> Result<u64, String> {
+    let mut index_cursor = VecCursor::new(header);
+    let mount_point_length =
+        u32::from_le_bytes(*index_cursor.read::<4>().map_err(|e| e.to_string())?) as usize;
+    if !(1..MAX_MOUNT_POINT_LENGTH).contains(&mount_point_length) {
+        return Err(format!("mount point length {}", mount_point_length));
+    }
+
+    let mount_point = index_cursor
+        .read_dyn(mount_point_length)
+        .map_err(|e| e.to_string())?;
+    let is_printable = mount_point.split_last().is_some_and(|(&nul, text)| {
+        nul == 0 && std::str::from_utf8(text).is_ok_and(|text| !text.chars().any(char::is_control))
+    });
+    if !is_printable {
+        return Err("mount point isn't NUL-terminated text".to_string());
+    }
+
+    let entry_count = i32::from_le_bytes(*index_cursor.read::<4>().map_err(|e| e.to_string())?);
+    if entry_count < 0 || entry_count as usize > index_size / MIN_ENTRY_SIZE {
+        return Err(format!("entry count {}", entry_count));
+    }
+    Ok(entry_count as u64)
+}
+
+/// Whether the index read from the pak is zlib-compressed rather than laid
+/// out as is.
+///
+/// Paks don't flag this anywhere, so it's told from the index itself: it
+/// has to start with a valid zlib header, and since a mount point length of
+/// 376 also reads as one, neither it nor its XOR with `key` may have a
+/// plausible index header.
+pub(crate) fn is_compressed_index(raw_index: &[u8], key: u8) -> bool {
+    let has_zlib_header = raw_index
+        .first_chunk::<2>()
+        .is_some_and(|header| check_zlib_header(*header).is_ok());
+    if !has_zlib_header {
+        return false;
+    }
+    let mut decrypted = raw_index.to_vec();
+    xor_each_byte(&mut decrypted, key);
+    check_index_header(raw_index).is_err() && check_index_header(&decrypted).is_err()
+}
+
+/// Undo the XOR of the index read from the pak if `encrypted`, or the other
+/// way round when that doesn't give a plausible index, since the encrypted
+/// flag in the footer of some modified paks is wrong.
+///
+/// Returns the index and whether it was decrypted.
+pub(crate) fn decode_index(
+    raw_index: Vec<u8>,
+    encrypted: bool,
+    key: u8,
+) -> Result<(Vec<u8>, bool), PakError> {
+    let header_size = raw_index.len().min(MAX_INDEX_HEADER_SIZE);
+    let (_, decrypted) =
+        decode_index_header(&raw_index[..header_size], raw_index.len(), encrypted, key)?;
+    let mut index_data = raw_index;
+    if decrypted {
+        xor_each_byte(&mut index_data, key);
+    }
+    Ok((index_data, decrypted))
+}
+
+/// [`decode_index`] for just `raw_header`, the first bytes of an index of
+/// `index_size` bytes as read from the pak, which is enough to tell which
+/// way round it decodes.
+///
+/// Returns the entry count and whether the index has to be decrypted.
+pub(crate) fn decode_index_header(
+    raw_header: &[u8],
+    index_size: usize,
+    encrypted: bool,
+    key: u8,
+) -> Result<(u64, bool), PakError> {
+    let mut decrypted = raw_header.to_vec();
+    xor_each_byte(&mut decrypted, key);
+    let (first, second) = if encrypted {
+        (decrypted.as_slice(), raw_header)
+    } else {
+        (raw_header, decrypted.as_slice())
+    };
+
+    let first_problem = match index_header_entry_count(first, index_size) {
+        Ok(entry_count) => return Ok((entry_count, encrypted)),
+        Err(problem) => problem,
+    };
+    let second_problem = match index_header_entry_count(second, index_size) {
+        Ok(entry_count) => return Ok((entry_count, !encrypted)),
+        Err(problem) => problem,
+    };
+    let (decrypted, plain) = if encrypted {
+        (first_problem, second_problem)
+    } else {
+        (second_problem, first_problem)
+    };
+    Err(PakError::IndexUndecodable { decrypted, plain })
+}
+
+/// What [`PakReader::index_stats`] reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexStats {
+    pub mount_point: String,
+    pub entries_count: u64,
+    /// Where the index starts in the pak.
+    pub index_offset: u64,
+    pub index_size: u64,
+    /// The hash stored in the footer next to the index location.
+    pub footer_hash: [u8; 20],
+    pub encrypted_entries: u64,
+    pub plain_entries: u64,
+    /// Number of entries per compression method, `0` meaning stored as is.
+    pub compression_methods: BTreeMap<u32, u64>,
+}
+
+/// What [`PakReader::entry_layout`] reports.
+///
+/// The payload is read as is from the pak, XORed with `xor_key` byte by byte
+/// if `encrypted`, then each block is zlib-decompressed in order. For
+/// example, with nothing but `pread` and `flate2`:
+///
+/// ```rust
+/// use flate2::read::ZlibDecoder;
+/// use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+/// use gfp::pak_reader::{EntryPayload, PakReader};
+/// use std::fs::File;
+/// use std::io::{Read, Seek, SeekFrom};
+///
+/// let pak_path = "test/normal/game_patch_1.32.11.13846.pak";
+/// let mut pak = GfpPakReaderV10::new(std::fs::read(pak_path)?);
+/// let mut file = File::open(pak_path)?;
+/// let mut pread = |offset: u64, length: u64| -> std::io::Result<Vec<u8>> {
+///     let mut data = vec![0u8; length as usize];
+///     file.seek(SeekFrom::Start(offset))?;
+///     file.read_exact(&mut data)?;
+///     Ok(data)
+/// };
+///
+/// for entry_id in 0..pak.entries_count()? {
+///     let layout = pak.entry_layout(entry_id)?;
+///     let undo_xor = |mut data: Vec<u8>| {
+///         if layout.encrypted {
+///             data.iter_mut().for_each(|byte| *byte ^= layout.xor_key);
+///         }
+///         data
+///     };
+///
+///     let mut content = Vec::new();
+///     match &layout.payload {
+///         &EntryPayload::Stored { offset, length } => {
+///             content = undo_xor(pread(offset, length)?);
+///         }
+///         EntryPayload::Blocks(blocks) => {
+///             for block in blocks {
+///                 let compressed = undo_xor(pread(block.offset, block.compressed_length)?);
+///                 ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut content)?;
+///             }
+///         }
+///     }
+///     assert_eq!(content.len() as u64, layout.file_size);
+///
+///     let mut expected = Vec::new();
+///     pak.extract_entry_to_writer(entry_id, &mut expected)?;
+///     assert_eq!(content, expected);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryLayout {
+    /// `0` for stored entries, `1` for zlib.
+    pub compression_method: u32,
+    pub encrypted: bool,
+    /// The byte every payload byte is XORed with when `encrypted`.
+    pub xor_key: u8,
+    /// Size of the entry once decompressed.
+    pub file_size: u64,
+    pub payload: EntryPayload,
+}
+
+impl EntryLayout {
+    /// Bytes the payload takes in the pak.
+    pub fn stored_size(&self) -> u64 {
+        match &self.payload {
+            EntryPayload::Stored { length, .. } => *length,
+            EntryPayload::Blocks(blocks) => {
+                blocks.iter().map(|block| block.compressed_length).sum()
+            }
+        }
+    }
+
+    /// [`ReadAt::prefetch`] each piece of the payload in `source`.
+    pub fn prefetch(&self, source: &(impl ReadAt + ?Sized)) -> std::io::Result<()> {
+        match &self.payload {
+            EntryPayload::Stored { offset, length } => source.prefetch(*offset, *length),
+            EntryPayload::Blocks(blocks) => blocks
+                .iter()
+                .try_for_each(|block| source.prefetch(block.offset, block.compressed_length)),
+        }
+    }
+
+    /// [`Self::stored_size`] over [`Self::file_size`], so 1 for stored
+    /// entries and close to 1 for content that was already compressed.
+    /// `None` for empty entries.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.file_size > 0).then(|| self.stored_size() as f64 / self.file_size as f64)
+    }
+}
+
+/// Where the bytes of an entry are, see [`EntryLayout`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryPayload {
+    /// `length` bytes at absolute `offset`, right after the entry's inline
+    /// header.
+    Stored { offset: u64, length: u64 },
+    /// Compressed blocks, in the order they are decompressed.
+    Blocks(Vec<BlockLayout>),
+}
+
+/// A compressed block of an entry, see [`EntryLayout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLayout {
+    /// Absolute offset of the block in the pak.
+    pub offset: u64,
+    pub compressed_length: u64,
+    pub decompressed_length: u64,
+}
+
+impl BlockLayout {
+    /// The layout of blocks given as `(offset, compressed_length)`, every one
+    /// decompressing to `block_size` bytes except for the remainder in the
+    /// last. A `block_size` of 0, which entries of a single block can have,
+    /// leaves the whole of `file_size` to the last block.
+    pub(crate) fn split(
+        file_size: u64,
+        block_size: u32,
+        blocks: impl ExactSizeIterator<Item = (u64, u64)>,
+    ) -> Vec<Self> {
+        let mut remaining = file_size;
+        let last = blocks.len().saturating_sub(1);
+        blocks
+            .enumerate()
+            .map(|(index, (offset, compressed_length))| {
+                let decompressed_length = if block_size == 0 && index == last {
+                    remaining
+                } else {
+                    remaining.min(block_size as u64)
+                };
+                remaining -= decompressed_length;
+                Self {
+                    offset,
+                    compressed_length,
+                    decompressed_length,
+                }
+            })
+            .collect()
+    }
+}
+
+impl IndexStats {
+    /// Stats of an index without entries, filled in with [`Self::count_entry`].
+    pub(crate) fn new(
+        mount_point: String,
+        index_offset: u64,
+        index_size: u64,
+        footer_hash: [u8; 20],
+    ) -> Self {
+        Self {
+            mount_point,
+            entries_count: 0,
+            index_offset,
+            index_size,
+            footer_hash,
+            encrypted_entries: 0,
+            plain_entries: 0,
+            compression_methods: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn count_entry(&mut self, encrypted: bool, compression_method: u32) {
+        self.entries_count += 1;
+        if encrypted {
+            self.encrypted_entries += 1;
+        } else {
+            self.plain_entries += 1;
+        }
+        *self
+            .compression_methods
+            .entry(compression_method)
+            .or_default() += 1;
+    }
 }
 
 pub mod implements {
     use crate::error::PakError;
-    use crate::pak_reader::gfp_v10::GfpPakReaderV10;
-    use crate::pak_reader::gfp_v7::GfpPakReaderV7;
     use crate::pak_reader::PakReader;
-    use crate::utils::glob_ext::glob_mapper;
-    use glob::PatternError;
+    use crate::pak_reader::gfp_v7::GfpPakReaderV7;
+    use crate::pak_reader::gfp_v10::GfpPakReaderV10;
+    #[cfg(feature = "std-fs")]
+    use crate::utils::glob_ext::{glob_no_follow, pattern_depth};
+    use crate::utils::read_at::ReadAt;
+    #[cfg(feature = "std-fs")]
+    use crate::{
+        pak_reader::detect::is_gfp_pak,
+        utils::glob_ext::GlobMapper,
+        utils::shared_read::is_sharing_violation,
+        utils::walk::{PakWalker, WalkError, sort_unique_paths},
+    };
+    #[cfg(feature = "std-fs")]
+    use glob::{MatchOptions, PatternError};
+    #[cfg(feature = "std-fs")]
     use std::path::{Path, PathBuf};
-    
+
+    /// The outcome of opening one pak matched by a glob pattern.
+    #[cfg(feature = "std-fs")]
+    pub type PakOpenResult = Result<Box<dyn PakReader>, PakError>;
+
+    /// A pak opened by [`open_all`] and its path.
+    #[cfg(feature = "std-fs")]
+    pub type OpenedPak = (PathBuf, Box<dyn PakReader>);
+
+    /// Create a reader of the given varient over any [`ReadAt`] source.
+    /// Varients other than 7 and 10 are [`PakError::InvalidData`].
+    pub fn new_pak<'a, R: ReadAt + 'a>(
+        source: R,
+        varient: i32,
+    ) -> Result<Box<dyn PakReader + 'a>, PakError> {
+        match varient {
+            7 => Ok(Box::new(GfpPakReaderV7::new(source))),
+            10 => Ok(Box::new(GfpPakReaderV10::new(source))),
+            _ => Err(invalid_varient(varient)),
+        }
+    }
+
+    fn invalid_varient(varient: i32) -> PakError {
+        PakError::invalid_data(format!("Invalid varient: {}", varient))
+    }
+
+    #[cfg(feature = "std-fs")]
     pub fn open_pak<P: AsRef<Path>>(path: P, varient: i32) -> Result<Box<dyn PakReader>, PakError> {
-        Ok(match varient {
-            7 => GfpPakReaderV7::open(path)?,
-            10 => GfpPakReaderV10::open(path)?,
-            _ => panic!("Invalid varient: {}", varient),
-        })
+        let path = path.as_ref();
+        match varient {
+            7 => GfpPakReaderV7::open(path),
+            10 => GfpPakReaderV10::open(path),
+            _ => return Err(invalid_varient(varient)),
+        }
+        .map_err(|error| open_error(path, error))
+    }
+
+    /// [`PakError::FileLocked`] for sharing violations, [`PakError::Io`]
+    /// otherwise.
+    #[cfg(feature = "std-fs")]
+    fn open_error(path: &Path, error: std::io::Error) -> PakError {
+        if is_sharing_violation(&error) {
+            PakError::FileLocked {
+                path: path.to_path_buf(),
+            }
+        } else {
+            PakError::Io(error)
+        }
+    }
+
+    /// [`open_pak`] bypassing the page cache, see
+    /// [`DirectFile`](crate::utils::direct_io::DirectFile).
+    #[cfg(feature = "std-fs")]
+    pub fn open_pak_direct<P: AsRef<Path>>(
+        path: P,
+        varient: i32,
+    ) -> Result<Box<dyn PakReader>, PakError> {
+        let path = path.as_ref();
+        match varient {
+            7 => GfpPakReaderV7::open_direct(path),
+            10 => GfpPakReaderV10::open_direct(path),
+            _ => return Err(invalid_varient(varient)),
+        }
+        .map_err(|error| open_error(path, error))
     }
 
+    /// [`open_pak`] reading the pak through a memory mapping, see
+    /// [`MappedFile`](crate::utils::mmap::MappedFile).
+    #[cfg(feature = "std-fs")]
+    pub fn open_pak_mapped<P: AsRef<Path>>(
+        path: P,
+        varient: i32,
+    ) -> Result<Box<dyn PakReader>, PakError> {
+        let path = path.as_ref();
+        match varient {
+            7 => GfpPakReaderV7::open_mapped(path),
+            10 => GfpPakReaderV10::open_mapped(path),
+            _ => return Err(invalid_varient(varient)),
+        }
+        .map_err(|error| open_error(path, error))
+    }
+
+    /// Open every pak matched by `pattern`, silently skipping the paths that
+    /// can't be accessed or opened.
+    ///
+    /// Use [`open_paks_by_glob_checked`] to find out why a pak was skipped.
+    #[cfg(feature = "std-fs")]
     pub fn open_paks_by_glob(
         pattern: &str,
         varient: i32,
-    ) -> Result<impl Iterator<Item = (PathBuf, Box<dyn PakReader>)>, PatternError> {
-        glob_mapper(move |result| match result {
-            Ok(pak_path) => match open_pak(&pak_path, varient) {
+    ) -> Result<impl Iterator<Item = (PathBuf, Box<dyn PakReader>)> + use<>, PatternError> {
+        Ok(
+            open_paks_by_glob_checked(pattern, varient)?.filter_map(|(pak_path, pak)| match pak {
                 Ok(pak) => Some((pak_path, pak)),
-                Err(e) => {
-                    eprintln!("Error opening pak file: {:?}", e);
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(path = %pak_path.display(), error = %_e, "skipping pak");
                     None
                 }
+            }),
+        )
+    }
+
+    /// How paks are found by [`open_paks_by_pattern`] and [`open_paks_in_dir`].
+    #[cfg(feature = "std-fs")]
+    #[derive(Debug, Clone)]
+    pub struct DiscoveryOptions {
+        /// Options used to match glob patterns.
+        pub match_options: MatchOptions,
+        /// Descend into symlinked directories. Directory walks enter each
+        /// directory only once, so link cycles can't make them loop. Glob
+        /// patterns are expanded by the `glob` crate when this is set, and
+        /// with [`glob_no_follow`] otherwise.
+        pub follow_symlinks: bool,
+        /// How deep below the root a directory walk looks for paks, like
+        /// `find -maxdepth`: `Some(1)` only looks at the files directly in it.
+        ///
+        /// For glob patterns, the depth is counted from the leading part of
+        /// the pattern without wildcards, see [`pattern_depth`], and applies
+        /// to explicit patterns such as `Paks/*/*.pak` as well.
+        pub max_depth: Option<usize>,
+        /// Extensions of the files a directory walk picks up, compared
+        /// case-insensitively. Ignored when [`Self::detect`] is set.
+        pub extensions: Vec<String>,
+        /// Collect every path first, drop the ones resolving to the same file
+        /// and sort them by canonical path, so the order doesn't depend on the
+        /// file system and a pak matched twice is only opened once.
+        ///
+        /// Anything that lets later paks override earlier ones relies on this
+        /// order. Turning it off yields paths in discovery order as soon as
+        /// they are found, which starts faster on huge trees.
+        pub sort: bool,
+        /// Recognize paks by their footer with [`open_detected_pak`], looking
+        /// at every file regardless of its extension. The `varient` passed
+        /// along is ignored then.
+        pub detect: bool,
+        /// Open the paks with [`open_pak_direct`], for extractions large
+        /// enough to thrash the page cache.
+        pub direct: bool,
+        /// Open the paks with [`open_pak_mapped`] instead, see
+        /// [`ReadStrategy::Mmap`](crate::pak_reader::extract::ReadStrategy::Mmap).
+        /// Ignored when [`Self::direct`] is set.
+        pub mmap: bool,
+    }
+
+    #[cfg(feature = "std-fs")]
+    impl Default for DiscoveryOptions {
+        fn default() -> Self {
+            Self {
+                match_options: MatchOptions::new(),
+                follow_symlinks: false,
+                max_depth: None,
+                extensions: vec!["pak".to_string()],
+                sort: true,
+                detect: false,
+                direct: false,
+                mmap: false,
+            }
+        }
+    }
+
+    /// Open every pak matched by `pattern`, yielding each path together with
+    /// the reader or the error that prevented it from being opened.
+    ///
+    /// Paks are opened one at a time as the iterator is advanced, and opening
+    /// doesn't read the pak yet, so taking only the first few items is cheap
+    /// even on a huge directory.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_by_glob_checked(
+        pattern: &str,
+        varient: i32,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<>, PatternError> {
+        open_paks_by_glob_with(pattern, varient, MatchOptions::new())
+    }
+
+    /// [`open_paks_by_glob_checked`] with custom [`MatchOptions`], e.g. for
+    /// case-insensitive matching.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_by_glob_with(
+        pattern: &str,
+        varient: i32,
+        options: MatchOptions,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<>, PatternError> {
+        open_paks_by_pattern(
+            pattern,
+            varient,
+            DiscoveryOptions {
+                match_options: options,
+                follow_symlinks: true,
+                ..DiscoveryOptions::default()
+            },
+        )
+    }
+
+    /// [`open_paks_by_glob_with`] that never descends into symlinked
+    /// directories, see [`glob_no_follow`].
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_by_glob_no_follow(
+        pattern: &str,
+        varient: i32,
+        options: MatchOptions,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<>, PatternError> {
+        open_paks_by_pattern(
+            pattern,
+            varient,
+            DiscoveryOptions {
+                match_options: options,
+                follow_symlinks: false,
+                ..DiscoveryOptions::default()
+            },
+        )
+    }
+
+    /// [`open_paks_by_glob_checked`] that recognizes paks by their footer with
+    /// [`open_detected_pak`] and skips matched files that aren't gfp paks.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_by_glob_detected(
+        pattern: &str,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<>, PatternError> {
+        open_paks_by_pattern(
+            pattern,
+            0,
+            DiscoveryOptions {
+                follow_symlinks: true,
+                detect: true,
+                ..DiscoveryOptions::default()
+            },
+        )
+    }
+
+    /// Open every pak matched by `pattern` as configured by `options`.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_by_pattern(
+        pattern: &str,
+        varient: i32,
+        options: DiscoveryOptions,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<>, PatternError> {
+        Ok(open_each(
+            glob_paths(pattern, &options)?,
+            options.sort,
+            opener(varient, &options),
+        ))
+    }
+
+    /// Open every pak under the directory `root`, yielding each path together
+    /// with the reader or the error that prevented it from being opened.
+    ///
+    /// Unlike [`open_paks_by_glob_checked`], `root` is used literally, so it
+    /// may contain glob metacharacters such as `[` and `]`.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_in_dir_checked<P: AsRef<Path>>(
+        root: P,
+        varient: i32,
+    ) -> impl Iterator<Item = (PathBuf, PakOpenResult)> {
+        open_paks_in_dir(root, varient, DiscoveryOptions::default())
+    }
+
+    /// [`open_paks_in_dir_checked`] that looks at every file regardless of its
+    /// extension and keeps the ones recognized by [`open_detected_pak`].
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_in_dir_detected<P: AsRef<Path>>(
+        root: P,
+    ) -> impl Iterator<Item = (PathBuf, PakOpenResult)> {
+        open_paks_in_dir(
+            root,
+            0,
+            DiscoveryOptions {
+                detect: true,
+                ..DiscoveryOptions::default()
+            },
+        )
+    }
+
+    /// Open every pak under the directory `root` as configured by `options`.
+    ///
+    /// [`DiscoveryOptions::match_options`] only applies to glob patterns.
+    #[cfg(feature = "std-fs")]
+    pub fn open_paks_in_dir<P: AsRef<Path>>(
+        root: P,
+        varient: i32,
+        options: DiscoveryOptions,
+    ) -> impl Iterator<Item = (PathBuf, PakOpenResult)> {
+        let open = opener(varient, &options);
+        let walker = PakWalker::new(root)
+            .max_depth(options.max_depth)
+            .follow_symlinks(options.follow_symlinks);
+        let walker = if options.detect {
+            walker.any_extension()
+        } else {
+            walker.extensions(options.extensions)
+        };
+        open_each(walker, options.sort, open)
+    }
+
+    /// Open every `.pak` file directly in `dir`, sorted by file name.
+    ///
+    /// Unlike [`open_paks_in_dir`] and the glob functions, subdirectories
+    /// aren't entered and nothing in `dir` is taken as a pattern. Fails if
+    /// the directory can't be read or any of the paks can't be opened.
+    #[cfg(feature = "std-fs")]
+    pub fn open_all<P: AsRef<Path>>(dir: P, varient: i32) -> Result<Vec<OpenedPak>, PakError> {
+        let mut pak_paths = vec![];
+        for dir_entry in std::fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            let is_pak = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("pak"));
+            if is_pak && path.is_file() {
+                pak_paths.push(path);
+            }
+        }
+        pak_paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        pak_paths
+            .into_iter()
+            .map(|path| {
+                let pak = open_pak(&path, varient)?;
+                Ok((path, pak))
+            })
+            .collect()
+    }
+
+    /// Open the file at `path` as the pak variant recognized from its footer.
+    ///
+    /// Returns `Ok(None)` if the file isn't a gfp pak, whatever its name.
+    #[cfg(feature = "std-fs")]
+    pub fn open_detected_pak<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<Option<Box<dyn PakReader>>, PakError> {
+        detect_and_open(path.as_ref(), |path, varient| open_pak(path, varient))
+    }
+
+    /// [`open_detected_pak`], with files that aren't gfp paks as errors:
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::PakReader;
+    /// use std::path::Path;
+    ///
+    /// let mut pak: Box<dyn PakReader> =
+    ///     Path::new("test/avatar/onreadypak_405399.pak").try_into()?;
+    /// assert_eq!(pak.parser_version(), 7);
+    /// assert!(Box::<dyn PakReader>::try_from(Path::new("Cargo.toml")).is_err());
+    /// # Ok::<(), gfp::error::PakError>(())
+    /// ```
+    #[cfg(feature = "std-fs")]
+    impl TryFrom<&Path> for Box<dyn PakReader> {
+        type Error = PakError;
+
+        fn try_from(path: &Path) -> Result<Self, Self::Error> {
+            open_detected_pak(path)?
+                .ok_or_else(|| PakError::invalid_data(format!("Not a gfp pak: {}", path.display())))
+        }
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn detect_and_open(
+        path: &Path,
+        open: PakOpener,
+    ) -> Result<Option<Box<dyn PakReader>>, PakError> {
+        match is_gfp_pak(path).map_err(|error| open_error(path, error))? {
+            Some(variant) => Ok(Some(open(path, variant.varient())?)),
+            None => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(path = %path.display(), "not a gfp pak");
+                Ok(None)
+            }
+        }
+    }
+
+    /// [`open_pak`] or [`open_pak_direct`].
+    #[cfg(feature = "std-fs")]
+    type PakOpener = fn(&Path, i32) -> PakOpenResult;
+
+    /// The paths matched by `pattern`, with errors in the shape of [`PakWalker`]'s.
+    #[cfg(feature = "std-fs")]
+    fn glob_paths(
+        pattern: &str,
+        options: &DiscoveryOptions,
+    ) -> Result<Box<dyn Iterator<Item = Result<PathBuf, WalkError>>>, PatternError> {
+        if !options.follow_symlinks {
+            return Ok(Box::new(glob_no_follow(
+                pattern,
+                options.match_options,
+                options.max_depth,
+            )?));
+        }
+
+        let pattern_owned = pattern.to_string();
+        let max_depth = options.max_depth;
+        Ok(Box::new(
+            GlobMapper::new(pattern)?
+                .with_options(options.match_options)
+                .map(move |result| match result {
+                    // `glob` can't be told to stop descending, so deeper matches are dropped
+                    Ok(path)
+                        if max_depth.is_some_and(|max_depth| {
+                            pattern_depth(&pattern_owned, &path)
+                                .is_none_or(|depth| depth > max_depth)
+                        }) =>
+                    {
+                        None
+                    }
+                    result => Some(result.map_err(|e| WalkError {
+                        path: e.path().to_path_buf(),
+                        error: e.into_error(),
+                    })),
+                }),
+        ))
+    }
+
+    /// The function opening each discovered path, returning `None` for files
+    /// that turn out not to be paks.
+    #[cfg(feature = "std-fs")]
+    fn opener(
+        varient: i32,
+        options: &DiscoveryOptions,
+    ) -> impl FnMut(&Path) -> Option<PakOpenResult> + use<> {
+        let detect = options.detect;
+        let open: PakOpener = if options.direct {
+            |pak_path, varient| open_pak_direct(pak_path, varient)
+        } else if options.mmap {
+            |pak_path, varient| open_pak_mapped(pak_path, varient)
+        } else {
+            |pak_path, varient| open_pak(pak_path, varient)
+        };
+        move |pak_path| {
+            if detect {
+                detect_and_open(pak_path, open).transpose()
+            } else {
+                Some(open(pak_path, varient))
+            }
+        }
+    }
+
+    /// Open each of `pak_paths` with `open`, sorting and deduplicating the
+    /// paths first if `sort` is set.
+    #[cfg(feature = "std-fs")]
+    fn open_each<I, O>(
+        pak_paths: I,
+        sort: bool,
+        mut open: O,
+    ) -> impl Iterator<Item = (PathBuf, PakOpenResult)> + use<I, O>
+    where
+        I: Iterator<Item = Result<PathBuf, WalkError>> + 'static,
+        O: FnMut(&Path) -> Option<PakOpenResult>,
+    {
+        let pak_paths: Box<dyn Iterator<Item = Result<PathBuf, WalkError>>> = if sort {
+            Box::new(sort_unique_paths(pak_paths).into_iter())
+        } else {
+            Box::new(pak_paths)
+        };
+        pak_paths.filter_map(move |result| match result {
+            Ok(pak_path) => {
+                let pak = open(&pak_path)?;
+                Some((pak_path, pak))
+            }
+            Err(e) => Some((e.path, Err(PakError::Io(e.error)))),
+        })
+    }
+
+    /// [`open_paks_by_pattern`] with a custom function to open each pak.
+    #[cfg(all(test, feature = "std-fs"))]
+    pub(crate) fn open_paks_by_glob_using<O>(
+        pattern: &str,
+        options: DiscoveryOptions,
+        mut open: O,
+    ) -> Result<impl Iterator<Item = (PathBuf, PakOpenResult)> + use<O>, PatternError>
+    where
+        O: FnMut(&Path) -> PakOpenResult,
+    {
+        Ok(open_each(
+            glob_paths(pattern, &options)?,
+            options.sort,
+            move |pak_path| Some(open(pak_path)),
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::implements::{
+        DiscoveryOptions, open_all, open_pak, open_pak_direct, open_pak_mapped, open_paks_by_glob,
+        open_paks_by_glob_checked, open_paks_by_glob_detected, open_paks_by_glob_using,
+        open_paks_by_glob_with, open_paks_by_pattern, open_paks_in_dir, open_paks_in_dir_detected,
+    };
+    use super::*;
+    use crate::pak_reader::ext::PakReaderExt;
+    use glob::MatchOptions;
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_extract_entry_into_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak("test/normal/game_patch_1.32.11.13846.pak", 10)?;
+        let output_path = pak.extract_entry_into_dir(3, temp_dir.path())?;
+        assert_eq!(
+            output_path,
+            temp_dir
+                .path()
+                .join("ShadowTrackerExtra/Content/Lua/common/lua_object.lua")
+        );
+        assert_eq!(
+            std::fs::metadata(&output_path)?.len(),
+            pak.entry_layout(3)?.file_size
+        );
+
+        // Extracting again overwrites the file
+        assert_eq!(pak.extract_entry_into_dir(3, temp_dir.path())?, output_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_to_file_at() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = "test/normal/game_patch_1.32.11.13992.pak";
+        let mut pak = open_pak(pak_path, 10)?;
+        let entry_ids: Vec<u64> = (0..pak.entries_count()?).step_by(7).collect();
+        let mut expected = b"head".to_vec();
+        let mut offsets = vec![];
+        for &entry_id in &entry_ids {
+            offsets.push(expected.len() as u64);
+            pak.extract_entry_to_writer(entry_id, &mut expected)?;
+        }
+
+        // Each thread fills its own ranges of the pre-sized file, in reverse
+        let mut output = tempfile::tempfile()?;
+        output.set_len(expected.len() as u64)?;
+        output.write_all(b"head")?;
+        std::thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+            let workers = (0..3)
+                .map(|worker| {
+                    let mut output = output.try_clone()?;
+                    let jobs: Vec<(u64, u64)> = entry_ids
+                        .iter()
+                        .copied()
+                        .zip(offsets.iter().copied())
+                        .skip(worker)
+                        .step_by(3)
+                        .collect();
+                    Ok(scope.spawn(move || -> Result<u64, PakError> {
+                        let mut pak = open_pak(pak_path, 10)?;
+                        let mut written = 0;
+                        for &(entry_id, offset) in jobs.iter().rev() {
+                            written +=
+                                pak.extract_entry_to_file_at(entry_id, &mut output, offset)?;
+                        }
+                        Ok(written)
+                    }))
+                })
+                .collect::<Result<Vec<_>, std::io::Error>>()?;
+            let written: u64 = workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .sum::<Result<u64, PakError>>()?;
+            assert_eq!(written, expected.len() as u64 - 4);
+            Ok(())
+        })?;
+
+        let mut actual = vec![];
+        std::io::Seek::rewind(&mut output)?;
+        std::io::Read::read_to_end(&mut output, &mut actual)?;
+        assert_eq!(actual.len(), expected.len());
+        assert!(actual == expected);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_prefetch_entry() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, version) in [
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+        ] {
+            let mut pak = open_pak(pak_path, version)?;
+            let mut in_memory: Box<dyn PakReader> = match version {
+                10 => Box::new(gfp_v10::GfpPakReaderV10::new(std::fs::read(pak_path)?)),
+                _ => Box::new(gfp_v7::GfpPakReaderV7::new(std::fs::read(pak_path)?)),
+            };
+            for entry_id in 0..pak.entries_count()? {
+                pak.prefetch_entry(entry_id)?;
+                in_memory.prefetch_entry(entry_id)?;
+                let mut content = vec![];
+                pak.extract_entry_to_writer(entry_id, &mut content)?;
+                let mut expected = vec![];
+                in_memory.extract_entry_to_writer(entry_id, &mut expected)?;
+                assert!(content == expected, "{} entry {}", pak_path, entry_id);
+            }
+        }
+
+        // Ranges past the end or beyond off_t are still just hints
+        let file = std::fs::File::open("test/normal/game_patch_1.32.11.13846.pak")?;
+        file.prefetch(1 << 40, 4096)?;
+        file.prefetch(0, u64::MAX)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_layout_split() {
+        let sizes = |file_size, block_size, count: u32| {
+            BlockLayout::split(
+                file_size,
+                block_size,
+                (0..count).map(|index| (index as u64 * 100, 50)),
+            )
+            .iter()
+            .map(|block| block.decompressed_length)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(150_000, 65536, 3), [65536, 65536, 18928]);
+        assert_eq!(sizes(4853, 0, 1), [4853]);
+        assert_eq!(sizes(4853, 0, 2), [0, 4853]);
+        assert!(sizes(0, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_varient() {
+        let pak_path = "test/normal/game_patch_1.32.11.13846.pak";
+        for result in [
+            open_pak(pak_path, 8).map(drop),
+            open_pak_direct(pak_path, 8).map(drop),
+            open_pak_mapped(pak_path, 8).map(drop),
+            implements::new_pak(&[][..], 8).map(drop),
+        ] {
+            assert!(
+                matches!(result, Err(PakError::InvalidData(message)) if message == "Invalid varient: 8")
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_all() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let sample = "test/normal/game_patch_1.32.11.13846.pak";
+        for name in ["b.pak", "a.pak", "c.PAK"] {
+            std::fs::copy(sample, temp_dir.path().join(name))?;
+        }
+        std::fs::write(temp_dir.path().join("notes.txt"), "not a pak")?;
+        std::fs::create_dir(temp_dir.path().join("sub.pak"))?;
+        std::fs::copy(sample, temp_dir.path().join("sub.pak/d.pak"))?;
+
+        let paks = open_all(temp_dir.path(), 10)?;
+        let names: Vec<_> = paks
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["a.pak", "b.pak", "c.PAK"]);
+        for (_, mut pak) in paks {
+            assert_eq!(pak.entries_count()?, 7);
+        }
+
+        std::fs::write(temp_dir.path().join("broken.pak"), "not a pak")?;
+        assert!(open_all(temp_dir.path(), 10).is_err());
+        assert!(open_all(temp_dir.path().join("missing"), 10).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entries_count_fast() -> Result<(), Box<dyn std::error::Error>> {
+        for (pattern, version) in [("test/normal/*.pak", 10), ("test/avatar/*.pak", 7)] {
+            for (pak_path, mut pak) in open_paks_by_glob(pattern, version)? {
+                let entries_count = pak.entries_count()?;
+                // A reader that hasn't loaded the entries yet
+                let mut fresh = open_pak(&pak_path, version)?;
+                assert_eq!(fresh.entries_count_fast()?, entries_count, "{:?}", pak_path);
+                assert_eq!(pak.entries_count_fast()?, entries_count);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_header_size() -> Result<(), Box<dyn std::error::Error>> {
+        let mut stored = vec![];
+        for (pattern, version) in [("test/normal/*.pak", 10), ("test/avatar/*.pak", 7)] {
+            for (pak_path, mut pak) in open_paks_by_glob(pattern, version)? {
+                let data = std::fs::read(&pak_path)?;
+                for entry_id in 0..pak.entries_count()? {
+                    let layout = pak.entry_layout(entry_id)?;
+                    let EntryPayload::Stored { offset, length } = layout.payload else {
+                        continue;
+                    };
+                    let hash = pak.entry_hash(entry_id)?;
+                    let header_offset = offset - INLINE_HEADER_SIZE;
+                    assert_eq!(
+                        inline_header_size(&data, header_offset, &hash, length, length)?,
+                        74
+                    );
+                    assert_eq!(pak.compute_entry_hash(entry_id)?, hash);
+                    stored.push((data.clone(), header_offset, hash, length));
+                }
+            }
+        }
+        assert!(!stored.is_empty());
+
+        let (data, header_offset, hash, length) = stored.pop().unwrap();
+        let at = |position: u64| usize::try_from(header_offset + position).unwrap();
+        // A header recording two blocks puts the data after them
+        let mut with_blocks = data.clone();
+        with_blocks[at(36)..at(40)].copy_from_slice(&1u32.to_le_bytes());
+        with_blocks[at(69)..at(73)].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            inline_header_size(&with_blocks, header_offset, &hash, length, length)?,
+            74 + 4 + 2 * 16
+        );
+        // Headers that don't match the index
+        let mut other_hash = data.clone();
+        other_hash[at(0)] ^= 0xFF;
+        assert_eq!(
+            inline_header_size(&other_hash, header_offset, &hash, length, length)?,
+            74
+        );
+        assert_eq!(
+            inline_header_size(&data, header_offset, &hash, length + 1, length)?,
+            74
+        );
+        assert_eq!(
+            inline_header_size(&data[..at(10)], header_offset, &hash, length, length)?,
+            74
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_ratio() -> Result<(), Box<dyn std::error::Error>> {
+        for (pattern, version) in [("test/normal/*.pak", 10), ("test/avatar/*.pak", 7)] {
+            for (_, mut pak) in open_paks_by_glob(pattern, version)? {
+                for entry_id in 0..pak.entries_count()? {
+                    let layout = pak.entry_layout(entry_id)?;
+                    let ratio = layout.compression_ratio().unwrap();
+                    assert!(ratio > 0.0 && ratio <= 1.1, "{} {:?}", ratio, layout);
+                    if let EntryPayload::Stored { .. } = layout.payload {
+                        assert_eq!(ratio, 1.0);
+                    }
+                }
+            }
+        }
+
+        let empty = EntryLayout {
+            compression_method: 0,
+            encrypted: false,
+            xor_key: 0,
+            file_size: 0,
+            payload: EntryPayload::Stored {
+                offset: 0,
+                length: 0,
             },
-            Err(e) => {
-                eprintln!("Error accessing entry: {:?}", e);
-                None
+        };
+        assert_eq!(empty.compression_ratio(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_point_lengths() -> Result<(), Box<dyn std::error::Error>> {
+        /// The pak at `pak_path` with the mount point in its index replaced
+        fn with_mount_point(
+            pak_path: &str,
+            version: i32,
+            mount_point: &str,
+        ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let data = std::fs::read(pak_path)?;
+            let stats = open_pak(pak_path, version)?.index_stats()?;
+            let index_offset = stats.index_offset as usize;
+            let old_length =
+                u32::from_le_bytes(data[index_offset..index_offset + 4].try_into()?) as usize;
+
+            let mut spliced = data[..index_offset].to_vec();
+            spliced.extend_from_slice(&(mount_point.len() as u32 + 1).to_le_bytes());
+            spliced.extend_from_slice(mount_point.as_bytes());
+            spliced.push(0);
+            spliced.extend_from_slice(&data[index_offset + 4 + old_length..]);
+
+            // v7 reads the index size from the footer, where it's XORed with a
+            // key, so flipping the bits that changed updates it
+            if version == 7 {
+                let field = spliced.len() - 16..spliced.len() - 8;
+                let index_size = (stats.index_size + mount_point.len() as u64 + 1)
+                    .wrapping_sub(old_length as u64);
+                let raw = u64::from_le_bytes(spliced[field.clone()].try_into()?)
+                    ^ stats.index_size
+                    ^ index_size;
+                spliced[field].copy_from_slice(&raw.to_le_bytes());
+            }
+            Ok(spliced)
+        }
+
+        // Lengths of 1, 9 and 200 counting the NUL
+        let long_mount_point = format!("../../../{}/", "A".repeat(189));
+        for (mount_point, expected) in [
+            ("", ""),
+            ("../../a/", "a/"),
+            (&long_mount_point, &long_mount_point[9..]),
+        ] {
+            for (pak_path, version) in [
+                ("test/normal/game_patch_1.32.11.13846.pak", 10),
+                ("test/avatar/onreadypak_405399.pak", 7),
+            ] {
+                let data = with_mount_point(pak_path, version, mount_point)?;
+                let mut pak: Box<dyn PakReader> = match version {
+                    10 => Box::new(gfp_v10::GfpPakReaderV10::new(data)),
+                    _ => Box::new(gfp_v7::GfpPakReaderV7::new(data)),
+                };
+                let mut original = open_pak(pak_path, version)?;
+                let original_mount_point = original.index_stats()?.mount_point;
+                assert_eq!(pak.index_stats()?.mount_point, expected);
+                assert_eq!(pak.entries_count()?, original.entries_count()?);
+
+                for entry_id in 0..pak.entries_count()? {
+                    let path = pak.get_entry_path(entry_id)?;
+                    let original_path = original.get_entry_path(entry_id)?;
+                    assert_eq!(
+                        path.strip_prefix(expected).unwrap_or(&path),
+                        original_path
+                            .strip_prefix(&original_mount_point)
+                            .unwrap_or(&original_path)
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_declared_and_parser_versions() -> Result<(), Box<dyn std::error::Error>> {
+        for (pattern, parser_version) in [("test/normal/*.pak", 10), ("test/avatar/*.pak", 7)] {
+            for (pak_path, mut pak) in open_paks_by_glob(pattern, parser_version)? {
+                let declared_version = pak.declared_version()?;
+                println!(
+                    "{}: declared {}, parser {}",
+                    pak_path.to_string_lossy(),
+                    declared_version,
+                    pak.parser_version()
+                );
+                assert_eq!(pak.parser_version(), parser_version as u32);
+                assert_eq!(declared_version, pak.parser_version());
+            }
+        }
+
+        // The wrong reader still reads the footer, and the mismatch shows
+        let mut pak = open_pak("test/normal/game_patch_1.32.11.13846.pak", 7)?;
+        assert_eq!(pak.parser_version(), 7);
+        assert_eq!(pak.declared_version()?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_path() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, parser_version) in [
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+            ("test/avatar/onreadypak_101005004.pak", 7),
+        ] {
+            let mut pak: Box<dyn PakReader> = Path::new(pak_path).try_into()?;
+            assert_eq!(pak.parser_version(), parser_version);
+            assert_eq!(
+                pak.entries_count()?,
+                open_pak(pak_path, parser_version as i32)?.entries_count()?
+            );
+        }
+
+        let Err(error) = Box::<dyn PakReader>::try_from(Path::new("Cargo.toml")) else {
+            panic!("Cargo.toml opened as a pak");
+        };
+        assert!(error.to_string().contains("Not a gfp pak"), "{}", error);
+        let missing = Box::<dyn PakReader>::try_from(Path::new("test/missing.pak"));
+        assert!(matches!(missing, Err(PakError::Io(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, varient) in [
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/avatar/onreadypak_101005004.pak", 7),
+        ] {
+            let mut pak = open_pak(pak_path, varient)?;
+            let index_offset = pak.index_stats()?.index_offset;
+            let mut multi_block = 0;
+            for entry_id in 0..pak.entries_count()? {
+                let layout = pak.entry_layout(entry_id)?;
+                let blocks = pak.entry_blocks(entry_id)?;
+                let EntryPayload::Blocks(block_layouts) = &layout.payload else {
+                    assert!(blocks.is_empty());
+                    continue;
+                };
+                assert_eq!(blocks.len(), block_layouts.len());
+                if blocks.len() > 1 {
+                    multi_block += 1;
+                }
+                // Each block follows the previous one, give or take padding
+                // to 16 bytes, all of them in the payload area before the index
+                for pair in blocks.windows(2) {
+                    let previous_end = pair[0].0 + pair[0].1;
+                    assert!(
+                        (previous_end..previous_end + 16).contains(&pair[1].0),
+                        "{} {:?}",
+                        entry_id,
+                        pair
+                    );
+                }
+                let &(last_offset, last_size) = blocks.last().unwrap();
+                assert!(blocks[0].0 > 0 && last_offset + last_size <= index_offset);
+            }
+            assert!(multi_block > 0, "{}", pak_path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        for (before, after, varient) in [
+            (
+                "test/normal/game_patch_1.32.11.13846.pak",
+                "test/normal/game_patch_1.32.11.13992.pak",
+                10,
+            ),
+            (
+                "test/avatar/onreadypak_405399.pak",
+                "test/avatar/onreadypak_101005004.pak",
+                7,
+            ),
+        ] {
+            let mut expected = open_pak(after, varient)?;
+            for open in [open_pak, open_pak_direct, open_pak_mapped] {
+                std::fs::copy(before, &pak_path)?;
+                let mut pak = open(&pak_path, varient)?;
+                assert_eq!(
+                    pak.get_entry_path(0)?,
+                    open_pak(before, varient)?.get_entry_path(0)?
+                );
+
+                // Replaced the way updaters do, by renaming over it
+                let new_path = temp_dir.path().join("a.pak.new");
+                std::fs::copy(after, &new_path)?;
+                std::fs::rename(&new_path, &pak_path)?;
+                pak.reopen(&pak_path)?;
+                assert_eq!(pak.entries_count()?, expected.entries_count()?);
+                assert_eq!(pak.index_stats()?, expected.index_stats()?);
+                for entry_id in 0..expected.entries_count()? {
+                    assert_eq!(
+                        pak.get_entry_path(entry_id)?,
+                        expected.get_entry_path(entry_id)?
+                    );
+                    assert_eq!(pak.entry_hash(entry_id)?, expected.entry_hash(entry_id)?);
+                }
+                assert_eq!(
+                    pak.extract_entry_to_vec(0)?,
+                    expected.extract_entry_to_vec(0)?
+                );
+            }
+        }
+
+        // In-memory readers read the file again, readers of a slice can't
+        let data = std::fs::read(&pak_path)?;
+        let mut pak = gfp_v7::GfpPakReaderV7::new(vec![]);
+        pak.reopen(&pak_path)?;
+        assert_eq!(pak.file, data);
+        let mut pak = gfp_v7::GfpPakReaderV7::new(data.as_slice());
+        pak.entries_count()?;
+        assert!(pak.reopen(&pak_path).is_err());
+        assert_eq!(
+            pak.get_entry_path(0)?,
+            open_pak("test/avatar/onreadypak_101005004.pak", 7)?.get_entry_path(0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_index_end() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, varient) in [
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/empty/empty.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+            ("test/avatar/onreadypak_101005004.pak", 7),
+        ] {
+            let mut pak = open_pak(pak_path, varient)?;
+            pak.set_strict(true);
+            assert!(pak.is_strict());
+            for entry_id in 0..pak.entries_count()? {
+                pak.get_entry_path(entry_id)
+                    .map_err(|e| e.context(pak_path.to_string()))?;
+            }
+        }
+
+        // Bytes between the index and the footer make the index longer than
+        // what's parsed
+        for (pak_path, varient, extra) in [
+            (
+                "test/normal/game_patch_1.32.11.13846.pak",
+                10,
+                INDEX_END_SLACK,
+            ),
+            ("test/avatar/onreadypak_405399.pak", 7, INDEX_END_SLACK),
+            ("test/avatar/onreadypak_405399.pak", 7, INDEX_END_SLACK - 1),
+        ] {
+            let data = std::fs::read(pak_path)?;
+            let (index, footer) = data.split_at(data.len() - gfp_v10::FOOTER_SIZE);
+            let mut padded = index.to_vec();
+            padded.resize(index.len() + extra, 0);
+            if varient == 7 {
+                // v7 paks record the index size in their footer
+                let stats = open_pak(pak_path, 7)?.index_stats()?;
+                padded.extend_from_slice(&gfp_v7::build_footer(
+                    false,
+                    stats.index_offset,
+                    stats.index_size + extra as u64,
+                    stats.footer_hash,
+                ));
+            } else {
+                padded.extend_from_slice(footer);
             }
-        })(pattern)
+            for strict in [false, true] {
+                let mut pak: Box<dyn PakReader> = match varient {
+                    7 => Box::new(gfp_v7::GfpPakReaderV7::new(padded.clone())),
+                    _ => Box::new(gfp_v10::GfpPakReaderV10::new(padded.clone())),
+                };
+                pak.set_strict(strict);
+                let result = pak.get_entry_path(0);
+                assert_eq!(
+                    result.is_ok(),
+                    !strict || extra < INDEX_END_SLACK,
+                    "{} {} {:?}",
+                    pak_path,
+                    extra,
+                    result
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_footer_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, varient) in [
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+            ("test/avatar/onreadypak_101005004.pak", 7),
+        ] {
+            let data = std::fs::read(pak_path)?;
+            let footer = &data[data.len() - gfp_v10::FOOTER_SIZE..];
+            let mut pak = open_pak(pak_path, varient)?;
+            assert_eq!(pak.footer_bytes()?, footer, "{}", pak_path);
+            // Parsing everything doesn't change the fields behind it
+            pak.index_stats()?;
+            assert_eq!(pak.footer_bytes()?, footer, "{}", pak_path);
+
+            // A footer built from the parsed fields reads back the same
+            let stats = pak.index_stats()?;
+            let build_footer = match varient {
+                7 => gfp_v7::build_footer,
+                _ => gfp_v10::build_footer,
+            };
+            let built = build_footer(
+                pak.encrypted()?,
+                stats.index_offset,
+                stats.index_size,
+                stats.footer_hash,
+            );
+            let mut rebuilt_data = data[..data.len() - built.len()].to_vec();
+            rebuilt_data.extend_from_slice(&built);
+            let mut rebuilt: Box<dyn PakReader> = match varient {
+                7 => Box::new(gfp_v7::GfpPakReaderV7::new(rebuilt_data)),
+                _ => Box::new(gfp_v10::GfpPakReaderV10::new(rebuilt_data)),
+            };
+            assert_eq!(rebuilt.footer_bytes()?, built);
+            assert_eq!(rebuilt.index_stats()?, stats);
+            // The index size field of v10 paks holds something else, which
+            // isn't read
+            let mut expected = footer.to_vec();
+            if varient == 10 {
+                expected[29..37].copy_from_slice(&built[29..37]);
+            }
+            assert_eq!(built.to_vec(), expected, "{}", pak_path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_suspend_resume() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        for (before, after, varient) in [
+            (
+                "test/normal/game_patch_1.32.11.13846.pak",
+                "test/normal/game_patch_1.32.11.13992.pak",
+                10,
+            ),
+            (
+                "test/avatar/onreadypak_405399.pak",
+                "test/avatar/onreadypak_101005004.pak",
+                7,
+            ),
+        ] {
+            for open in [open_pak, open_pak_direct, open_pak_mapped] {
+                std::fs::copy(before, &pak_path)?;
+                let mut pak = open(&pak_path, varient)?;
+                assert_eq!(pak.path(), Some(pak_path.as_path()));
+                let content = pak.extract_entry_to_vec(0)?;
+
+                // Parsed state is kept, reads wait for the resume
+                pak.suspend();
+                assert!(pak.is_suspended());
+                assert!(pak.extract_entry_to_vec(0).is_err());
+                pak.resume()?;
+                assert!(!pak.is_suspended());
+                assert_eq!(pak.extract_entry_to_vec(0)?, content);
+                pak.resume()?;
+
+                // Another pak, then the same one with a byte of its footer
+                // changed
+                let mut changed = std::fs::read(before)?;
+                let last = changed.len() - 1;
+                changed[last] ^= 1;
+                for replacement in [std::fs::read(after)?, changed] {
+                    pak.suspend();
+                    let new_path = temp_dir.path().join("a.pak.new");
+                    std::fs::write(&new_path, replacement)?;
+                    std::fs::rename(&new_path, &pak_path)?;
+                    let error = pak.resume().unwrap_err();
+                    assert!(
+                        matches!(error.root_cause(), PakError::PakChanged { path, .. } if *path == pak_path),
+                        "{}",
+                        error
+                    );
+                    assert!(pak.is_suspended());
+                    std::fs::copy(before, &pak_path)?;
+                    pak.resume()?;
+                    assert_eq!(pak.extract_entry_to_vec(0)?, content);
+                }
+            }
+        }
+
+        // Readers not opened by path keep their source
+        let mut pak = gfp_v7::GfpPakReaderV7::new(std::fs::read(&pak_path)?);
+        assert_eq!(pak.path(), None);
+        pak.suspend();
+        assert!(!pak.is_suspended());
+        pak.resume()?;
+        assert_eq!(
+            pak.entries_count()?,
+            open_pak(&pak_path, 7)?.entries_count()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_by_glob_checked() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let valid_pak = temp_dir.path().join("valid.pak");
+        let garbage_pak = temp_dir.path().join("garbage.pak");
+        std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", &valid_pak)?;
+        std::fs::write(&garbage_pak, b"not a pak")?;
+
+        let pattern = temp_dir.path().join("*.pak").to_string_lossy().to_string();
+        let results: Vec<_> = open_paks_by_glob_checked(&pattern, 10)?.collect();
+        assert_eq!(results.len(), 2);
+
+        for (pak_path, pak) in results {
+            if pak_path == valid_pak {
+                assert_eq!(pak?.declared_version()?, 10);
+            } else {
+                assert_eq!(pak_path, garbage_pak);
+                assert!(pak.is_err());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_detected_paks() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        std::fs::copy(
+            "test/normal/game_patch_1.32.11.13846.pak",
+            temp_dir.path().join("a.pak.bak"),
+        )?;
+        std::fs::copy(
+            "test/avatar/onreadypak_405399.pak",
+            temp_dir.path().join("b"),
+        )?;
+        std::fs::write(temp_dir.path().join("foreign.pak"), [0u8; 4096])?;
+
+        let mut versions = vec![];
+        for (pak_path, pak) in open_paks_in_dir_detected(temp_dir.path()) {
+            versions.push((pak_path, pak?.declared_version()?));
+        }
+        assert_eq!(
+            versions,
+            vec![
+                (temp_dir.path().join("a.pak.bak"), 10),
+                (temp_dir.path().join("b"), 7),
+            ]
+        );
+
+        let pattern = temp_dir.path().join("*.pak").to_string_lossy().to_string();
+        assert_eq!(open_paks_by_glob_detected(&pattern)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_by_glob_case_insensitive() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        std::fs::copy(
+            "test/normal/game_patch_1.32.11.13846.pak",
+            temp_dir.path().join("a.pak"),
+        )?;
+
+        let pattern = temp_dir.path().join("*.PAK").to_string_lossy().to_string();
+        assert_eq!(open_paks_by_glob_checked(&pattern, 10)?.count(), 0);
+
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        let results: Vec<_> = open_paks_by_glob_with(&pattern, 10, options)?.collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, temp_dir.path().join("a.pak"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_by_pattern_max_depth() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        for name in ["a.pak", "sub/b.pak", "sub/deeper/c.pak"] {
+            let pak_path = root.join(name);
+            std::fs::create_dir_all(pak_path.parent().unwrap())?;
+            std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", pak_path)?;
+        }
+
+        let pattern = root.join("**/*.pak").to_string_lossy().to_string();
+        for follow_symlinks in [false, true] {
+            let options = DiscoveryOptions {
+                max_depth: Some(1),
+                follow_symlinks,
+                ..DiscoveryOptions::default()
+            };
+            let paths: Vec<_> = open_paks_by_pattern(&pattern, 10, options.clone())?
+                .map(|(pak_path, _)| pak_path)
+                .collect();
+            assert_eq!(paths, vec![root.join("a.pak")]);
+
+            let options = DiscoveryOptions {
+                max_depth: Some(2),
+                ..options
+            };
+            let paths: Vec<_> = open_paks_by_pattern(&pattern, 10, options)?
+                .map(|(pak_path, _)| pak_path)
+                .collect();
+            assert_eq!(paths, vec![root.join("a.pak"), root.join("sub/b.pak")]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_sorted() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        for name in ["z.pak", "m/c.pak", "a/b.pak"] {
+            let pak_path = root.join(name);
+            std::fs::create_dir_all(pak_path.parent().unwrap())?;
+            std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", pak_path)?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("z.pak"), root.join("zz.pak"))?;
+
+        let sorted = vec![
+            root.join("a/b.pak"),
+            root.join("m/c.pak"),
+            root.join("z.pak"),
+        ];
+        let paths_of = |paks: &mut dyn Iterator<Item = (PathBuf, _)>| -> Vec<PathBuf> {
+            paks.map(|(pak_path, _)| pak_path).collect()
+        };
+
+        let pattern = root.join("**/*.pak").to_string_lossy().to_string();
+        let options = DiscoveryOptions::default();
+        assert_eq!(
+            paths_of(&mut open_paks_by_pattern(&pattern, 10, options.clone())?),
+            sorted
+        );
+        assert_eq!(
+            paths_of(&mut open_paks_in_dir(root, 10, options.clone())),
+            sorted
+        );
+
+        // Files of a directory come before its subdirectories in walk order
+        let options = DiscoveryOptions {
+            sort: false,
+            ..options
+        };
+        let mut unsorted = vec![root.join("z.pak")];
+        #[cfg(unix)]
+        unsorted.push(root.join("zz.pak"));
+        unsorted.extend([root.join("a/b.pak"), root.join("m/c.pak")]);
+        assert_eq!(paths_of(&mut open_paks_in_dir(root, 10, options)), unsorted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_paks_by_glob_is_lazy() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        for name in ["a.pak", "b.pak", "c.pak"] {
+            std::fs::copy(
+                "test/normal/game_patch_1.32.11.13846.pak",
+                temp_dir.path().join(name),
+            )?;
+        }
+
+        let pattern = temp_dir.path().join("*.pak").to_string_lossy().to_string();
+        let mut opened = 0;
+        let paks = open_paks_by_glob_using(&pattern, DiscoveryOptions::default(), |pak_path| {
+            opened += 1;
+            open_pak(pak_path, 10)
+        })?;
+
+        assert_eq!(paks.take(1).count(), 1);
+        assert_eq!(opened, 1);
+        Ok(())
     }
 }
@@ -0,0 +1,93 @@
+use crate::error::PakError;
+use crate::pak_reader::{EntryPayload, PakReader};
+use std::collections::BTreeMap;
+
+/// A piece of payload used by more than one entry, see
+/// [`BlockMapReport::shared`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedPiece {
+    pub offset: u64,
+    pub length: u64,
+    /// The entries using the piece, sorted.
+    pub entry_ids: Vec<u64>,
+}
+
+/// How the payloads of the entries of a pak sit in it, see
+/// [`check_block_map`].
+///
+/// Nothing in the format requires the blocks of an entry to be in ascending
+/// order or to belong to a single entry, and the readers don't rely on
+/// either, so only [`Self::overlapping`] points at a broken pak.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockMapReport {
+    /// Entries whose blocks aren't in ascending order of offset.
+    pub out_of_order: Vec<u64>,
+    /// Blocks or stored payloads used by several entries, as packers that
+    /// deduplicate content write them. Each entry extracts them on its own.
+    pub shared: Vec<SharedPiece>,
+    /// Pairs of entries with pieces that overlap without being the same
+    /// piece, which no packer writes on purpose.
+    pub overlapping: Vec<(u64, u64)>,
+}
+
+impl BlockMapReport {
+    /// Whether the payloads are laid out one after the other, each used by
+    /// a single entry.
+    pub fn is_plain(&self) -> bool {
+        self.out_of_order.is_empty() && self.shared.is_empty() && self.overlapping.is_empty()
+    }
+}
+
+/// Look at where the payload of every entry of `pak` is, from the index
+/// alone.
+pub fn check_block_map<P: PakReader + ?Sized>(pak: &mut P) -> Result<BlockMapReport, PakError> {
+    let mut report = BlockMapReport::default();
+    // Entries using each piece, by offset and length
+    let mut pieces: BTreeMap<(u64, u64), Vec<u64>> = BTreeMap::new();
+    for entry_id in 0..pak.entries_count()? {
+        let ranges = match pak.entry_layout(entry_id)?.payload {
+            EntryPayload::Stored { offset, length } => vec![(offset, length)],
+            EntryPayload::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| (block.offset, block.compressed_length))
+                .collect(),
+        };
+        if ranges.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+            report.out_of_order.push(entry_id);
+        }
+        for range in ranges {
+            if range.1 > 0 {
+                let entry_ids = pieces.entry(range).or_default();
+                if entry_ids.last() != Some(&entry_id) {
+                    entry_ids.push(entry_id);
+                }
+            }
+        }
+    }
+
+    // The piece reaching furthest so far, and its first entry
+    let mut furthest: Option<(u64, u64)> = None;
+    for (&(offset, length), entry_ids) in &pieces {
+        if entry_ids.len() > 1 {
+            report.shared.push(SharedPiece {
+                offset,
+                length,
+                entry_ids: entry_ids.clone(),
+            });
+        }
+        let end = offset.saturating_add(length);
+        match furthest {
+            Some((furthest_end, furthest_entry)) if offset < furthest_end => {
+                let pair = (furthest_entry, entry_ids[0]);
+                if !report.overlapping.contains(&pair) {
+                    report.overlapping.push(pair);
+                }
+                if end > furthest_end {
+                    furthest = Some((end, entry_ids[0]));
+                }
+            }
+            _ => furthest = Some((end, entry_ids[0])),
+        }
+    }
+    Ok(report)
+}
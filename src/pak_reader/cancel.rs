@@ -0,0 +1,48 @@
+use crate::error::PakError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A flag shared between an extraction and whoever may want to stop it, e.g.
+/// a Cancel button or a Ctrl-C handler.
+///
+/// Clones share the same flag. Readers check it between entries and between
+/// compressed blocks and stop with [`PakError::Cancelled`] once it's tripped.
+///
+/// ```rust
+/// use gfp::error::PakError;
+/// use gfp::pak_reader::cancel::CancellationToken;
+///
+/// let cancel = CancellationToken::new();
+/// let handle = cancel.clone();
+/// assert!(cancel.check().is_ok());
+///
+/// handle.cancel();
+/// assert!(cancel.is_cancelled());
+/// assert!(matches!(cancel.check(), Err(PakError::Cancelled)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every holder of the token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// `Err(PakError::Cancelled)` once the token is tripped.
+    pub fn check(&self) -> Result<(), PakError> {
+        if self.is_cancelled() {
+            Err(PakError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use crate::utils::hex;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Computed entry hashes of one pak, kept in a `.gfpsum` file next to it.
+///
+/// Hashing a whole pak means reading all of it, so the hashes are saved and
+/// reused as long as the pak's modification time stays the same. The sidecar
+/// sits beside the pak, so it's keyed by the pak path, then by its mtime, then
+/// by entry id:
+///
+/// ```text
+/// gfpsum 1 <mtime seconds> <mtime nanoseconds>
+/// <entry id> <sha1 hex>
+/// ...
+/// ```
+///
+/// A sidecar that is missing, unreadable or written for another mtime is
+/// treated as empty and replaced on [`Self::save`].
+pub struct ChecksumCache {
+    sidecar_path: PathBuf,
+    mtime: Duration,
+    hashes: BTreeMap<u64, [u8; 20]>,
+    is_dirty: bool,
+    hits: u64,
+    misses: u64,
+}
+
+impl ChecksumCache {
+    pub const EXTENSION: &'static str = "gfpsum";
+    const HEADER: &'static str = "gfpsum 1";
+
+    /// Path of the sidecar file for `pak_path`, e.g. `a.pak.gfpsum`.
+    pub fn sidecar_path<P: AsRef<Path>>(pak_path: P) -> PathBuf {
+        let mut sidecar_path = pak_path.as_ref().as_os_str().to_os_string();
+        sidecar_path.push(".");
+        sidecar_path.push(Self::EXTENSION);
+        PathBuf::from(sidecar_path)
+    }
+
+    /// Load the cached hashes of the pak at `pak_path`.
+    ///
+    /// Fails only if the pak itself can't be accessed.
+    pub fn load<P: AsRef<Path>>(pak_path: P) -> std::io::Result<Self> {
+        let mtime = std::fs::metadata(&pak_path)?
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let sidecar_path = Self::sidecar_path(&pak_path);
+        let hashes = std::fs::read_to_string(&sidecar_path)
+            .ok()
+            .and_then(|content| Self::parse(&content, mtime));
+
+        Ok(Self {
+            sidecar_path,
+            mtime,
+            is_dirty: hashes.is_none(),
+            hashes: hashes.unwrap_or_default(),
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    fn parse(content: &str, mtime: Duration) -> Option<BTreeMap<u64, [u8; 20]>> {
+        let mut lines = content.lines();
+        let header = format!(
+            "{} {} {}",
+            Self::HEADER,
+            mtime.as_secs(),
+            mtime.subsec_nanos()
+        );
+        if lines.next()? != header {
+            return None;
+        }
+
+        let mut hashes = BTreeMap::new();
+        for line in lines {
+            let (entry_id, hash) = line.split_once(' ')?;
+            let bytes = hex::decode(hash).ok()?.try_into().ok()?;
+            hashes.insert(entry_id.parse().ok()?, bytes);
+        }
+        Some(hashes)
+    }
+
+    /// The computed hash of an entry, taken from the cache if present and
+    /// computed with [`PakReader::compute_entry_hash`] otherwise.
+    pub fn entry_hash(
+        &mut self,
+        pak: &mut dyn PakReader,
+        entry_id: u64,
+    ) -> Result<[u8; 20], PakError> {
+        if let Some(hash) = self.hashes.get(&entry_id) {
+            self.hits += 1;
+            return Ok(*hash);
+        }
+
+        let hash = pak.compute_entry_hash(entry_id)?;
+        self.misses += 1;
+        self.hashes.insert(entry_id, hash);
+        self.is_dirty = true;
+        Ok(hash)
+    }
+
+    /// [`PakReaderExt::verify_entry`](crate::pak_reader::ext::PakReaderExt::verify_entry)
+    /// using the cached hash when possible.
+    pub fn verify_entry(
+        &mut self,
+        pak: &mut dyn PakReader,
+        entry_id: u64,
+    ) -> Result<bool, PakError> {
+        Ok(self.entry_hash(pak, entry_id)? == pak.entry_hash(entry_id)?)
+    }
+
+    /// Number of hashes served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of hashes that had to be computed.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Write the sidecar file if anything changed since it was loaded.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if !self.is_dirty {
+            return Ok(());
+        }
+
+        let mut content = format!(
+            "{} {} {}\n",
+            Self::HEADER,
+            self.mtime.as_secs(),
+            self.mtime.subsec_nanos()
+        );
+        for (entry_id, hash) in &self.hashes {
+            content.push_str(&format!("{} {}\n", entry_id, hex::encode(hash)));
+        }
+        std::fs::File::create(&self.sidecar_path)?.write_all(content.as_bytes())?;
+        self.is_dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::open_pak;
+    use tempfile::TempDir;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+
+    fn hash_all(cache: &mut ChecksumCache, pak_path: &Path) -> Result<Vec<[u8; 20]>, PakError> {
+        let mut pak = open_pak(pak_path, 10)?;
+        (0..pak.entries_count()?)
+            .map(|entry_id| cache.entry_hash(pak.as_mut(), entry_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_cache_hit_skips_recomputation() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        std::fs::copy(PAK_1, &pak_path)?;
+
+        let mut cache = ChecksumCache::load(&pak_path)?;
+        let computed = hash_all(&mut cache, &pak_path)?;
+        assert_eq!(cache.misses(), computed.len() as u64);
+        assert_eq!(cache.hits(), 0);
+        cache.save()?;
+        assert!(ChecksumCache::sidecar_path(&pak_path).is_file());
+
+        let mut cache = ChecksumCache::load(&pak_path)?;
+        let cached = hash_all(&mut cache, &pak_path)?;
+        assert_eq!(cached, computed);
+        assert_eq!(cache.hits(), computed.len() as u64);
+        assert_eq!(cache.misses(), 0);
+
+        let mut pak = open_pak(&pak_path, 10)?;
+        for entry_id in 0..pak.entries_count()? {
+            assert!(cache.verify_entry(pak.as_mut(), entry_id)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_invalidated_by_mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        std::fs::copy(PAK_1, &pak_path)?;
+
+        let mut cache = ChecksumCache::load(&pak_path)?;
+        hash_all(&mut cache, &pak_path)?;
+        cache.save()?;
+
+        std::fs::File::options()
+            .write(true)
+            .open(&pak_path)?
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1))?;
+
+        let mut cache = ChecksumCache::load(&pak_path)?;
+        let computed = hash_all(&mut cache, &pak_path)?;
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.misses(), computed.len() as u64);
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::pak_reader::{gfp_v7, gfp_v10};
+use crate::utils::read_at::ReadAt;
+use std::io;
+#[cfg(feature = "std-fs")]
+use std::path::Path;
+
+/// The pak variant recognized from a footer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectedVariant {
+    V7,
+    V10,
+}
+
+impl DetectedVariant {
+    /// The `varient` number accepted by [`super::implements::new_pak`] and
+    /// [`super::implements::open_pak`].
+    pub fn varient(self) -> i32 {
+        match self {
+            Self::V7 => 7,
+            Self::V10 => 10,
+        }
+    }
+}
+
+/// Recognize a pak by its footer alone, whatever its file name.
+///
+/// Only the last 45 bytes are read. They are deobfuscated under both the v10
+/// and the v7 scheme and accepted if the encrypted flag, the version and the
+/// index location all make sense, so paks of other games (including vanilla
+/// Unreal Engine paks) are rejected.
+pub fn detect_variant<R: ReadAt + ?Sized>(source: &R) -> io::Result<Option<DetectedVariant>> {
+    const _: () = assert!(gfp_v7::FOOTER_SIZE == gfp_v10::FOOTER_SIZE);
+
+    let Some(info_offset) = source.size()?.checked_sub(gfp_v10::FOOTER_SIZE as u64) else {
+        return Ok(None);
+    };
+    let mut footer = [0u8; gfp_v10::FOOTER_SIZE];
+    if source.read_at(&mut footer, info_offset)? != footer.len() {
+        return Ok(None);
+    }
+
+    Ok(if gfp_v10::is_plausible_footer(footer, info_offset) {
+        Some(DetectedVariant::V10)
+    } else if gfp_v7::is_plausible_footer(footer, info_offset) {
+        Some(DetectedVariant::V7)
+    } else {
+        None
+    })
+}
+
+/// [`detect_variant`] for the file at `path`.
+///
+/// Directories and files too small to hold a footer are not paks.
+#[cfg(feature = "std-fs")]
+pub fn is_gfp_pak<P: AsRef<Path>>(path: P) -> io::Result<Option<DetectedVariant>> {
+    let file = crate::utils::shared_read::open_shared_read(path)?;
+    if !file.metadata()?.is_file() {
+        return Ok(None);
+    }
+    detect_variant(&file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_detect_test_paks() -> Result<(), Box<dyn std::error::Error>> {
+        for pak_path in [
+            "test/normal/game_patch_1.32.11.13846.pak",
+            "test/normal/game_patch_1.32.11.13992.pak",
+        ] {
+            assert_eq!(is_gfp_pak(pak_path)?, Some(DetectedVariant::V10));
+        }
+        for pak_path in [
+            "test/avatar/onreadypak_405399.pak",
+            "test/avatar/onreadypak_101005004.pak",
+        ] {
+            assert_eq!(is_gfp_pak(pak_path)?, Some(DetectedVariant::V7));
+        }
+        assert_eq!(is_gfp_pak("test")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_vanilla_ue_pak() -> Result<(), Box<dyn std::error::Error>> {
+        // FPakInfo of a version 3 pak: magic, version, index offset, index size, index hash
+        let mut data = vec![0u8; 1024];
+        data.extend(0x5A6F12E1u32.to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend(512u64.to_le_bytes());
+        data.extend(256u64.to_le_bytes());
+        data.extend([0xAB; 20]);
+        assert_eq!(detect_variant(data.as_slice())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_random_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        for size in [0, 10, 44, 45, 46, 4096, 65536] {
+            let data: Vec<u8> = (0..size)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            assert_eq!(detect_variant(data.as_slice())?, None);
+        }
+        Ok(())
+    }
+}
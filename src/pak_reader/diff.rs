@@ -0,0 +1,301 @@
+use crate::error::PakError;
+use crate::pak_reader::{EntryLayout, EntryPayload, PakReader};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// How [`diff_paks`] compares the entries found on both sides.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Compare [`Fingerprint`]s instead of hashes, which only needs the
+    /// index. This is a heuristic: an entry rewritten with the same sizes
+    /// passes for unchanged.
+    pub quick: bool,
+    /// With [`Self::quick`], hash the payloads of the entries whose
+    /// fingerprints differ, so no entry is reported changed by the heuristic
+    /// alone.
+    pub confirm: bool,
+}
+
+/// The outcome for a path in a [`diff_paks`] report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+/// What a [`Change`] is based on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffBasis {
+    /// The path is only on one side.
+    Path,
+    /// The hash recorded in the index, or the hash of the payload as stored
+    /// when the recorded one is zeroed or when confirming a fingerprint.
+    Hash,
+    /// A [`Fingerprint`], which is a heuristic.
+    Fingerprint,
+}
+
+impl DiffBasis {
+    pub fn name(self) -> &'static str {
+        match self {
+            DiffBasis::Path => "path",
+            DiffBasis::Hash => "hash",
+            DiffBasis::Fingerprint => "fingerprint",
+        }
+    }
+}
+
+/// What an entry looks like in the index, compared by
+/// [`DiffOptions::quick`] diffs: its sizes and how its payload is split into
+/// blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub file_size: u64,
+    pub compressed_length: u64,
+    /// 0 for entries stored without compression.
+    pub num_of_blocks: usize,
+    /// Compressed length of the first block, or of the payload of a stored
+    /// entry.
+    pub first_block_length: u64,
+}
+
+impl Fingerprint {
+    pub fn of(layout: &EntryLayout) -> Self {
+        let (num_of_blocks, first_block_length) = match &layout.payload {
+            EntryPayload::Stored { length, .. } => (0, *length),
+            EntryPayload::Blocks(blocks) => (
+                blocks.len(),
+                blocks.first().map_or(0, |block| block.compressed_length),
+            ),
+        };
+        Self {
+            file_size: layout.file_size,
+            compressed_length: layout.stored_size(),
+            num_of_blocks,
+            first_block_length,
+        }
+    }
+}
+
+/// A path in a [`diff_paks`] report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryDiff {
+    pub path: String,
+    pub change: Change,
+    pub basis: DiffBasis,
+}
+
+/// Compare the entries of the `old` paks with those of the `new` ones by
+/// path.
+///
+/// On each side, a path in several paks is taken from the last of them, the
+/// way a merged extraction would overwrite it. Entries on both sides are
+/// compared by hash, or by [`Fingerprint`] for [`DiffOptions::quick`]
+/// diffs. The report lists every path, sorted, with what each verdict is
+/// based on.
+pub fn diff_paks(
+    old: &mut [(PathBuf, Box<dyn PakReader>)],
+    new: &mut [(PathBuf, Box<dyn PakReader>)],
+    options: &DiffOptions,
+) -> Result<Vec<EntryDiff>, PakError> {
+    let old_entries = entries_by_path(old)?;
+    let new_entries = entries_by_path(new)?;
+    let paths: BTreeSet<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+
+    let mut report = Vec::with_capacity(paths.len());
+    for path in paths {
+        let (change, basis) = match (old_entries.get(path), new_entries.get(path)) {
+            (Some(_), None) => (Change::Removed, DiffBasis::Path),
+            (None, Some(_)) => (Change::Added, DiffBasis::Path),
+            (Some(&(old_pak, old_id)), Some(&(new_pak, new_id))) => {
+                let old = old[old_pak].1.as_mut();
+                let new = new[new_pak].1.as_mut();
+                compare(old, old_id, new, new_id, options)
+                    .map_err(|e| e.context(format!("comparing {}", path)))?
+            }
+            (None, None) => unreachable!(),
+        };
+        report.push(EntryDiff {
+            path: path.clone(),
+            change,
+            basis,
+        });
+    }
+    Ok(report)
+}
+
+/// Where the entry of each path is: the index of its pak and its id there
+fn entries_by_path(
+    paks: &mut [(PathBuf, Box<dyn PakReader>)],
+) -> Result<BTreeMap<String, (usize, u64)>, PakError> {
+    let mut entries = BTreeMap::new();
+    for (index, (pak_path, pak)) in paks.iter_mut().enumerate() {
+        let context = |e: PakError| e.context(format!("reading {}", pak_path.display()));
+        for entry_id in 0..pak.entries_count().map_err(context)? {
+            entries.insert(
+                pak.get_entry_path(entry_id).map_err(context)?,
+                (index, entry_id),
+            );
+        }
+    }
+    Ok(entries)
+}
+
+fn compare(
+    old: &mut dyn PakReader,
+    old_id: u64,
+    new: &mut dyn PakReader,
+    new_id: u64,
+    options: &DiffOptions,
+) -> Result<(Change, DiffBasis), PakError> {
+    let verdict = |same| {
+        if same {
+            Change::Unchanged
+        } else {
+            Change::Changed
+        }
+    };
+    if !options.quick {
+        let same = content_hash(old, old_id)? == content_hash(new, new_id)?;
+        return Ok((verdict(same), DiffBasis::Hash));
+    }
+
+    let same =
+        Fingerprint::of(&old.entry_layout(old_id)?) == Fingerprint::of(&new.entry_layout(new_id)?);
+    if !same && options.confirm {
+        let same = old.compute_entry_hash(old_id)? == new.compute_entry_hash(new_id)?;
+        return Ok((verdict(same), DiffBasis::Hash));
+    }
+    Ok((verdict(same), DiffBasis::Fingerprint))
+}
+
+/// The recorded hash of an entry, or the hash of its payload if it's zeroed
+fn content_hash(pak: &mut dyn PakReader, entry_id: u64) -> Result<[u8; 20], PakError> {
+    let hash = pak.entry_hash(entry_id)?;
+    if hash == [0; 20] {
+        pak.compute_entry_hash(entry_id)
+    } else {
+        Ok(hash)
+    }
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::{OpenedPak, new_pak, open_pak};
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+    const PAK_2: &str = "test/normal/game_patch_1.32.11.13992.pak";
+
+    fn opened(pak_path: &str) -> Result<Vec<OpenedPak>, PakError> {
+        Ok(vec![(PathBuf::from(pak_path), open_pak(pak_path, 10)?)])
+    }
+
+    #[test]
+    fn test_diff_paks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut old = opened(PAK_1)?;
+        let mut new = opened(PAK_2)?;
+        let old_count = old[0].1.entries_count()?;
+        let new_count = new[0].1.entries_count()?;
+
+        let full = diff_paks(&mut old, &mut new, &DiffOptions::default())?;
+        let count = |change| full.iter().filter(|diff| diff.change == change).count() as u64;
+        assert_eq!(
+            count(Change::Removed) + count(Change::Changed),
+            old_count - count(Change::Unchanged)
+        );
+        assert_eq!(
+            count(Change::Added) + count(Change::Changed),
+            new_count - count(Change::Unchanged)
+        );
+        assert!(full.windows(2).all(|pair| pair[0].path < pair[1].path));
+        for diff in &full {
+            let expected = match diff.change {
+                Change::Added | Change::Removed => DiffBasis::Path,
+                _ => DiffBasis::Hash,
+            };
+            assert_eq!(diff.basis, expected, "{:?}", diff);
+        }
+
+        // The same pak is unchanged by every measure
+        for options in [
+            DiffOptions::default(),
+            DiffOptions {
+                quick: true,
+                confirm: false,
+            },
+            DiffOptions {
+                quick: true,
+                confirm: true,
+            },
+        ] {
+            let report = diff_paks(&mut opened(PAK_1)?, &mut opened(PAK_1)?, &options)?;
+            assert_eq!(report.len() as u64, old_count);
+            assert!(report.iter().all(|diff| diff.change == Change::Unchanged));
+        }
+
+        let quick = diff_paks(
+            &mut old,
+            &mut new,
+            &DiffOptions {
+                quick: true,
+                confirm: false,
+            },
+        )?;
+        let confirmed = diff_paks(
+            &mut old,
+            &mut new,
+            &DiffOptions {
+                quick: true,
+                confirm: true,
+            },
+        )?;
+        for ((full, quick), confirmed) in full.iter().zip(&quick).zip(&confirmed) {
+            assert_eq!(full.path, quick.path);
+            if quick.change == Change::Changed {
+                assert_eq!(quick.basis, DiffBasis::Fingerprint);
+                assert_eq!(confirmed.basis, DiffBasis::Hash);
+                assert_eq!(confirmed.change, full.change);
+            } else {
+                assert_eq!(confirmed, quick);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_quick_diff_ignores_hashes() -> Result<(), Box<dyn std::error::Error>> {
+        // Zeroing the recorded hashes leaves the fingerprints alone
+        let mut data = std::fs::read(PAK_1)?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let index_offset = pak.index_stats()?.index_offset as usize;
+        let index = data[index_offset..].to_vec();
+        for entry_id in 0..pak.entries_count()? {
+            let hash = pak.entry_hash(entry_id)?;
+            let position = index_offset + find(&index, &hash).unwrap();
+            data[position..position + 20].fill(0);
+        }
+        let mut zeroed = vec![(PathBuf::from("zeroed.pak"), new_pak(data, 10)?)];
+        assert_eq!(zeroed[0].1.entry_hash(0)?, [0; 20]);
+
+        for options in [
+            DiffOptions::default(),
+            DiffOptions {
+                quick: true,
+                confirm: false,
+            },
+        ] {
+            let report = diff_paks(&mut opened(PAK_1)?, &mut zeroed, &options)?;
+            assert!(report.iter().all(|diff| diff.change == Change::Unchanged));
+        }
+        Ok(())
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+}
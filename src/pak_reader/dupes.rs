@@ -0,0 +1,275 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// What entries with the same content share, see [`DupeKey::of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DupeKey {
+    /// The hash recorded for the entry in the index.
+    Hash([u8; 20]),
+    /// For entries whose recorded hash is zeroed: the size of the entry once
+    /// decompressed and the SHA-1 of its first [`DupeKey::SAMPLE_SIZE`]
+    /// decompressed bytes.
+    Sample { file_size: u64, hash: [u8; 20] },
+}
+
+impl DupeKey {
+    pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+    /// The key of an entry. Only entries without a recorded hash are read,
+    /// and only up to the first compressed block covering the sample.
+    pub fn of<P: PakReader + ?Sized>(pak: &mut P, entry_id: u64) -> Result<Self, PakError> {
+        let hash = pak.entry_hash(entry_id)?;
+        if hash != [0; 20] {
+            return Ok(Self::Hash(hash));
+        }
+
+        let mut sample = Sampler {
+            data: Vec::with_capacity(Self::SAMPLE_SIZE),
+        };
+        match pak.extract_entry_to_writer(entry_id, &mut sample) {
+            Ok(()) => {}
+            // The sampler refuses anything past the sample
+            Err(PakError::Io(_)) if sample.is_full() => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self::Sample {
+            file_size: pak.entry_layout(entry_id)?.file_size,
+            hash: sha1_smol::Sha1::from(&sample.data).digest().bytes(),
+        })
+    }
+}
+
+/// Keeps the first [`DupeKey::SAMPLE_SIZE`] bytes written to it and fails
+/// afterwards, which stops the extraction.
+struct Sampler {
+    data: Vec<u8>,
+}
+
+impl Sampler {
+    fn is_full(&self) -> bool {
+        self.data.len() == DupeKey::SAMPLE_SIZE
+    }
+}
+
+impl Write for Sampler {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.is_full() {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        let length = buf.len().min(DupeKey::SAMPLE_SIZE - self.data.len());
+        self.data.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An entry in a [`DupeGroup`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DupeMember {
+    pub pak_path: PathBuf,
+    pub entry_id: u64,
+    pub path: String,
+}
+
+/// Entries sharing a [`DupeKey`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DupeGroup {
+    pub key: DupeKey,
+    /// Bytes each member takes in its pak, as stored.
+    pub stored_size: u64,
+    pub members: Vec<DupeMember>,
+}
+
+impl DupeGroup {
+    /// Bytes taken by every member but one.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.stored_size * (self.members.len() as u64).saturating_sub(1)
+    }
+
+    /// Whether the members come from more than one pak.
+    pub fn spans_paks(&self) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.pak_path != self.members[0].pak_path)
+    }
+}
+
+/// What [`analyze_duplicates`] finds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DupeReport {
+    /// Groups of more than one member, the most wasteful first.
+    pub groups: Vec<DupeGroup>,
+}
+
+impl DupeReport {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.groups.iter().map(DupeGroup::wasted_bytes).sum()
+    }
+
+    /// Leave out the groups whose members all come from one pak.
+    pub fn across_paks_only(mut self) -> Self {
+        self.groups.retain(DupeGroup::spans_paks);
+        self
+    }
+}
+
+/// Collects the entries of paks one at a time into a [`DupeReport`], so the
+/// caller decides what to do about a pak that fails to read.
+#[derive(Debug, Default)]
+pub struct DupeAnalyzer {
+    groups: HashMap<DupeKey, DupeGroup>,
+}
+
+impl DupeAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Group every entry of `pak`. Nothing is added if reading fails.
+    pub fn add_pak<P: PakReader + ?Sized>(
+        &mut self,
+        pak_path: &Path,
+        pak: &mut P,
+    ) -> Result<(), PakError> {
+        let mut entries = vec![];
+        for entry_id in 0..pak.entries_count()? {
+            entries.push((
+                DupeKey::of(pak, entry_id)?,
+                pak.entry_layout(entry_id)?.stored_size(),
+                DupeMember {
+                    pak_path: pak_path.to_path_buf(),
+                    entry_id,
+                    path: pak.get_entry_path(entry_id)?,
+                },
+            ));
+        }
+
+        for (key, stored_size, member) in entries {
+            self.groups
+                .entry(key)
+                .or_insert_with(|| DupeGroup {
+                    key,
+                    stored_size,
+                    members: vec![],
+                })
+                .members
+                .push(member);
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> DupeReport {
+        let mut groups: Vec<DupeGroup> = self
+            .groups
+            .into_values()
+            .filter(|group| group.members.len() > 1)
+            .collect();
+        for group in &mut groups {
+            group.members.sort();
+        }
+        groups.sort_by(|a, b| {
+            b.wasted_bytes()
+                .cmp(&a.wasted_bytes())
+                .then_with(|| a.members.cmp(&b.members))
+        });
+        DupeReport { groups }
+    }
+}
+
+/// Group the entries of `paks` by content.
+///
+/// Entries are compared by the hash recorded in the index, falling back to
+/// their size and a hash of their first bytes when the recorded hash is
+/// zeroed, see [`DupeKey`].
+pub fn analyze_duplicates<'a, P: PakReader + ?Sized + 'a>(
+    paks: impl IntoIterator<Item = (&'a Path, &'a mut P)>,
+) -> Result<DupeReport, PakError> {
+    let mut analyzer = DupeAnalyzer::new();
+    for (pak_path, pak) in paks {
+        analyzer.add_pak(pak_path, pak)?;
+    }
+    Ok(analyzer.finish())
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::open_pak;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+
+    #[test]
+    fn test_analyze_duplicates() -> Result<(), Box<dyn std::error::Error>> {
+        // The same pak twice, under two names
+        let mut pak_1 = open_pak(PAK_1, 10)?;
+        let mut pak_2 = open_pak(PAK_1, 10)?;
+        let entries_count = pak_1.entries_count()?;
+        let report = analyze_duplicates([
+            (Path::new("a.pak"), pak_1.as_mut()),
+            (Path::new("b.pak"), pak_2.as_mut()),
+        ])?;
+
+        assert_eq!(report.groups.len() as u64, entries_count);
+        for group in &report.groups {
+            assert_eq!(group.members.len(), 2);
+            assert!(group.spans_paks());
+            assert_eq!(group.wasted_bytes(), group.stored_size);
+        }
+        assert!(
+            report
+                .groups
+                .windows(2)
+                .all(|pair| pair[0].wasted_bytes() >= pair[1].wasted_bytes())
+        );
+
+        let mut pak = open_pak(PAK_1, 10)?;
+        let stored_size: u64 = (0..entries_count)
+            .map(|entry_id| Ok(pak.entry_layout(entry_id)?.stored_size()))
+            .sum::<Result<_, PakError>>()?;
+        assert_eq!(report.wasted_bytes(), stored_size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_across_paks_only() {
+        let member = |pak_path: &str, entry_id| DupeMember {
+            pak_path: PathBuf::from(pak_path),
+            entry_id,
+            path: format!("{}", entry_id),
+        };
+        let report = DupeReport {
+            groups: vec![
+                DupeGroup {
+                    key: DupeKey::Hash([1; 20]),
+                    stored_size: 10,
+                    members: vec![member("a.pak", 0), member("a.pak", 1)],
+                },
+                DupeGroup {
+                    key: DupeKey::Hash([2; 20]),
+                    stored_size: 5,
+                    members: vec![member("a.pak", 2), member("b.pak", 0)],
+                },
+            ],
+        };
+        assert_eq!(report.wasted_bytes(), 15);
+
+        let report = report.across_paks_only();
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].key, DupeKey::Hash([2; 20]));
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut sampler = Sampler { data: vec![] };
+        let data = vec![7u8; DupeKey::SAMPLE_SIZE + 10];
+        assert!(sampler.write_all(&data).is_err());
+        assert!(sampler.is_full());
+        assert_eq!(sampler.data, data[..DupeKey::SAMPLE_SIZE]);
+    }
+}
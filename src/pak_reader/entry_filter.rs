@@ -0,0 +1,190 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Which entries a selective operation works on, by entry path.
+///
+/// Filters compose with [`Self::And`], [`Self::Or`] and [`Self::Not`], so a
+/// selection such as "the entries in this list, except those in that one"
+/// is a single value:
+///
+/// ```rust
+/// use gfp::pak_reader::entry_filter::EntryFilter;
+///
+/// let include = EntryFilter::from_list("# Lua and a texture\nre:\\.lua$\nUI/HUD/foo.png\n")?;
+/// let exclude = EntryFilter::from_list("*/logic/*")?;
+/// let filter = EntryFilter::And(vec![include, EntryFilter::Not(Box::new(exclude))]);
+/// assert!(filter.matches("Lua/common/lua_object.lua"));
+/// assert!(filter.matches("UI/HUD/foo.png"));
+/// assert!(!filter.matches("Lua/client/logic/logic_pakversion.lua"));
+/// assert!(!filter.matches("UI/HUD/bar.png"));
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub enum EntryFilter {
+    /// Every entry.
+    #[default]
+    All,
+    /// Paths matching a glob pattern. `*` also matches `/`.
+    Glob(glob::Pattern),
+    /// Paths in which the regex finds a match; anchor it with `^` and `$` to
+    /// match whole paths.
+    Regex(regex::Regex),
+    /// These exact paths.
+    Set(HashSet<String>),
+    /// Paths matching every filter, so an empty `And` matches everything.
+    And(Vec<EntryFilter>),
+    /// Paths matching any of the filters, so an empty `Or` matches nothing.
+    Or(Vec<EntryFilter>),
+    Not(Box<EntryFilter>),
+}
+
+impl EntryFilter {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Glob(pattern) => pattern.matches(path),
+            Self::Regex(regex) => regex.is_match(path),
+            Self::Set(paths) => paths.contains(path),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(path)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(path)),
+            Self::Not(filter) => !filter.matches(path),
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        matches!(self, Self::All)
+    }
+
+    /// The filter of a single list line: a regex after a `re:` prefix, a
+    /// glob pattern if the line has any of `*?[`, or else a literal path.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::entry_filter::EntryFilter;
+    ///
+    /// assert!(matches!(EntryFilter::parse_line("re:^UI/")?, EntryFilter::Regex(_)));
+    /// assert!(matches!(EntryFilter::parse_line("UI/**/*.png")?, EntryFilter::Glob(_)));
+    /// assert!(matches!(EntryFilter::parse_line("UI/HUD/foo.png")?, EntryFilter::Set(_)));
+    /// assert!(EntryFilter::parse_line("re:(").is_err());
+    /// # Ok::<(), gfp::error::PakError>(())
+    /// ```
+    pub fn parse_line(line: &str) -> Result<Self, PakError> {
+        if let Some(regex) = line.strip_prefix("re:") {
+            return regex::Regex::new(regex)
+                .map(Self::Regex)
+                .map_err(|e| PakError::invalid_data(format!("Invalid regex '{}': {}", regex, e)));
+        }
+        if line.contains(['*', '?', '[']) {
+            return glob::Pattern::new(line)
+                .map(Self::Glob)
+                .map_err(|e| PakError::invalid_data(format!("Invalid pattern '{}': {}", line, e)));
+        }
+        Ok(Self::Set(HashSet::from([line.to_string()])))
+    }
+
+    /// The paths matching any line of `list`, as [`Self::parse_line`] reads
+    /// them. Blank lines and lines starting with `#` are skipped, and the
+    /// literal paths are gathered in a single [`Self::Set`].
+    pub fn from_list(list: &str) -> Result<Self, PakError> {
+        let mut paths = HashSet::new();
+        let mut filters = vec![];
+        for (index, line) in list.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Self::parse_line(line).map_err(|e| e.context(format!("line {}", index + 1)))? {
+                Self::Set(set) => paths.extend(set),
+                filter => filters.push(filter),
+            }
+        }
+        if !paths.is_empty() {
+            filters.push(Self::Set(paths));
+        }
+        Ok(Self::Or(filters))
+    }
+
+    /// [`Self::from_list`] of the file at `path`.
+    pub fn from_list_file<P: AsRef<Path>>(path: P) -> Result<Self, PakError> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(PakError::from)
+            .and_then(|list| Self::from_list(&list))
+            .map_err(|e| e.context(format!("reading {}", path.display())))
+    }
+
+    /// Ids of the entries of `pak` whose path matches, in id order.
+    pub fn select<P: PakReader + ?Sized>(&self, pak: &mut P) -> Result<Vec<u64>, PakError> {
+        let entries_count = pak.entries_count()?;
+        if self.is_all() {
+            return Ok((0..entries_count).collect());
+        }
+        let mut entry_ids = vec![];
+        for entry_id in 0..entries_count {
+            if self.matches(&pak.get_entry_path(entry_id)?) {
+                entry_ids.push(entry_id);
+            }
+        }
+        Ok(entry_ids)
+    }
+}
+
+impl From<glob::Pattern> for EntryFilter {
+    fn from(pattern: glob::Pattern) -> Self {
+        Self::Glob(pattern)
+    }
+}
+
+impl From<regex::Regex> for EntryFilter {
+    fn from(regex: regex::Regex) -> Self {
+        Self::Regex(regex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::open_pak;
+
+    const PAK_2: &str = "test/normal/game_patch_1.32.11.13992.pak";
+
+    #[test]
+    fn test_from_list() -> Result<(), Box<dyn std::error::Error>> {
+        let list = "\
+            # Comments and blank lines are skipped\n\
+            \n\
+            ShadowTrackerExtra/Content/Lua/common/lua_object.lua\n\
+            \tre:/logic_[a-z]+\\.lua$  \n\
+            *.uexp\n";
+        let filter = EntryFilter::from_list(list)?;
+        let EntryFilter::Or(filters) = &filter else {
+            panic!("{:?}", filter);
+        };
+        assert_eq!(filters.len(), 3);
+
+        let mut pak = open_pak(PAK_2, 10)?;
+        let selected = filter.select(pak.as_mut())?;
+        assert!(!selected.is_empty());
+        for entry_id in 0..pak.entries_count()? {
+            let path = pak.get_entry_path(entry_id)?;
+            let expected = path == "ShadowTrackerExtra/Content/Lua/common/lua_object.lua"
+                || (path.ends_with(".lua") && path.contains("/logic_"))
+                || path.ends_with(".uexp");
+            assert_eq!(selected.contains(&entry_id), expected, "{}", path);
+        }
+
+        assert!(
+            EntryFilter::from_list("# nothing")?
+                .select(pak.as_mut())?
+                .is_empty()
+        );
+        assert_eq!(
+            EntryFilter::All.select(pak.as_mut())?.len() as u64,
+            pak.entries_count()?
+        );
+        let error = EntryFilter::from_list("a.lua\nre:[").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+use crate::pak_reader::io_stats::IoCounters;
+use crate::pak_reader::{EntryLayout, EntryPayload};
+use crate::utils::check_zlib_header;
+use crate::utils::read_at::ReadAt;
+use flate2::bufread::{DeflateDecoder, ZlibDecoder};
+use std::io::{self, BufReader, Read, Write};
+
+/// Buffer cap [`EntryReader`]s are usually given.
+pub const DEFAULT_BUFFER_CAP: usize = 64 * 1024;
+
+/// Streams the content of an entry out of its [`EntryLayout`], undoing the
+/// XOR and decompressing as the caller reads.
+///
+/// Unlike [`PakReader::extract_entry_to_writer`](crate::pak_reader::PakReader::extract_entry_to_writer),
+/// which decompresses a whole block at a time, this feeds each block to the
+/// decoder through a buffer of at most `buffer_cap` bytes and decompresses
+/// straight into the buffer passed to [`Read::read`]. So however large a
+/// block is, the reader holds at most `buffer_cap` bytes of payload plus the
+/// decoder's fixed state, about 32 KiB for the deflate window, and
+/// [`Self::copy_to`] `buffer_cap` bytes more for its output buffer.
+///
+/// ```rust
+/// use gfp::pak_reader::PakReader;
+/// use gfp::pak_reader::entry_reader::EntryReader;
+/// use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+///
+/// let data = std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?;
+/// let mut pak = GfpPakReaderV10::new(data.as_slice());
+/// let layout = pak.entry_layout(3)?;
+///
+/// let mut content = Vec::new();
+/// EntryReader::new(&data, layout, 512).copy_to(&mut content)?;
+///
+/// let mut expected = Vec::new();
+/// pak.extract_entry_to_writer(3, &mut expected)?;
+/// assert_eq!(content, expected);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct EntryReader<'a, R: ReadAt + ?Sized> {
+    source: &'a R,
+    layout: EntryLayout,
+    buffer_cap: usize,
+    /// Index of the next payload piece to read, a block or the stored bytes
+    next_piece: usize,
+    current: Option<Piece<'a, R>>,
+    /// Content bytes read so far
+    produced: u64,
+    /// Those of the reader the entry came from, if any
+    counters: Option<&'a IoCounters>,
+}
+
+enum Piece<'a, R: ReadAt + ?Sized> {
+    Stored(PayloadRead<'a, R>),
+    Zlib(ZlibDecoder<BufReader<PayloadRead<'a, R>>>),
+    Deflate(DeflateDecoder<BufReader<PayloadRead<'a, R>>>),
+}
+
+impl<'a, R: ReadAt + ?Sized> EntryReader<'a, R> {
+    /// A reader of the entry laid out as `layout` in `source`. A
+    /// `buffer_cap` of 0 is taken as 1.
+    pub fn new(source: &'a R, layout: EntryLayout, buffer_cap: usize) -> Self {
+        Self {
+            source,
+            layout,
+            buffer_cap: buffer_cap.max(1),
+            next_piece: 0,
+            current: None,
+            produced: 0,
+            counters: None,
+        }
+    }
+
+    /// Count the reads and decompression in `counters`
+    pub(crate) fn with_counters(mut self, counters: &'a IoCounters) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    /// Write the rest of the content to `output` in pieces of at most
+    /// `buffer_cap` bytes, returning how many bytes were written.
+    pub fn copy_to(&mut self, output: &mut dyn Write) -> io::Result<u64> {
+        let mut buffer = vec![0u8; self.buffer_cap];
+        let mut written = 0;
+        loop {
+            let count = self.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(written);
+            }
+            output.write_all(&buffer[..count])?;
+            written += count as u64;
+        }
+    }
+
+    /// The next piece of the payload, `None` once all were read
+    fn open_next_piece(&mut self) -> io::Result<Option<Piece<'a, R>>> {
+        let xor_key = self.layout.encrypted.then_some(self.layout.xor_key);
+        let piece = match &self.layout.payload {
+            &EntryPayload::Stored { offset, length } if self.next_piece == 0 => Piece::Stored(
+                PayloadRead::new(self.source, self.counters, offset, length, xor_key),
+            ),
+            EntryPayload::Blocks(blocks) if self.next_piece < blocks.len() => {
+                let block = blocks[self.next_piece];
+                let payload = PayloadRead::new(
+                    self.source,
+                    self.counters,
+                    block.offset,
+                    block.compressed_length,
+                    xor_key,
+                );
+                // Like zlib_decompress, take blocks without a zlib header as raw deflate
+                let mut header = [0u8; 2];
+                let is_zlib = payload.peek(&mut header)? == 2 && check_zlib_header(header).is_ok();
+                let buffered = BufReader::with_capacity(self.buffer_cap, payload);
+                if is_zlib {
+                    Piece::Zlib(ZlibDecoder::new(buffered))
+                } else {
+                    Piece::Deflate(DeflateDecoder::new(buffered))
+                }
+            }
+            _ => return Ok(None),
+        };
+        self.next_piece += 1;
+        Ok(Some(piece))
+    }
+}
+
+impl<R: ReadAt + ?Sized> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let piece = match &mut self.current {
+                Some(piece) => piece,
+                None => match self.open_next_piece()? {
+                    Some(piece) => self.current.insert(piece),
+                    None if self.produced != self.layout.file_size => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Entry decompressed to {} bytes, expected {}",
+                                self.produced, self.layout.file_size
+                            ),
+                        ));
+                    }
+                    None => return Ok(0),
+                },
+            };
+            let count = match piece {
+                Piece::Stored(payload) => {
+                    let length = buf.len().min(self.buffer_cap);
+                    payload.read(&mut buf[..length])?
+                }
+                Piece::Zlib(decoder) => decoder.read(buf)?,
+                Piece::Deflate(decoder) => decoder.read(buf)?,
+            };
+            if let (Some(counters), Piece::Zlib(_) | Piece::Deflate(_)) = (self.counters, piece) {
+                counters.record_decompressed(count);
+            }
+            if count > 0 {
+                self.produced += count as u64;
+                return Ok(count);
+            }
+            self.current = None;
+        }
+    }
+}
+
+/// `length` payload bytes at `offset` in the source, XORed with `xor_key`
+/// if any
+struct PayloadRead<'a, R: ReadAt + ?Sized> {
+    source: &'a R,
+    counters: Option<&'a IoCounters>,
+    offset: u64,
+    remaining: u64,
+    xor_key: Option<u8>,
+}
+
+impl<'a, R: ReadAt + ?Sized> PayloadRead<'a, R> {
+    fn new(
+        source: &'a R,
+        counters: Option<&'a IoCounters>,
+        offset: u64,
+        length: u64,
+        xor_key: Option<u8>,
+    ) -> Self {
+        Self {
+            source,
+            counters,
+            offset,
+            remaining: length,
+            xor_key,
+        }
+    }
+
+    /// Read the next bytes without consuming them
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = (buf.len() as u64).min(self.remaining) as usize;
+        let count = self.source.read_at(&mut buf[..length], self.offset)?;
+        if let Some(counters) = self.counters {
+            counters.record_read(count);
+        }
+        if let Some(key) = self.xor_key {
+            buf[..count].iter_mut().for_each(|byte| *byte ^= key);
+        }
+        Ok(count)
+    }
+}
+
+impl<R: ReadAt + ?Sized> Read for PayloadRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let count = self.peek(buf)?;
+        if count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Payload ends early at {:#X}, {} bytes missing",
+                    self.offset, self.remaining
+                ),
+            ));
+        }
+        self.offset += count as u64;
+        self.remaining -= count as u64;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::{BlockLayout, PakReader};
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::cell::Cell;
+
+    /// A source that records the largest read made from it
+    struct MeteredSource {
+        data: Vec<u8>,
+        largest_read: Cell<usize>,
+    }
+
+    impl ReadAt for MeteredSource {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.largest_read
+                .set(self.largest_read.get().max(buf.len()));
+            self.data.read_at(buf, offset)
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            self.data.size()
+        }
+    }
+
+    #[test]
+    fn test_large_block_small_cap() -> Result<(), Box<dyn std::error::Error>> {
+        // 8 MiB decompressing from a single block
+        let content: Vec<u8> = (0..8u32 << 20)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8 & 0x0F)
+            .collect();
+        let mut encoder = ZlibEncoder::new(b"junk".to_vec(), Compression::fast());
+        encoder.write_all(&content)?;
+        let mut data = encoder.finish()?;
+        data[4..].iter_mut().for_each(|byte| *byte ^= 0x79);
+        let layout = EntryLayout {
+            compression_method: 1,
+            encrypted: true,
+            xor_key: 0x79,
+            file_size: content.len() as u64,
+            payload: EntryPayload::Blocks(vec![BlockLayout {
+                offset: 4,
+                compressed_length: data.len() as u64 - 4,
+                decompressed_length: content.len() as u64,
+            }]),
+        };
+        let source = MeteredSource {
+            data,
+            largest_read: Cell::new(0),
+        };
+
+        let mut reader = EntryReader::new(&source, layout.clone(), 4096);
+        let mut output = Vec::new();
+        assert_eq!(reader.copy_to(&mut output)?, content.len() as u64);
+        assert!(output == content);
+        assert!(source.largest_read.get() <= 4096);
+
+        // The content has to match the size the layout gives
+        let short = EntryLayout {
+            file_size: content.len() as u64 + 1,
+            ..layout
+        };
+        let error = EntryReader::new(&source, short, 4096)
+            .copy_to(&mut std::io::sink())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_extraction() -> Result<(), Box<dyn std::error::Error>> {
+        for (pak_path, version) in [
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+        ] {
+            let data = std::fs::read(pak_path)?;
+            let mut pak: Box<dyn PakReader + '_> = match version {
+                10 => Box::new(crate::pak_reader::gfp_v10::GfpPakReaderV10::new(
+                    data.as_slice(),
+                )),
+                _ => Box::new(crate::pak_reader::gfp_v7::GfpPakReaderV7::new(
+                    data.as_slice(),
+                )),
+            };
+            for entry_id in 0..pak.entries_count()? {
+                let mut expected = Vec::new();
+                pak.extract_entry_to_writer(entry_id, &mut expected)?;
+                let mut content = Vec::new();
+                EntryReader::new(data.as_slice(), pak.entry_layout(entry_id)?, 100)
+                    .read_to_end(&mut content)?;
+                assert!(content == expected, "{} entry {}", pak_path, entry_id);
+            }
+        }
+
+        let mut pak = crate::pak_reader::gfp_v10::GfpPakReaderV10::new(std::fs::read(
+            "test/normal/game_patch_1.32.11.13846.pak",
+        )?);
+        let mut content = Vec::new();
+        pak.entry_reader(0, DEFAULT_BUFFER_CAP)?
+            .read_to_end(&mut content)?;
+        let mut expected = Vec::new();
+        pak.extract_entry_to_writer(0, &mut expected)?;
+        assert!(content == expected);
+        Ok(())
+    }
+}
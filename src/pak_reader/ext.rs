@@ -0,0 +1,511 @@
+use crate::error::PakError;
+use crate::pak_reader::cancel::CancellationToken;
+#[cfg(feature = "std-fs")]
+use crate::pak_reader::entry_filter::EntryFilter;
+#[cfg(feature = "std-fs")]
+use crate::pak_reader::extract::{self, extract_entry_atomic, join_entry_path};
+use crate::pak_reader::metadata::{self, PakMetadata};
+use crate::pak_reader::{EntryPayload, PakReader};
+use std::collections::HashMap;
+use std::io::Cursor;
+#[cfg(feature = "std-fs")]
+use std::path::Path;
+use std::sync::Arc;
+
+/// Default of [`ContentsIter::with_max_entry_size`].
+pub const DEFAULT_MAX_ENTRY_SIZE: u64 = 256 << 20;
+
+/// Conveniences built on [`PakReader`], implemented for every reader.
+///
+/// ```rust
+/// use gfp::pak_reader::ext::PakReaderExt;
+/// use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+///
+/// let mut pak = GfpPakReaderV10::new(std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?);
+/// let lua_paths: Vec<String> = pak
+///     .collect_paths()?
+///     .into_iter()
+///     .filter(|path| path.ends_with(".lua"))
+///     .collect();
+/// assert_eq!(
+///     lua_paths,
+///     [
+///         "ShadowTrackerExtra/Content/Lua/client/logic/login/logic_pakversion.lua",
+///         "ShadowTrackerExtra/Content/Lua/common/lua_object.lua",
+///     ]
+/// );
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub trait PakReaderExt: PakReader {
+    /// Call `f` with the id and path of each entry, in id order.
+    fn for_each_entry(&mut self, mut f: impl FnMut(u64, &str)) -> Result<(), PakError> {
+        for entry_id in 0..self.entries_count()? {
+            f(entry_id, &self.get_entry_path(entry_id)?);
+        }
+        Ok(())
+    }
+
+    /// The paths of all entries, by entry id.
+    fn collect_paths(&mut self) -> Result<Vec<String>, PakError> {
+        self.preload_entry_paths()?;
+        let mut paths = Vec::new();
+        self.for_each_entry(|_, path| paths.push(path.to_string()))?;
+        Ok(paths)
+    }
+
+    /// Extract the entries whose path matches `filter` below `out_dir`,
+    /// returning their ids.
+    ///
+    /// Each file is written as [`extract_entry_atomic`] does, so a failure
+    /// leaves no partial file behind. Empty path components are dropped, so
+    /// paks mounted at `/` extract below `out_dir` too, and entries whose
+    /// path would still leave it, e.g. with `..`, are skipped.
+    #[cfg(feature = "std-fs")]
+    fn extract_matching(
+        &mut self,
+        filter: &EntryFilter,
+        out_dir: &Path,
+    ) -> Result<Vec<u64>, PakError> {
+        let mut extracted = vec![];
+        for entry_id in filter.select(self)? {
+            let entry_path = self.get_entry_path(entry_id)?;
+            let Ok(output_path) = join_entry_path(out_dir, &entry_path) else {
+                continue;
+            };
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            extract_entry_atomic(self, entry_id, &output_path, &CancellationToken::new())?;
+            extracted.push(entry_id);
+        }
+        Ok(extracted)
+    }
+
+    /// Iterate over the path and content of each entry, in id order,
+    /// extracting each entry only when the iterator gets to it, so one
+    /// entry at a time is in memory.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::ext::PakReaderExt;
+    /// use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+    ///
+    /// let mut pak = GfpPakReaderV10::new(std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?);
+    /// for content in pak.iter_contents()?.with_max_entry_size(1 << 20) {
+    ///     let (path, data) = content?;
+    ///     println!("{} {}", path, data.len());
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    fn iter_contents(&mut self) -> Result<ContentsIter<'_, Self>, PakError> {
+        Ok(ContentsIter {
+            entries_count: self.entries_count()?,
+            pak: self,
+            next_id: 0,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+        })
+    }
+
+    /// The client build the pak belongs to, from its file name and a
+    /// version manifest entry, with its footer version; see [`PakMetadata`].
+    /// Probes the entries of [`metadata::MANIFEST_PATHS`], extracting only
+    /// the first one found.
+    ///
+    /// [`PakReader::load_path_index`]
+    fn pak_metadata(&mut self) -> Result<PakMetadata, PakError> {
+        metadata::pak_metadata(self, metadata::MANIFEST_PATHS)
+    }
+
+    /// [`Self::pak_metadata`] probing `manifest_paths` instead, none for no
+    /// manifest at all.
+    fn pak_metadata_with(&mut self, manifest_paths: &[&str]) -> Result<PakMetadata, PakError> {
+        metadata::pak_metadata(self, manifest_paths)
+    }
+
+    /// `(offset, size)` in the pak of each compression block of the entry,
+    /// as listed in the index, and none for stored entries. A shortcut into
+    /// [`PakReader::entry_layout`] for matching the block table with the bytes
+    /// of the pak.
+    ///
+    /// [`PakReader::load_entries`]
+    fn entry_blocks(&mut self, entry_id: u64) -> Result<Vec<(u64, u64)>, PakError> {
+        Ok(match self.entry_layout(entry_id)?.payload {
+            EntryPayload::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| (block.offset, block.compressed_length))
+                .collect(),
+            EntryPayload::Stored { .. } => vec![],
+        })
+    }
+
+    /// A reader of the entry's whole content, held in memory and shared with
+    /// the [`ExtractionCache`](crate::pak_reader::extraction_cache::ExtractionCache)
+    /// if the reader has one, which is consulted first.
+    ///
+    /// [`PakReader::load_entries`]
+    fn open_entry(&mut self, entry_id: u64) -> Result<Cursor<Arc<[u8]>>, PakError> {
+        let content = match self.extraction_cache().cloned() {
+            Some(cache) => cache.content(self, entry_id)?,
+            None => {
+                let mut content = vec![];
+                self.extract_entry_to_writer(entry_id, &mut content)?;
+                content.into()
+            }
+        };
+        Ok(Cursor::new(content))
+    }
+
+    /// The entry's content, through the extraction cache if the reader has
+    /// one, see [`Self::open_entry`].
+    ///
+    /// [`PakReader::load_entries`]
+    fn extract_entry_to_vec(&mut self, entry_id: u64) -> Result<Vec<u8>, PakError> {
+        if self.extraction_cache().is_some() {
+            return Ok(self.open_entry(entry_id)?.into_inner().to_vec());
+        }
+        let mut content = vec![];
+        self.extract_entry_to_writer(entry_id, &mut content)?;
+        Ok(content)
+    }
+
+    /// Write the entry's content into `output` from `offset` onwards with
+    /// positioned writes, and return how many bytes were written. The file's
+    /// length and cursor are left alone, so writing past its end extends it
+    /// and the caller is responsible for pre-sizing it or ordering the
+    /// writes when that matters.
+    ///
+    /// Readers writing disjoint ranges of one file can run on different
+    /// threads with their own [`std::fs::File::try_clone`] of it, see
+    /// [`FileWriterAt`](crate::utils::write_at::FileWriterAt).
+    ///
+    /// [`PakReader::load_entries`]
+    #[cfg(feature = "std-fs")]
+    fn extract_entry_to_file_at(
+        &mut self,
+        entry_id: u64,
+        output: &mut std::fs::File,
+        offset: u64,
+    ) -> Result<u64, PakError> {
+        let mut writer = crate::utils::write_at::FileWriterAt::new(output, offset);
+        self.extract_entry_to_writer(entry_id, &mut writer)?;
+        Ok(writer.written())
+    }
+
+    /// Extract the entry below `dir` at its own path, made a valid file name
+    /// with [`sanitize_path`](extract::sanitize_path), creating the
+    /// directories in between, and return the path written. Like
+    /// [`extract::extract_entry_atomic`], no partial file is left behind.
+    /// Empty path components are dropped, as for paks mounted at `/`, and
+    /// entry paths that would still leave `dir` fail.
+    ///
+    /// [`PakReader::load_entry_paths`]
+    #[cfg(feature = "std-fs")]
+    fn extract_entry_into_dir(
+        &mut self,
+        entry_id: u64,
+        dir: &std::path::Path,
+    ) -> Result<std::path::PathBuf, PakError> {
+        let entry_path = extract::sanitize_path(&self.get_entry_path(entry_id)?);
+        let output_path = join_entry_path(dir, &entry_path)?;
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        extract::extract_entry_atomic(self, entry_id, &output_path, &CancellationToken::new())?;
+        Ok(output_path)
+    }
+
+    /// Check the entry's stored bytes against the hash recorded in the index.
+    ///
+    /// Some packers leave the hash zeroed; such entries never verify, so check
+    /// [`PakReader::entry_hash`] first if they need to be told apart.
+    fn verify_entry(&mut self, entry_id: u64) -> Result<bool, PakError> {
+        Ok(self.compute_entry_hash(entry_id)? == self.entry_hash(entry_id)?)
+    }
+
+    /// [`Self::verify_entry`] every entry, returning the ids of those that
+    /// don't verify. `cancel` is checked between entries.
+    fn verify_all(&mut self, cancel: &CancellationToken) -> Result<Vec<u64>, PakError> {
+        let mut mismatched = Vec::new();
+        for entry_id in 0..self.entries_count()? {
+            cancel.check()?;
+            if !self.verify_entry(entry_id)? {
+                mismatched.push(entry_id);
+            }
+        }
+        Ok(mismatched)
+    }
+
+    /// Extract every entry into memory, keyed by entry path.
+    ///
+    /// The whole decompressed content of the pak is held in memory at once,
+    /// which is fine for tests and small paks. Large paks should be streamed
+    /// entry by entry with [`PakReader::extract_entry_to_writer`] instead.
+    fn extract_all_to_map(&mut self) -> Result<HashMap<String, Vec<u8>>, PakError> {
+        self.extract_all_to_map_cancellable(&CancellationToken::new())
+    }
+
+    /// [`Self::extract_all_to_map`] that can be stopped with `cancel`, see
+    /// [`PakReader::extract_entry_to_writer_cancellable`].
+    fn extract_all_to_map_cancellable(
+        &mut self,
+        cancel: &CancellationToken,
+    ) -> Result<HashMap<String, Vec<u8>>, PakError> {
+        let mut contents = HashMap::new();
+        for entry_id in 0..self.entries_count()? {
+            let mut data = Vec::new();
+            self.extract_entry_to_writer_cancellable(entry_id, &mut data, cancel)?;
+            contents.insert(self.get_entry_path(entry_id)?, data);
+        }
+        Ok(contents)
+    }
+}
+
+impl<T: PakReader + ?Sized> PakReaderExt for T {}
+
+/// What [`PakReaderExt::iter_contents`] returns.
+///
+/// An entry that fails, including one larger than the maximum size, yields
+/// an error and the iterator goes on with the next.
+pub struct ContentsIter<'a, P: PakReader + ?Sized> {
+    pak: &'a mut P,
+    next_id: u64,
+    entries_count: u64,
+    max_entry_size: u64,
+}
+
+impl<P: PakReader + ?Sized> ContentsIter<'_, P> {
+    /// Fail entries larger than `max_entry_size` once decompressed instead
+    /// of extracting them, [`DEFAULT_MAX_ENTRY_SIZE`] by default.
+    pub fn with_max_entry_size(mut self, max_entry_size: u64) -> Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    fn read_entry(&mut self, entry_id: u64) -> Result<(String, Vec<u8>), PakError> {
+        let file_size = self.pak.entry_layout(entry_id)?.file_size;
+        if file_size > self.max_entry_size {
+            return Err(PakError::invalid_data(format!(
+                "Entry is {} bytes, more than the {} allowed",
+                file_size, self.max_entry_size
+            )));
+        }
+        Ok((
+            self.pak.get_entry_path(entry_id)?,
+            self.pak.extract_entry_to_vec(entry_id)?,
+        ))
+    }
+}
+
+impl<P: PakReader + ?Sized> Iterator for ContentsIter<'_, P> {
+    type Item = Result<(String, Vec<u8>), PakError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_id >= self.entries_count {
+            return None;
+        }
+        let entry_id = self.next_id;
+        self.next_id += 1;
+        Some(
+            self.read_entry(entry_id)
+                .map_err(|e| e.context(format!("extracting entry {}", entry_id))),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = usize::try_from(self.entries_count - self.next_id).ok();
+        (remaining.unwrap_or(usize::MAX), remaining)
+    }
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::open_pak;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+    const PAK_2: &str = "test/normal/game_patch_1.32.11.13992.pak";
+
+    /// PAK_1 with the only occurrence of `from` in it overwritten by `to`,
+    /// written to `name` in `temp_dir`
+    fn crafted_pak(
+        temp_dir: &TempDir,
+        name: &str,
+        from: &[u8],
+        to: &[u8],
+    ) -> std::io::Result<PathBuf> {
+        let mut data = std::fs::read(PAK_1)?;
+        let positions: Vec<_> = (0..data.len() - from.len())
+            .filter(|&at| data[at..at + from.len()] == *from)
+            .collect();
+        assert_eq!(positions.len(), 1);
+        data[positions[0]..positions[0] + to.len()].copy_from_slice(to);
+        let pak_path = temp_dir.path().join(name);
+        std::fs::write(&pak_path, data)?;
+        Ok(pak_path)
+    }
+
+    /// PAK_1 mounted at `/` instead of `../../../`, so that its entry paths
+    /// are absolute
+    fn root_mounted_pak(temp_dir: &TempDir) -> std::io::Result<PathBuf> {
+        crafted_pak(
+            temp_dir,
+            "root_mounted.pak",
+            b"../../../ShadowTrackerExtra/",
+            b"/////////ShadowTrackerExtra/",
+        )
+    }
+
+    #[test]
+    fn test_collect_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = open_pak(PAK_2, 10)?;
+        let paths = pak.collect_paths()?;
+        assert_eq!(paths.len() as u64, pak.entries_count()?);
+        for (entry_id, path) in paths.iter().enumerate() {
+            assert_eq!(*path, pak.get_entry_path(entry_id as u64)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_contents() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::pak_reader::extract::{ExtractEvent, extract_pak};
+
+        let temp_dir = TempDir::new()?;
+        let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+        let mut pak = open_pak(pak_path, 10)?;
+        let mut extracted = vec![];
+        extract_pak(
+            pak.as_mut(),
+            pak_path,
+            temp_dir.path(),
+            &Default::default(),
+            &CancellationToken::new(),
+            |event| {
+                if let ExtractEvent::Extracted(file) = event {
+                    extracted.push((file.entry_path.clone(), file.path.clone()));
+                }
+            },
+        )?;
+
+        let contents = pak.iter_contents()?;
+        assert_eq!(contents.size_hint(), (7, Some(7)));
+        let contents = contents.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(contents.len(), extracted.len());
+        for ((path, data), (entry_path, file_path)) in contents.iter().zip(&extracted) {
+            assert_eq!(path, entry_path);
+            assert_eq!(*data, std::fs::read(temp_dir.path().join(file_path))?);
+        }
+
+        // Entries over the limit fail alone
+        let largest = contents.iter().map(|(_, data)| data.len()).max().unwrap();
+        let results: Vec<_> = pak
+            .iter_contents()?
+            .with_max_entry_size(largest as u64 - 1)
+            .collect();
+        assert_eq!(results.len(), contents.len());
+        for (result, (path, data)) in results.iter().zip(&contents) {
+            match result {
+                Ok(content) => assert_eq!(content, &(path.clone(), data.clone())),
+                Err(e) => {
+                    assert_eq!(data.len(), largest);
+                    assert!(e.to_string().contains("more than the"), "{}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_matching() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(PAK_2, 10)?;
+        let pattern = glob::Pattern::new("ShadowTrackerExtra/Content/Lua/**/*.lua")?;
+
+        let extracted = pak.extract_matching(&pattern.clone().into(), temp_dir.path())?;
+        assert!(!extracted.is_empty());
+        for entry_id in 0..pak.entries_count()? {
+            let entry_path = pak.get_entry_path(entry_id)?;
+            assert_eq!(
+                temp_dir.path().join(&entry_path).is_file(),
+                pattern.matches(&entry_path)
+            );
+            assert_eq!(extracted.contains(&entry_id), pattern.matches(&entry_path));
+        }
+
+        let mut content = Vec::new();
+        pak.extract_entry_to_writer(extracted[0], &mut content)?;
+        let entry_path = pak.get_entry_path(extracted[0])?;
+        assert_eq!(std::fs::read(temp_dir.path().join(entry_path))?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_matching_root_mounted() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(root_mounted_pak(&temp_dir)?, 10)?;
+        let entry_path = pak.get_entry_path(3)?;
+        assert!(entry_path.starts_with("/"), "{}", entry_path);
+
+        let out_dir = temp_dir.path().join("out");
+        let pattern = glob::Pattern::new("**/lua_object.lua")?;
+        assert_eq!(pak.extract_matching(&pattern.into(), &out_dir)?, [3]);
+        assert_eq!(
+            std::fs::read(out_dir.join(entry_path.trim_start_matches('/')))?,
+            pak.extract_entry_to_vec(3)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_into_dir_root_mounted() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(root_mounted_pak(&temp_dir)?, 10)?;
+        let out_dir = temp_dir.path().join("out");
+        let output_path = pak.extract_entry_into_dir(3, &out_dir)?;
+        assert_eq!(
+            output_path,
+            out_dir.join("ShadowTrackerExtra/Content/Lua/common/lua_object.lua")
+        );
+        assert_eq!(std::fs::read(output_path)?, pak.extract_entry_to_vec(3)?);
+
+        let pak_path = crafted_pak(
+            &temp_dir,
+            "escaping.pak",
+            b"Lua/common/\0",
+            b"../../../x/\0",
+        )?;
+        let error = open_pak(pak_path, 10)?
+            .extract_entry_into_dir(3, &out_dir)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("leaves the output directory"),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_matching_escaping_entry() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = crafted_pak(
+            &temp_dir,
+            "escaping.pak",
+            b"Lua/common/\0",
+            b"../../../x/\0",
+        )?;
+        let mut pak = open_pak(pak_path, 10)?;
+        assert_eq!(
+            pak.get_entry_path(3)?,
+            "ShadowTrackerExtra/Content/../../../x/lua_object.lua"
+        );
+
+        let out_dir = temp_dir.path().join("out/deep");
+        let extracted = pak.extract_matching(&EntryFilter::All, &out_dir)?;
+        assert_eq!(extracted, [0, 1, 2, 4, 5, 6]);
+        assert!(!temp_dir.path().join("out/x").exists());
+        assert!(!temp_dir.path().join("x").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,2298 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use crate::pak_reader::cancel::CancellationToken;
+use crate::pak_reader::entry_filter::EntryFilter;
+use crate::utils::file_time::{format_rfc3339, parse_rfc3339, set_file_mtime};
+use crate::utils::{check_zlib_header, hex};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Where the entries of each pak go below the output directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputLayout {
+    /// Every pak into the output directory itself, so later paks overwrite
+    /// the files of earlier ones.
+    #[default]
+    Merged,
+    /// Each pak into a directory named after its file stem, e.g.
+    /// `out/game_patch_1.32.11.13846/...`.
+    PerPak,
+}
+
+impl FromStr for OutputLayout {
+    type Err = String;
+
+    /// `merged` or `per-pak`.
+    fn from_str(layout: &str) -> Result<Self, Self::Err> {
+        match layout {
+            "merged" => Ok(Self::Merged),
+            "per-pak" => Ok(Self::PerPak),
+            _ => Err(format!(
+                "Unknown layout '{}', expected merged or per-pak",
+                layout
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Merged => "merged",
+            Self::PerPak => "per-pak",
+        })
+    }
+}
+
+/// The modification time [`extract_pak`] gives the files it writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MtimePolicy {
+    /// Whenever the file is written.
+    #[default]
+    Now,
+    /// The modification time of the pak the file comes from.
+    Pak,
+    /// A fixed time, for reproducible output.
+    Fixed(SystemTime),
+}
+
+impl MtimePolicy {
+    /// The time to give the files extracted from the pak at `pak_path`,
+    /// `None` to leave them as written.
+    pub(crate) fn resolve(&self, pak_path: &Path) -> std::io::Result<Option<SystemTime>> {
+        match *self {
+            Self::Now => Ok(None),
+            Self::Pak => Ok(Some(std::fs::metadata(pak_path)?.modified()?)),
+            Self::Fixed(mtime) => Ok(Some(mtime)),
+        }
+    }
+}
+
+impl FromStr for MtimePolicy {
+    type Err = String;
+
+    /// `now`, `pak` or `fixed:<RFC 3339 timestamp>`.
+    ///
+    /// ```rust
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use gfp::pak_reader::extract::MtimePolicy;
+    ///
+    /// assert_eq!("pak".parse(), Ok(MtimePolicy::Pak));
+    /// assert_eq!(
+    ///     "fixed:1970-01-01T00:01:00Z".parse(),
+    ///     Ok(MtimePolicy::Fixed(UNIX_EPOCH + Duration::from_secs(60)))
+    /// );
+    /// assert!("fixed:yesterday".parse::<MtimePolicy>().is_err());
+    /// ```
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "now" => Ok(Self::Now),
+            "pak" => Ok(Self::Pak),
+            _ => match policy.strip_prefix("fixed:") {
+                Some(timestamp) => parse_rfc3339(timestamp).map(Self::Fixed),
+                None => Err(format!(
+                    "Unknown mtime '{}', expected now, pak or fixed:<RFC 3339 timestamp>",
+                    policy
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for MtimePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Now => f.write_str("now"),
+            Self::Pak => f.write_str("pak"),
+            Self::Fixed(mtime) => write!(f, "fixed:{}", format_rfc3339(*mtime)),
+        }
+    }
+}
+
+/// How [`extract_pak`] reads the pak.
+///
+/// Whether hints pay off depends on the storage: they help most on spinning
+/// disks and network mounts, and little once the pak is in the page cache.
+/// To compare the strategies, drop the cache before each run (on Linux,
+/// `sync; echo 3 > /proc/sys/vm/drop_caches` as root) and time
+/// `gfp unpack --read-strategy <strategy>` on the same paks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadStrategy {
+    /// Read each entry when it's extracted and leave read-ahead to the OS.
+    #[default]
+    Default,
+    /// Have the OS read the next entry while the current one is
+    /// decompressed and written, see [`PakReader::prefetch_entry`]. Only
+    /// Linux acts on the hint.
+    Prefetch,
+    /// Prefetch like [`Self::Prefetch`], for paks opened through a memory
+    /// mapping with
+    /// [`open_pak_mapped`](crate::pak_reader::implements::open_pak_mapped),
+    /// which [`extract_pak`] can't do by itself.
+    Mmap,
+}
+
+impl FromStr for ReadStrategy {
+    type Err = String;
+
+    /// `default`, `prefetch` or `mmap`.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::extract::ReadStrategy;
+    ///
+    /// assert_eq!("prefetch".parse(), Ok(ReadStrategy::Prefetch));
+    /// assert!("readahead".parse::<ReadStrategy>().is_err());
+    /// ```
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy {
+            "default" => Ok(Self::Default),
+            "prefetch" => Ok(Self::Prefetch),
+            "mmap" => Ok(Self::Mmap),
+            _ => Err(format!(
+                "Unknown read strategy '{}', expected default, prefetch or mmap",
+                strategy
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ReadStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::Prefetch => "prefetch",
+            Self::Mmap => "mmap",
+        })
+    }
+}
+
+/// A rule of [`ExtractOptions::path_rewrites`]: entry paths starting with
+/// `from` have it replaced with `to`.
+///
+/// Prefixes are compared as strings, so `from` should end with `/` to only
+/// match whole directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl PathRewrite {
+    /// `path` with the prefix replaced, `None` if it doesn't start with it.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        path.strip_prefix(&self.from)
+            .map(|rest| format!("{}{}", self.to, rest))
+    }
+}
+
+impl FromStr for PathRewrite {
+    type Err = String;
+
+    /// `FROM=TO`, split at the first `=`.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::extract::PathRewrite;
+    ///
+    /// let rewrite: PathRewrite = "../../../ShooterGame/Content/=Content/".parse()?;
+    /// assert_eq!(
+    ///     rewrite.apply("../../../ShooterGame/Content/Maps/a.umap").as_deref(),
+    ///     Some("Content/Maps/a.umap")
+    /// );
+    /// assert_eq!(rewrite.apply("Engine/a.ini"), None);
+    /// assert!("Content/".parse::<PathRewrite>().is_err());
+    /// # Ok::<(), String>(())
+    /// ```
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        match rule.split_once('=') {
+            Some((from, to)) => Ok(Self {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            None => Err(format!("Invalid path rewrite '{}', expected FROM=TO", rule)),
+        }
+    }
+}
+
+impl std::fmt::Display for PathRewrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.from, self.to)
+    }
+}
+
+/// `path` rewritten by the first of `rewrites` whose prefix it starts with,
+/// `None` if none does.
+///
+/// ```rust
+/// use gfp::pak_reader::extract::{PathRewrite, rewrite_path};
+///
+/// let rewrites: Vec<PathRewrite> = ["Content/Lua/=lua/", "Content/=assets/"]
+///     .iter()
+///     .map(|rule| rule.parse())
+///     .collect::<Result<_, _>>()?;
+/// assert_eq!(rewrite_path("Content/Lua/a.lua", &rewrites).as_deref(), Some("lua/a.lua"));
+/// assert_eq!(rewrite_path("Content/Maps/a.umap", &rewrites).as_deref(), Some("assets/Maps/a.umap"));
+/// assert_eq!(rewrite_path("Engine/a.ini", &rewrites), None);
+/// # Ok::<(), String>(())
+/// ```
+pub fn rewrite_path(path: &str, rewrites: &[PathRewrite]) -> Option<String> {
+    rewrites.iter().find_map(|rewrite| rewrite.apply(path))
+}
+
+/// Serde through the same strings as `FromStr` and `Display`, which are
+/// also what the `gfp` options take
+macro_rules! serde_as_str {
+    ($($name:ty),*) => {$(
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+    )*};
+}
+
+serde_as_str!(OutputLayout, MtimePolicy, ReadStrategy, PathRewrite);
+
+/// How [`extract_pak`] lays out what it writes.
+///
+/// The defaults extract every entry as is. Options are set with the `with_`
+/// methods, or read from TOML with [`Self::from_toml`], using the names of
+/// the `gfp unpack` options:
+///
+/// ```rust
+/// use gfp::pak_reader::extract::{ExtractOptions, MtimePolicy, OutputLayout};
+///
+/// let options = ExtractOptions::default()
+///     .with_layout(OutputLayout::PerPak)
+///     .with_strip_components(2)
+///     .with_mtime(MtimePolicy::Pak);
+/// let toml = options.to_toml();
+/// assert_eq!(
+///     toml,
+///     "layout = \"per-pak\"\nresume = false\nstrip-components = 2\nmtime = \"pak\"\n\
+///      sanitize-names = false\nread-strategy = \"default\"\nrewrite-strict = false\n\
+///      ignore-changes = false\ninflate-inner = false\nkeep-raw = false\n"
+/// );
+/// let parsed =
+///     ExtractOptions::from_toml("layout = \"per-pak\"\nstrip-components = 2\nmtime = \"pak\"")?;
+/// assert_eq!(parsed.strip_components, 2);
+/// assert_eq!(parsed.to_toml(), toml);
+/// assert!(ExtractOptions::from_toml("strip_components = 2").is_err());
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+///
+/// [`Self::filter`] can't be written in TOML and is left out.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ExtractOptions {
+    pub layout: OutputLayout,
+    /// Keep a [`ResumeJournal`] for each pak and skip the entries it lists
+    /// as already extracted.
+    pub resume: bool,
+    /// Leading components dropped from each entry path, like
+    /// `tar --strip-components`, see [`strip_components`].
+    pub strip_components: usize,
+    pub mtime: MtimePolicy,
+    /// Rewrite entry paths that can't be file names on Windows, see
+    /// [`sanitize_path`].
+    pub sanitize_names: bool,
+    /// Only extract the entries with these ids, as listed by `ls`, instead
+    /// of every entry. Ids past the last entry fail the whole pak.
+    pub entry_ids: Option<Vec<u64>>,
+    /// Only extract the entries whose path matches, out of those selected
+    /// by [`Self::entry_ids`].
+    #[serde(skip)]
+    pub filter: EntryFilter,
+    pub read_strategy: ReadStrategy,
+    /// Rewrite the start of entry paths before anything else is done to
+    /// them, with the first rule that matches, see [`rewrite_path`].
+    /// Entries no rule matches keep their path. Entries rewritten onto the
+    /// same path overwrite each other, the later one last.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub path_rewrites: Vec<PathRewrite>,
+    /// Fail on entries none of [`Self::path_rewrites`] matches instead of
+    /// keeping their path.
+    pub rewrite_strict: bool,
+    /// Don't check that the pak is unchanged before each entry, see
+    /// [`PakReader::check_unchanged`].
+    pub ignore_changes: bool,
+    /// Replace each extracted file that is itself a whole zlib stream with
+    /// what it inflates to, see [`inflate_inner`]. Resumed entries are left
+    /// as the earlier run wrote them.
+    pub inflate_inner: bool,
+    /// Keep the files [`Self::inflate_inner`] inflates, with `.z` appended
+    /// to their name.
+    pub keep_raw: bool,
+}
+
+impl ExtractOptions {
+    pub fn with_layout(mut self, layout: OutputLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    pub fn with_strip_components(mut self, count: usize) -> Self {
+        self.strip_components = count;
+        self
+    }
+
+    pub fn with_mtime(mut self, mtime: MtimePolicy) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    pub fn with_sanitize_names(mut self, sanitize_names: bool) -> Self {
+        self.sanitize_names = sanitize_names;
+        self
+    }
+
+    pub fn with_entry_ids(mut self, entry_ids: impl IntoIterator<Item = u64>) -> Self {
+        self.entry_ids = Some(entry_ids.into_iter().collect());
+        self
+    }
+
+    pub fn with_filter(mut self, filter: EntryFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_read_strategy(mut self, read_strategy: ReadStrategy) -> Self {
+        self.read_strategy = read_strategy;
+        self
+    }
+
+    pub fn with_path_rewrites(mut self, rewrites: impl IntoIterator<Item = PathRewrite>) -> Self {
+        self.path_rewrites = rewrites.into_iter().collect();
+        self
+    }
+
+    pub fn with_rewrite_strict(mut self, rewrite_strict: bool) -> Self {
+        self.rewrite_strict = rewrite_strict;
+        self
+    }
+
+    pub fn with_ignore_changes(mut self, ignore_changes: bool) -> Self {
+        self.ignore_changes = ignore_changes;
+        self
+    }
+
+    pub fn with_inflate_inner(mut self, inflate_inner: bool) -> Self {
+        self.inflate_inner = inflate_inner;
+        self
+    }
+
+    pub fn with_keep_raw(mut self, keep_raw: bool) -> Self {
+        self.keep_raw = keep_raw;
+        self
+    }
+
+    /// The options set in `toml`, the others left to their defaults.
+    pub fn from_toml(toml: &str) -> Result<Self, PakError> {
+        toml::from_str(toml)
+            .map_err(|e| PakError::invalid_data(format!("Invalid extract options: {}", e)))
+    }
+
+    /// [`Self::from_toml`] of the file at `path`.
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, PakError> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(PakError::from)
+            .and_then(|toml| Self::from_toml(&toml))
+            .map_err(|e| e.context(format!("reading {}", path.display())))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("extract options are plain values")
+    }
+
+    /// The directory the entries of the pak at `pak_path` are written to.
+    ///
+    /// ```rust
+    /// use std::path::Path;
+    /// use gfp::pak_reader::extract::{ExtractOptions, OutputLayout};
+    ///
+    /// let pak_path = Path::new("Paks/game_patch_1.32.11.13846.pak");
+    /// let options = ExtractOptions { layout: OutputLayout::PerPak, ..Default::default() };
+    /// assert_eq!(
+    ///     options.pak_output_dir(Path::new("out"), pak_path),
+    ///     Path::new("out/game_patch_1.32.11.13846")
+    /// );
+    /// assert_eq!(
+    ///     ExtractOptions::default().pak_output_dir(Path::new("out"), pak_path),
+    ///     Path::new("out")
+    /// );
+    /// ```
+    pub fn pak_output_dir(&self, output_root: &Path, pak_path: &Path) -> PathBuf {
+        match self.layout {
+            OutputLayout::Merged => output_root.to_path_buf(),
+            OutputLayout::PerPak => {
+                output_root.join(pak_path.file_stem().unwrap_or(pak_path.as_os_str()))
+            }
+        }
+    }
+
+    /// Ids of the entries of `pak` to extract, in id order.
+    pub(crate) fn select_entries(&self, pak: &mut dyn PakReader) -> Result<Vec<u64>, PakError> {
+        let entries_count = pak.entries_count()?;
+        let entry_ids = match &self.entry_ids {
+            Some(entry_ids) => {
+                let mut entry_ids = entry_ids.clone();
+                entry_ids.sort_unstable();
+                entry_ids.dedup();
+                if let Some(&entry_id) = entry_ids.last()
+                    && entry_id >= entries_count
+                {
+                    return Err(PakError::invalid_data(format!(
+                        "Entry id {} out of range, the pak has {} entries",
+                        entry_id, entries_count
+                    )));
+                }
+                entry_ids
+            }
+            None => (0..entries_count).collect(),
+        };
+        if self.filter.is_all() {
+            return Ok(entry_ids);
+        }
+        let mut selected = Vec::with_capacity(entry_ids.len());
+        for entry_id in entry_ids {
+            if self.filter.matches(&pak.get_entry_path(entry_id)?) {
+                selected.push(entry_id);
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Where the entry at `entry_path` goes relative to the output directory
+    /// of its pak, `None` when stripping components leaves nothing, and
+    /// whether [`sanitize_path`] changed it. Fails on paths no rewrite
+    /// matches when [`Self::rewrite_strict`] is set.
+    pub(crate) fn entry_output_path(
+        &self,
+        entry_path: &str,
+    ) -> Result<(Option<String>, bool), PakError> {
+        let rewritten_path = match rewrite_path(entry_path, &self.path_rewrites) {
+            Some(path) => path,
+            None if self.rewrite_strict => {
+                return Err(PakError::invalid_data(format!(
+                    "No path rewrite matches {}",
+                    entry_path
+                )));
+            }
+            None => entry_path.to_string(),
+        };
+        let sanitized_path = if self.sanitize_names {
+            sanitize_path(&rewritten_path)
+        } else {
+            rewritten_path.clone()
+        };
+        let renamed = sanitized_path != rewritten_path;
+        Ok((
+            strip_components(&sanitized_path, self.strip_components),
+            renamed,
+        ))
+    }
+}
+
+/// A file written by [`extract_pak`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// Path of the file relative to the output directory.
+    pub path: PathBuf,
+    pub pak_path: PathBuf,
+    pub entry_id: u64,
+    pub entry_path: String,
+    /// The hash recorded for the entry in the index.
+    pub hash: [u8; 20],
+    /// Whether an earlier run already extracted the file and it was skipped,
+    /// see [`ExtractOptions::resume`].
+    pub resumed: bool,
+    /// Whether [`sanitize_path`] changed the path of the file.
+    pub renamed: bool,
+    /// What this run wrote, `None` for resumed files.
+    pub written: Option<WrittenData>,
+}
+
+/// What [`extract_entry_atomic`] wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrittenData {
+    pub length: u64,
+    /// SHA-1 of the bytes written, which is the hash of the decompressed
+    /// content rather than the one recorded in the index.
+    pub sha1: [u8; 20],
+}
+
+/// Hashes and counts what goes through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: sha1_smol::Sha1,
+    length: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = self.inner.write(buf)?;
+        self.hasher.update(&buf[..length]);
+        self.length += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What [`extract_pak`] reports as it goes.
+#[derive(Debug, Clone, Copy)]
+pub enum ExtractEvent<'a> {
+    /// A file was written, or skipped because an earlier run wrote it.
+    Extracted(&'a ExtractedFile),
+    /// An entry was left out.
+    Skipped {
+        entry_id: u64,
+        entry_path: &'a str,
+        reason: &'static str,
+    },
+    /// Extracting an entry failed, and [`extract_pak`] returns `error` next.
+    Failed {
+        entry_id: u64,
+        entry_path: &'a str,
+        error: &'a PakError,
+    },
+}
+
+/// `path` without its first `count` components, or `None` if nothing would
+/// be left of it. Empty components don't count.
+///
+/// ```rust
+/// use gfp::pak_reader::extract::strip_components;
+///
+/// let path = "ShadowTrackerExtra/Content/Lua/common/lua_object.lua";
+/// assert_eq!(strip_components(path, 0).as_deref(), Some(path));
+/// assert_eq!(
+///     strip_components(path, 2).as_deref(),
+///     Some("Lua/common/lua_object.lua")
+/// );
+/// assert_eq!(strip_components(path, 5), None);
+/// ```
+pub fn strip_components(path: &str, count: usize) -> Option<String> {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect();
+    if components.len() > count {
+        Some(components[count..].join("/"))
+    } else {
+        None
+    }
+}
+
+/// `path` with each component made a valid Windows file name: the
+/// characters `<>:"|?*\` and control characters become `_`, as do
+/// trailing dots and spaces, and device names such as `CON` or `com1.txt`
+/// get a leading `_`.
+///
+/// ```rust
+/// use gfp::pak_reader::extract::sanitize_path;
+///
+/// assert_eq!(sanitize_path("Lua/a:b"), "Lua/a_b");
+/// assert_eq!(sanitize_path("a<b>|c?*.lua"), "a_b__c__.lua");
+/// assert_eq!(sanitize_path("Maps/CON/aux.txt"), "Maps/_CON/_aux.txt");
+/// assert_eq!(sanitize_path("a. /b"), "a__/b");
+/// assert_eq!(sanitize_path("Lua/common/lua_object.lua"), "Lua/common/lua_object.lua");
+/// ```
+pub fn sanitize_path(path: &str) -> String {
+    const RESERVED_NAMES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+    const NUMBERED_NAMES: [&str; 2] = ["COM", "LPT"];
+
+    let components: Vec<String> = path
+        .split('/')
+        .map(|component| {
+            let mut name: String = component
+                .chars()
+                .map(|c| {
+                    if c.is_control() || "<>:\"|?*\\".contains(c) {
+                        '_'
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+            let kept = name.trim_end_matches(['.', ' ']).len();
+            if kept < name.len() && !matches!(name.as_str(), "." | "..") {
+                let trailing = name.len() - kept;
+                name.truncate(kept);
+                name.push_str(&"_".repeat(trailing));
+            }
+
+            let stem = name
+                .split('.')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_uppercase();
+            let is_device = RESERVED_NAMES.contains(&stem.as_str())
+                || (stem.len() == 4
+                    && NUMBERED_NAMES.contains(&&stem[..3])
+                    && stem.as_bytes()[3].is_ascii_digit()
+                    && stem.as_bytes()[3] != b'0');
+            if is_device {
+                name.insert(0, '_');
+            }
+            name
+        })
+        .collect();
+    components.join("/")
+}
+
+/// `relative`, a `/` separated path, below `output_root`, refusing paths
+/// that would leave it such as absolute ones or ones with `..`.
+pub(crate) fn join_inside(output_root: &Path, relative: &str) -> Result<PathBuf, PakError> {
+    let is_inside = !relative.is_empty()
+        && relative.split('/').all(|component| !component.is_empty())
+        && Path::new(relative)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !is_inside {
+        return Err(PakError::invalid_data(format!(
+            "Output path {:?} leaves the output directory",
+            relative
+        )));
+    }
+    Ok(output_root.join(relative))
+}
+
+/// `entry_path` below `output_root` without its empty components, such as
+/// the leading one of paks mounted at `/`, see [`strip_components`].
+/// Entry paths that would leave `output_root` fail, see [`join_inside`].
+pub(crate) fn join_entry_path(output_root: &Path, entry_path: &str) -> Result<PathBuf, PakError> {
+    join_inside(
+        output_root,
+        &strip_components(entry_path, 0).unwrap_or_default(),
+    )
+}
+
+/// Extract an entry to `output_path` without ever leaving a partial file
+/// there.
+///
+/// The entry is written to `<output_path>.part` first and renamed once
+/// complete. The `.part` file is removed if extraction fails or is
+/// cancelled through `cancel`.
+pub fn extract_entry_atomic<P: PakReader + ?Sized>(
+    pak: &mut P,
+    entry_id: u64,
+    output_path: &Path,
+    cancel: &CancellationToken,
+) -> Result<WrittenData, PakError> {
+    let part_path = with_suffix(output_path, ".part");
+
+    let result = (|| -> Result<WrittenData, PakError> {
+        let mut output = HashingWriter {
+            inner: File::create(&part_path)?,
+            hasher: sha1_smol::Sha1::new(),
+            length: 0,
+        };
+        pak.extract_entry_to_writer_cancellable(entry_id, &mut output, cancel)?;
+        drop(output.inner);
+        std::fs::rename(&part_path, output_path)?;
+        Ok(WrittenData {
+            length: output.length,
+            sha1: output.hasher.digest().bytes(),
+        })
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part_path);
+    }
+    result
+}
+
+/// `path` with `suffix` appended to its file name
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Largest size [`extract_pak`] lets [`inflate_inner`] inflate a file to;
+/// files that would be larger are left as they are.
+pub const INFLATE_INNER_MAX_SIZE: u64 = 256 << 20;
+
+/// Replace the file at `path` with what it inflates to, if it is a whole
+/// zlib stream, as some entries are once extracted.
+///
+/// Detection is conservative, so that files which merely start like zlib,
+/// `78 9C` say, are left alone: the file must start with a zlib header,
+/// inflate without error to at most `max_size` bytes, end with the stream,
+/// and match the stream's checksum. Returns what was written, or `None` if
+/// the file was left as it is.
+///
+/// The inflated file is written to `<path>.part` first and renamed over
+/// `path`. With `keep_raw` the original is kept as `<path>.z`.
+///
+/// ```rust
+/// use flate2::{Compression, write::ZlibEncoder};
+/// use gfp::pak_reader::extract::inflate_inner;
+/// use std::io::Write;
+///
+/// let temp_dir = tempfile::TempDir::new()?;
+/// let path = temp_dir.path().join("config.ini");
+/// let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+/// encoder.write_all(b"[Core]\nEnabled=1\n")?;
+/// std::fs::write(&path, encoder.finish()?)?;
+///
+/// let written = inflate_inner(&path, true, 1 << 20)?.unwrap();
+/// assert_eq!(written.length, 17);
+/// assert_eq!(std::fs::read(&path)?, b"[Core]\nEnabled=1\n");
+/// assert!(temp_dir.path().join("config.ini.z").is_file());
+///
+/// // Already inflated
+/// assert_eq!(inflate_inner(&path, false, 1 << 20)?, None);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn inflate_inner(
+    path: &Path,
+    keep_raw: bool,
+    max_size: u64,
+) -> Result<Option<WrittenData>, PakError> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut header = [0; 2];
+    if file_size < header.len() as u64 {
+        return Ok(None);
+    }
+    file.read_exact(&mut header)?;
+    if check_zlib_header(header).is_err() {
+        return Ok(None);
+    }
+    file.rewind()?;
+
+    let part_path = with_suffix(path, ".part");
+    let result = (|| -> Result<Option<WrittenData>, PakError> {
+        let mut input = BufReader::new(file);
+        let mut decompress = flate2::Decompress::new(true);
+        let mut buffer = vec![0; 64 << 10];
+        let mut output = HashingWriter {
+            inner: File::create(&part_path)?,
+            hasher: sha1_smol::Sha1::new(),
+            length: 0,
+        };
+        loop {
+            let chunk = input.fill_buf()?;
+            let is_eof = chunk.is_empty();
+            let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+            let flush = if is_eof {
+                flate2::FlushDecompress::Finish
+            } else {
+                flate2::FlushDecompress::None
+            };
+            let Ok(status) = decompress.decompress(chunk, &mut buffer, flush) else {
+                return Ok(None);
+            };
+            input.consume((decompress.total_in() - total_in) as usize);
+            let produced = (decompress.total_out() - total_out) as usize;
+            if output.length + produced as u64 > max_size {
+                return Ok(None);
+            }
+            output.write_all(&buffer[..produced])?;
+            match status {
+                flate2::Status::StreamEnd => break,
+                // The stream is cut short
+                _ if is_eof && produced == 0 => return Ok(None),
+                _ => {}
+            }
+        }
+        // Anything after the stream means it isn't the whole file
+        if decompress.total_in() != file_size {
+            return Ok(None);
+        }
+        drop(output.inner);
+        if keep_raw {
+            std::fs::rename(path, with_suffix(path, ".z"))?;
+        }
+        std::fs::rename(&part_path, path)?;
+        Ok(Some(WrittenData {
+            length: output.length,
+            sha1: output.hasher.digest().bytes(),
+        }))
+    })();
+    if !matches!(result, Ok(Some(_))) {
+        let _ = std::fs::remove_file(&part_path);
+    }
+    result
+}
+
+/// Extract every entry of the pak at `pak_path` below `output_root`, laid
+/// out as `options` says, calling `on_event` for each entry.
+///
+/// The returned report has a row for each event. When extraction fails
+/// there's no report, but `on_event` has seen every row up to the failure.
+///
+/// Before each entry is written the pak is checked to be unchanged, unless
+/// [`ExtractOptions::ignore_changes`] is set, so that a pak the game updates
+/// meanwhile fails with [`PakError::PakChanged`] instead of filling files
+/// with whatever is now at the offsets parsed.
+pub fn extract_pak(
+    pak: &mut dyn PakReader,
+    pak_path: &Path,
+    output_root: &Path,
+    options: &ExtractOptions,
+    cancel: &CancellationToken,
+    mut on_event: impl FnMut(ExtractEvent),
+) -> Result<ExtractReport, PakError> {
+    let mut report = ExtractReport::default();
+    let mut on_event = |event: ExtractEvent| {
+        report.rows.push(ManifestRow::from_event(pak_path, &event));
+        on_event(event);
+    };
+
+    let output_dir = options.pak_output_dir(output_root, pak_path);
+    let mtime = options.mtime.resolve(pak_path)?;
+    let mut journal = if options.resume {
+        Some(ResumeJournal::open(ResumeJournal::path(
+            &output_dir,
+            pak_path,
+        ))?)
+    } else {
+        None
+    };
+
+    let entry_ids = options.select_entries(pak)?;
+
+    for (index, &entry_id) in entry_ids.iter().enumerate() {
+        cancel.check()?;
+        // Have the OS read the next entry while this one is decompressed;
+        // it's only a hint, so a failure here is left to that entry
+        if options.read_strategy != ReadStrategy::Default
+            && let Some(&next_id) = entry_ids.get(index + 1)
+        {
+            let _ = pak.prefetch_entry(next_id);
+        }
+        let entry_path = pak.get_entry_path(entry_id)?;
+        let (relative_path, renamed) = match options.entry_output_path(&entry_path) {
+            Ok(output_path) => output_path,
+            Err(error) => {
+                on_event(ExtractEvent::Failed {
+                    entry_id,
+                    entry_path: &entry_path,
+                    error: &error,
+                });
+                return Err(error);
+            }
+        };
+        #[cfg(feature = "tracing")]
+        if renamed {
+            tracing::info!(entry_id, from = %entry_path, to = ?relative_path, "renamed entry");
+        }
+        let Some(relative_path) = relative_path else {
+            on_event(ExtractEvent::Skipped {
+                entry_id,
+                entry_path: &entry_path,
+                reason: "Not enough path components to strip",
+            });
+            continue;
+        };
+        // Entry paths come from the pak, and a crafted one could hold `..`
+        let Ok(output_path) = join_inside(&output_dir, &relative_path) else {
+            on_event(ExtractEvent::Skipped {
+                entry_id,
+                entry_path: &entry_path,
+                reason: "Path leaves the output directory",
+            });
+            continue;
+        };
+        let resumed = journal
+            .as_ref()
+            .is_some_and(|journal| journal.is_completed(entry_id));
+        let mut written = None;
+        if !resumed {
+            if !options.ignore_changes {
+                pak.check_unchanged()?;
+            }
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let result =
+                extract_entry_atomic(pak, entry_id, &output_path, cancel).and_then(|data| {
+                    if !options.inflate_inner {
+                        return Ok(data);
+                    }
+                    let inflated =
+                        inflate_inner(&output_path, options.keep_raw, INFLATE_INNER_MAX_SIZE)?;
+                    #[cfg(feature = "tracing")]
+                    if inflated.is_some() {
+                        tracing::info!(entry_id, path = %entry_path, "inflated inner zlib stream");
+                    }
+                    Ok(inflated.unwrap_or(data))
+                });
+            match result {
+                Ok(data) => written = Some(data),
+                Err(error) => {
+                    on_event(ExtractEvent::Failed {
+                        entry_id,
+                        entry_path: &entry_path,
+                        error: &error,
+                    });
+                    return Err(error);
+                }
+            }
+            if let Some(mtime) = mtime {
+                set_file_mtime(&output_path, mtime)?;
+            }
+            if let Some(journal) = journal.as_mut() {
+                journal.complete(entry_id)?;
+            }
+        }
+
+        on_event(ExtractEvent::Extracted(&ExtractedFile {
+            path: output_path
+                .strip_prefix(output_root)
+                .unwrap_or(&output_path)
+                .to_path_buf(),
+            pak_path: pak_path.to_path_buf(),
+            entry_id,
+            hash: pak.entry_hash(entry_id)?,
+            resumed,
+            renamed,
+            written,
+            entry_path,
+        }));
+    }
+    Ok(report)
+}
+
+/// What happened to an entry, see [`ManifestRow`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestStatus {
+    Ok,
+    /// An earlier run extracted the entry, see [`ExtractOptions::resume`].
+    Resumed,
+    Skipped(String),
+    Failed(String),
+}
+
+/// A line of the [`Manifest`]: an entry [`extract_pak`] went through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestRow {
+    pub pak_path: PathBuf,
+    pub entry_id: u64,
+    pub entry_path: String,
+    /// Path of the file relative to the output directory, if one was
+    /// written now or earlier.
+    pub output_path: Option<PathBuf>,
+    pub written: Option<WrittenData>,
+    pub status: ManifestStatus,
+}
+
+impl ManifestRow {
+    pub fn from_event(pak_path: &Path, event: &ExtractEvent) -> Self {
+        let row = |entry_id, entry_path: &str, status| Self {
+            pak_path: pak_path.to_path_buf(),
+            entry_id,
+            entry_path: entry_path.to_string(),
+            output_path: None,
+            written: None,
+            status,
+        };
+        match *event {
+            ExtractEvent::Extracted(file) => Self {
+                output_path: Some(file.path.clone()),
+                written: file.written,
+                ..row(
+                    file.entry_id,
+                    &file.entry_path,
+                    if file.resumed {
+                        ManifestStatus::Resumed
+                    } else {
+                        ManifestStatus::Ok
+                    },
+                )
+            },
+            ExtractEvent::Skipped {
+                entry_id,
+                entry_path,
+                reason,
+            } => row(
+                entry_id,
+                entry_path,
+                ManifestStatus::Skipped(reason.to_string()),
+            ),
+            ExtractEvent::Failed {
+                entry_id,
+                entry_path,
+                error,
+            } => row(
+                entry_id,
+                entry_path,
+                ManifestStatus::Failed(error.to_string()),
+            ),
+        }
+    }
+}
+
+/// The rows of an [`extract_pak`] run, the same the [`Manifest`] gets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    pub rows: Vec<ManifestRow>,
+}
+
+/// Counts of what [`extract_pak`] did, across any number of paks.
+///
+/// ```rust
+/// use gfp::pak_reader::extract::{ExtractEvent, ExtractTotals};
+///
+/// let mut totals = ExtractTotals::default();
+/// totals.record(&ExtractEvent::Skipped {
+///     entry_id: 0,
+///     entry_path: "a.lua",
+///     reason: "Not enough path components to strip",
+/// });
+/// assert_eq!(totals.skipped, 1);
+/// assert_eq!(totals.entries(), 1);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExtractTotals {
+    /// Entries written by this run.
+    pub extracted: u64,
+    /// Entries an earlier run wrote, see [`ExtractOptions::resume`].
+    pub resumed: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    /// Bytes written by this run.
+    pub bytes_written: u64,
+}
+
+impl ExtractTotals {
+    pub fn record(&mut self, event: &ExtractEvent) {
+        match event {
+            ExtractEvent::Extracted(file) if file.resumed => self.resumed += 1,
+            ExtractEvent::Extracted(file) => {
+                self.extracted += 1;
+                self.bytes_written += file.written.map_or(0, |written| written.length);
+            }
+            ExtractEvent::Skipped { .. } => self.skipped += 1,
+            ExtractEvent::Failed { .. } => self.failed += 1,
+        }
+    }
+
+    /// Every entry counted, whatever happened to it.
+    pub fn entries(&self) -> u64 {
+        self.extracted + self.resumed + self.skipped + self.failed
+    }
+}
+
+/// A TSV record of every entry an unpack went through, written a line at a
+/// time so an interrupted run leaves every line before the interruption:
+///
+/// ```text
+/// pak<TAB>entry_id<TAB>entry_path<TAB>output_path<TAB>bytes<TAB>sha1<TAB>status<TAB>reason
+/// Paks/game_patch_1.32.11.13846.pak<TAB>3<TAB>ShadowTrackerExtra/Content/Lua/common/lua_object.lua<TAB>ShadowTrackerExtra/Content/Lua/common/lua_object.lua<TAB>4853<TAB><sha1 hex><TAB>ok<TAB>
+/// ```
+///
+/// `status` is `ok`, `resumed`, `skipped` or `failed`, the last two with a
+/// `reason`. `bytes` and `sha1` describe what was written and are empty
+/// unless `ok`; `output_path` is empty for skipped and failed entries.
+/// Paths use `/` on every platform.
+#[derive(Debug)]
+pub struct Manifest {
+    file: File,
+}
+
+impl Manifest {
+    /// Create the manifest at `path`, replacing any earlier one.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(path)?;
+        file.write_all(b"pak\tentry_id\tentry_path\toutput_path\tbytes\tsha1\tstatus\treason\n")?;
+        Ok(Self { file })
+    }
+
+    pub fn append(&mut self, row: &ManifestRow) -> std::io::Result<()> {
+        let (status, reason) = match &row.status {
+            ManifestStatus::Ok => ("ok", ""),
+            ManifestStatus::Resumed => ("resumed", ""),
+            ManifestStatus::Skipped(reason) => ("skipped", reason.as_str()),
+            ManifestStatus::Failed(reason) => ("failed", reason.as_str()),
+        };
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            row.pak_path.to_string_lossy(),
+            row.entry_id,
+            row.entry_path,
+            row.output_path
+                .as_deref()
+                .map(slash_path)
+                .unwrap_or_default(),
+            row.written
+                .map(|data| data.length.to_string())
+                .unwrap_or_default(),
+            row.written
+                .map(|data| hex::encode(data.sha1))
+                .unwrap_or_default(),
+            status,
+            reason
+        );
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// `path` with `/` between its components.
+pub(crate) fn slash_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// The ids of the entries of a pak that are completely extracted, one per
+/// line in a `.gfpresume` file that only ever grows.
+///
+/// An id is appended once its file has been renamed into place, so an
+/// interrupted run leaves at worst an entry that is extracted again, never
+/// one that is listed but incomplete. A last line cut short by the
+/// interruption is dropped when the journal is opened.
+#[derive(Debug)]
+pub struct ResumeJournal {
+    file: File,
+    completed: HashSet<u64>,
+}
+
+impl ResumeJournal {
+    pub const EXTENSION: &'static str = "gfpresume";
+
+    /// Where the journal of the pak at `pak_path` is kept: in the pak's
+    /// output directory, named after the pak file, e.g.
+    /// `out/game_patch_1.32.11.13846.pak.gfpresume`.
+    pub fn path(output_dir: &Path, pak_path: &Path) -> PathBuf {
+        let mut file_name = pak_path
+            .file_name()
+            .unwrap_or(pak_path.as_os_str())
+            .to_os_string();
+        file_name.push(".");
+        file_name.push(Self::EXTENSION);
+        output_dir.join(file_name)
+    }
+
+    /// Open the journal at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let content = std::fs::read(path)?;
+        let complete_length = content
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |index| index + 1);
+        if complete_length != content.len() {
+            file.set_len(complete_length as u64)?;
+        }
+
+        let completed = String::from_utf8_lossy(&content[..complete_length])
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect();
+        Ok(Self { file, completed })
+    }
+
+    pub fn is_completed(&self, entry_id: u64) -> bool {
+        self.completed.contains(&entry_id)
+    }
+
+    /// Record that `entry_id` is completely extracted.
+    pub fn complete(&mut self, entry_id: u64) -> std::io::Result<()> {
+        if self.completed.insert(entry_id) {
+            self.file.write_all(format!("{}\n", entry_id).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Which pak and entry each extracted file came from, saved as
+/// `provenance.tsv` in the output directory:
+///
+/// ```text
+/// path<TAB>pak<TAB>entry_id<TAB>hash
+/// ShadowTrackerExtra/Content/Lua/common/lua_object.lua<TAB>Paks/game_patch_1.32.11.13846.pak<TAB>3<TAB><sha1 hex>
+/// ```
+///
+/// Paths use `/` on every platform. A file written several times is listed
+/// once, with the pak that wrote it last.
+#[derive(Debug, Default)]
+pub struct Provenance {
+    files: BTreeMap<PathBuf, ExtractedFile>,
+}
+
+impl Provenance {
+    pub const FILE_NAME: &'static str = "provenance.tsv";
+
+    pub fn record(&mut self, file: &ExtractedFile) {
+        self.files.insert(file.path.clone(), file.clone());
+    }
+
+    /// The recorded files, ordered by path.
+    pub fn files(&self) -> impl Iterator<Item = &ExtractedFile> {
+        self.files.values()
+    }
+
+    /// Write `provenance.tsv` into `output_root`.
+    pub fn save(&self, output_root: &Path) -> std::io::Result<()> {
+        let mut content = String::from("path\tpak\tentry_id\thash\n");
+        for file in self.files() {
+            content.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                slash_path(&file.path),
+                file.pak_path.to_string_lossy(),
+                file.entry_id,
+                hex::encode(file.hash)
+            ));
+        }
+        std::fs::create_dir_all(output_root)?;
+        File::create(output_root.join(Self::FILE_NAME))?.write_all(content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::{new_pak, open_pak, open_pak_mapped};
+    use crate::utils::read_at::ReadAt;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+    const PAK_2: &str = "test/normal/game_patch_1.32.11.13992.pak";
+
+    #[test]
+    fn test_extract_per_pak_with_provenance() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let output_root = temp_dir.path();
+        let options = ExtractOptions {
+            layout: OutputLayout::PerPak,
+            ..Default::default()
+        };
+
+        let mut provenance = Provenance::default();
+        let mut pak = open_pak(PAK_1, 10)?;
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            output_root,
+            &options,
+            &CancellationToken::new(),
+            |event| {
+                if let ExtractEvent::Extracted(file) = event {
+                    provenance.record(file);
+                }
+            },
+        )?;
+        provenance.save(output_root)?;
+
+        let lua_object = Path::new("game_patch_1.32.11.13846")
+            .join("ShadowTrackerExtra/Content/Lua/common/lua_object.lua");
+        assert!(output_root.join(&lua_object).is_file());
+        assert_eq!(provenance.files().count() as u64, pak.entries_count()?);
+
+        let content = std::fs::read_to_string(output_root.join(Provenance::FILE_NAME))?;
+        let entry_id = pak
+            .find_entry_by_path("ShadowTrackerExtra/Content/Lua/common/lua_object.lua")?
+            .unwrap();
+        let line = format!(
+            "game_patch_1.32.11.13846/ShadowTrackerExtra/Content/Lua/common/lua_object.lua\t{}\t{}\t{}",
+            PAK_1,
+            entry_id,
+            hex::encode(pak.entry_hash(entry_id)?)
+        );
+        assert!(content.starts_with("path\tpak\tentry_id\thash\n"));
+        assert!(content.lines().any(|l| l == line), "{}", content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_changed_pak() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::{Seek, SeekFrom};
+
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("game_patch_1.32.11.13846.pak");
+        std::fs::copy(PAK_1, &pak_path)?;
+        let output_root = temp_dir.path().join("out");
+        let mut pak = open_pak(&pak_path, 10)?;
+        assert_eq!(pak.entries_count()?, 7);
+
+        // The game rewrites the footer between listing and extraction
+        let mut file = OpenOptions::new().write(true).open(&pak_path)?;
+        file.seek(SeekFrom::End(-30))?;
+        file.write_all(&[0xAA; 20])?;
+        drop(file);
+
+        let mut events = 0;
+        let result = extract_pak(
+            pak.as_mut(),
+            &pak_path,
+            &output_root,
+            &ExtractOptions::default(),
+            &CancellationToken::new(),
+            |_| events += 1,
+        );
+        assert!(
+            matches!(&result, Err(PakError::PakChanged { path, .. }) if *path == pak_path),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(events, 0);
+        assert!(!output_root.exists());
+
+        let options = ExtractOptions::default().with_ignore_changes(true);
+        let report = extract_pak(
+            pak.as_mut(),
+            &pak_path,
+            &output_root,
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+        assert_eq!(report.rows.len(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_resume() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let output_root = temp_dir.path();
+        let options = ExtractOptions {
+            resume: true,
+            ..Default::default()
+        };
+        let mut pak = open_pak(PAK_1, 10)?;
+        let entries_count = pak.entries_count()?;
+
+        // Interrupted after half of the entries
+        let cancel = CancellationToken::new();
+        let mut written = vec![];
+        let result = extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            output_root,
+            &options,
+            &cancel,
+            |event| {
+                if let ExtractEvent::Extracted(file) = event {
+                    written.push(file.entry_id);
+                    if written.len() as u64 == entries_count / 2 {
+                        cancel.cancel();
+                    }
+                }
+            },
+        );
+        assert!(matches!(result, Err(PakError::Cancelled)));
+        assert_eq!(written.len() as u64, entries_count / 2);
+
+        // A line cut short by the interruption
+        let journal_path = ResumeJournal::path(output_root, Path::new(PAK_1));
+        OpenOptions::new()
+            .append(true)
+            .open(&journal_path)?
+            .write_all(b"6")?;
+
+        let mut resumed = vec![];
+        let mut totals = ExtractTotals::default();
+        let mut written_length = 0;
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            output_root,
+            &options,
+            &CancellationToken::new(),
+            |event| {
+                totals.record(&event);
+                let ExtractEvent::Extracted(file) = event else {
+                    panic!("{:?}", event);
+                };
+                assert!(output_root.join(&file.path).is_file());
+                if file.resumed {
+                    resumed.push(file.entry_id);
+                } else {
+                    written.push(file.entry_id);
+                    written_length += std::fs::metadata(output_root.join(&file.path))
+                        .unwrap()
+                        .len();
+                }
+            },
+        )?;
+        assert_eq!(resumed, (0..entries_count / 2).collect::<Vec<_>>());
+        assert_eq!(
+            totals,
+            ExtractTotals {
+                extracted: entries_count - entries_count / 2,
+                resumed: entries_count / 2,
+                skipped: 0,
+                failed: 0,
+                bytes_written: written_length,
+            }
+        );
+        assert_eq!(totals.entries(), entries_count);
+        written.sort();
+        assert_eq!(written, (0..entries_count).collect::<Vec<_>>());
+
+        let journal = std::fs::read_to_string(&journal_path)?;
+        let mut journal_ids: Vec<u64> = journal
+            .lines()
+            .map(|line| line.parse())
+            .collect::<Result<_, _>>()?;
+        journal_ids.sort();
+        assert_eq!(journal_ids, (0..entries_count).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_ids() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let options = ExtractOptions {
+            entry_ids: Some(vec![2, 0, 2]),
+            ..Default::default()
+        };
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+
+        let mut files = vec![];
+        let mut dirs = vec![temp_dir.path().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for dir_entry in std::fs::read_dir(dir)? {
+                let path = dir_entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path.strip_prefix(temp_dir.path())?.to_path_buf());
+                }
+            }
+        }
+        files.sort();
+        let expected: Vec<PathBuf> = [0, 2]
+            .into_iter()
+            .map(|entry_id| Ok(PathBuf::from(pak.get_entry_path(entry_id)?)))
+            .collect::<Result<_, PakError>>()?;
+        assert_eq!(files, expected);
+
+        let options = ExtractOptions {
+            entry_ids: Some(vec![0, pak.entries_count()?]),
+            ..Default::default()
+        };
+        let mut events = 0;
+        let result = extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |_| events += 1,
+        );
+        assert!(matches!(result, Err(PakError::InvalidData(_))));
+        assert_eq!(events, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_filter() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let excluded = HashSet::from([pak.get_entry_path(1)?]);
+        let options = ExtractOptions {
+            entry_ids: Some(vec![0, 1, 2, 3]),
+            filter: EntryFilter::Not(Box::new(EntryFilter::Set(excluded))),
+            ..Default::default()
+        };
+        let report = extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+        let extracted: Vec<u64> = report.rows.iter().map(|row| row.entry_id).collect();
+        assert_eq!(extracted, [0, 2, 3]);
+        assert!(!temp_dir.path().join(pak.get_entry_path(1)?).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let mut extract = |mtime| -> Result<Vec<SystemTime>, Box<dyn std::error::Error>> {
+            let options = ExtractOptions {
+                mtime,
+                ..Default::default()
+            };
+            let mut paths = vec![];
+            extract_pak(
+                pak.as_mut(),
+                Path::new(PAK_1),
+                temp_dir.path(),
+                &options,
+                &CancellationToken::new(),
+                |event| {
+                    if let ExtractEvent::Extracted(file) = event {
+                        paths.push(temp_dir.path().join(&file.path));
+                    }
+                },
+            )?;
+            Ok(paths
+                .iter()
+                .map(|path| std::fs::metadata(path)?.modified())
+                .collect::<std::io::Result<_>>()?)
+        };
+
+        let fixed: MtimePolicy = "fixed:2024-05-01T12:00:00Z".parse()?;
+        let MtimePolicy::Fixed(fixed_time) = fixed else {
+            unreachable!()
+        };
+        let mtimes = extract(fixed)?;
+        assert!(!mtimes.is_empty());
+        assert!(mtimes.iter().all(|&mtime| mtime == fixed_time));
+
+        let pak_time = std::fs::metadata(PAK_1)?.modified()?;
+        assert!(
+            extract(MtimePolicy::Pak)?
+                .iter()
+                .all(|&mtime| mtime == pak_time)
+        );
+
+        let before = SystemTime::now() - std::time::Duration::from_secs(1);
+        assert!(
+            extract(MtimePolicy::Now)?
+                .iter()
+                .all(|&mtime| mtime >= before)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_options_toml() -> Result<(), Box<dyn std::error::Error>> {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mtime = UNIX_EPOCH + Duration::from_millis(1_714_564_800_250);
+        let options = ExtractOptions::default()
+            .with_layout(OutputLayout::PerPak)
+            .with_resume(true)
+            .with_strip_components(3)
+            .with_mtime(MtimePolicy::Fixed(mtime))
+            .with_sanitize_names(true)
+            .with_entry_ids([2, 0])
+            .with_read_strategy(ReadStrategy::Prefetch)
+            .with_path_rewrites(["ShadowTrackerExtra/Content/=content/".parse()?])
+            .with_rewrite_strict(true);
+        let toml = options.to_toml();
+        assert!(
+            toml.contains("mtime = \"fixed:2024-05-01T12:00:00.25Z\"\n"),
+            "{}",
+            toml
+        );
+        let parsed = ExtractOptions::from_toml(&toml)?;
+        assert_eq!(parsed.to_toml(), toml);
+        assert_eq!(parsed.layout, OutputLayout::PerPak);
+        assert!(parsed.resume && parsed.sanitize_names);
+        assert_eq!(parsed.strip_components, 3);
+        assert_eq!(parsed.mtime, MtimePolicy::Fixed(mtime));
+        assert_eq!(parsed.entry_ids, Some(vec![2, 0]));
+        assert_eq!(parsed.read_strategy, ReadStrategy::Prefetch);
+        assert_eq!(parsed.path_rewrites, options.path_rewrites);
+        assert!(parsed.rewrite_strict);
+        assert!(parsed.filter.is_all());
+
+        // Options read from a file extract like those set in code
+        let temp_dir = TempDir::new()?;
+        let options_path = temp_dir.path().join("extract.toml");
+        std::fs::write(&options_path, "strip-components = 1\nentry-ids = [3]\n")?;
+        let options = ExtractOptions::from_toml_file(&options_path)?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let report = extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+        assert_eq!(report.rows.len(), 1);
+        let entry_path = pak.get_entry_path(3)?;
+        let stripped = strip_components(&entry_path, 1).unwrap();
+        assert!(temp_dir.path().join(stripped).is_file());
+
+        for invalid in [
+            "strip_components = 1",
+            "mtime = \"yesterday\"",
+            "layout = \"flat\"",
+            "resume = \"yes\"",
+            "path-rewrites = [\"content\"]",
+        ] {
+            assert!(ExtractOptions::from_toml(invalid).is_err(), "{}", invalid);
+        }
+        assert!(ExtractOptions::from_toml_file(temp_dir.path().join("missing.toml")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_strip_components() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let output_root = temp_dir.path();
+        let options = ExtractOptions {
+            strip_components: 1,
+            ..Default::default()
+        };
+
+        let mut pak = open_pak(PAK_1, 10)?;
+        let mut paths = vec![];
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            output_root,
+            &options,
+            &CancellationToken::new(),
+            |event| match event {
+                ExtractEvent::Extracted(file) => paths.push(file.path.clone()),
+                ExtractEvent::Skipped { .. } | ExtractEvent::Failed { .. } => {
+                    panic!("{:?}", event)
+                }
+            },
+        )?;
+
+        assert_eq!(paths.len() as u64, pak.entries_count()?);
+        assert!(paths.iter().all(|path| path.starts_with("Content")));
+        assert!(
+            output_root
+                .join("Content/Lua/common/lua_object.lua")
+                .is_file()
+        );
+        assert!(!output_root.join("ShadowTrackerExtra").exists());
+
+        // Stripping everything leaves nothing to write
+        let options = ExtractOptions {
+            strip_components: 100,
+            ..Default::default()
+        };
+        let mut skipped = 0;
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            &output_root.join("empty"),
+            &options,
+            &CancellationToken::new(),
+            |event| match event {
+                ExtractEvent::Skipped { .. } => skipped += 1,
+                _ => panic!("{:?}", event),
+            },
+        )?;
+        assert_eq!(skipped, pak.entries_count()?);
+        assert!(!output_root.join("empty").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_report_and_manifest() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let output_root = temp_dir.path().join("out");
+        let manifest_path = temp_dir.path().join("manifest.tsv");
+        let options = ExtractOptions {
+            strip_components: 5,
+            resume: true,
+            ..Default::default()
+        };
+
+        let mut pak = open_pak(PAK_1, 10)?;
+        let extract = |pak: &mut dyn PakReader| -> Result<_, Box<dyn std::error::Error>> {
+            let mut manifest = Manifest::create(&manifest_path)?;
+            let mut result = Ok(());
+            let report = extract_pak(
+                pak,
+                Path::new(PAK_1),
+                &output_root,
+                &options,
+                &CancellationToken::new(),
+                |event| {
+                    if result.is_ok() {
+                        result =
+                            manifest.append(&ManifestRow::from_event(Path::new(PAK_1), &event));
+                    }
+                },
+            )?;
+            result?;
+            Ok(report)
+        };
+
+        let report = extract(pak.as_mut())?;
+        assert_eq!(report.rows.len() as u64, pak.entries_count()?);
+        for row in &report.rows {
+            match &row.status {
+                ManifestStatus::Ok => {
+                    let content =
+                        std::fs::read(output_root.join(row.output_path.as_ref().unwrap()))?;
+                    assert_eq!(
+                        row.written,
+                        Some(WrittenData {
+                            length: content.len() as u64,
+                            sha1: sha1_smol::Sha1::from(&content).digest().bytes(),
+                        })
+                    );
+                }
+                ManifestStatus::Skipped(_) => assert_eq!(row.output_path, None),
+                status => panic!("{:?}", status),
+            }
+        }
+
+        let content = std::fs::read_to_string(&manifest_path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), report.rows.len() + 1);
+        let lua_row = &report.rows[2];
+        assert_eq!(
+            lines[3],
+            format!(
+                "{}\t2\tShadowTrackerExtra/Content/Lua/client/logic/login/logic_pakversion.lua\tlogin/logic_pakversion.lua\t181\t{}\tok\t",
+                PAK_1,
+                hex::encode(lua_row.written.unwrap().sha1)
+            )
+        );
+        assert_eq!(
+            lines[4],
+            format!(
+                "{}\t3\tShadowTrackerExtra/Content/Lua/common/lua_object.lua\t\t\t\tskipped\tNot enough path components to strip",
+                PAK_1
+            )
+        );
+
+        // A second run only resumes
+        let report = extract(pak.as_mut())?;
+        assert!(report.rows.iter().all(|row| matches!(
+            (&row.status, row.written),
+            (ManifestStatus::Resumed | ManifestStatus::Skipped(_), None)
+        )));
+        assert!(std::fs::read_to_string(&manifest_path)?.contains("\tresumed\t"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_sanitize_names() -> Result<(), Box<dyn std::error::Error>> {
+        // The index isn't encrypted, so the path can be edited in place
+        let mut data = std::fs::read(PAK_1)?;
+        let name = data
+            .windows(b"lua_object.lua".len())
+            .rposition(|window| window == b"lua_object.lua")
+            .unwrap();
+        data[name + 6] = b':';
+        let mut pak = new_pak(data, 10)?;
+        assert_eq!(
+            pak.get_entry_path(3)?,
+            "ShadowTrackerExtra/Content/Lua/common/lua_ob:ect.lua"
+        );
+
+        let temp_dir = TempDir::new()?;
+        let options = ExtractOptions {
+            sanitize_names: true,
+            strip_components: 2,
+            entry_ids: Some(vec![2, 3]),
+            ..Default::default()
+        };
+        let mut renamed = vec![];
+        extract_pak(
+            pak.as_mut(),
+            Path::new("a.pak"),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |event| {
+                if let ExtractEvent::Extracted(file) = event {
+                    renamed.push((file.entry_id, file.renamed));
+                }
+            },
+        )?;
+        assert_eq!(renamed, [(2, false), (3, true)]);
+        assert!(temp_dir.path().join("Lua/common/lua_ob_ect.lua").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_path_rewrites() -> Result<(), Box<dyn std::error::Error>> {
+        let rewrites = |rules: &[&str]| -> Result<Vec<PathRewrite>, String> {
+            rules.iter().map(|rule| rule.parse()).collect()
+        };
+        let extract = |options: &ExtractOptions| -> Result<Vec<(u64, PathBuf)>, PakError> {
+            let temp_dir = TempDir::new()?;
+            let mut pak = open_pak(PAK_1, 10)?;
+            let mut written = vec![];
+            extract_pak(
+                pak.as_mut(),
+                Path::new(PAK_1),
+                temp_dir.path(),
+                options,
+                &CancellationToken::new(),
+                |event| {
+                    if let ExtractEvent::Extracted(file) = event {
+                        assert!(temp_dir.path().join(&file.path).is_file());
+                        written.push((file.entry_id, file.path.clone()));
+                    }
+                },
+            )?;
+            Ok(written)
+        };
+
+        // Overlapping prefixes: the first rule that matches wins, so the
+        // longer one has to come first
+        let options = ExtractOptions::default().with_path_rewrites(rewrites(&[
+            "ShadowTrackerExtra/Content/Lua/=lua/",
+            "ShadowTrackerExtra/Content/=content/",
+        ])?);
+        let written = extract(&options)?;
+        assert_eq!(written.len(), 7);
+        assert_eq!(written[3], (3, PathBuf::from("lua/common/lua_object.lua")));
+        assert_eq!(
+            written[6].1,
+            Path::new("content/ShaderMaps/2FEC6CAD4916C29E73026D9DD243B1F8.nt")
+        );
+        let options = ExtractOptions::default().with_path_rewrites(rewrites(&[
+            "ShadowTrackerExtra/Content/=content/",
+            "ShadowTrackerExtra/Content/Lua/=lua/",
+        ])?);
+        assert_eq!(
+            extract(&options)?[3].1,
+            Path::new("content/Lua/common/lua_object.lua")
+        );
+
+        // Rewrites come before stripping components, and entries no rule
+        // matches keep their path
+        let options = ExtractOptions::default()
+            .with_path_rewrites(rewrites(&["ShadowTrackerExtra/Content/Lua/=lua/"])?)
+            .with_strip_components(1);
+        let written = extract(&options)?;
+        assert_eq!(
+            written[2].1,
+            Path::new("client/logic/login/logic_pakversion.lua")
+        );
+        assert_eq!(
+            written[6].1,
+            Path::new("Content/ShaderMaps/2FEC6CAD4916C29E73026D9DD243B1F8.nt")
+        );
+
+        // Two entries rewritten onto one path: the later one overwrites the
+        // earlier, as paks do in the merged layout
+        let options = ExtractOptions::default()
+            .with_path_rewrites(rewrites(&[
+                "ShadowTrackerExtra/Content/AR/PF/WScene/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.uexp=sky.uexp",
+                "ShadowTrackerExtra/Content/Platform/WR/Arts_Scenes/_Baltic_PC/Sky/MI_Sky_Baltic_Clear.uexp=sky.uexp",
+            ])?)
+            .with_entry_ids([0, 5]);
+        let written = extract(&options)?;
+        assert_eq!(
+            written,
+            [
+                (0, PathBuf::from("sky.uexp")),
+                (5, PathBuf::from("sky.uexp"))
+            ]
+        );
+        let temp_dir = TempDir::new()?;
+        let mut pak = open_pak(PAK_1, 10)?;
+        let mut provenance = Provenance::default();
+        extract_pak(
+            pak.as_mut(),
+            Path::new(PAK_1),
+            temp_dir.path(),
+            &options,
+            &CancellationToken::new(),
+            |event| {
+                if let ExtractEvent::Extracted(file) = event {
+                    provenance.record(file);
+                }
+            },
+        )?;
+        let mut expected = vec![];
+        pak.extract_entry_to_writer(5, &mut expected)?;
+        assert_eq!(std::fs::read(temp_dir.path().join("sky.uexp"))?, expected);
+        assert_eq!(
+            provenance
+                .files()
+                .map(|file| file.entry_id)
+                .collect::<Vec<_>>(),
+            [5]
+        );
+
+        // Strict rewrites fail on the first entry no rule matches
+        let options = ExtractOptions::default()
+            .with_path_rewrites(rewrites(&["ShadowTrackerExtra/Content/Lua/=lua/"])?)
+            .with_rewrite_strict(true);
+        assert_eq!(
+            extract(&options).unwrap_err().to_string(),
+            "Invalid data: No path rewrite matches \
+             ShadowTrackerExtra/Content/AR/PF/WScene/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.uexp"
+        );
+        let written = extract(&options.with_entry_ids([2, 3]))?;
+        assert_eq!(
+            written[0].1,
+            Path::new("lua/client/logic/login/logic_pakversion.lua")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_provenance_keeps_last_writer() {
+        let file = |pak_path: &str, entry_id| ExtractedFile {
+            path: PathBuf::from("a/b.lua"),
+            pak_path: PathBuf::from(pak_path),
+            entry_id,
+            entry_path: "a/b.lua".to_string(),
+            hash: [0; 20],
+            resumed: false,
+            renamed: false,
+            written: None,
+        };
+        let mut provenance = Provenance::default();
+        provenance.record(&file("a.pak", 1));
+        provenance.record(&file("b.pak", 2));
+        assert_eq!(
+            provenance.files().collect::<Vec<_>>(),
+            vec![&file("b.pak", 2)]
+        );
+    }
+
+    #[test]
+    fn test_extract_escaping_entry_path() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::pak_reader::plan::PlannedPak;
+
+        // The directory of entry 3 renamed to one climbing out of the output
+        let mut data = std::fs::read(PAK_1)?;
+        let (from, to) = (b"Lua/common/\0", b"../../../x/\0");
+        let positions: Vec<_> = (0..data.len() - from.len())
+            .filter(|&at| data[at..at + from.len()] == from[..])
+            .collect();
+        assert_eq!(positions.len(), 1);
+        data[positions[0]..positions[0] + to.len()].copy_from_slice(to);
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("crafted.pak");
+        std::fs::write(&pak_path, data)?;
+
+        let mut pak = open_pak(&pak_path, 10)?;
+        assert_eq!(
+            pak.get_entry_path(3)?,
+            "ShadowTrackerExtra/Content/../../../x/lua_object.lua"
+        );
+        let output_root = temp_dir.path().join("out/deep");
+        let mut skipped = vec![];
+        let report = extract_pak(
+            pak.as_mut(),
+            &pak_path,
+            &output_root,
+            &ExtractOptions::default(),
+            &CancellationToken::new(),
+            |event| {
+                if let ExtractEvent::Skipped {
+                    entry_id, reason, ..
+                } = event
+                {
+                    skipped.push((entry_id, reason));
+                }
+            },
+        )?;
+        assert_eq!(skipped, [(3, "Path leaves the output directory")]);
+        assert_eq!(report.rows.len(), 7);
+        assert!(!temp_dir.path().join("out/x").exists());
+        assert!(!temp_dir.path().join("x").exists());
+
+        // Plans leave it out too, so that apply doesn't refuse them
+        let planned = PlannedPak::plan(pak.as_mut(), &pak_path, &ExtractOptions::default())?;
+        assert_eq!(planned.entries.len(), 6);
+        assert!(planned.entries.iter().all(|entry| entry.entry_id != 3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inflate_inner() -> Result<(), Box<dyn std::error::Error>> {
+        use flate2::{Compression, write::ZlibEncoder};
+
+        let temp_dir = TempDir::new()?;
+        let content = b"[/Script/Engine.RendererSettings]\nr.MobileHDR=False\n".repeat(40);
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&content)?;
+        let zlib = encoder.finish()?;
+        assert_eq!(zlib[..2], [0x78, 0x9C]);
+
+        let path = temp_dir.path().join("DeviceProfiles.ini");
+        std::fs::write(&path, &zlib)?;
+        let written = inflate_inner(&path, false, 1 << 20)?.unwrap();
+        assert_eq!(written.length, content.len() as u64);
+        assert_eq!(
+            written.sha1,
+            sha1_smol::Sha1::from(&content).digest().bytes()
+        );
+        assert_eq!(std::fs::read(&path)?, content);
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+
+        // Files that only look like zlib are left as they are
+        let mut lookalike = vec![0x78, 0x9C];
+        lookalike.extend((0..200u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+        let mut trailing = zlib.clone();
+        trailing.extend_from_slice(b"trailer");
+        let mut bad_checksum = zlib.clone();
+        *bad_checksum.last_mut().unwrap() ^= 1;
+        for (name, data, max_size) in [
+            ("lookalike.bin", lookalike, 1 << 20),
+            (
+                "bad_header.bin",
+                [&[0x78, 0x9D][..], &zlib[2..]].concat(),
+                1 << 20,
+            ),
+            ("truncated.ini", zlib[..zlib.len() / 2].to_vec(), 1 << 20),
+            ("trailing.ini", trailing, 1 << 20),
+            ("bad_checksum.ini", bad_checksum, 1 << 20),
+            ("too_large.ini", zlib.clone(), content.len() as u64 - 1),
+            ("short.bin", vec![0x78], 1 << 20),
+        ] {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, &data)?;
+            assert_eq!(inflate_inner(&path, true, max_size)?, None, "{name}");
+            assert_eq!(std::fs::read(&path)?, data, "{name}");
+        }
+        assert!(
+            std::fs::read_dir(temp_dir.path())?.all(|entry| entry
+                .unwrap()
+                .path()
+                .extension()
+                .unwrap()
+                != "part")
+        );
+        assert!(!temp_dir.path().join("lookalike.bin.z").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_inflate_inner() -> Result<(), Box<dyn std::error::Error>> {
+        use flate2::{Compression, write::ZlibEncoder};
+
+        // Entries 2 and 6 are stored: the first becomes a whole zlib stream
+        // of the same length, the second one that only starts like one
+        let mut data = std::fs::read(PAK_1)?;
+        let stored = |data: &[u8], entry_id| -> Result<std::ops::Range<usize>, PakError> {
+            match new_pak(data, 10)?.entry_layout(entry_id)?.payload {
+                crate::pak_reader::EntryPayload::Stored { offset, length } => {
+                    Ok(offset as usize..(offset + length) as usize)
+                }
+                payload => panic!("{:?}", payload),
+            }
+        };
+        let inner = stored(&data, 2)?;
+        // A stored deflate block adds 11 bytes of framing
+        let content: Vec<u8> = b"[Core.Log]\nLogPakFile=Verbose\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(inner.len() - 11)
+            .collect();
+        let mut encoder = ZlibEncoder::new(vec![], Compression::none());
+        encoder.write_all(&content)?;
+        let zlib = encoder.finish()?;
+        data[inner].copy_from_slice(&zlib);
+        let lookalike = stored(&data, 6)?;
+        data[lookalike.start..lookalike.start + 2].copy_from_slice(&[0x78, 0x9C]);
+
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("inner.pak");
+        std::fs::write(&pak_path, &data)?;
+        let extract = |options: &ExtractOptions, output_root: &Path| {
+            let mut pak = open_pak(&pak_path, 10)?;
+            extract_pak(
+                pak.as_mut(),
+                &pak_path,
+                output_root,
+                options,
+                &CancellationToken::new(),
+                |_| {},
+            )
+        };
+        let plain = extract(&ExtractOptions::default(), &temp_dir.path().join("plain"))?;
+        let options = ExtractOptions::default()
+            .with_inflate_inner(true)
+            .with_keep_raw(true);
+        let inflated = extract(&options, &temp_dir.path().join("inflated"))?;
+        assert_eq!(inflated.rows.len(), 7);
+
+        for (entry_id, (plain, inflated)) in plain.rows.iter().zip(&inflated.rows).enumerate() {
+            let output_path = inflated.output_path.as_ref().unwrap();
+            let inflated_path = temp_dir.path().join("inflated").join(output_path);
+            let plain_path = temp_dir.path().join("plain").join(output_path);
+            let raw_path = with_suffix(&inflated_path, ".z");
+            if entry_id == 2 {
+                assert_eq!(std::fs::read(&plain_path)?, zlib);
+                assert_eq!(std::fs::read(&inflated_path)?, content);
+                assert_eq!(std::fs::read(&raw_path)?, zlib);
+                let written = inflated.written.unwrap();
+                assert_eq!(written.length, content.len() as u64);
+                assert_eq!(
+                    written.sha1,
+                    sha1_smol::Sha1::from(&content).digest().bytes()
+                );
+            } else {
+                assert_eq!(inflated.written, plain.written, "{}", entry_id);
+                assert_eq!(std::fs::read(&inflated_path)?, std::fs::read(&plain_path)?);
+                assert!(!raw_path.exists(), "{}", entry_id);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_atomic_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+        /// Once a token is set, the next read trips it, like Ctrl-C in the
+        /// middle of an extraction
+        struct CancelOnRead {
+            data: Vec<u8>,
+            cancel: Mutex<Option<CancellationToken>>,
+        }
+        impl ReadAt for CancelOnRead {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+                if let Some(cancel) = self.cancel.lock().unwrap().as_ref() {
+                    cancel.cancel();
+                }
+                self.data.read_at(buf, offset)
+            }
+            fn size(&self) -> std::io::Result<u64> {
+                self.data.size()
+            }
+        }
+
+        let source = CancelOnRead {
+            data: std::fs::read(PAK_2)?,
+            cancel: Mutex::new(None),
+        };
+        let mut pak = new_pak(&source, 10)?;
+        pak.get_entry_path(0)?;
+
+        let temp_dir = TempDir::new()?;
+        let mut cancelled = false;
+        for entry_id in 0..pak.entries_count()? {
+            let output_path = temp_dir.path().join(entry_id.to_string());
+            let cancel = CancellationToken::new();
+            *source.cancel.lock().unwrap() = Some(cancel.clone());
+
+            // Entries of a single block are only checked before it, so they complete
+            match extract_entry_atomic(pak.as_mut(), entry_id, &output_path, &cancel) {
+                Ok(_) => assert!(output_path.is_file()),
+                Err(PakError::Cancelled) => {
+                    assert!(!output_path.exists());
+                    cancelled = true;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        assert!(cancelled);
+
+        for dir_entry in std::fs::read_dir(temp_dir.path())? {
+            let file_name = dir_entry?.file_name();
+            assert!(!file_name.to_string_lossy().ends_with(".part"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_read_strategy() -> Result<(), Box<dyn std::error::Error>> {
+        /// Counts the prefetch hints it gets
+        struct CountPrefetches {
+            data: Vec<u8>,
+            prefetches: Mutex<usize>,
+        }
+        impl ReadAt for CountPrefetches {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+                self.data.read_at(buf, offset)
+            }
+            fn size(&self) -> std::io::Result<u64> {
+                self.data.size()
+            }
+            fn prefetch(&self, _offset: u64, _length: u64) -> std::io::Result<()> {
+                *self.prefetches.lock().unwrap() += 1;
+                Ok(())
+            }
+        }
+
+        let source = CountPrefetches {
+            data: std::fs::read(PAK_1)?,
+            prefetches: Mutex::new(0),
+        };
+        let mut pak = new_pak(&source, 10)?;
+        let entries_count = pak.entries_count()? as usize;
+        let temp_dir = TempDir::new()?;
+        let extract = |pak: &mut dyn PakReader, read_strategy, output_root: &Path| {
+            let options = ExtractOptions {
+                read_strategy,
+                ..Default::default()
+            };
+            let mut hashes = BTreeMap::new();
+            extract_pak(
+                pak,
+                Path::new(PAK_1),
+                output_root,
+                &options,
+                &CancellationToken::new(),
+                |event| {
+                    if let ExtractEvent::Extracted(file) = event {
+                        let content = std::fs::read(output_root.join(&file.path)).unwrap();
+                        hashes.insert(file.path.clone(), sha1_smol::Sha1::from(content).digest());
+                    }
+                },
+            )
+            .map(|_| hashes)
+        };
+
+        let expected = extract(
+            pak.as_mut(),
+            ReadStrategy::Default,
+            &temp_dir.path().join("0"),
+        )?;
+        assert_eq!(expected.len(), entries_count);
+        assert_eq!(*source.prefetches.lock().unwrap(), 0);
+
+        let hashes = extract(
+            pak.as_mut(),
+            ReadStrategy::Prefetch,
+            &temp_dir.path().join("1"),
+        )?;
+        assert_eq!(hashes, expected);
+        assert_eq!(*source.prefetches.lock().unwrap(), entries_count - 1);
+
+        let mut mapped = open_pak_mapped(PAK_1, 10)?;
+        let hashes = extract(
+            mapped.as_mut(),
+            ReadStrategy::Mmap,
+            &temp_dir.path().join("2"),
+        )?;
+        assert_eq!(hashes, expected);
+        Ok(())
+    }
+}
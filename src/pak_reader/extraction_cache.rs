@@ -0,0 +1,332 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Decompressed entry contents kept in memory, so reading the same content
+/// again, from the same pak or another, doesn't read or inflate anything.
+///
+/// Readers go through the cache once it's set with
+/// [`PakReader::set_extraction_cache`], in
+/// [`open_entry`](crate::pak_reader::ext::PakReaderExt::open_entry) and
+/// [`extract_entry_to_vec`](crate::pak_reader::ext::PakReaderExt::extract_entry_to_vec).
+/// One cache can be shared by any number of readers, on any number of
+/// threads. Entries are keyed by their hash, so the same content in two paks
+/// is cached once; entries whose hash is zeroed are keyed by reader and
+/// entry id instead, and only the reader that cached them finds them.
+///
+/// Once the contents take more than the budget, the least recently used
+/// ones are dropped. Contents larger than the whole budget aren't cached.
+///
+/// ```rust
+/// use gfp::pak_reader::PakReader;
+/// use gfp::pak_reader::ext::PakReaderExt;
+/// use gfp::pak_reader::extraction_cache::ExtractionCache;
+/// use gfp::pak_reader::gfp_v10::GfpPakReaderV10;
+/// use std::sync::Arc;
+///
+/// let cache = Arc::new(ExtractionCache::new(64 << 20));
+/// let mut pak = GfpPakReaderV10::new(std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?);
+/// pak.set_extraction_cache(Some(cache.clone()));
+///
+/// let first = pak.extract_entry_to_vec(3)?;
+/// pak.reset_stats();
+/// assert_eq!(pak.extract_entry_to_vec(3)?, first);
+/// assert_eq!(pak.stats().reads, 0);
+/// assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct ExtractionCache {
+    budget: u64,
+    lru: Mutex<Lru>,
+    next_pak_id: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    bytes_hit: AtomicU64,
+}
+
+/// What [`ExtractionCache::stats`] reports, over every reader using the
+/// cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes of content served from the cache.
+    pub bytes_hit: u64,
+    /// Entries and bytes held right now.
+    pub entries: u64,
+    pub size: u64,
+}
+
+/// What an entry's content is cached under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    /// The hash recorded for the entry.
+    Hash([u8; 20]),
+    /// An entry with a zeroed hash, by the id the cache gave its reader.
+    Entry { pak_id: u64, entry_id: u64 },
+}
+
+/// Least recently used order of the cached contents
+#[derive(Debug, Default)]
+struct Lru {
+    contents: HashMap<CacheKey, (Arc<[u8]>, u64)>,
+    by_use: BTreeMap<u64, CacheKey>,
+    size: u64,
+    tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &CacheKey) -> Option<Arc<[u8]>> {
+        self.tick += 1;
+        let (content, last_used) = self.contents.get_mut(key)?;
+        self.by_use.remove(last_used);
+        *last_used = self.tick;
+        self.by_use.insert(self.tick, *key);
+        Some(content.clone())
+    }
+}
+
+impl ExtractionCache {
+    /// A cache holding at most `budget` bytes of decompressed content.
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            lru: Mutex::default(),
+            next_pak_id: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            bytes_hit: AtomicU64::new(0),
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap_or_else(PoisonError::into_inner);
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes_hit: self.bytes_hit.load(Ordering::Relaxed),
+            entries: lru.contents.len() as u64,
+            size: lru.size,
+        }
+    }
+
+    /// The content cached under `key`, which counts as its latest use.
+    pub fn get(&self, key: &CacheKey) -> Option<Arc<[u8]>> {
+        self.lru
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .touch(key)
+    }
+
+    /// Cache `content` under `key`, dropping the least recently used
+    /// contents to stay within the budget.
+    pub fn insert(&self, key: CacheKey, content: Arc<[u8]>) {
+        let length = content.len() as u64;
+        if length > self.budget {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap_or_else(PoisonError::into_inner);
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((previous, last_used)) = lru.contents.insert(key, (content, tick)) {
+            lru.by_use.remove(&last_used);
+            lru.size -= previous.len() as u64;
+        }
+        lru.by_use.insert(tick, key);
+        lru.size += length;
+        while lru.size > self.budget {
+            let Some((_, oldest)) = lru.by_use.pop_first() else {
+                break;
+            };
+            if let Some((content, _)) = lru.contents.remove(&oldest) {
+                lru.size -= content.len() as u64;
+            }
+        }
+    }
+
+    /// Drop every cached content. The stats keep counting.
+    pub fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap_or_else(PoisonError::into_inner);
+        lru.contents.clear();
+        lru.by_use.clear();
+        lru.size = 0;
+    }
+
+    /// A new id for a reader's entries without hash
+    fn next_pak_id(&self) -> u64 {
+        self.next_pak_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// An [`ExtractionCache`] as set on one reader, see
+/// [`PakReader::set_extraction_cache`].
+#[derive(Debug, Clone)]
+pub struct ReaderCache {
+    cache: Arc<ExtractionCache>,
+    pak_id: u64,
+}
+
+impl ReaderCache {
+    pub(crate) fn new(cache: Arc<ExtractionCache>) -> Self {
+        let pak_id = cache.next_pak_id();
+        Self { cache, pak_id }
+    }
+
+    pub fn cache(&self) -> &Arc<ExtractionCache> {
+        &self.cache
+    }
+
+    /// The content of the entry of `pak`, from the cache or extracted and
+    /// cached. Two threads missing the same content both extract it.
+    pub(crate) fn content<P: PakReader + ?Sized>(
+        &self,
+        pak: &mut P,
+        entry_id: u64,
+    ) -> Result<Arc<[u8]>, PakError> {
+        let hash = pak.entry_hash(entry_id)?;
+        let key = if hash == [0u8; 20] {
+            CacheKey::Entry {
+                pak_id: self.pak_id,
+                entry_id,
+            }
+        } else {
+            CacheKey::Hash(hash)
+        };
+        if let Some(content) = self.cache.get(&key) {
+            self.cache.hits.fetch_add(1, Ordering::Relaxed);
+            self.cache
+                .bytes_hit
+                .fetch_add(content.len() as u64, Ordering::Relaxed);
+            return Ok(content);
+        }
+        self.cache.misses.fetch_add(1, Ordering::Relaxed);
+        let mut content = vec![];
+        pak.extract_entry_to_writer(entry_id, &mut content)?;
+        let content: Arc<[u8]> = content.into();
+        self.cache.insert(key, content.clone());
+        Ok(content)
+    }
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::*;
+    use crate::pak_reader::ext::PakReaderExt;
+    use crate::pak_reader::implements::open_pak;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+    const PAK_2: &str = "test/normal/game_patch_1.32.11.13992.pak";
+
+    #[test]
+    fn test_shared_across_readers() -> Result<(), Box<dyn std::error::Error>> {
+        let cache = Arc::new(ExtractionCache::new(16 << 20));
+        let mut first = open_pak(PAK_1, 10)?;
+        let mut second = open_pak(PAK_2, 10)?;
+        first.set_extraction_cache(Some(cache.clone()));
+        second.set_extraction_cache(Some(cache.clone()));
+
+        // An entry whose content is in both paks
+        let hashes = (0..first.entries_count()?)
+            .map(|entry_id| first.entry_hash(entry_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (second_id, first_id) = (0..second.entries_count()?)
+            .find_map(|entry_id| {
+                let hash = second.entry_hash(entry_id).ok()?;
+                let position = hashes.iter().position(|&other| other == hash)?;
+                Some((entry_id, position as u64))
+            })
+            .expect("an entry in both paks");
+
+        let content = first.extract_entry_to_vec(first_id)?;
+        second.entries_count()?;
+        second.reset_stats();
+        let mut reader = second.open_entry(second_id)?;
+        assert_eq!(second.stats(), Default::default());
+        let mut read = vec![];
+        std::io::Read::read_to_end(&mut reader, &mut read)?;
+        assert_eq!(read, content);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                bytes_hit: content.len() as u64,
+                entries: 1,
+                size: content.len() as u64,
+            }
+        );
+
+        // Without a cache every extraction reads
+        second.set_extraction_cache(None);
+        assert!(second.extraction_cache().is_none());
+        second.reset_stats();
+        assert_eq!(second.extract_entry_to_vec(second_id)?, content);
+        assert!(second.stats().reads > 0);
+        assert_eq!(cache.stats().hits, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_budget() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = open_pak(PAK_1, 10)?;
+        let sizes = (0..pak.entries_count()?)
+            .map(|entry_id| Ok(pak.entry_layout(entry_id)?.file_size))
+            .collect::<Result<Vec<_>, PakError>>()?;
+        // Entries 2 and 3 fit together, and 0 is larger than the budget
+        let cache = Arc::new(ExtractionCache::new(sizes[2] + sizes[3]));
+        assert!(sizes[0] > cache.budget());
+        pak.set_extraction_cache(Some(cache.clone()));
+
+        pak.extract_entry_to_vec(0)?;
+        pak.extract_entry_to_vec(2)?;
+        pak.extract_entry_to_vec(3)?;
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().size, sizes[2] + sizes[3]);
+
+        // Using 2 again makes 3 the one dropped for 6
+        pak.extract_entry_to_vec(2)?;
+        pak.extract_entry_to_vec(6)?;
+        let stats = cache.stats();
+        assert!(stats.size <= cache.budget());
+        assert_eq!((stats.hits, stats.misses), (1, 4));
+        pak.reset_stats();
+        pak.extract_entry_to_vec(2)?;
+        assert_eq!(pak.stats().reads, 0);
+        pak.extract_entry_to_vec(3)?;
+        assert!(pak.stats().reads > 0);
+
+        cache.clear();
+        assert_eq!((cache.stats().entries, cache.stats().size), (0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zeroed_hash_keyed_by_reader() {
+        let cache = Arc::new(ExtractionCache::new(1024));
+        let first = ReaderCache::new(cache.clone());
+        let second = ReaderCache::new(cache.clone());
+        assert_ne!(first.pak_id, second.pak_id);
+
+        let key = CacheKey::Entry {
+            pak_id: first.pak_id,
+            entry_id: 0,
+        };
+        cache.insert(key, Arc::from(&b"content"[..]));
+        assert_eq!(cache.get(&key).as_deref(), Some(&b"content"[..]));
+        assert!(
+            cache
+                .get(&CacheKey::Entry {
+                    pak_id: second.pak_id,
+                    entry_id: 0
+                })
+                .is_none()
+        );
+    }
+}
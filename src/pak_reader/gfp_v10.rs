@@ -1,26 +1,81 @@
 use crate::error::PakError;
-use crate::pak_reader::PakReader;
+use crate::pak_reader::cancel::CancellationToken;
+#[cfg(feature = "std-fs")]
+use crate::pak_reader::check_footer;
+use crate::pak_reader::entry_reader::EntryReader;
+use crate::pak_reader::extraction_cache::{ExtractionCache, ReaderCache};
+use crate::pak_reader::io_stats::{CountedRead, IoCounters, IoStats};
+use crate::pak_reader::path_match::{PathMatch, PathMatchIndexes};
+use crate::pak_reader::{
+    BlockLayout, EntryLayout, EntryPayload, IndexStats, MAX_INDEX_HEADER_SIZE, MIN_ENTRY_SIZE,
+    PakReader, check_count, check_index_end, decode_index, decode_index_header, inline_header_size,
+    is_compressed_index, name_length, payload_hash_ranges, read_mount_point,
+};
+#[cfg(feature = "std-fs")]
+use crate::utils::direct_io::DirectFile;
 use crate::utils::file_reader::VecCursor;
-use crate::utils::{read_file_at, utf16le_to_utf8_inplace, xor_each_byte, zlib_decompress};
+#[cfg(feature = "std-fs")]
+use crate::utils::mmap::MappedFile;
+#[cfg(feature = "std-fs")]
+use crate::utils::read_at::PathSource;
+use crate::utils::read_at::ReadAt;
+#[cfg(feature = "std-fs")]
+use crate::utils::read_at::Truncated;
+#[cfg(feature = "std-fs")]
+use crate::utils::shared_read::open_shared_read;
+use crate::utils::{
+    check_zlib_header, sha1_at, sha1_of_ranges, to_usize, utf16le_to_utf8_inplace, xor_each_byte,
+    zlib_decompress_bounded,
+};
+use std::collections::HashMap;
 use std::ffi::CString;
+#[cfg(feature = "std-fs")]
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::Write;
+#[cfg(feature = "std-fs")]
+use std::path::Path;
+use std::sync::Arc;
 
 /// total size: 45 Bytes
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 struct RawPakInfo {
     encrypted: u8, // 1B
-    /// unused
+    /// unused, [`Self::MAGIC`] in every known pak
     magic: u32, // 4B
     version: u32,  // 4B
-    /// unused
     hash: [u8; 20], // 20B
-    /// unused
+    /// unused, the index size is taken from `index_offset` instead
     index_size: u64, // 8B
     index_offset: u64, // 8B
 }
 impl RawPakInfo {
+    const SIZE: usize = size_of::<RawPakInfo>();
+    const VERSION: u32 = 10;
+    const MAX_INDEX_SIZE: u64 = crate::pak_reader::MAX_INDEX_SIZE;
+
+    const MAGIC: u32 = crate::pak_reader::FOOTER_MAGIC;
+
+    const OFFSET_XOR_KEY: u64 = 0xD74AF37FAA6B020Du64;
+    const ENCRYPTED_XOR_KEY: u8 = 0x6Cu8;
+
+    /// Reinterpret the footer bytes and undo the obfuscation
+    fn from_bytes(buffer: [u8; Self::SIZE]) -> Self {
+        let mut info = unsafe { std::mem::transmute::<[u8; Self::SIZE], Self>(buffer) };
+        info.encrypted ^= Self::ENCRYPTED_XOR_KEY;
+        info.index_offset ^= Self::OFFSET_XOR_KEY;
+        info
+    }
+
+    /// Obfuscate the fields again, the footer bytes [`Self::from_bytes`]
+    /// was given for a parsed footer
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut info = self;
+        info.encrypted ^= Self::ENCRYPTED_XOR_KEY;
+        info.index_offset ^= Self::OFFSET_XOR_KEY;
+        unsafe { std::mem::transmute::<Self, [u8; Self::SIZE]>(info) }
+    }
+
     fn is_encrypted(&self) -> bool {
         self.encrypted != 0
     }
@@ -32,11 +87,19 @@ struct CompressionBlock {
     end: u64,
 }
 impl CompressionBlock {
+    /// Bytes of a block in the index: its start and end.
+    const SIZE: usize = 16;
+
     fn offset(&self) -> u64 {
         self.start
     }
-    fn size(&self) -> u64 {
-        self.end - self.start
+    fn size(&self) -> Result<u64, PakError> {
+        self.end.checked_sub(self.start).ok_or_else(|| {
+            PakError::invalid_data(format!(
+                "Compression block ends before it starts: {:#X}..{:#X}",
+                self.start, self.end
+            ))
+        })
     }
 }
 
@@ -54,76 +117,456 @@ struct Entry {
     pub encrypted: u8,
 }
 
+impl Entry {
+    /// Read the entry's record in the index
+    fn read_from(&mut self, index_cursor: &mut VecCursor<u8>) -> Result<(), PakError> {
+        self.file_hash.copy_from_slice(index_cursor.read::<20>()?);
+        self.file_offset = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.file_size = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.compression_method = u32::from_le_bytes(*index_cursor.read::<4>()?);
+        self.compressed_length = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.dummy.copy_from_slice(index_cursor.read::<21>()?);
+
+        if self.compression_method != 0 {
+            self.num_of_blocks = u32::from_le_bytes(*index_cursor.read::<4>()?);
+            let num_of_blocks = check_count(
+                "Block count",
+                self.num_of_blocks.into(),
+                CompressionBlock::SIZE,
+                index_cursor,
+            )?;
+            self.blocks.reserve(num_of_blocks);
+            for _ in 0..num_of_blocks {
+                let block = CompressionBlock {
+                    start: u64::from_le_bytes(*index_cursor.read::<8>()?),
+                    end: u64::from_le_bytes(*index_cursor.read::<8>()?),
+                };
+                self.blocks.push(block);
+            }
+        } else {
+            self.num_of_blocks = 0;
+        }
+
+        self.compressed_block_size = u32::from_le_bytes(*index_cursor.read::<4>()?);
+        self.encrypted = index_cursor.read::<1>()?[0];
+        Ok(())
+    }
+
+    /// The entry's blocks with what each decompresses to, see
+    /// [`BlockLayout::split`]
+    fn block_layouts(&self) -> Result<Vec<BlockLayout>, PakError> {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| Ok((block.offset(), block.size()?)))
+            .collect::<Result<Vec<_>, PakError>>()?;
+        Ok(BlockLayout::split(
+            self.file_size,
+            self.compressed_block_size,
+            blocks.into_iter(),
+        ))
+    }
+
+    /// Make sure the entry has as many blocks as it says, so a block list
+    /// that came out short can't pass for a shorter entry
+    fn check_blocks(&self) -> Result<(), PakError> {
+        if self.blocks.len() != self.num_of_blocks as usize {
+            return Err(PakError::invalid_data(format!(
+                "Entry has {} compression blocks but {} were read",
+                self.num_of_blocks,
+                self.blocks.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Where a NUL-terminated name is in the index data
+#[derive(Debug, Clone, Copy)]
+struct NameSpan {
+    offset: usize,
+    /// Bytes of a UTF-8 name, or the negated number of UTF-16 code units
+    size: i32,
+}
+
+impl NameSpan {
+    /// Bytes a name of `size` takes in the index.
+    fn length(size: i32) -> Result<usize, PakError> {
+        if size > 0 {
+            Ok(size as usize)
+        } else {
+            to_usize(u64::from(size.unsigned_abs()) * 2)
+        }
+    }
+
+    /// Read the size of a name at the cursor, then skip the name.
+    fn read(index_cursor: &mut VecCursor<u8>) -> Result<Self, PakError> {
+        let size = i32::from_le_bytes(*index_cursor.read::<4>()?);
+        let offset = index_cursor.skip(name_length(size, index_cursor)?)?;
+        Ok(Self { offset, size })
+    }
+
+    /// `text` without the name in front, `None` if it doesn't start with
+    /// the name. UTF-8 names are compared as they are in the index.
+    fn strip_from(self, index_data: &[u8], text: &str) -> Result<Option<usize>, PakError> {
+        if self.size > 0 {
+            let name = &index_data[self.offset..self.offset + self.size as usize];
+            let Some((0, name)) = name.split_last() else {
+                return Ok(None);
+            };
+            Ok(text.as_bytes().starts_with(name).then_some(name.len()))
+        } else {
+            let name = self.decode(index_data)?;
+            Ok(text.starts_with(&name).then_some(name.len()))
+        }
+    }
+
+    fn decode(self, index_data: &[u8]) -> Result<String, PakError> {
+        let mut data = index_data[self.offset..self.offset + Self::length(self.size)?].to_vec();
+        if self.size <= 0 {
+            utf16le_to_utf8_inplace(&mut data, self.offset)?;
+        }
+        Ok(CString::from_vec_with_nul(data)?.into_string()?)
+    }
+}
+
+/// Where the path of an entry is in the index data
+#[derive(Debug, Clone, Copy)]
+struct PathSpan {
+    /// Index of the directory in `dir_spans`
+    dir: usize,
+    name: NameSpan,
+}
+
+/// Size of the pak info at the end of a v10 pak.
+pub const FOOTER_SIZE: usize = RawPakInfo::SIZE;
+
+/// The footer of a new v10 pak whose index is at `index_offset`, obfuscated
+/// the way [`PakReader::footer_bytes`] reads it back. Readers take the index
+/// size from `index_offset`; `index_size` is written as is to the field that
+/// known paks leave unused.
+///
+/// ```rust
+/// use gfp::pak_reader::PakReader;
+/// use gfp::pak_reader::gfp_v10::{GfpPakReaderV10, build_footer};
+///
+/// let mut pak = GfpPakReaderV10::new(std::fs::read("test/normal/game_patch_1.32.11.13846.pak")?);
+/// let stats = pak.index_stats()?;
+/// let footer = build_footer(false, stats.index_offset, stats.index_size, stats.footer_hash);
+/// let original = pak.footer_bytes()?;
+/// // Only the unused index size field differs
+/// assert_eq!(footer[..29], original[..29]);
+/// assert_eq!(footer[37..], original[37..]);
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+pub fn build_footer(
+    encrypted: bool,
+    index_offset: u64,
+    index_size: u64,
+    hash: [u8; 20],
+) -> [u8; FOOTER_SIZE] {
+    RawPakInfo {
+        encrypted: encrypted as u8,
+        magic: RawPakInfo::MAGIC,
+        version: RawPakInfo::VERSION,
+        hash,
+        index_size,
+        index_offset,
+    }
+    .to_bytes()
+}
+
+/// Whether `footer`, the last [`FOOTER_SIZE`] bytes of a source, deobfuscates
+/// to a plausible v10 pak info. `info_offset` is where the footer starts.
+pub(crate) fn is_plausible_footer(footer: [u8; FOOTER_SIZE], info_offset: u64) -> bool {
+    let info = RawPakInfo::from_bytes(footer);
+    info.encrypted <= 1
+        && info.version == RawPakInfo::VERSION
+        && info_offset
+            .checked_sub(info.index_offset)
+            .is_some_and(|index_size| index_size <= RawPakInfo::MAX_INDEX_SIZE)
+}
+
 /// 参考 `src/c/gfp.c`
-pub struct GfpPakReaderV10 {
-    pub file: File,
+pub struct GfpPakReaderV10<R: ReadAt> {
+    pub file: R,
 
     is_info_loaded: bool,
     is_entries_loaded: bool,
+    is_path_table_loaded: bool,
     is_entry_paths_loaded: bool,
+    is_path_index_loaded: bool,
 
     // Stage: info
     info: RawPakInfo,
+    /// From the index offset to the footer
+    index_size: u64,
 
     // Stage: entries
+    index_compressed: bool,
+    index_encrypted: bool,
     index_data: Vec<u8>,
     index_offset: usize,
     mount_point: String,
     entries: Vec<Entry>,
 
-    // Stage entry paths
+    // Stage: path table
+    /// Where each directory name is in the index data
+    dir_spans: Vec<NameSpan>,
+    /// By entry id, `None` for entries no directory lists
+    path_spans: Vec<Option<PathSpan>>,
+
+    // Stage: entry paths
     entry_paths: Vec<String>,
+
+    // Stage: path index
+    path_index: HashMap<String, u64>,
+    path_match_indexes: PathMatchIndexes,
+
+    counters: IoCounters,
+    extraction_cache: Option<ReaderCache>,
+    /// Size of the pak and its footer when the info was loaded, which
+    /// [`PakReader::resume`] checks
+    loaded_footer: Option<(u64, [u8; RawPakInfo::SIZE])>,
+    strict: bool,
 }
 
-impl GfpPakReaderV10 {
-    const PAK_INFO_SIZE: usize = size_of::<RawPakInfo>();
-    const OFFSET_XOR_KEY: u64 = 0xD74AF37FAA6B020Du64;
-    const ENCRYPTED_XOR_KEY: u8 = 0x6Cu8;
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV10<PathSource<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = open_shared_read(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV10<PathSource<DirectFile>> {
+    pub fn open_direct<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = DirectFile::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV10<PathSource<MappedFile>> {
+    pub fn open_mapped<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = MappedFile::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV10<Truncated<File>> {
+    /// Open a pak embedded in a bigger file, like an executable it's
+    /// appended to, whose footer ends at `trailer_end_offset` instead of at
+    /// the end of the file.
+    ///
+    /// The footer doesn't say where the pak starts, so the offsets of the
+    /// index and the entries are used as they are, from the start of the
+    /// file, which is how packers embedding paks write them. Nothing past
+    /// `trailer_end_offset` is read, and it's the [`PakReader::pak_size`].
+    pub fn open_at<P: AsRef<Path>>(
+        path: P,
+        trailer_end_offset: u64,
+    ) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let file = open_shared_read(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || trailer_end_offset < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        if trailer_end_offset > metadata.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Trailer end offset {:#X} is past the end of the file ({:#X})",
+                    trailer_end_offset,
+                    metadata.len()
+                ),
+            ));
+        }
+        Ok(Box::new(Self::new(Truncated::new(
+            file,
+            trailer_end_offset,
+        ))))
+    }
+}
+
+impl<R: ReadAt> GfpPakReaderV10<R> {
+    pub fn new(file: R) -> Self {
+        Self {
+            file,
+            is_info_loaded: false,
+            is_entries_loaded: false,
+            is_path_table_loaded: false,
+            is_entry_paths_loaded: false,
+            is_path_index_loaded: false,
+
+            info: RawPakInfo {
+                encrypted: 0,
+                magic: 0,
+                version: 0,
+                hash: [0; 20],
+                index_size: 0,
+                index_offset: 0,
+            },
+            index_size: 0,
+            index_compressed: false,
+            index_encrypted: false,
+            index_data: vec![],
+            index_offset: 0,
+            mount_point: String::new(),
+            entries: vec![],
+            dir_spans: vec![],
+            path_spans: vec![],
+            entry_paths: vec![],
+            path_index: HashMap::new(),
+            path_match_indexes: PathMatchIndexes::default(),
+            counters: IoCounters::default(),
+            extraction_cache: None,
+            loaded_footer: None,
+            strict: false,
+        }
+    }
+
+    /// Whether the index is stored zlib-compressed in the pak. Nothing in
+    /// the footer says so: an index is taken as compressed when it starts
+    /// with a zlib header and doesn't read as an index, XORed or not.
+    pub fn index_compressed(&mut self) -> Result<bool, PakError> {
+        self.load_entries()?;
+        Ok(self.index_compressed)
+    }
+
+    /// Whether the index is XORed in the pak. That's what the encrypted flag
+    /// of the footer says, see [`PakReader::encrypted`], unless the index
+    /// only reads as one the other way round.
+    pub fn index_encrypted(&mut self) -> Result<bool, PakError> {
+        self.load_entries()?;
+        Ok(self.index_encrypted)
+    }
+
+    /// An [`EntryReader`] streaming the entry's content with at most
+    /// `buffer_cap` bytes of its payload buffered.
+    pub fn entry_reader(
+        &mut self,
+        entry_id: u64,
+        buffer_cap: usize,
+    ) -> Result<EntryReader<'_, R>, PakError> {
+        let layout = self.entry_layout(entry_id)?;
+        Ok(EntryReader::new(&self.file, layout, buffer_cap).with_counters(&self.counters))
+    }
+
+    /// The pak file, with the reads counted in [`PakReader::stats`]
+    fn source(&self) -> CountedRead<'_, R> {
+        CountedRead {
+            source: &self.file,
+            counters: &self.counters,
+        }
+    }
+
     const DECRYPT_KEY: u8 = 0x79u8;
     const CHUNK_SIZE: usize = 65536;
 
+    /// Where byte `start` of a stored entry is in the pak, past the header
+    /// in front of its data
+    fn stored_offset(&self, entry: &Entry, start: u64) -> Result<u64, PakError> {
+        let header_size = inline_header_size(
+            &self.source(),
+            entry.file_offset,
+            &entry.file_hash,
+            entry.file_size,
+            entry.compressed_length,
+        )?;
+        entry
+            .file_offset
+            .checked_add(header_size)
+            .and_then(|offset| offset.checked_add(start))
+            .ok_or_else(|| {
+                PakError::invalid_data(format!("Invalid entry offset: {:#X}", entry.file_offset))
+            })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(pak_size = tracing::field::Empty))
+    )]
     fn load_pak_info(&mut self) -> Result<(), PakError> {
         if self.is_info_loaded {
             return Ok(());
         }
-        let file_size = self
-            .file
-            .seek(SeekFrom::End(0))
-            .expect("Unable to seek to end of file");
-
-        self.file
-            .seek(SeekFrom::Start(file_size - 45))
-            .expect("Unable to seek to pak header");
-
-        let mut buffer = [0u8; Self::PAK_INFO_SIZE];
-        self.file
-            .read_exact(&mut buffer)
-            .expect("Failed to read pak header");
-
-        self.info = unsafe { std::mem::transmute(buffer) };
+        let file_size = self.file.size()?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pak_size", file_size);
+        let info_offset = file_size
+            .checked_sub(RawPakInfo::SIZE as u64)
+            .ok_or_else(|| PakError::invalid_data(format!("File too small: {}", file_size)))?;
+
+        let mut buffer = [0u8; RawPakInfo::SIZE];
+        if self.source().read_at(&mut buffer, info_offset)? != RawPakInfo::SIZE {
+            return Err(PakError::invalid_data("Failed to read pak header"));
+        }
 
-        // deobfuscation
-        self.info.encrypted ^= Self::ENCRYPTED_XOR_KEY;
-        self.info.index_offset ^= Self::OFFSET_XOR_KEY;
+        self.info = RawPakInfo::from_bytes(buffer);
 
         {
-            let index_size = self
-                .file
-                .seek(SeekFrom::End(-(self.info.index_offset as i64)))?
-                - 45;
-            if index_size > 52428800 {
+            let index_size = info_offset
+                .checked_sub(self.info.index_offset)
+                .ok_or_else(|| {
+                    PakError::invalid_data(format!("Invalid index offset: {}", {
+                        self.info.index_offset
+                    }))
+                })?;
+            if index_size > RawPakInfo::MAX_INDEX_SIZE {
                 return Err(PakError::invalid_data(format!(
                     "Invalid index data size: {}",
                     index_size
                 )));
             }
-            self.info.index_size = index_size;
+            self.index_size = index_size;
         }
 
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            encrypted = self.info.is_encrypted(),
+            version = { self.info.version },
+            index_size = { self.index_size },
+            "pak info loaded"
+        );
+        self.loaded_footer = Some((file_size, buffer));
         self.is_info_loaded = true;
         Ok(())
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(entries = tracing::field::Empty))
+    )]
     fn load_entries(&mut self) -> Result<(), PakError> {
         if self.is_entries_loaded {
             return Ok(());
@@ -133,25 +576,37 @@ impl GfpPakReaderV10 {
 
         // Index data
         {
-            let mut index_data: Vec<u8> = vec![0u8; self.info.index_size as usize];
-            read_file_at(&mut self.file, &mut index_data, self.info.index_offset)?;
-
-            if self.info.is_encrypted() {
-                xor_each_byte(&mut index_data, Self::DECRYPT_KEY);
+            let mut index_data: Vec<u8> = vec![0u8; to_usize(self.index_size)?];
+            self.source()
+                .read_at(&mut index_data, self.info.index_offset)?;
+
+            // Compressed indexes are inflated before the XOR is undone
+            self.index_compressed = is_compressed_index(&index_data, Self::DECRYPT_KEY);
+            if self.index_compressed {
+                index_data = zlib_decompress_bounded(&index_data, RawPakInfo::MAX_INDEX_SIZE)?;
+                self.counters.record_decompressed(index_data.len());
+                #[cfg(feature = "tracing")]
+                tracing::debug!(size = index_data.len(), "index decompressed");
             }
 
-            self.index_data = index_data;
+            // The flag in the footer was wrong if the index only made sense
+            // the other way, `info` keeps it as the footer has it
+            (self.index_data, self.index_encrypted) =
+                decode_index(index_data, self.info.is_encrypted(), Self::DECRYPT_KEY)?;
         }
 
         // Entries
         {
             let mut index_cursor = VecCursor::new(&self.index_data);
 
-            let mount_point_length = u32::from_le_bytes(*index_cursor.read_nocheck::<4>()) as usize;
-            index_cursor.move_by(9);
-            let mount_point_data = index_cursor.read_dyn(mount_point_length - 9)?;
+            let mount_point = read_mount_point(&mut index_cursor)?;
 
-            let entry_count = i32::from_le_bytes(*index_cursor.read_nocheck::<4>());
+            let entry_count = i32::from_le_bytes(*index_cursor.read::<4>()?);
+            let entry_count = u64::try_from(entry_count).map_err(|_| {
+                PakError::invalid_data(format!("Invalid entry count: {}", entry_count))
+            })?;
+            let entry_count =
+                check_count("Entry count", entry_count, MIN_ENTRY_SIZE, &index_cursor)?;
 
             self.entries = vec![
                 Entry {
@@ -166,196 +621,638 @@ impl GfpPakReaderV10 {
                     compressed_block_size: 0,
                     encrypted: 0,
                 };
-                entry_count as usize
+                entry_count
             ];
 
-            for entry_id in 0..entry_count as usize {
-                let entry = &mut self.entries[entry_id];
-
-                entry.file_hash.copy_from_slice(index_cursor.read::<20>()?);
-                entry.file_offset = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.file_size = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.compression_method = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                entry.compressed_length = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.dummy.copy_from_slice(index_cursor.read::<21>()?);
-
-                if entry.compression_method != 0 {
-                    entry.num_of_blocks = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                    for _ in 0..entry.num_of_blocks {
-                        let block = CompressionBlock {
-                            start: u64::from_le_bytes(*index_cursor.read::<8>()?),
-                            end: u64::from_le_bytes(*index_cursor.read::<8>()?),
-                        };
-                        entry.blocks.push(block);
-                    }
-                } else {
-                    entry.num_of_blocks = 0;
-                }
-
-                entry.compressed_block_size = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                entry.encrypted = index_cursor.read::<1>()?[0];
+            for (entry_id, entry) in self.entries.iter_mut().enumerate() {
+                entry
+                    .read_from(&mut index_cursor)
+                    .and_then(|()| entry.check_blocks())
+                    .map_err(|e| e.context(format!("parsing entry {}", entry_id)))?;
             }
 
-            self.mount_point = CString::from_vec_with_nul(mount_point_data)?.into_string()?;
+            self.mount_point = mount_point;
             self.index_offset = index_cursor.offset;
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("entries", self.entries.len());
             self.is_entries_loaded = true;
         }
         Ok(())
     }
 
-    fn load_entry_paths(&mut self) -> Result<(), PakError> {
-        if self.is_entry_paths_loaded {
+    /// Scan the directory records after the entries for where each entry
+    /// path is, without decoding any of them.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(dirs = tracing::field::Empty))
+    )]
+    fn load_path_table(&mut self) -> Result<(), PakError> {
+        if self.is_path_table_loaded {
             return Ok(());
         }
         self.load_entries()?;
 
+        // Placeholder paks without entries may end the index right after the entry count
+        if self.entries.is_empty() && self.index_offset == self.index_data.len() {
+            self.is_path_table_loaded = true;
+            return Ok(());
+        }
+
         let mut index_cursor = VecCursor::new_with_offset(&self.index_data, self.index_offset);
 
-        let entry_count: u64 = u64::from_le_bytes(*index_cursor.read::<8>()?);
-        let dir_count: u64 = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        // Not the number of entries, the paks seen have many more; entry ids
+        // are checked against the entries instead, which sizes no allocation
+        // by a count that could be anything
+        let _path_table_count = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        // A directory takes at least its name size and file count, a file
+        // its name size and entry id
+        let dir_count = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        let dir_count = check_count("Directory count", dir_count, 12, &index_cursor)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("dirs", dir_count);
 
-        self.entry_paths = vec![String::new(); entry_count as usize];
+        let mut dir_spans = Vec::new();
+        let mut path_spans = vec![None; self.entries.len()];
 
         for _ in 0..dir_count {
-            let dir_len: usize = u32::from_le_bytes(*index_cursor.read::<4>()?) as usize;
-
-            let dir_name =
-                CString::from_vec_with_nul(index_cursor.read_dyn(dir_len)?)?.into_string()?;
+            // Directory names are stored like file names, in UTF-16 when
+            // their size is negative
+            dir_spans.push(NameSpan::read(&mut index_cursor)?);
 
             let dir_files = u64::from_le_bytes(*index_cursor.read::<8>()?);
+            let dir_files = check_count("File count", dir_files, 8, &index_cursor)?;
             for _ in 0..dir_files {
-                let entry_path_size: i32 = i32::from_le_bytes(*index_cursor.read::<4>()?);
-                let entry_path = if entry_path_size > 0 {
-                    let data = index_cursor.read_dyn(entry_path_size as usize)?;
-                    CString::from_vec_with_nul(data)?.into_string()?
-                } else {
-                    let mut data = index_cursor.read_dyn((-entry_path_size * 2) as usize)?;
-                    utf16le_to_utf8_inplace(&mut data);
-                    CString::from_vec_with_nul(data)?.into_string()?
-                };
-
+                let name = NameSpan::read(&mut index_cursor)?;
                 let entry_id = i32::from_le_bytes(*index_cursor.read::<4>()?);
-                if entry_id < 0 {
-                    return Err(PakError::invalid_data(format!(
-                        "Negative entry_id: {}",
-                        entry_id
-                    )));
-                }
-                self.entry_paths[entry_id as usize] =
-                    format!("{}{}{}", self.mount_point, dir_name, entry_path);
+                let span = usize::try_from(entry_id)
+                    .ok()
+                    .and_then(|entry_id| path_spans.get_mut(entry_id))
+                    .ok_or_else(|| {
+                        PakError::invalid_data(format!("Invalid entry_id: {}", entry_id))
+                    })?;
+                *span = Some(PathSpan {
+                    dir: dir_spans.len() - 1,
+                    name,
+                });
             }
         }
+
+        check_index_end(index_cursor.offset, self.index_data.len(), self.strict)?;
+        self.dir_spans = dir_spans;
+        self.path_spans = path_spans;
+        self.is_path_table_loaded = true;
+        Ok(())
+    }
+
+    /// Decode the path of an entry from the index data. Entries no directory
+    /// lists have an empty path.
+    fn decode_entry_path(&self, entry_id: usize) -> Result<String, PakError> {
+        let Some(span) = self.path_spans[entry_id] else {
+            return Ok(String::new());
+        };
+
+        let dir_name = self.dir_spans[span.dir].decode(&self.index_data)?;
+        let entry_path = span.name.decode(&self.index_data)?;
+
+        Ok(format!("{}{}{}", self.mount_point, dir_name, entry_path))
+    }
+
+    /// Decode every entry path up front, for lookups and listings that need
+    /// all of them.
+    fn load_entry_paths(&mut self) -> Result<(), PakError> {
+        if self.is_entry_paths_loaded {
+            return Ok(());
+        }
+        self.load_path_table()?;
+
+        self.entry_paths = (0..self.path_spans.len())
+            .map(|entry_id| {
+                self.decode_entry_path(entry_id)
+                    .map_err(|e| e.context(format!("decoding the path of entry {}", entry_id)))
+            })
+            .collect::<Result<_, _>>()?;
         self.is_entry_paths_loaded = true;
         Ok(())
     }
-}
 
-impl PakReader for GfpPakReaderV10 {
-    fn new(file: File) -> Self {
-        Self {
-            file,
-            is_info_loaded: false,
-            is_entries_loaded: false,
-            is_entry_paths_loaded: false,
+    fn load_path_index(&mut self) -> Result<(), PakError> {
+        if self.is_path_index_loaded {
+            return Ok(());
+        }
+        self.load_entry_paths()?;
 
-            info: RawPakInfo {
-                encrypted: 0,
-                magic: 0,
-                version: 0,
-                hash: [0; 20],
-                index_size: 0,
-                index_offset: 0,
-            },
-            index_data: vec![],
-            index_offset: 0,
-            mount_point: String::new(),
-            entries: vec![],
-            entry_paths: vec![],
+        for (entry_id, path) in self.entry_paths.iter().enumerate() {
+            self.path_index
+                .entry(path.clone())
+                .or_insert(entry_id as u64);
+        }
+        self.is_path_index_loaded = true;
+        Ok(())
+    }
+
+    fn decompress_block(
+        &self,
+        entry_id: u64,
+        entry: &Entry,
+        block_index: usize,
+    ) -> Result<Vec<u8>, PakError> {
+        let block = entry.blocks.get(block_index).ok_or_else(|| {
+            PakError::invalid_data(format!(
+                "Entry {} has no block {}, only {}",
+                entry_id,
+                block_index,
+                entry.blocks.len()
+            ))
+        })?;
+        let block_size = block.size()?;
+        let mut compressed_data = vec![0u8; to_usize(block_size)?];
+
+        let bytes_read = self
+            .source()
+            .read_at(&mut compressed_data, block.offset())?;
+        if bytes_read != compressed_data.len() {
+            return Err(PakError::invalid_data(format!(
+                "Failed to read compressed chunk at {:08X}, read/expected: {}/{}",
+                block.offset(),
+                bytes_read,
+                block_size
+            )));
+        }
+
+        if entry.encrypted != 0 {
+            xor_each_byte(&mut compressed_data, Self::DECRYPT_KEY);
+        }
+
+        if entry.compression_method != 1 {
+            return Err(PakError::invalid_data(format!(
+                "Unknown compression method '{}', only '1' is supported.",
+                entry.compression_method
+            )));
+        }
+
+        // No block decompresses to more than the block size, which is 0 for
+        // some single-block entries
+        let max_size = match entry.compressed_block_size {
+            0 => entry.file_size,
+            block_size => entry.file_size.min(block_size.into()),
+        };
+        let data = zlib_decompress_bounded(&compressed_data, max_size).map_err(|error| {
+            PakError::Decompress {
+                entry_id: Some(entry_id),
+                block: Some(block_index as u32),
+                source: Box::new(error),
+            }
+        })?;
+        self.counters.record_decompressed(data.len());
+        Ok(data)
+    }
+
+    fn extract_stored(
+        &self,
+        entry: &Entry,
+        start: u64,
+        end: u64,
+        output: &mut dyn Write,
+        cancel: &CancellationToken,
+    ) -> Result<(), PakError> {
+        let mut file_offset = self.stored_offset(entry, start)?;
+        let mut file_size = end - start;
+
+        while file_size > 0 {
+            cancel.check()?;
+            let bytes_to_read = std::cmp::min(file_size, Self::CHUNK_SIZE as u64) as usize;
+            let mut decompressed_data = vec![0u8; bytes_to_read];
+            let bytes_read = self.source().read_at(&mut decompressed_data, file_offset)?;
+            // Sources may read less than asked at their end instead of
+            // failing, as Windows files do
+            if bytes_read != bytes_to_read {
+                return Err(PakError::invalid_data(format!(
+                    "Failed to read stored chunk at {:08X}, read/expected: {}/{}",
+                    file_offset, bytes_read, bytes_to_read
+                )));
+            }
+
+            if entry.encrypted != 0 {
+                xor_each_byte(&mut decompressed_data, Self::DECRYPT_KEY);
+            }
+
+            output
+                .write_all(&decompressed_data)
+                .map_err(PakError::output)?;
+
+            file_size -= bytes_to_read as u64;
+            file_offset += bytes_to_read as u64;
         }
+        Ok(())
     }
+}
 
+impl<R: ReadAt> PakReader for GfpPakReaderV10<R> {
     fn encrypted(&mut self) -> Result<bool, PakError> {
         self.load_pak_info()?;
         Ok(self.info.is_encrypted())
     }
 
-    fn version(&mut self) -> Result<u32, PakError> {
+    fn declared_version(&mut self) -> Result<u32, PakError> {
         self.load_pak_info()?;
         Ok(self.info.version)
     }
 
+    fn parser_version(&self) -> u32 {
+        RawPakInfo::VERSION
+    }
+
+    fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    fn stats(&self) -> IoStats {
+        self.counters.snapshot()
+    }
+
+    fn reset_stats(&mut self) {
+        self.counters.reset();
+    }
+
+    fn set_extraction_cache(&mut self, cache: Option<Arc<ExtractionCache>>) {
+        self.extraction_cache = cache.map(ReaderCache::new);
+    }
+
+    fn extraction_cache(&self) -> Option<&ReaderCache> {
+        self.extraction_cache.as_ref()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn reopen(&mut self, path: &Path) -> Result<(), PakError> {
+        let file = R::open_path(path)
+            .map_err(|e| PakError::from(e).context(format!("reopening {}", path.display())))?;
+        let fresh = Self::new(file);
+        let counters = std::mem::take(&mut self.counters);
+        // Entries without hash are keyed by reader, so the old file's ones
+        // must not be found under the new file
+        let extraction_cache = self
+            .extraction_cache
+            .take()
+            .map(|cache| ReaderCache::new(cache.cache().clone()));
+        *self = Self {
+            counters,
+            extraction_cache,
+            strict: self.strict,
+            ..fresh
+        };
+        Ok(())
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn path(&self) -> Option<&Path> {
+        self.file.path()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn suspend(&mut self) {
+        self.file.suspend();
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn resume(&mut self) -> Result<(), PakError> {
+        let path = self.file.path().unwrap_or(Path::new("")).to_path_buf();
+        if !self
+            .file
+            .resume()
+            .map_err(|e| PakError::from(e).context(format!("reopening {}", path.display())))?
+        {
+            return Ok(());
+        }
+        if let Err(e) = self.check_unchanged() {
+            self.file.suspend();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn check_unchanged(&self) -> Result<(), PakError> {
+        match self.loaded_footer {
+            Some((size, footer)) if !self.file.is_suspended() => {
+                let path = self.file.path().unwrap_or(Path::new(""));
+                check_footer(&self.source(), path, size, &footer)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn is_suspended(&self) -> bool {
+        self.file.is_suspended()
+    }
+
+    fn pak_size(&mut self) -> Result<u64, PakError> {
+        Ok(self.file.size()?)
+    }
+
     fn entries_count(&mut self) -> Result<u64, PakError> {
         self.load_entries()?;
         Ok(self.entries.len() as u64)
     }
 
-    fn extract_entry_to_file(&mut self, entry_id: u64, output: &mut File) -> Result<(), PakError> {
+    fn entries_count_fast(&mut self) -> Result<u64, PakError> {
+        if self.is_entries_loaded {
+            return Ok(self.entries.len() as u64);
+        }
+        self.load_pak_info()?;
+        let index_size = to_usize(self.index_size)?;
+        let mut header = vec![0u8; index_size.min(MAX_INDEX_HEADER_SIZE)];
+        let read = self.source().read_at(&mut header, self.info.index_offset)?;
+        header.truncate(read);
+        match decode_index_header(
+            &header,
+            index_size,
+            self.info.is_encrypted(),
+            Self::DECRYPT_KEY,
+        ) {
+            Ok((entry_count, _)) => Ok(entry_count),
+            // The count of a compressed index is only known once it's inflated
+            Err(_) if is_compressed_index(&header, Self::DECRYPT_KEY) => self.entries_count(),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn footer_bytes(&mut self) -> Result<[u8; FOOTER_SIZE], PakError> {
+        self.load_pak_info()?;
+        Ok(self.info.to_bytes())
+    }
+
+    fn index_stats(&mut self) -> Result<IndexStats, PakError> {
+        self.load_entries()?;
+        let mut stats = IndexStats::new(
+            self.mount_point.clone(),
+            self.info.index_offset,
+            self.index_size,
+            self.info.hash,
+        );
+        for entry in &self.entries {
+            stats.count_entry(entry.encrypted != 0, entry.compression_method);
+        }
+        Ok(stats)
+    }
+
+    fn raw_index(&mut self) -> Result<&[u8], PakError> {
         self.load_entries()?;
-        let entries = &self.entries;
-        let entry = entries[entry_id as usize].clone();
+        Ok(&self.index_data)
+    }
 
-        if entry.num_of_blocks > 0 {
-            for block in &entry.blocks {
-                let mut compressed_data = vec![0u8; block.size() as usize];
-
-                let bytes_read = read_file_at(&self.file, &mut compressed_data, block.offset())?;
-                if bytes_read != block.size() as usize {
-                    return Err(PakError::invalid_data(format!(
-                        "Failed to read compressed chunk at {:08X}, read/expected: {}/{}",
-                        block.offset(),
-                        bytes_read,
-                        block.size()
-                    )));
-                }
+    fn entry_layout(&mut self, entry_id: u64) -> Result<EntryLayout, PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        let payload = if entry.num_of_blocks > 0 {
+            EntryPayload::Blocks(entry.block_layouts()?)
+        } else {
+            EntryPayload::Stored {
+                offset: self.stored_offset(entry, 0)?,
+                length: entry.file_size,
+            }
+        };
+        Ok(EntryLayout {
+            compression_method: entry.compression_method,
+            encrypted: entry.encrypted != 0,
+            xor_key: Self::DECRYPT_KEY,
+            file_size: entry.file_size,
+            payload,
+        })
+    }
 
-                if entry.encrypted != 0 {
-                    xor_each_byte(&mut compressed_data, Self::DECRYPT_KEY);
-                }
+    fn prefetch_entry(&mut self, entry_id: u64) -> Result<(), PakError> {
+        let layout = self.entry_layout(entry_id)?;
+        layout.prefetch(&self.file)?;
+        Ok(())
+    }
 
-                if entry.compression_method != 1 {
-                    return Err(PakError::invalid_data(format!(
-                        "Unknown compression method '{}', only '1' is supported.",
-                        entry.compression_method
-                    )));
-                }
+    fn extract_entry_to_writer(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+    ) -> Result<(), PakError> {
+        self.extract_entry_to_writer_cancellable(entry_id, output, &CancellationToken::new())
+    }
 
-                let decompressed_data =
-                    zlib_decompress(&compressed_data, entry.compressed_block_size as usize)
-                        .ok_or_else(|| std::io::Error::other("ZLIB decompression failed"))?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(entry_id, bytes = tracing::field::Empty, blocks = tracing::field::Empty)
+        )
+    )]
+    fn extract_entry_to_writer_cancellable(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+        cancel: &CancellationToken,
+    ) -> Result<(), PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        entry
+            .check_blocks()
+            .map_err(|e| e.context(format!("extracting entry {}", entry_id)))?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current()
+            .record("bytes", entry.file_size)
+            .record("blocks", entry.num_of_blocks);
 
-                output.write_all(&decompressed_data)?;
+        if entry.num_of_blocks > 0 {
+            for block_index in 0..entry.blocks.len() {
+                cancel.check()?;
+                output
+                    .write_all(&self.decompress_block(entry_id, entry, block_index)?)
+                    .map_err(PakError::output)?;
             }
         } else {
-            let mut file_offset = entry.file_offset + 74;
-            let mut file_size = entry.file_size;
+            self.extract_stored(entry, 0, entry.file_size, output, cancel)?;
+        }
+        output.flush().map_err(PakError::output)?;
+        Ok(())
+    }
 
-            while file_size > 0 {
-                let bytes_to_read = std::cmp::min(file_size as usize, Self::CHUNK_SIZE);
-                let mut decompressed_data = vec![0u8; bytes_to_read];
-                let _bytes_read = read_file_at(&self.file, &mut decompressed_data, file_offset)?;
+    fn extract_entry_range(
+        &mut self,
+        entry_id: u64,
+        start: u64,
+        length: u64,
+        output: &mut dyn Write,
+    ) -> Result<u64, PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        entry
+            .check_blocks()
+            .map_err(|e| e.context(format!("extracting entry {}", entry_id)))?;
+        let start = start.min(entry.file_size);
+        let end = start.saturating_add(length).min(entry.file_size);
+        if start == end {
+            return Ok(0);
+        }
 
-                if entry.encrypted != 0 {
-                    xor_each_byte(&mut decompressed_data, Self::DECRYPT_KEY);
+        if entry.num_of_blocks > 0 {
+            let mut written = 0;
+            let mut block_start = 0;
+            for (block_index, block) in entry.block_layouts()?.iter().enumerate() {
+                let block_end = block_start + block.decompressed_length;
+                if block_end > start {
+                    let data = self.decompress_block(entry_id, entry, block_index)?;
+                    let to = to_usize(end - block_start)?.min(data.len());
+                    let from = to_usize(start.saturating_sub(block_start))?.min(to);
+                    output
+                        .write_all(&data[from..to])
+                        .map_err(PakError::output)?;
+                    written += (to - from) as u64;
+                }
+                if block_end >= end {
+                    break;
                 }
+                block_start = block_end;
+            }
+            output.flush().map_err(PakError::output)?;
+            Ok(written)
+        } else {
+            self.extract_stored(entry, start, end, output, &CancellationToken::new())?;
+            output.flush().map_err(PakError::output)?;
+            Ok(end - start)
+        }
+    }
 
-                output.write_all(&decompressed_data)?;
+    fn validate_entry_headers(&mut self, entry_id: u64) -> Result<(), PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        if entry.num_of_blocks > 0 && entry.compression_method != 1 {
+            return Err(PakError::invalid_data(format!(
+                "Unknown compression method '{}', only '1' is supported.",
+                entry.compression_method
+            )));
+        }
 
-                file_size -= bytes_to_read as u64;
-                file_offset += bytes_to_read as u64;
+        for (block_index, block) in entry.blocks.iter().enumerate() {
+            let mut header = [0u8; 2];
+            let block_size = block.size()?;
+            let bytes_read = if block_size >= 2 {
+                self.source().read_at(&mut header, block.offset())?
+            } else {
+                0
+            };
+            if entry.encrypted != 0 {
+                xor_each_byte(&mut header, Self::DECRYPT_KEY);
             }
+
+            let result = if bytes_read == header.len() {
+                check_zlib_header(header)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("Block of {} bytes is too short", block_size),
+                ))
+            };
+            result.map_err(|error| PakError::Decompress {
+                entry_id: Some(entry_id),
+                block: Some(block_index as u32),
+                source: Box::new(error),
+            })?;
         }
         Ok(())
     }
 
     fn get_entry_path(&mut self, entry_id: u64) -> Result<String, PakError> {
+        if self.is_entry_paths_loaded {
+            return Ok(self.entry_paths[to_usize(entry_id)?].clone());
+        }
+        self.load_path_table()?;
+        self.decode_entry_path(to_usize(entry_id)?)
+    }
+
+    fn preload_entry_paths(&mut self) -> Result<(), PakError> {
+        self.load_entry_paths()
+    }
+
+    fn entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError> {
+        self.load_entries()?;
+        Ok(self.entries[to_usize(entry_id)?].file_hash)
+    }
+
+    fn compute_entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        if entry.blocks.is_empty() {
+            return sha1_at(
+                &self.source(),
+                self.stored_offset(entry, 0)?,
+                entry.compressed_length,
+            );
+        }
+        let blocks = entry
+            .blocks
+            .iter()
+            .map(|block| Ok((block.offset(), block.size()?)))
+            .collect::<Result<Vec<_>, PakError>>()?;
+        sha1_of_ranges(
+            &self.source(),
+            &payload_hash_ranges(&blocks, entry.compressed_length),
+        )
+    }
+
+    fn find_entry_by_path(&mut self, path: &str) -> Result<Option<u64>, PakError> {
+        self.load_path_index()?;
+        Ok(self.path_index.get(path).copied())
+    }
+
+    /// Walks the path table, comparing the names in it, until an entry
+    /// matches; only UTF-16 names are decoded.
+    fn contains_path(&mut self, path: &str) -> Result<bool, PakError> {
+        if self.is_path_index_loaded {
+            return Ok(self.path_index.contains_key(path));
+        }
+        self.load_path_table()?;
+        let Some(path) = path.strip_prefix(self.mount_point.as_str()) else {
+            return Ok(false);
+        };
+        for span in self.path_spans.iter().flatten() {
+            let Some(dir_length) = self.dir_spans[span.dir].strip_from(&self.index_data, path)?
+            else {
+                continue;
+            };
+            let Some(name) = path.get(dir_length..) else {
+                continue;
+            };
+            if span.name.strip_from(&self.index_data, name)? == Some(name.len()) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn find_entry_by_path_with(
+        &mut self,
+        path: &str,
+        path_match: PathMatch,
+    ) -> Result<Option<u64>, PakError> {
+        if path_match == PathMatch::Exact {
+            return self.find_entry_by_path(path);
+        }
         self.load_entry_paths()?;
-        Ok(self.entry_paths[entry_id as usize].clone())
+        self.path_match_indexes.find(
+            path,
+            path_match,
+            &self.mount_point,
+            self.entry_paths.iter().map(String::as_str),
+        )
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std-fs"))]
 mod tests {
     use super::*;
+    use crate::pak_reader::block_map::check_block_map;
+    use crate::pak_reader::ext::PakReaderExt;
     use crate::pak_reader::implements::open_paks_by_glob;
     use tempfile::TempDir;
 
@@ -368,12 +1265,645 @@ mod tests {
         for (pak_path, mut pak) in open_paks_by_glob(GFP_PAKS_PATTERN, 10).unwrap() {
             println!("[{}]", pak_path.to_string_lossy());
             println!("IsEncrypted: {}", pak.encrypted()?);
-            println!("Version: {}", pak.version()?);
+            println!("Version: {}", pak.declared_version()?);
             println!();
         }
         Ok(())
     }
 
+    #[test]
+    fn test_empty_pak() -> Result<(), Box<dyn std::error::Error>> {
+        // A placeholder pak: the index ends right after an entry count of 0
+        let pak_path = "test/empty/empty.pak";
+        assert_eq!(
+            crate::pak_reader::detect::is_gfp_pak(pak_path)?,
+            Some(crate::pak_reader::detect::DetectedVariant::V10)
+        );
+        let mut pak = GfpPakReaderV10::new(std::fs::read(pak_path)?);
+        assert_eq!(pak.entries_count_fast()?, 0);
+        assert_eq!(pak.entries_count()?, 0);
+        assert_eq!(
+            pak.index_stats()?.mount_point,
+            "ShadowTrackerExtra/Content/"
+        );
+        assert_eq!(pak.raw_index()?.len(), 45);
+        assert_eq!(pak.find_entry_by_path("Lua/common/lua_object.lua")?, None);
+
+        let temp_dir = TempDir::new()?;
+        let report = crate::pak_reader::extract::extract_pak(
+            &mut pak,
+            Path::new(pak_path),
+            temp_dir.path(),
+            &Default::default(),
+            &CancellationToken::new(),
+            |_| panic!("the pak has no entries"),
+        )?;
+        assert!(report.rows.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_broken_pipe_reading_pak() -> Result<(), Box<dyn std::error::Error>> {
+        /// Reads of entry payloads fail with BrokenPipe, as a source behind
+        /// a pipe can
+        struct BrokenPayloads {
+            data: Vec<u8>,
+            index_offset: u64,
+        }
+        impl ReadAt for BrokenPayloads {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+                if offset < self.index_offset {
+                    return Err(std::io::ErrorKind::BrokenPipe.into());
+                }
+                self.data.read_at(buf, offset)
+            }
+            fn size(&self) -> std::io::Result<u64> {
+                self.data.size()
+            }
+        }
+
+        let data = std::fs::read(PAK_1)?;
+        let index_offset = GfpPakReaderV10::new(data.as_slice())
+            .index_stats()?
+            .index_offset;
+        let mut pak = GfpPakReaderV10::new(BrokenPayloads { data, index_offset });
+        for entry_id in [0, 2] {
+            // Only a closed output is OutputClosed, not a failing pak
+            let error = pak
+                .extract_entry_to_writer(entry_id, &mut vec![])
+                .unwrap_err();
+            assert!(
+                matches!(error.root_cause(), PakError::Io(e) if e.kind() == std::io::ErrorKind::BrokenPipe),
+                "{:?}",
+                error
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_index() -> Result<(), Box<dyn std::error::Error>> {
+        use flate2::{Compression, write::ZlibEncoder};
+
+        let data = std::fs::read(PAK_1)?;
+        let mut original = GfpPakReaderV10::new(data.clone());
+        assert!(!original.index_compressed()?);
+        let stats = original.index_stats()?;
+        let index = stats.index_offset as usize..data.len() - RawPakInfo::SIZE;
+
+        // The index starts at the same offset, so the footer stays valid
+        let mut encoder = ZlibEncoder::new(data[..index.start].to_vec(), Compression::best());
+        encoder.write_all(&data[index.clone()])?;
+        let mut compressed = encoder.finish()?;
+        compressed.extend_from_slice(&data[index.end..]);
+        assert!(compressed.len() < data.len());
+
+        assert_eq!(
+            GfpPakReaderV10::new(compressed.as_slice()).entries_count_fast()?,
+            stats.entries_count
+        );
+        let mut pak = GfpPakReaderV10::new(compressed);
+        assert!(pak.index_compressed()?);
+        assert_eq!(pak.entries_count()?, stats.entries_count);
+        assert_eq!(pak.index_stats()?.mount_point, stats.mount_point);
+        for entry_id in 0..stats.entries_count {
+            assert_eq!(
+                pak.get_entry_path(entry_id)?,
+                original.get_entry_path(entry_id)?
+            );
+            let (mut expected, mut actual) = (vec![], vec![]);
+            original.extract_entry_to_writer(entry_id, &mut expected)?;
+            pak.extract_entry_to_writer(entry_id, &mut actual)?;
+            assert_eq!(actual, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_error_context() -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = std::fs::read(PAK_1)?;
+        let index_offset = GfpPakReaderV10::new(data.as_slice())
+            .index_stats()?
+            .index_offset as usize;
+        let mount_point_length =
+            u32::from_le_bytes(data[index_offset..index_offset + 4].try_into()?) as usize;
+        let count_offset = index_offset + 4 + mount_point_length;
+        assert_eq!(data[count_offset..count_offset + 4], 7u32.to_le_bytes());
+        // The directory records after the last entry don't parse as entries
+        data[count_offset..count_offset + 4].copy_from_slice(&12u32.to_le_bytes());
+
+        let error = GfpPakReaderV10::new(data).entries_count().unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.starts_with("while parsing entry 7: "),
+            "{}",
+            message
+        );
+        assert!(message.contains("doesn't fit in the"), "{}", message);
+        assert!(matches!(
+            error.root_cause(),
+            PakError::IndexOverrun {
+                what: "Block count",
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(std::fs::read(PAK_1)?);
+        pak.load_entries()?;
+        let entry_id = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks > 0)
+            .expect("a compressed entry") as u64;
+        // As if the block list had come out of the index empty
+        pak.entries[entry_id as usize].blocks.clear();
+
+        let mut output = tempfile::tempfile()?;
+        let error = pak
+            .extract_entry_to_file(entry_id, &mut output)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "while extracting entry {}: Invalid data: Entry has {} compression blocks but 0 were read",
+                entry_id, pak.entries[entry_id as usize].num_of_blocks
+            )
+        );
+        assert_eq!(output.metadata()?.len(), 0);
+        assert!(
+            pak.extract_entry_range(entry_id, 0, 10, &mut vec![])
+                .is_err()
+        );
+        Ok(())
+    }
+
+    /// PAK_1 as it would be written `shift` bytes into a file, with the
+    /// offsets in the entries and the footer moved along
+    fn moved_pak(shift: u64) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let data = std::fs::read(PAK_1)?;
+        let mut original = GfpPakReaderV10::new(data.as_slice());
+        original.load_entries()?;
+        assert!(!original.info.is_encrypted());
+
+        let index_offset = original.info.index_offset as usize;
+        let mount_point_length =
+            u32::from_le_bytes(data[index_offset..index_offset + 4].try_into()?) as usize;
+        let mut index = data[index_offset..index_offset + 4 + mount_point_length + 4].to_vec();
+        for entry in &original.entries {
+            index.extend_from_slice(&entry.file_hash);
+            index.extend_from_slice(&(entry.file_offset + shift).to_le_bytes());
+            index.extend_from_slice(&entry.file_size.to_le_bytes());
+            index.extend_from_slice(&entry.compression_method.to_le_bytes());
+            index.extend_from_slice(&entry.compressed_length.to_le_bytes());
+            index.extend_from_slice(&entry.dummy);
+            if entry.compression_method != 0 {
+                index.extend_from_slice(&entry.num_of_blocks.to_le_bytes());
+                for block in &entry.blocks {
+                    index.extend_from_slice(&(block.start + shift).to_le_bytes());
+                    index.extend_from_slice(&(block.end + shift).to_le_bytes());
+                }
+            }
+            index.extend_from_slice(&entry.compressed_block_size.to_le_bytes());
+            index.push(entry.encrypted);
+        }
+        index.extend_from_slice(&original.index_data[original.index_offset..]);
+        let mut footer = data[data.len() - RawPakInfo::SIZE..].to_vec();
+        let raw_index_offset = (original.info.index_offset + shift) ^ RawPakInfo::OFFSET_XOR_KEY;
+        footer[37..45].copy_from_slice(&raw_index_offset.to_le_bytes());
+
+        let mut moved = data[..index_offset].to_vec();
+        moved.extend_from_slice(&index);
+        moved.extend_from_slice(&footer);
+        Ok(moved)
+    }
+
+    #[test]
+    fn test_open_at() -> Result<(), Box<dyn std::error::Error>> {
+        const PREFIX: u64 = 4099;
+        let mut original = GfpPakReaderV10::open(PAK_1)?;
+
+        // PAK_1 in the middle of a file, as if appended to an executable
+        // with something else after it
+        let moved = moved_pak(PREFIX)?;
+        let mut container = vec![0x7F; PREFIX as usize];
+        container.extend_from_slice(&moved);
+        container.extend_from_slice(&[0xA5; 300]);
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("game.exe");
+        std::fs::write(&path, &container)?;
+
+        let trailer_end = PREFIX + moved.len() as u64;
+        let mut pak = GfpPakReaderV10::open_at(&path, trailer_end)?;
+        assert_eq!(pak.pak_size()?, trailer_end);
+        assert_eq!(pak.entries_count()?, original.entries_count()?);
+        for entry_id in 0..pak.entries_count()? {
+            assert_eq!(
+                pak.get_entry_path(entry_id)?,
+                original.get_entry_path(entry_id)?
+            );
+            assert!(pak.verify_entry(entry_id)?);
+            let (mut expected, mut actual) = (vec![], vec![]);
+            original.extract_entry_to_writer(entry_id, &mut expected)?;
+            pak.extract_entry_to_writer(entry_id, &mut actual)?;
+            assert_eq!(actual, expected);
+        }
+
+        // Looking for the footer at the end of the file finds the padding
+        assert!(GfpPakReaderV10::open(&path)?.entries_count().is_err());
+        assert!(GfpPakReaderV10::open_at(&path, container.len() as u64 + 1).is_err());
+        assert!(GfpPakReaderV10::open_at(&path, 44).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_overruns() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(PAK_1)?;
+        let mut original = GfpPakReaderV10::new(data.as_slice());
+        original.load_entries()?;
+        let index_start = original.info.index_offset as usize;
+        let index_end = data.len() - RawPakInfo::SIZE;
+        let mount_point_length =
+            u32::from_le_bytes(data[index_start..index_start + 4].try_into()?) as usize;
+        let count_start = index_start + 4 + mount_point_length;
+        let table_start = index_start + original.index_offset;
+        let dir_start = table_start + 16;
+        let dir_size = i32::from_le_bytes(data[dir_start..dir_start + 4].try_into()?);
+        let files_start = dir_start + 4 + dir_size as usize;
+
+        // The root cause of parsing PAK_1 with `bytes` at `offset`
+        let overrun = |offset: usize, bytes: &[u8]| {
+            let mut data = data.clone();
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            let mut pak = GfpPakReaderV10::new(data);
+            match pak.entries_count().and_then(|_| pak.get_entry_path(0)) {
+                Err(error) => match error.root_cause() {
+                    PakError::IndexOverrun {
+                        what,
+                        value,
+                        offset,
+                        remaining,
+                    } => (*what, *value, *offset + index_start, *remaining),
+                    other => panic!("{}", other),
+                },
+                Ok(path) => panic!("parsed {}", path),
+            }
+        };
+
+        // Entry counts are already checked with the index header
+        let mut huge_count = data.clone();
+        huge_count[count_start..count_start + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(
+            GfpPakReaderV10::new(huge_count).entries_count(),
+            Err(PakError::IndexUndecodable { .. })
+        ));
+        assert_eq!(
+            overrun(table_start + 8, &u64::MAX.to_le_bytes()).0,
+            "Directory count"
+        );
+        assert_eq!(
+            overrun(files_start, &u64::MAX.to_le_bytes()),
+            (
+                "File count",
+                u64::MAX,
+                files_start + 8,
+                index_end - files_start - 8
+            )
+        );
+        // i32::MIN UTF-16 code units, whose byte length doesn't fit in an i32
+        assert_eq!(
+            overrun(dir_start, &i32::MIN.to_le_bytes()),
+            (
+                "Name length",
+                1 << 32,
+                dir_start + 4,
+                index_end - dir_start - 4
+            )
+        );
+        // One byte more than the whole rest of the index
+        let remaining = index_end - dir_start - 4;
+        assert_eq!(
+            overrun(dir_start, &(remaining as i32 + 1).to_le_bytes()),
+            (
+                "Name length",
+                remaining as u64 + 1,
+                dir_start + 4,
+                remaining
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_path() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::open(PAK_2)?;
+        let paths = (0..pak.entries_count()?)
+            .map(|entry_id| pak.get_entry_path(entry_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut pak = GfpPakReaderV10::new(std::fs::read(PAK_2)?);
+        for path in &paths {
+            assert!(pak.contains_path(path)?, "{}", path);
+            assert!(!pak.contains_path(&format!("{}x", path))?);
+            assert!(!pak.contains_path(&path[..path.len() - 1])?);
+        }
+        assert!(!pak.contains_path("")?);
+        assert!(!pak.contains_path("ShadowTrackerExtra/Content/")?);
+        assert!(!pak.contains_path("Lua/common/lua_object.lua")?);
+        assert!(!pak.is_path_index_loaded && !pak.is_entry_paths_loaded);
+
+        // Answered from the lookup table once there is one
+        pak.find_entry_by_path(&paths[0])?;
+        assert!(pak.contains_path(&paths[0])?);
+        assert!(!pak.contains_path("missing")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utf16_dir_name() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(PAK_1)?;
+        let mut original = GfpPakReaderV10::new(data.as_slice());
+        original.load_entries()?;
+        let paths = (0..original.entries_count()?)
+            .map(|entry_id| original.get_entry_path(entry_id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The first directory name after the entry and directory counts,
+        // stored again in UTF-16 with a negated size, non-ASCII included
+        let index_start = original.info.index_offset as usize;
+        let dir_start = index_start + original.index_offset + 16;
+        let dir_size = i32::from_le_bytes(data[dir_start..dir_start + 4].try_into()?);
+        let dir_end = dir_start + 4 + dir_size as usize;
+        let dir_name = std::str::from_utf8(&data[dir_start + 4..dir_end - 1])?;
+        let new_dir_name = format!("{}é/", dir_name);
+        let utf16: Vec<u16> = new_dir_name.encode_utf16().chain([0]).collect();
+
+        let mut pak = data[..dir_start].to_vec();
+        pak.extend_from_slice(&(-(utf16.len() as i32)).to_le_bytes());
+        pak.extend(utf16.iter().flat_map(|unit| unit.to_le_bytes()));
+        pak.extend_from_slice(&data[dir_end..]);
+
+        let mut pak = GfpPakReaderV10::new(pak);
+        let prefix = format!("{}{}", original.mount_point, dir_name);
+        let mut renamed = 0;
+        for (entry_id, path) in paths.iter().enumerate() {
+            let new_path = pak.get_entry_path(entry_id as u64)?;
+            match path.strip_prefix(&prefix) {
+                Some(name) if new_path != *path => {
+                    assert_eq!(
+                        new_path,
+                        format!("{}{}{}", original.mount_point, new_dir_name, name)
+                    );
+                    renamed += 1;
+                }
+                _ => assert_eq!(new_path, *path),
+            }
+        }
+        assert!(renamed > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entries_beyond_4_gib() -> Result<(), Box<dyn std::error::Error>> {
+        const SHIFT: u64 = 5 << 30;
+        let mut original = GfpPakReaderV10::open(PAK_1)?;
+
+        // The same pak behind a 5 GiB hole
+        let moved = moved_pak(SHIFT)?;
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("huge.pak");
+        {
+            use std::io::{Seek, SeekFrom};
+            let mut file = File::create(&pak_path)?;
+            file.seek(SeekFrom::Start(SHIFT))?;
+            file.write_all(&moved)?;
+        }
+
+        let mut pak = GfpPakReaderV10::open(&pak_path)?;
+        assert_eq!(pak.pak_size()?, SHIFT + moved.len() as u64);
+        assert_eq!(pak.entries_count()?, original.entries_count()?);
+        for entry_id in 0..pak.entries_count()? {
+            let layout = pak.entry_layout(entry_id)?;
+            match &layout.payload {
+                EntryPayload::Stored { offset, .. } => assert!(*offset > SHIFT),
+                EntryPayload::Blocks(blocks) => assert!(blocks.iter().all(|b| b.offset > SHIFT)),
+            }
+            assert!(pak.verify_entry(entry_id)?);
+            let (mut expected, mut actual) = (vec![], vec![]);
+            original.extract_entry_to_writer(entry_id, &mut expected)?;
+            pak.extract_entry_to_writer(entry_id, &mut actual)?;
+            assert_eq!(actual, expected);
+        }
+        Ok(())
+    }
+
+    /// Ids of entries and their content
+    type Contents = Vec<(u64, Vec<u8>)>;
+
+    /// PAK_1 with entries 0 and 1 turned into entries of several blocks that
+    /// are interleaved in the pak, entry 0's in descending order, and entry 3
+    /// using the same blocks as entry 0. Also returns the new content of
+    /// these entries.
+    fn interleaved_pak() -> Result<(Vec<u8>, Contents), Box<dyn std::error::Error>> {
+        use flate2::Compression;
+        use flate2::write::ZlibEncoder;
+        const BLOCK_SIZE: usize = 32;
+
+        let data = std::fs::read(PAK_1)?;
+        let mut original = GfpPakReaderV10::new(data.as_slice());
+        original.load_entries()?;
+        let index_offset = original.info.index_offset as usize;
+
+        let content_a: Vec<u8> = (0..100).collect();
+        let content_b = b"interleaved ".repeat(5);
+        let compress = |block: &[u8]| -> std::io::Result<Vec<u8>> {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(block)?;
+            encoder.finish()
+        };
+        let blocks = [
+            content_a
+                .chunks(BLOCK_SIZE)
+                .map(compress)
+                .collect::<Result<Vec<_>, _>>()?,
+            content_b
+                .chunks(BLOCK_SIZE)
+                .map(compress)
+                .collect::<Result<Vec<_>, _>>()?,
+        ];
+        assert_eq!((blocks[0].len(), blocks[1].len()), (4, 2));
+
+        // The blocks go after the original payloads as A3 B0 A2 A1 B1 A0
+        let mut pak = data[..index_offset].to_vec();
+        let mut ranges = [vec![CompressionBlock { start: 0, end: 0 }; 4], vec![]];
+        ranges[1] = ranges[0][..2].to_vec();
+        for (content, block) in [(0, 3), (1, 0), (0, 2), (0, 1), (1, 1), (0, 0)] {
+            let start = pak.len() as u64;
+            pak.extend_from_slice(&blocks[content][block]);
+            ranges[content][block] = CompressionBlock {
+                start,
+                end: pak.len() as u64,
+            };
+        }
+
+        let mut entries = original.entries.clone();
+        for (entry_id, content, blocks) in [
+            (0, &content_a, &ranges[0]),
+            (1, &content_b, &ranges[1]),
+            (3, &content_a, &ranges[0]),
+        ] {
+            let mut hasher = sha1_smol::Sha1::new();
+            for block in blocks {
+                hasher.update(&pak[block.start as usize..block.end as usize]);
+            }
+            let entry = &mut entries[entry_id];
+            entry.file_hash = hasher.digest().bytes();
+            entry.file_size = content.len() as u64;
+            entry.compression_method = 1;
+            entry.compressed_length = blocks.iter().map(|block| block.end - block.start).sum();
+            entry.num_of_blocks = blocks.len() as u32;
+            entry.blocks = blocks.clone();
+            entry.compressed_block_size = BLOCK_SIZE as u32;
+            entry.encrypted = 0;
+        }
+
+        let mount_point_length =
+            u32::from_le_bytes(data[index_offset..index_offset + 4].try_into()?) as usize;
+        let new_index_offset = pak.len() as u64;
+        pak.extend_from_slice(&data[index_offset..index_offset + 4 + mount_point_length + 4]);
+        for entry in &entries {
+            pak.extend_from_slice(&entry.file_hash);
+            pak.extend_from_slice(&entry.file_offset.to_le_bytes());
+            pak.extend_from_slice(&entry.file_size.to_le_bytes());
+            pak.extend_from_slice(&entry.compression_method.to_le_bytes());
+            pak.extend_from_slice(&entry.compressed_length.to_le_bytes());
+            pak.extend_from_slice(&entry.dummy);
+            if entry.compression_method != 0 {
+                pak.extend_from_slice(&entry.num_of_blocks.to_le_bytes());
+                for block in &entry.blocks {
+                    pak.extend_from_slice(&block.start.to_le_bytes());
+                    pak.extend_from_slice(&block.end.to_le_bytes());
+                }
+            }
+            pak.extend_from_slice(&entry.compressed_block_size.to_le_bytes());
+            pak.push(entry.encrypted);
+        }
+        pak.extend_from_slice(&original.index_data[original.index_offset..]);
+        let mut footer = data[data.len() - RawPakInfo::SIZE..].to_vec();
+        footer[37..45]
+            .copy_from_slice(&(new_index_offset ^ RawPakInfo::OFFSET_XOR_KEY).to_le_bytes());
+        pak.extend_from_slice(&footer);
+
+        Ok((
+            pak,
+            vec![(0, content_a.clone()), (1, content_b), (3, content_a)],
+        ))
+    }
+
+    #[test]
+    fn test_interleaved_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        let (data, contents) = interleaved_pak()?;
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        let mut original = GfpPakReaderV10::new(File::open(PAK_1)?);
+        assert_eq!(pak.entries_count()?, original.entries_count()?);
+        for entry_id in 0..pak.entries_count()? {
+            let expected = match contents.iter().find(|(id, _)| *id == entry_id) {
+                Some((_, content)) => content.clone(),
+                None => {
+                    let mut content = vec![];
+                    original.extract_entry_to_writer(entry_id, &mut content)?;
+                    content
+                }
+            };
+            let mut content = vec![];
+            pak.extract_entry_to_writer(entry_id, &mut content)?;
+            assert_eq!(content, expected, "entry {}", entry_id);
+            let mut range = vec![];
+            pak.extract_entry_range(entry_id, 20, 50, &mut range)?;
+            assert_eq!(
+                range,
+                expected[20.min(expected.len())..70.min(expected.len())]
+            );
+            assert!(pak.verify_entry(entry_id)?, "entry {}", entry_id);
+        }
+
+        let report = check_block_map(&mut pak)?;
+        assert_eq!(report.out_of_order, [0, 3]);
+        assert_eq!(report.shared.len(), 4);
+        assert!(report.shared.iter().all(|piece| piece.entry_ids == [0, 3]));
+        assert!(report.overlapping.is_empty());
+        assert!(check_block_map(&mut original)?.is_plain());
+
+        // The second block of entry 1 reaching back into entry 0's
+        pak.entries[1].blocks[1].start -= 1;
+        assert_eq!(check_block_map(&mut pak)?.overlapping, [(0, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflowing_offsets() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(std::fs::read(PAK_1)?);
+        pak.load_entries()?;
+        let stored = pak
+            .entries
+            .iter()
+            .position(|e| e.blocks.is_empty())
+            .unwrap();
+        let compressed = pak
+            .entries
+            .iter()
+            .position(|e| !e.blocks.is_empty())
+            .unwrap();
+        pak.entries[stored].file_offset = u64::MAX - 10;
+        let block = &mut pak.entries[compressed].blocks[0];
+        (block.start, block.end) = (block.end, block.start);
+
+        for entry_id in [stored, compressed] {
+            let error = pak
+                .extract_entry_to_writer(entry_id as u64, &mut std::io::sink())
+                .unwrap_err();
+            assert!(matches!(error, PakError::InvalidData(_)), "{}", error);
+            assert!(pak.entry_layout(entry_id as u64).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_stored_entry() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(std::fs::read(PAK_1)?);
+        pak.load_entries()?;
+        let entry_id = pak
+            .entries
+            .iter()
+            .position(|e| e.blocks.is_empty() && e.file_size > 100)
+            .expect("a stored entry") as u64;
+        let payload_offset = pak.stored_offset(&pak.entries[entry_id as usize], 0)?;
+
+        // Memory sources read less at their end, as Windows files do
+        pak.file.truncate(payload_offset as usize + 100);
+        let error = pak
+            .extract_entry_to_writer(entry_id, &mut vec![])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid data: Failed to read stored chunk at {:08X}, read/expected: 100/{}",
+                payload_offset, pak.entries[entry_id as usize].file_size
+            )
+        );
+
+        // Files cut while the pak is open fail however the platform reads them
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&std::fs::read(PAK_1)?)?;
+        let mut pak = GfpPakReaderV10::new(file.try_clone()?);
+        pak.load_entries()?;
+        file.set_len(payload_offset + 100)?;
+        assert!(pak.extract_entry_to_writer(entry_id, &mut vec![]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_list_pak_entries() -> Result<(), Box<dyn std::error::Error>> {
         for (pak_path, mut pak) in open_paks_by_glob(GFP_PAKS_PATTERN, 10).unwrap() {
@@ -412,4 +1942,419 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_index_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::open(PAK_1)?;
+        let stats = pak.index_stats()?;
+        assert_eq!(stats.mount_point, "ShadowTrackerExtra/Content/");
+        assert_eq!(stats.entries_count, pak.entries_count()?);
+        assert_eq!(stats.index_offset + stats.index_size + 45, pak.pak_size()?);
+        assert_eq!(
+            stats.encrypted_entries + stats.plain_entries,
+            stats.entries_count
+        );
+        assert_eq!(
+            stats.compression_methods,
+            std::collections::BTreeMap::from([(0, 2), (1, 5)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_entry_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let mut lazy_pak = GfpPakReaderV10::new(File::open(PAK_2)?);
+        let mut eager_pak = GfpPakReaderV10::new(File::open(PAK_2)?);
+        eager_pak.preload_entry_paths()?;
+
+        for entry_id in (0..lazy_pak.entries_count()?).rev() {
+            assert_eq!(
+                lazy_pak.get_entry_path(entry_id)?,
+                eager_pak.get_entry_path(entry_id)?
+            );
+        }
+        assert!(!lazy_pak.is_entry_paths_loaded);
+        assert!(lazy_pak.entry_paths.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_layout() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(File::open(PAK_1)?);
+        for entry_id in 0..pak.entries_count()? {
+            let layout = pak.entry_layout(entry_id)?;
+            let entry = &pak.entries[entry_id as usize];
+            match &layout.payload {
+                EntryPayload::Stored { offset, length } => {
+                    assert_eq!(layout.compression_method, 0);
+                    assert_eq!(*offset, entry.file_offset + 74);
+                    assert_eq!(*length, entry.file_size);
+                }
+                EntryPayload::Blocks(blocks) => {
+                    assert_eq!(blocks.len(), entry.num_of_blocks as usize);
+                    let decompressed: u64 = blocks.iter().map(|b| b.decompressed_length).sum();
+                    assert_eq!(decompressed, layout.file_size);
+                    for pair in blocks.windows(2) {
+                        assert!(pair[0].offset + pair[0].compressed_length <= pair[1].offset);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_range() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(File::open(PAK_2)?);
+        pak.load_entries()?;
+        let multi_block = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks > 1)
+            .unwrap() as u64;
+        let stored = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks == 0)
+            .map(|entry_id| entry_id as u64);
+
+        for entry_id in [Some(0), Some(multi_block), stored].into_iter().flatten() {
+            let mut full = Vec::new();
+            pak.extract_entry_to_writer(entry_id, &mut full)?;
+            let size = full.len() as u64;
+            let block_size = pak.entries[entry_id as usize].compressed_block_size as u64;
+            for (start, length) in [
+                (0, size),
+                (0, 1),
+                (1, 10),
+                (block_size.saturating_sub(3), 6),
+                (block_size, block_size + 1),
+                (size - 1, 100),
+                (size, 1),
+                (size + 5, 1),
+                (3, u64::MAX),
+            ] {
+                let mut range = Vec::new();
+                let written = pak.extract_entry_range(entry_id, start, length, &mut range)?;
+                let from = start.min(size) as usize;
+                let to = start.saturating_add(length).min(size) as usize;
+                assert_eq!(range, full[from..to], "{} {}:{}", entry_id, start, length);
+                assert_eq!(written, range.len() as u64);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_range_zero_block_size() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(File::open(PAK_2)?);
+        pak.load_entries()?;
+        let entry_id = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks == 1)
+            .unwrap();
+        let mut full = Vec::new();
+        pak.extract_entry_to_writer(entry_id as u64, &mut full)?;
+        pak.entries[entry_id].compressed_block_size = 0;
+        let size = full.len() as u64;
+        for (start, length) in [(0, size), (1, 10), (size - 1, 100), (size, 1)] {
+            let mut range = Vec::new();
+            let written = pak.extract_entry_range(entry_id as u64, start, length, &mut range)?;
+            let from = start.min(size) as usize;
+            let to = start.saturating_add(length).min(size) as usize;
+            assert_eq!(range, full[from..to], "{}:{}", start, length);
+            assert_eq!(written, range.len() as u64);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_pak_size() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::open(PAK_1)?;
+        assert_eq!(pak.pak_size()?, std::fs::metadata(PAK_1)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_io_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::new(File::open(PAK_1)?);
+        assert_eq!(pak.stats(), IoStats::default());
+        pak.entries_count()?;
+        let stats = pak.stats();
+        assert!(stats.reads >= 2);
+        assert!(stats.bytes_read > pak.index_stats()?.index_size);
+
+        // Entry 0 is compressed in a single block, entry 2 is stored
+        for (entry_id, compressed) in [(0, true), (2, false)] {
+            let layout = pak.entry_layout(entry_id)?;
+            pak.reset_stats();
+            assert_eq!(pak.stats(), IoStats::default());
+            pak.extract_entry_to_writer(entry_id, &mut vec![])?;
+            let stats = pak.stats();
+            assert!(stats.reads >= 1);
+            assert!(stats.bytes_read >= layout.stored_size());
+            let decompressed = if compressed { layout.file_size } else { 0 };
+            assert_eq!(stats.bytes_decompressed, decompressed);
+
+            pak.reset_stats();
+            pak.entry_reader(entry_id, 512)?.copy_to(&mut vec![])?;
+            let stats = pak.stats();
+            assert!(stats.bytes_read >= layout.stored_size());
+            assert_eq!(stats.bytes_decompressed, decompressed);
+            if compressed {
+                assert!(stats.reads > 1, "{:?}", stats);
+            }
+
+            pak.reset_stats();
+            pak.compute_entry_hash(entry_id)?;
+            assert!(pak.stats().bytes_read >= layout.stored_size());
+            assert_eq!(pak.stats().bytes_decompressed, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_all_to_map() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV10::open(PAK_1)?;
+        let contents = pak.extract_all_to_map()?;
+
+        assert_eq!(contents.len() as u64, pak.entries_count()?);
+        let lua_object = &contents["ShadowTrackerExtra/Content/Lua/common/lua_object.lua"];
+        assert!(!lua_object.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_from_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(PAK_2)?;
+        let mut memory_pak = GfpPakReaderV10::new(data.as_slice());
+        let mut file_pak = GfpPakReaderV10::open(PAK_2)?;
+
+        assert_eq!(memory_pak.declared_version()?, file_pak.declared_version()?);
+        assert_eq!(memory_pak.entries_count()?, file_pak.entries_count()?);
+        for entry_id in 0..memory_pak.entries_count()? {
+            assert_eq!(
+                memory_pak.get_entry_path(entry_id)?,
+                file_pak.get_entry_path(entry_id)?
+            );
+        }
+
+        let mut memory_output = Vec::new();
+        memory_pak.extract_entry_to_writer(0, &mut memory_output)?;
+        let mut file_output = Vec::new();
+        file_pak.extract_entry_to_writer(0, &mut file_output)?;
+        assert_eq!(memory_output, file_output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_between_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        struct CancelOnWrite<'a> {
+            data: Vec<u8>,
+            cancel: &'a CancellationToken,
+        }
+        impl Write for CancelOnWrite<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.cancel.cancel();
+                self.data.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut pak = GfpPakReaderV10::new(File::open(PAK_2)?);
+        pak.load_entries()?;
+        let entry_id = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks > 1)
+            .unwrap() as u64;
+
+        let cancel = CancellationToken::new();
+        let mut output = CancelOnWrite {
+            data: Vec::new(),
+            cancel: &cancel,
+        };
+        let result = pak.extract_entry_to_writer_cancellable(entry_id, &mut output, &cancel);
+        assert!(matches!(result, Err(PakError::Cancelled)));
+
+        let mut full = Vec::new();
+        pak.extract_entry_to_writer(entry_id, &mut full)?;
+        assert!(!output.data.is_empty());
+        assert!(output.data.len() < full.len());
+        assert!(full.starts_with(&output.data));
+
+        assert!(matches!(
+            pak.extract_all_to_map_cancellable(&cancel),
+            Err(PakError::Cancelled)
+        ));
+        assert!(matches!(pak.verify_all(&cancel), Err(PakError::Cancelled)));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_extract_direct() -> Result<(), Box<dyn std::error::Error>> {
+        for pak_path in [PAK_1, PAK_2] {
+            let mut direct_pak = GfpPakReaderV10::open_direct(pak_path)?;
+            let mut pak = GfpPakReaderV10::open(pak_path)?;
+            assert_eq!(direct_pak.entries_count()?, pak.entries_count()?);
+            for entry_id in 0..pak.entries_count()?.min(10) {
+                let mut direct_output = Vec::new();
+                direct_pak.extract_entry_to_writer(entry_id, &mut direct_output)?;
+                let mut output = Vec::new();
+                pak.extract_entry_to_writer(entry_id, &mut output)?;
+                assert_eq!(direct_output, output);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_entry_headers() -> Result<(), Box<dyn std::error::Error>> {
+        for pak_path in [PAK_1, PAK_2] {
+            let mut pak = GfpPakReaderV10::new(File::open(pak_path)?);
+            for entry_id in 0..pak.entries_count()? {
+                pak.validate_entry_headers(entry_id)?;
+            }
+        }
+
+        let mut data = std::fs::read(PAK_1)?;
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        pak.load_entries()?;
+        let (entry_id, block) = pak
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.num_of_blocks > 0)
+            .map(|(entry_id, entry)| (entry_id as u64, entry.blocks[0]))
+            .unwrap();
+        data[block.start as usize..block.start as usize + 2].copy_from_slice(&[0xFF, 0xFF]);
+
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        assert!(matches!(
+            pak.validate_entry_headers(entry_id),
+            Err(PakError::Decompress { entry_id: Some(id), block: Some(0), .. }) if id == entry_id
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_encrypted_flag() -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = std::fs::read(PAK_1)?;
+        let footer_offset = data.len() - FOOTER_SIZE;
+        data[footer_offset] ^= 1;
+
+        let mut pak = GfpPakReaderV10::new(data.clone());
+        assert!(pak.encrypted()?);
+        let mut expected = GfpPakReaderV10::open(PAK_1)?;
+        assert_eq!(pak.entries_count()?, expected.entries_count()?);
+        assert!(!pak.index_encrypted()?);
+        // The footer stays as the pak has it
+        assert!(pak.encrypted()?);
+        assert_eq!(pak.footer_bytes()?, data[footer_offset..]);
+        for entry_id in 0..pak.entries_count()? {
+            assert_eq!(
+                pak.get_entry_path(entry_id)?,
+                expected.get_entry_path(entry_id)?
+            );
+        }
+
+        // Neither way makes sense of an overwritten index
+        let index_offset = pak.index_stats()?.index_offset as usize;
+        data[index_offset..index_offset + 4].fill(0xFF);
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        let error = pak.entries_count().unwrap_err();
+        assert!(
+            matches!(&error, PakError::IndexUndecodable { decrypted, plain }
+                if decrypted.starts_with("mount point length") && plain.starts_with("mount point length")),
+            "{:?}",
+            error
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_entries_in_encrypted_pak() -> Result<(), Box<dyn std::error::Error>> {
+        // PAK_1 with its index encrypted and the footer saying so, while the
+        // entries stay plain: only the index is XORed on the way in
+        let mut expected = GfpPakReaderV10::open(PAK_1)?;
+        assert!(!expected.encrypted()?);
+        let stats = expected.index_stats()?;
+        let mut data = std::fs::read(PAK_1)?;
+        let index_range =
+            stats.index_offset as usize..(stats.index_offset + stats.index_size) as usize;
+        xor_each_byte(
+            &mut data[index_range],
+            GfpPakReaderV10::<&[u8]>::DECRYPT_KEY,
+        );
+        let footer_offset = data.len() - FOOTER_SIZE;
+        data[footer_offset] ^= 1;
+
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        assert!(pak.encrypted()?);
+        assert_eq!(pak.entries_count()?, expected.entries_count()?);
+        // Still encrypted once the index is loaded, so it wasn't read as plain
+        assert!(pak.encrypted()?);
+        for entry_id in 0..pak.entries_count()? {
+            assert_eq!(
+                pak.get_entry_path(entry_id)?,
+                expected.get_entry_path(entry_id)?
+            );
+            assert!(!pak.entry_layout(entry_id)?.encrypted);
+            let mut content = vec![];
+            expected.extract_entry_to_writer(entry_id, &mut content)?;
+
+            let mut extracted = vec![];
+            pak.extract_entry_to_writer(entry_id, &mut extracted)?;
+            assert_eq!(extracted, content, "entry {}", entry_id);
+            let mut streamed = vec![];
+            pak.entry_reader(entry_id, 512)?.copy_to(&mut streamed)?;
+            assert_eq!(streamed, content, "entry {}", entry_id);
+            assert_eq!(
+                pak.verify_entry(entry_id)?,
+                expected.verify_entry(entry_id)?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_block_error() -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = std::fs::read(PAK_1)?;
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        pak.load_entries()?;
+        let (entry_id, entry) = pak
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.num_of_blocks > 0)
+            .map(|(entry_id, entry)| (entry_id as u64, entry.clone()))
+            .unwrap();
+
+        let garbage = if entry.encrypted != 0 {
+            0xFF ^ GfpPakReaderV10::<&[u8]>::DECRYPT_KEY
+        } else {
+            0xFF
+        };
+        let block = &entry.blocks[0];
+        data[block.start as usize..block.end as usize].fill(garbage);
+
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        let error = pak
+            .extract_entry_to_writer(entry_id, &mut Vec::new())
+            .unwrap_err();
+        assert!(
+            matches!(error, PakError::Decompress { entry_id: Some(id), block: Some(0), .. } if id == entry_id),
+            "{:?}",
+            error
+        );
+        assert!(error.to_string().starts_with(&format!(
+            "Decompression failed for entry {}, block 0: ",
+            entry_id
+        )));
+        Ok(())
+    }
 }
@@ -1,25 +1,86 @@
 use crate::error::PakError;
-use crate::pak_reader::PakReader;
+use crate::pak_reader::cancel::CancellationToken;
+#[cfg(feature = "std-fs")]
+use crate::pak_reader::check_footer;
+use crate::pak_reader::entry_reader::EntryReader;
+use crate::pak_reader::extraction_cache::{ExtractionCache, ReaderCache};
+use crate::pak_reader::io_stats::{CountedRead, IoCounters, IoStats};
+use crate::pak_reader::path_match::{PathMatch, PathMatchIndexes};
+use crate::pak_reader::{
+    BlockLayout, EntryLayout, EntryPayload, IndexStats, MAX_INDEX_HEADER_SIZE, MIN_ENTRY_SIZE,
+    PakReader, check_count, check_index_end, decode_index, decode_index_header, inline_header_size,
+    name_length, payload_hash_ranges, read_mount_point,
+};
+#[cfg(feature = "std-fs")]
+use crate::utils::direct_io::DirectFile;
 use crate::utils::file_reader::VecCursor;
-use crate::utils::{read_file_at, utf16le_to_utf8_inplace, xor_each_byte, zlib_decompress};
+#[cfg(feature = "std-fs")]
+use crate::utils::mmap::MappedFile;
+#[cfg(feature = "std-fs")]
+use crate::utils::read_at::PathSource;
+use crate::utils::read_at::ReadAt;
+#[cfg(feature = "std-fs")]
+use crate::utils::shared_read::open_shared_read;
+use crate::utils::{
+    check_zlib_header, sha1_at, sha1_of_ranges, to_usize, utf16le_to_utf8_inplace, xor_each_byte,
+    zlib_decompress_bounded,
+};
+use std::collections::HashMap;
 use std::ffi::CString;
+#[cfg(feature = "std-fs")]
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::Write;
+#[cfg(feature = "std-fs")]
+use std::path::Path;
+use std::sync::Arc;
 
 /// Pak file header information for avatar pak files
 /// Total size: 45 bytes
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 struct RawPakInfo {
-    encrypted: u8,   // 1 byte
-    magic: u32,      // 4 bytes
-    version: u32,    // 4 bytes
-    hash: [u8; 20],  // 20 bytes
+    encrypted: u8, // 1 byte
+    /// unused, [`Self::MAGIC`] in every known pak
+    magic: u32, // 4 bytes
+    version: u32,  // 4 bytes
+    hash: [u8; 20], // 20 bytes
     index_size: u64, // 8 bytes
-    offset: u64,     // 8 bytes
+    offset: u64,   // 8 bytes
 }
 
 impl RawPakInfo {
+    const SIZE: usize = std::mem::size_of::<RawPakInfo>();
+    const VERSION: u32 = 7;
+    const MAGIC: u32 = crate::pak_reader::FOOTER_MAGIC;
+
+    const OFFSET_XOR_KEY: u64 = 0xD74AF37FAA6B020D;
+    const SIZE_XOR_KEY: u64 = 0x8924B0E3298B7069;
+    const ENCRYPTED_XOR_KEY: u8 = 0x6C;
+    const HASH_KEY: [u8; 20] = [
+        0x9B, 0x31, 0x24, 0x61, 0xCB, 0xD3, 0xF5, 0x18, 0x20, 0xA1, 0x1B, 0xFB, 0xFD, 0x40, 0xB6,
+        0x00, 0x1E, 0x53, 0x5C, 0x24,
+    ];
+
+    /// Reinterpret the footer bytes and undo the obfuscation
+    fn from_bytes(buffer: [u8; Self::SIZE]) -> Self {
+        let mut info = unsafe { std::mem::transmute::<[u8; Self::SIZE], Self>(buffer) };
+        info.encrypted ^= Self::ENCRYPTED_XOR_KEY;
+        for i in 0..20 {
+            info.hash[i] ^= Self::HASH_KEY[i];
+        }
+        info.offset ^= Self::OFFSET_XOR_KEY;
+        info.index_size ^= Self::SIZE_XOR_KEY;
+        info
+    }
+
+    /// Obfuscate the fields again, the footer bytes [`Self::from_bytes`]
+    /// was given for a parsed footer
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        // Every field is XORed, so obfuscating is deobfuscating again
+        let info = Self::from_bytes(unsafe { std::mem::transmute::<Self, [u8; Self::SIZE]>(self) });
+        unsafe { std::mem::transmute::<Self, [u8; Self::SIZE]>(info) }
+    }
+
     /// Check if pak file is encrypted
     fn is_encrypted(&self) -> bool {
         self.encrypted != 0
@@ -34,14 +95,22 @@ struct CompressionBlock {
 }
 
 impl CompressionBlock {
+    /// Bytes of a block in the index: its start and end.
+    const SIZE: usize = 16;
+
     /// Get block offset in file
     fn offset(&self) -> u64 {
         self.start
     }
 
     /// Get block size
-    fn size(&self) -> u64 {
-        self.end - self.start
+    fn size(&self) -> Result<u64, PakError> {
+        self.end.checked_sub(self.start).ok_or_else(|| {
+            PakError::invalid_data(format!(
+                "Compression block ends before it starts: {:#X}..{:#X}",
+                self.start, self.end
+            ))
+        })
     }
 }
 
@@ -61,69 +130,348 @@ struct Entry {
     path: String,
 }
 
+impl Entry {
+    /// Read the entry's record in the index, its path included
+    fn read_from(&mut self, index_cursor: &mut VecCursor<u8>) -> Result<(), PakError> {
+        let entry_path_size = i32::from_le_bytes(*index_cursor.read::<4>()?);
+
+        match entry_path_size {
+            8192.. => {
+                return Err(PakError::invalid_data(format!(
+                    "Entry path too long: {}",
+                    entry_path_size
+                )));
+            }
+            ..0 => {
+                let data_offset = index_cursor.offset;
+                let length = name_length(entry_path_size, index_cursor)?;
+                let mut data = index_cursor.read_dyn(length)?;
+                utf16le_to_utf8_inplace(&mut data, data_offset)?;
+                self.path = CString::from_vec_with_nul(data)?.into_string()?;
+            }
+            _ => {
+                let data = index_cursor.read_dyn(name_length(entry_path_size, index_cursor)?)?;
+                self.path = CString::from_vec_with_nul(data)?.into_string()?;
+            }
+        }
+
+        self.file_hash.copy_from_slice(index_cursor.read::<20>()?);
+        self.file_offset = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.file_size = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.compression_method = u32::from_le_bytes(*index_cursor.read::<4>()?);
+        self.compressed_length = u64::from_le_bytes(*index_cursor.read::<8>()?);
+        self.dummy.copy_from_slice(index_cursor.read::<21>()?);
+
+        if self.compression_method != 0 {
+            self.num_of_blocks = u32::from_le_bytes(*index_cursor.read::<4>()?);
+            let num_of_blocks = check_count(
+                "Block count",
+                self.num_of_blocks.into(),
+                CompressionBlock::SIZE,
+                index_cursor,
+            )?;
+            self.blocks.reserve(num_of_blocks);
+            for _ in 0..num_of_blocks {
+                let block = CompressionBlock {
+                    start: u64::from_le_bytes(*index_cursor.read::<8>()?),
+                    end: u64::from_le_bytes(*index_cursor.read::<8>()?),
+                };
+                self.blocks.push(block);
+            }
+        } else {
+            self.num_of_blocks = 0;
+        }
+
+        self.compressed_block_size = u32::from_le_bytes(*index_cursor.read::<4>()?);
+        self.encrypted = index_cursor.read::<1>()?[0];
+        Ok(())
+    }
+
+    /// The entry's blocks with what each decompresses to, see
+    /// [`BlockLayout::split`]
+    fn block_layouts(&self) -> Result<Vec<BlockLayout>, PakError> {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| Ok((block.offset(), block.size()?)))
+            .collect::<Result<Vec<_>, PakError>>()?;
+        Ok(BlockLayout::split(
+            self.file_size,
+            self.compressed_block_size,
+            blocks.into_iter(),
+        ))
+    }
+
+    /// Make sure the entry has as many blocks as it says, so a block list
+    /// that came out short can't pass for a shorter entry
+    fn check_blocks(&self) -> Result<(), PakError> {
+        if self.blocks.len() != self.num_of_blocks as usize {
+            return Err(PakError::invalid_data(format!(
+                "Entry has {} compression blocks but {} were read",
+                self.num_of_blocks,
+                self.blocks.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Size of the pak info at the end of a v7 pak.
+pub const FOOTER_SIZE: usize = RawPakInfo::SIZE;
+
+/// The footer of a new v7 pak whose index is the `index_size` bytes at
+/// `index_offset`, obfuscated the way [`PakReader::footer_bytes`] reads it
+/// back.
+///
+/// ```rust
+/// use gfp::pak_reader::PakReader;
+/// use gfp::pak_reader::gfp_v7::{GfpPakReaderV7, build_footer};
+///
+/// let mut pak = GfpPakReaderV7::new(std::fs::read("test/avatar/onreadypak_405399.pak")?);
+/// let stats = pak.index_stats()?;
+/// let footer = build_footer(false, stats.index_offset, stats.index_size, stats.footer_hash);
+/// assert_eq!(footer, pak.footer_bytes()?);
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+pub fn build_footer(
+    encrypted: bool,
+    index_offset: u64,
+    index_size: u64,
+    hash: [u8; 20],
+) -> [u8; FOOTER_SIZE] {
+    RawPakInfo {
+        encrypted: encrypted as u8,
+        magic: RawPakInfo::MAGIC,
+        version: RawPakInfo::VERSION,
+        hash,
+        index_size,
+        offset: index_offset,
+    }
+    .to_bytes()
+}
+
+/// Whether `footer`, the last [`FOOTER_SIZE`] bytes of a source, deobfuscates
+/// to a plausible v7 pak info. `info_offset` is where the footer starts.
+pub(crate) fn is_plausible_footer(footer: [u8; FOOTER_SIZE], info_offset: u64) -> bool {
+    let info = RawPakInfo::from_bytes(footer);
+    info.encrypted <= 1
+        && info.version == RawPakInfo::VERSION
+        && info
+            .offset
+            .checked_add(info.index_size)
+            .is_some_and(|index_end| index_end <= info_offset)
+}
+
 /// 参考 `src/c/gfp_avatar.c`
-pub struct GfpPakReaderV7 {
-    pub file: File,
+pub struct GfpPakReaderV7<R: ReadAt> {
+    pub file: R,
 
     is_info_loaded: bool,
     is_entries_loaded: bool,
+    is_path_index_loaded: bool,
 
     // Stage: info
     info: RawPakInfo,
 
     // Stage: entries
+    index_encrypted: bool,
     index_data: Vec<u8>,
     index_offset: usize,
     mount_point: String,
     entries: Vec<Entry>,
+
+    // Stage: path index
+    path_index: HashMap<String, u64>,
+    path_match_indexes: PathMatchIndexes,
+
+    counters: IoCounters,
+    extraction_cache: Option<ReaderCache>,
+    /// Size of the pak and its footer when the info was loaded, which
+    /// [`PakReader::resume`] checks
+    loaded_footer: Option<(u64, [u8; RawPakInfo::SIZE])>,
+    strict: bool,
 }
 
-impl GfpPakReaderV7 {
-    const PAK_INFO_SIZE: usize = std::mem::size_of::<RawPakInfo>();
-    const OFFSET_XOR_KEY: u64 = 0xD74AF37FAA6B020D;
-    const SIZE_XOR_KEY: u64 = 0x8924B0E3298B7069;
-    const ENCRYPTED_XOR_KEY: u8 = 0x6C;
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV7<PathSource<File>> {
+    /// Open a pak file by path
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = open_shared_read(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV7<PathSource<DirectFile>> {
+    /// Open a pak file by path, bypassing the page cache, see [`DirectFile`]
+    pub fn open_direct<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = DirectFile::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl GfpPakReaderV7<PathSource<MappedFile>> {
+    /// Open a pak file by path, mapped into memory, see [`MappedFile`]
+    pub fn open_mapped<P: AsRef<Path>>(path: P) -> Result<Box<dyn PakReader>, std::io::Error> {
+        let path = path.as_ref();
+        let file = MappedFile::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() < RawPakInfo::SIZE as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a pak file",
+            ));
+        }
+        Ok(Box::new(Self::new(PathSource::new(path, file))))
+    }
+}
+
+impl<R: ReadAt> GfpPakReaderV7<R> {
+    /// Create a new GfpAvatarPakReader instance
+    pub fn new(file: R) -> Self {
+        Self {
+            file,
+            is_info_loaded: false,
+            is_entries_loaded: false,
+            is_path_index_loaded: false,
+            info: RawPakInfo {
+                encrypted: 0,
+                magic: 0,
+                version: 0,
+                hash: [0; 20],
+                index_size: 0,
+                offset: 0,
+            },
+            index_encrypted: false,
+            index_data: vec![],
+            index_offset: 0,
+            mount_point: String::new(),
+            entries: vec![],
+            path_index: HashMap::new(),
+            path_match_indexes: PathMatchIndexes::default(),
+            counters: IoCounters::default(),
+            extraction_cache: None,
+            loaded_footer: None,
+            strict: false,
+        }
+    }
+
+    /// Whether the index is XORed in the pak. That's what the encrypted flag
+    /// of the footer says, see [`PakReader::encrypted`], unless the index
+    /// only reads as one the other way round.
+    pub fn index_encrypted(&mut self) -> Result<bool, PakError> {
+        self.load_entries()?;
+        Ok(self.index_encrypted)
+    }
+
+    /// An [`EntryReader`] streaming the entry's content with at most
+    /// `buffer_cap` bytes of its payload buffered.
+    pub fn entry_reader(
+        &mut self,
+        entry_id: u64,
+        buffer_cap: usize,
+    ) -> Result<EntryReader<'_, R>, PakError> {
+        let layout = self.entry_layout(entry_id)?;
+        Ok(EntryReader::new(&self.file, layout, buffer_cap).with_counters(&self.counters))
+    }
+
+    /// The pak file, with the reads counted in [`PakReader::stats`]
+    fn source(&self) -> CountedRead<'_, R> {
+        CountedRead {
+            source: &self.file,
+            counters: &self.counters,
+        }
+    }
+
     const DECRYPT_KEY: u8 = 0x79;
     const CHUNK_SIZE: usize = 65536;
-    const HASH_KEY: [u8; 20] = [
-        0x9B, 0x31, 0x24, 0x61, 0xCB, 0xD3, 0xF5, 0x18, 0x20, 0xA1, 0x1B, 0xFB, 0xFD, 0x40, 0xB6,
-        0x00, 0x1E, 0x53, 0x5C, 0x24,
-    ];
+
+    /// Where byte `start` of a stored entry is in the pak, past the header
+    /// in front of its data
+    fn stored_offset(&self, entry: &Entry, start: u64) -> Result<u64, PakError> {
+        let header_size = inline_header_size(
+            &self.source(),
+            entry.file_offset,
+            &entry.file_hash,
+            entry.file_size,
+            entry.compressed_length,
+        )?;
+        entry
+            .file_offset
+            .checked_add(header_size)
+            .and_then(|offset| offset.checked_add(start))
+            .ok_or_else(|| {
+                PakError::invalid_data(format!("Invalid entry offset: {:#X}", entry.file_offset))
+            })
+    }
 
     /// Load pak file header information
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(pak_size = tracing::field::Empty))
+    )]
     fn load_pak_info(&mut self) -> Result<(), PakError> {
         if self.is_info_loaded {
             return Ok(());
         }
 
-        let file_size = self
-            .file
-            .seek(SeekFrom::End(0))
-            .expect("Unable to seek to end of file");
-
-        self.file
-            .seek(SeekFrom::Start(file_size - 45))
-            .expect("Unable to seek to pak header");
+        let file_size = self.file.size()?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("pak_size", file_size);
+        let info_offset = file_size
+            .checked_sub(RawPakInfo::SIZE as u64)
+            .ok_or_else(|| PakError::invalid_data(format!("File too small: {}", file_size)))?;
 
-        let mut buffer = [0u8; Self::PAK_INFO_SIZE];
-        self.file
-            .read_exact(&mut buffer)
-            .expect("Failed to read pak header");
-
-        self.info = unsafe { std::mem::transmute(buffer) };
+        let mut buffer = [0u8; RawPakInfo::SIZE];
+        if self.source().read_at(&mut buffer, info_offset)? != RawPakInfo::SIZE {
+            return Err(PakError::invalid_data("Failed to read pak header"));
+        }
 
-        // Deobfuscation
-        self.info.encrypted ^= Self::ENCRYPTED_XOR_KEY;
-        for i in 0..20 {
-            self.info.hash[i] ^= Self::HASH_KEY[i];
+        self.info = RawPakInfo::from_bytes(buffer);
+        let index_end = self.info.offset.checked_add(self.info.index_size);
+        if self.info.index_size > crate::pak_reader::MAX_INDEX_SIZE
+            || index_end.is_none_or(|index_end| index_end > info_offset)
+        {
+            return Err(PakError::invalid_data(format!(
+                "Invalid index: {} bytes at {}",
+                { self.info.index_size },
+                { self.info.offset }
+            )));
         }
-        self.info.offset ^= Self::OFFSET_XOR_KEY;
-        self.info.index_size ^= Self::SIZE_XOR_KEY;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            encrypted = self.info.is_encrypted(),
+            version = { self.info.version },
+            index_size = { self.info.index_size },
+            "pak info loaded"
+        );
+        self.loaded_footer = Some((file_size, buffer));
         self.is_info_loaded = true;
         Ok(())
     }
 
     /// Load file entries from pak
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(entries = tracing::field::Empty))
+    )]
     fn load_entries(&mut self) -> Result<(), PakError> {
         self.load_pak_info()?;
 
@@ -133,25 +481,27 @@ impl GfpPakReaderV7 {
 
         // Index data
         {
-            let mut index_data: Vec<u8> = vec![0u8; self.info.index_size as usize];
-            read_file_at(&mut self.file, &mut index_data, self.info.offset)?;
+            let mut index_data: Vec<u8> = vec![0u8; to_usize(self.info.index_size)?];
+            self.source().read_at(&mut index_data, self.info.offset)?;
 
-            if self.info.is_encrypted() {
-                xor_each_byte(&mut index_data, Self::DECRYPT_KEY);
-            }
-
-            self.index_data = index_data;
+            // The flag in the footer was wrong if the index only made sense
+            // the other way, `info` keeps it as the footer has it
+            (self.index_data, self.index_encrypted) =
+                decode_index(index_data, self.info.is_encrypted(), Self::DECRYPT_KEY)?;
         }
 
         // Entries
         {
             let mut index_cursor = VecCursor::new(&self.index_data);
 
-            let mount_point_length = u32::from_le_bytes(*index_cursor.read_nocheck::<4>()) as usize;
-            index_cursor.move_by(9);
-            let mount_point_data = index_cursor.read_dyn(mount_point_length - 9)?;
+            let mount_point = read_mount_point(&mut index_cursor)?;
 
-            let entry_count = i32::from_le_bytes(*index_cursor.read_nocheck::<4>());
+            let entry_count = i32::from_le_bytes(*index_cursor.read::<4>()?);
+            let entry_count = u64::try_from(entry_count).map_err(|_| {
+                PakError::invalid_data(format!("Invalid entry count: {}", entry_count))
+            })?;
+            let entry_count =
+                check_count("Entry count", entry_count, MIN_ENTRY_SIZE, &index_cursor)?;
 
             self.entries = vec![
                 Entry {
@@ -167,161 +517,462 @@ impl GfpPakReaderV7 {
                     encrypted: 0,
                     path: String::new(),
                 };
-                entry_count as usize
+                entry_count
             ];
 
-            for entry_id in 0..entry_count as usize {
-                let entry = &mut self.entries[entry_id];
-
-                let entry_path_size = i32::from_le_bytes(*index_cursor.read::<4>()?);
-
-                match entry_path_size {
-                    8192.. => {
-                        return Err(PakError::invalid_data(format!(
-                            "Entry path too long: {}",
-                            entry_path_size
-                        )));
-                    }
-                    ..0 => {
-                        let mut data = index_cursor.read_dyn((-entry_path_size * 2) as usize)?;
-                        utf16le_to_utf8_inplace(&mut data);
-                        entry.path = CString::from_vec_with_nul(data)?.into_string()?;
-                    }
-                    _ => {
-                        let data = index_cursor.read_dyn(entry_path_size as usize)?;
-                        entry.path = CString::from_vec_with_nul(data)?.into_string()?;
-                    }
-                }
-
-                entry.file_hash.copy_from_slice(index_cursor.read::<20>()?);
-                entry.file_offset = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.file_size = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.compression_method = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                entry.compressed_length = u64::from_le_bytes(*index_cursor.read::<8>()?);
-                entry.dummy.copy_from_slice(index_cursor.read::<21>()?);
-
-                if entry.compression_method != 0 {
-                    entry.num_of_blocks = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                    for _ in 0..entry.num_of_blocks {
-                        let block = CompressionBlock {
-                            start: u64::from_le_bytes(*index_cursor.read::<8>()?),
-                            end: u64::from_le_bytes(*index_cursor.read::<8>()?),
-                        };
-                        entry.blocks.push(block);
-                    }
-                } else {
-                    entry.num_of_blocks = 0;
-                }
-
-                entry.compressed_block_size = u32::from_le_bytes(*index_cursor.read::<4>()?);
-                entry.encrypted = index_cursor.read::<1>()?[0];
+            for (entry_id, entry) in self.entries.iter_mut().enumerate() {
+                entry
+                    .read_from(&mut index_cursor)
+                    .and_then(|()| entry.check_blocks())
+                    .map_err(|e| e.context(format!("parsing entry {}", entry_id)))?;
             }
 
-            self.mount_point = CString::from_vec_with_nul(mount_point_data)?.into_string()?;
+            check_index_end(index_cursor.offset, self.index_data.len(), self.strict)?;
+            self.mount_point = mount_point;
             self.index_offset = index_cursor.offset;
         }
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("entries", self.entries.len());
         self.is_entries_loaded = true;
 
         Ok(())
     }
-}
 
-impl PakReader for GfpPakReaderV7 {
-    /// Create a new GfpAvatarPakReader instance
-    fn new(file: File) -> Self {
-        Self {
-            file,
-            is_info_loaded: false,
-            is_entries_loaded: false,
-            info: RawPakInfo {
-                encrypted: 0,
-                magic: 0,
-                version: 0,
-                hash: [0; 20],
-                index_size: 0,
-                offset: 0,
-            },
-            index_data: vec![],
-            index_offset: 0,
-            mount_point: String::new(),
-            entries: vec![],
+    /// Build the path to entry id lookup table
+    fn load_path_index(&mut self) -> Result<(), PakError> {
+        if self.is_path_index_loaded {
+            return Ok(());
         }
+        self.load_entries()?;
+
+        for (entry_id, entry) in self.entries.iter().enumerate() {
+            self.path_index
+                .entry(entry.path.clone())
+                .or_insert(entry_id as u64);
+        }
+        self.is_path_index_loaded = true;
+        Ok(())
+    }
+
+    /// Read, decrypt and decompress one compressed block of an entry
+    fn decompress_block(
+        &self,
+        entry_id: u64,
+        entry: &Entry,
+        block_index: usize,
+    ) -> Result<Vec<u8>, PakError> {
+        let block = entry.blocks.get(block_index).ok_or_else(|| {
+            PakError::invalid_data(format!(
+                "Entry {} has no block {}, only {}",
+                entry_id,
+                block_index,
+                entry.blocks.len()
+            ))
+        })?;
+        let block_size = block.size()?;
+        let mut compressed_data = vec![0u8; to_usize(block_size)?];
+
+        let bytes_read = self
+            .source()
+            .read_at(&mut compressed_data, block.offset())?;
+        if bytes_read != compressed_data.len() {
+            return Err(PakError::invalid_data(format!(
+                "Failed to read compressed chunk at {:08X}, read/expected: {}/{}",
+                block.offset(),
+                bytes_read,
+                block_size
+            )));
+        }
+
+        if entry.encrypted != 0 {
+            xor_each_byte(&mut compressed_data, Self::DECRYPT_KEY);
+        }
+
+        if entry.compression_method != 1 {
+            return Err(PakError::invalid_data(format!(
+                "Unknown compression method '{}', only '1' is supported.",
+                entry.compression_method
+            )));
+        }
+
+        // No block decompresses to more than the block size, which is 0 for
+        // some single-block entries
+        let max_size = match entry.compressed_block_size {
+            0 => entry.file_size,
+            block_size => entry.file_size.min(block_size.into()),
+        };
+        let data = zlib_decompress_bounded(&compressed_data, max_size).map_err(|error| {
+            PakError::Decompress {
+                entry_id: Some(entry_id),
+                block: Some(block_index as u32),
+                source: Box::new(error),
+            }
+        })?;
+        self.counters.record_decompressed(data.len());
+        Ok(data)
     }
 
- 
+    /// Copy bytes `start..end` of a stored entry to `output`
+    fn extract_stored(
+        &self,
+        entry: &Entry,
+        start: u64,
+        end: u64,
+        output: &mut dyn Write,
+        cancel: &CancellationToken,
+    ) -> Result<(), PakError> {
+        let mut file_offset = self.stored_offset(entry, start)?;
+        let mut file_size = end - start;
+
+        while file_size > 0 {
+            cancel.check()?;
+            let bytes_to_read = std::cmp::min(file_size, Self::CHUNK_SIZE as u64) as usize;
+            let mut decompressed_data = vec![0u8; bytes_to_read];
+            let bytes_read = self.source().read_at(&mut decompressed_data, file_offset)?;
+            // Sources may read less than asked at their end instead of
+            // failing, as Windows files do
+            if bytes_read != bytes_to_read {
+                return Err(PakError::invalid_data(format!(
+                    "Failed to read stored chunk at {:08X}, read/expected: {}/{}",
+                    file_offset, bytes_read, bytes_to_read
+                )));
+            }
+
+            if entry.encrypted != 0 {
+                xor_each_byte(&mut decompressed_data, Self::DECRYPT_KEY);
+            }
+
+            output
+                .write_all(&decompressed_data)
+                .map_err(PakError::output)?;
+
+            file_size -= bytes_to_read as u64;
+            file_offset += bytes_to_read as u64;
+        }
+        Ok(())
+    }
+}
 
+impl<R: ReadAt> PakReader for GfpPakReaderV7<R> {
     /// Check if pak file is encrypted
     fn encrypted(&mut self) -> Result<bool, PakError> {
         self.load_pak_info()?;
         Ok(self.info.is_encrypted())
     }
 
-    /// Get pak file version
-    fn version(&mut self) -> Result<u32, PakError> {
+    /// Get the version field of the pak info
+    fn declared_version(&mut self) -> Result<u32, PakError> {
         self.load_pak_info()?;
         Ok(self.info.version)
     }
 
+    /// Get the pak version this reader parses
+    fn parser_version(&self) -> u32 {
+        RawPakInfo::VERSION
+    }
+
+    /// Set whether to fail on signs of an unknown format
+    fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    fn stats(&self) -> IoStats {
+        self.counters.snapshot()
+    }
+
+    fn reset_stats(&mut self) {
+        self.counters.reset();
+    }
+
+    fn set_extraction_cache(&mut self, cache: Option<Arc<ExtractionCache>>) {
+        self.extraction_cache = cache.map(ReaderCache::new);
+    }
+
+    fn extraction_cache(&self) -> Option<&ReaderCache> {
+        self.extraction_cache.as_ref()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn reopen(&mut self, path: &Path) -> Result<(), PakError> {
+        let file = R::open_path(path)
+            .map_err(|e| PakError::from(e).context(format!("reopening {}", path.display())))?;
+        let fresh = Self::new(file);
+        let counters = std::mem::take(&mut self.counters);
+        // Entries without hash are keyed by reader, so the old file's ones
+        // must not be found under the new file
+        let extraction_cache = self
+            .extraction_cache
+            .take()
+            .map(|cache| ReaderCache::new(cache.cache().clone()));
+        *self = Self {
+            counters,
+            extraction_cache,
+            strict: self.strict,
+            ..fresh
+        };
+        Ok(())
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn path(&self) -> Option<&Path> {
+        self.file.path()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn suspend(&mut self) {
+        self.file.suspend();
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn resume(&mut self) -> Result<(), PakError> {
+        let path = self.file.path().unwrap_or(Path::new("")).to_path_buf();
+        if !self
+            .file
+            .resume()
+            .map_err(|e| PakError::from(e).context(format!("reopening {}", path.display())))?
+        {
+            return Ok(());
+        }
+        if let Err(e) = self.check_unchanged() {
+            self.file.suspend();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn check_unchanged(&self) -> Result<(), PakError> {
+        match self.loaded_footer {
+            Some((size, footer)) if !self.file.is_suspended() => {
+                let path = self.file.path().unwrap_or(Path::new(""));
+                check_footer(&self.source(), path, size, &footer)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn is_suspended(&self) -> bool {
+        self.file.is_suspended()
+    }
+
+    /// Get size of pak file
+    fn pak_size(&mut self) -> Result<u64, PakError> {
+        Ok(self.file.size()?)
+    }
+
     /// Get number of entries in pak file
     fn entries_count(&mut self) -> Result<u64, PakError> {
         self.load_entries()?;
         Ok(self.entries.len() as u64)
     }
 
-    /// Extract an entry to a file
-    fn extract_entry_to_file(&mut self, entry_id: u64, output: &mut File) -> Result<(), PakError> {
+    fn entries_count_fast(&mut self) -> Result<u64, PakError> {
+        if self.is_entries_loaded {
+            return Ok(self.entries.len() as u64);
+        }
+        self.load_pak_info()?;
+        let index_size = to_usize(self.info.index_size)?;
+        let mut header = vec![0u8; index_size.min(MAX_INDEX_HEADER_SIZE)];
+        let read = self.source().read_at(&mut header, self.info.offset)?;
+        header.truncate(read);
+        let (entry_count, _) = decode_index_header(
+            &header,
+            index_size,
+            self.info.is_encrypted(),
+            Self::DECRYPT_KEY,
+        )?;
+        Ok(entry_count)
+    }
+
+    /// Get the footer as read from the pak
+    fn footer_bytes(&mut self) -> Result<[u8; FOOTER_SIZE], PakError> {
+        self.load_pak_info()?;
+        Ok(self.info.to_bytes())
+    }
+
+    /// Get statistics of the index
+    fn index_stats(&mut self) -> Result<IndexStats, PakError> {
         self.load_entries()?;
-        let entry = self.entries[entry_id as usize].clone();
+        let mut stats = IndexStats::new(
+            self.mount_point.clone(),
+            self.info.offset,
+            self.info.index_size,
+            self.info.hash,
+        );
+        for entry in &self.entries {
+            stats.count_entry(entry.encrypted != 0, entry.compression_method);
+        }
+        Ok(stats)
+    }
 
-        if entry.num_of_blocks > 0 {
-            for block in &entry.blocks {
-                let mut compressed_data = vec![0u8; block.size() as usize];
-
-                let bytes_read = read_file_at(&self.file, &mut compressed_data, block.offset())?;
-                if bytes_read != block.size() as usize {
-                    return Err(PakError::invalid_data(format!(
-                        "Failed to read compressed chunk at {:08X}, read/expected: {}/{}",
-                        block.offset(),
-                        bytes_read,
-                        block.size()
-                    )));
-                }
+    fn raw_index(&mut self) -> Result<&[u8], PakError> {
+        self.load_entries()?;
+        Ok(&self.index_data)
+    }
 
-                if entry.encrypted != 0 {
-                    xor_each_byte(&mut compressed_data, Self::DECRYPT_KEY);
-                }
+    /// Get where the entry's bytes are in the pak
+    fn entry_layout(&mut self, entry_id: u64) -> Result<EntryLayout, PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        let payload = if entry.num_of_blocks > 0 {
+            EntryPayload::Blocks(entry.block_layouts()?)
+        } else {
+            EntryPayload::Stored {
+                offset: self.stored_offset(entry, 0)?,
+                length: entry.file_size,
+            }
+        };
+        Ok(EntryLayout {
+            compression_method: entry.compression_method,
+            encrypted: entry.encrypted != 0,
+            xor_key: Self::DECRYPT_KEY,
+            file_size: entry.file_size,
+            payload,
+        })
+    }
 
-                if entry.compression_method != 1 {
-                    return Err(PakError::invalid_data(format!(
-                        "Unknown compression method '{}', only '1' is supported.",
-                        entry.compression_method
-                    )));
-                }
+    fn prefetch_entry(&mut self, entry_id: u64) -> Result<(), PakError> {
+        let layout = self.entry_layout(entry_id)?;
+        layout.prefetch(&self.file)?;
+        Ok(())
+    }
 
-                let decompressed_data =
-                    zlib_decompress(&compressed_data, entry.compressed_block_size as usize)
-                        .ok_or_else(|| std::io::Error::other("ZLIB decompression failed"))?;
+    /// Extract an entry to a file
+    fn extract_entry_to_writer(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+    ) -> Result<(), PakError> {
+        self.extract_entry_to_writer_cancellable(entry_id, output, &CancellationToken::new())
+    }
 
-                output.write_all(&decompressed_data)?;
+    /// Extract an entry, checking `cancel` before each compressed block or stored chunk
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(entry_id, bytes = tracing::field::Empty, blocks = tracing::field::Empty)
+        )
+    )]
+    fn extract_entry_to_writer_cancellable(
+        &mut self,
+        entry_id: u64,
+        output: &mut dyn Write,
+        cancel: &CancellationToken,
+    ) -> Result<(), PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        entry
+            .check_blocks()
+            .map_err(|e| e.context(format!("extracting entry {}", entry_id)))?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current()
+            .record("bytes", entry.file_size)
+            .record("blocks", entry.num_of_blocks);
+
+        if entry.num_of_blocks > 0 {
+            for block_index in 0..entry.blocks.len() {
+                cancel.check()?;
+                output
+                    .write_all(&self.decompress_block(entry_id, entry, block_index)?)
+                    .map_err(PakError::output)?;
             }
         } else {
-            let mut file_offset = entry.file_offset + 74;
-            let mut file_size = entry.file_size;
+            self.extract_stored(entry, 0, entry.file_size, output, cancel)?;
+        }
+        output.flush().map_err(PakError::output)?;
+        Ok(())
+    }
 
-            while file_size > 0 {
-                let bytes_to_read = std::cmp::min(file_size as usize, Self::CHUNK_SIZE);
-                let mut decompressed_data = vec![0u8; bytes_to_read];
-                let _bytes_read = read_file_at(&self.file, &mut decompressed_data, file_offset)?;
+    /// Extract part of an entry, reading only the blocks covering it
+    fn extract_entry_range(
+        &mut self,
+        entry_id: u64,
+        start: u64,
+        length: u64,
+        output: &mut dyn Write,
+    ) -> Result<u64, PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        entry
+            .check_blocks()
+            .map_err(|e| e.context(format!("extracting entry {}", entry_id)))?;
+        let start = start.min(entry.file_size);
+        let end = start.saturating_add(length).min(entry.file_size);
+        if start == end {
+            return Ok(0);
+        }
 
-                if entry.encrypted != 0 {
-                    xor_each_byte(&mut decompressed_data, Self::DECRYPT_KEY);
+        if entry.num_of_blocks > 0 {
+            let mut written = 0;
+            let mut block_start = 0;
+            for (block_index, block) in entry.block_layouts()?.iter().enumerate() {
+                let block_end = block_start + block.decompressed_length;
+                if block_end > start {
+                    let data = self.decompress_block(entry_id, entry, block_index)?;
+                    let to = to_usize(end - block_start)?.min(data.len());
+                    let from = to_usize(start.saturating_sub(block_start))?.min(to);
+                    output
+                        .write_all(&data[from..to])
+                        .map_err(PakError::output)?;
+                    written += (to - from) as u64;
+                }
+                if block_end >= end {
+                    break;
                 }
+                block_start = block_end;
+            }
+            output.flush().map_err(PakError::output)?;
+            Ok(written)
+        } else {
+            self.extract_stored(entry, start, end, output, &CancellationToken::new())?;
+            output.flush().map_err(PakError::output)?;
+            Ok(end - start)
+        }
+    }
 
-                output.write_all(&decompressed_data)?;
+    /// Check the zlib header of each compressed block
+    fn validate_entry_headers(&mut self, entry_id: u64) -> Result<(), PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        if entry.num_of_blocks > 0 && entry.compression_method != 1 {
+            return Err(PakError::invalid_data(format!(
+                "Unknown compression method '{}', only '1' is supported.",
+                entry.compression_method
+            )));
+        }
 
-                file_size -= bytes_to_read as u64;
-                file_offset += bytes_to_read as u64;
+        for (block_index, block) in entry.blocks.iter().enumerate() {
+            let mut header = [0u8; 2];
+            let block_size = block.size()?;
+            let bytes_read = if block_size >= 2 {
+                self.source().read_at(&mut header, block.offset())?
+            } else {
+                0
+            };
+            if entry.encrypted != 0 {
+                xor_each_byte(&mut header, Self::DECRYPT_KEY);
             }
+
+            let result = if bytes_read == header.len() {
+                check_zlib_header(header)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("Block of {} bytes is too short", block_size),
+                ))
+            };
+            result.map_err(|error| PakError::Decompress {
+                entry_id: Some(entry_id),
+                block: Some(block_index as u32),
+                source: Box::new(error),
+            })?;
         }
         Ok(())
     }
@@ -329,30 +980,152 @@ impl PakReader for GfpPakReaderV7 {
     /// Get entry path by ID
     fn get_entry_path(&mut self, entry_id: u64) -> Result<String, PakError> {
         self.load_entries()?;
-        Ok(self.entries[entry_id as usize].path.clone())
+        Ok(self.entries[to_usize(entry_id)?].path.clone())
+    }
+
+    /// Get the hash recorded for an entry
+    fn entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError> {
+        self.load_entries()?;
+        Ok(self.entries[to_usize(entry_id)?].file_hash)
+    }
+
+    /// Hash the stored bytes of an entry
+    fn compute_entry_hash(&mut self, entry_id: u64) -> Result<[u8; 20], PakError> {
+        self.load_entries()?;
+        let entry = &self.entries[to_usize(entry_id)?];
+        if entry.blocks.is_empty() {
+            return sha1_at(
+                &self.source(),
+                self.stored_offset(entry, 0)?,
+                entry.compressed_length,
+            );
+        }
+        let blocks = entry
+            .blocks
+            .iter()
+            .map(|block| Ok((block.offset(), block.size()?)))
+            .collect::<Result<Vec<_>, PakError>>()?;
+        sha1_of_ranges(
+            &self.source(),
+            &payload_hash_ranges(&blocks, entry.compressed_length),
+        )
+    }
+
+    /// Find entry ID by path
+    fn find_entry_by_path(&mut self, path: &str) -> Result<Option<u64>, PakError> {
+        self.load_path_index()?;
+        Ok(self.path_index.get(path).copied())
+    }
+
+    /// Compares the path of each entry until one matches.
+    fn contains_path(&mut self, path: &str) -> Result<bool, PakError> {
+        if self.is_path_index_loaded {
+            return Ok(self.path_index.contains_key(path));
+        }
+        self.load_entries()?;
+        Ok(self.entries.iter().any(|entry| entry.path == path))
+    }
+
+    /// Find entry ID by path, see [`PathMatch`]
+    fn find_entry_by_path_with(
+        &mut self,
+        path: &str,
+        path_match: PathMatch,
+    ) -> Result<Option<u64>, PakError> {
+        if path_match == PathMatch::Exact {
+            return self.find_entry_by_path(path);
+        }
+        self.load_entries()?;
+        self.path_match_indexes.find(
+            path,
+            path_match,
+            &self.mount_point,
+            self.entries.iter().map(|entry| entry.path.as_str()),
+        )
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std-fs"))]
 mod test {
     use super::*;
     use crate::pak_reader::implements::open_paks_by_glob;
     use tempfile::TempDir;
-    
+
     const GFP_AVATAR_PAKS_PATTERN: &str = "./test/avatar/*.pak";
     const AVATAR_PAK_1: &str = "test/avatar/onreadypak_405399.pak";
     const AVATAR_PAK_2: &str = "test/avatar/onreadypak_101005004.pak";
 
+    #[test]
+    fn test_wrong_encrypted_flag() -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = std::fs::read(AVATAR_PAK_1)?;
+        let footer_offset = data.len() - RawPakInfo::SIZE;
+        data[footer_offset] ^= 1;
+
+        let mut expected = GfpPakReaderV7::new(std::fs::read(AVATAR_PAK_1)?);
+        let mut pak = GfpPakReaderV7::new(data.clone());
+        assert_ne!(pak.encrypted()?, expected.encrypted()?);
+        assert_eq!(pak.entries_count()?, expected.entries_count()?);
+        assert_eq!(pak.index_encrypted()?, expected.encrypted()?);
+        // The footer stays as the pak has it
+        assert_ne!(pak.encrypted()?, expected.encrypted()?);
+        assert_eq!(pak.footer_bytes()?, data[footer_offset..]);
+        assert_eq!(pak.get_entry_path(0)?, expected.get_entry_path(0)?);
+        Ok(())
+    }
+
     #[test]
     fn test_get_pak_info() -> Result<(), Box<dyn std::error::Error>> {
         for (pak_path, mut pak) in open_paks_by_glob(GFP_AVATAR_PAKS_PATTERN, 7).unwrap() {
             println!("[{}]", pak_path.to_string_lossy());
             println!("IsEncrypted: {}", pak.encrypted()?);
-            println!("Version: {}", pak.version()?);
+            println!("Version: {}", pak.declared_version()?);
             println!();
         }
         Ok(())
     }
+    #[test]
+    fn test_index_overruns() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(AVATAR_PAK_1)?;
+        let mut original = GfpPakReaderV7::new(data.as_slice());
+        original.load_entries()?;
+        let index_start = original.info.offset as usize;
+        let mount_point_length =
+            u32::from_le_bytes(data[index_start..index_start + 4].try_into()?) as usize;
+        let path_start = index_start + 4 + mount_point_length + 4;
+
+        for size in [i32::MIN, -i32::MAX, (data.len() - path_start) as i32] {
+            let mut data = data.clone();
+            data[path_start..path_start + 4].copy_from_slice(&size.to_le_bytes());
+            let error = GfpPakReaderV7::new(data).entries_count().unwrap_err();
+            assert!(
+                matches!(
+                    error.root_cause(),
+                    PakError::IndexOverrun {
+                        what: "Name length",
+                        ..
+                    }
+                ),
+                "{}",
+                error
+            );
+        }
+
+        // A v10 pak without entries, whose index is read as a v7 one
+        let empty = std::fs::read("test/empty/empty.pak")?;
+        assert!(GfpPakReaderV7::new(empty).entries_count().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_path() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV7::new(std::fs::read(AVATAR_PAK_1)?);
+        let path = pak.get_entry_path(3)?;
+        assert!(pak.contains_path(&path)?);
+        assert!(!pak.contains_path(&path[1..])?);
+        assert!(!pak.is_path_index_loaded);
+        Ok(())
+    }
+
     #[test]
     fn test_load_entries() -> Result<(), Box<dyn std::error::Error>> {
         for (pak_path, mut pak) in open_paks_by_glob(GFP_AVATAR_PAKS_PATTERN, 7).unwrap() {
@@ -403,4 +1176,79 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_validate_entry_headers() -> Result<(), Box<dyn std::error::Error>> {
+        for (_, mut pak) in open_paks_by_glob(GFP_AVATAR_PAKS_PATTERN, 7).unwrap() {
+            for entry_id in 0..pak.entries_count()? {
+                pak.validate_entry_headers(entry_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_range() -> Result<(), Box<dyn std::error::Error>> {
+        for (_, mut pak) in open_paks_by_glob(GFP_AVATAR_PAKS_PATTERN, 7).unwrap() {
+            for entry_id in 0..pak.entries_count()? {
+                let mut full = Vec::new();
+                pak.extract_entry_to_writer(entry_id, &mut full)?;
+                let size = full.len() as u64;
+                for (start, length) in [(0, size), (1, 70000), (65530, 10), (size / 2, size)] {
+                    let mut range = Vec::new();
+                    pak.extract_entry_range(entry_id, start, length, &mut range)?;
+                    let from = start.min(size) as usize;
+                    let to = start.saturating_add(length).min(size) as usize;
+                    assert_eq!(range, full[from..to]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry_range_zero_block_size() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = GfpPakReaderV7::new(File::open(AVATAR_PAK_1)?);
+        pak.load_entries()?;
+        let entry_id = pak
+            .entries
+            .iter()
+            .position(|entry| entry.num_of_blocks == 1)
+            .unwrap();
+        let mut full = Vec::new();
+        pak.extract_entry_to_writer(entry_id as u64, &mut full)?;
+        pak.entries[entry_id].compressed_block_size = 0;
+        let size = full.len() as u64;
+        for (start, length) in [(0, size), (1, 10), (size - 1, 100), (size, 1)] {
+            let mut range = Vec::new();
+            let written = pak.extract_entry_range(entry_id as u64, start, length, &mut range)?;
+            let from = start.min(size) as usize;
+            let to = start.saturating_add(length).min(size) as usize;
+            assert_eq!(range, full[from..to], "{}:{}", start, length);
+            assert_eq!(written, range.len() as u64);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_from_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(AVATAR_PAK_2)?;
+        let mut memory_pak = GfpPakReaderV7::new(data);
+        let mut file_pak = GfpPakReaderV7::open(AVATAR_PAK_2)?;
+
+        assert_eq!(memory_pak.entries_count()?, file_pak.entries_count()?);
+        for entry_id in 0..memory_pak.entries_count()? {
+            assert_eq!(
+                memory_pak.get_entry_path(entry_id)?,
+                file_pak.get_entry_path(entry_id)?
+            );
+        }
+
+        let mut memory_output = Vec::new();
+        memory_pak.extract_entry_to_writer(0, &mut memory_output)?;
+        let mut file_output = Vec::new();
+        file_pak.extract_entry_to_writer(0, &mut file_output)?;
+        assert_eq!(memory_output, file_output);
+        Ok(())
+    }
 }
@@ -0,0 +1,73 @@
+use crate::utils::read_at::ReadAt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What [`PakReader::stats`](crate::pak_reader::PakReader::stats) reports:
+/// the I/O a reader did since it was created or its stats were reset.
+///
+/// Reads are counted as calls to the source's [`ReadAt::read_at`], which is
+/// a system call each for files, and only the payloads the reader
+/// decompressed itself count as decompressed, index included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub reads: u64,
+    pub bytes_read: u64,
+    pub bytes_decompressed: u64,
+}
+
+/// The counters behind [`IoStats`], owned by a reader. Relaxed atomics, so
+/// counting costs next to nothing and works through `&self`.
+#[derive(Debug, Default)]
+pub(crate) struct IoCounters {
+    reads: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_decompressed: AtomicU64,
+}
+
+impl IoCounters {
+    pub(crate) fn record_read(&self, bytes: usize) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_decompressed(&self, bytes: usize) {
+        self.bytes_decompressed
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> IoStats {
+        IoStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_decompressed: self.bytes_decompressed.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.reads.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.bytes_decompressed.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A source whose reads are counted in `counters`
+pub(crate) struct CountedRead<'a, R: ReadAt + ?Sized> {
+    pub(crate) source: &'a R,
+    pub(crate) counters: &'a IoCounters,
+}
+
+impl<R: ReadAt + ?Sized> ReadAt for CountedRead<'_, R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let count = self.source.read_at(buf, offset)?;
+        self.counters.record_read(count);
+        Ok(count)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.source.size()
+    }
+
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        self.source.prefetch(offset, length)
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use crate::pak_reader::ext::PakReaderExt;
+use crate::utils::pak_version::{self, PakVersion};
+
+/// Entries [`PakReaderExt::pak_metadata`] looks for a version manifest at, in
+/// order. Their paths stay the same from one build to the next.
+pub const MANIFEST_PATHS: &[&str] = &[
+    "ShadowTrackerExtra/Content/Version/version.json",
+    "ShadowTrackerExtra/Content/Version/version.txt",
+    "ShadowTrackerExtra/Content/version.ini",
+];
+
+/// Manifests larger than this aren't read, they can't be the small text
+/// file a manifest is.
+pub const MAX_MANIFEST_SIZE: u64 = 64 << 10;
+
+/// What a pak says about the client build it belongs to, see
+/// [`PakReaderExt::pak_metadata`]. Each field comes from one place, which its
+/// name says; any of them may be missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PakMetadata {
+    /// The version field of the footer, see [`PakReader::declared_version`].
+    pub footer_version: u32,
+    /// The version in the pak's file name, see [`pak_version::parse`], for
+    /// readers opened by path.
+    pub name_version: Option<PakVersion>,
+    /// The first manifest entry found, and the version it names.
+    pub manifest: Option<(String, PakVersion)>,
+}
+
+/// Where [`PakMetadata::build`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildSource {
+    Manifest,
+    FileName,
+}
+
+impl PakMetadata {
+    /// The build the pak targets: the manifest's if it has one, which
+    /// renaming the pak doesn't change, else the file name's.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::metadata::{BuildSource, PakMetadata};
+    /// use gfp::utils::pak_version::parse;
+    ///
+    /// let metadata = PakMetadata {
+    ///     footer_version: 10,
+    ///     name_version: parse("game_patch_1.32.11.13846.pak"),
+    ///     manifest: None,
+    /// };
+    /// let (build, source) = metadata.build().unwrap();
+    /// assert_eq!((build.to_string().as_str(), source), ("1.32.11.13846", BuildSource::FileName));
+    /// ```
+    pub fn build(&self) -> Option<(&PakVersion, BuildSource)> {
+        match (&self.manifest, &self.name_version) {
+            (Some((_, version)), _) => Some((version, BuildSource::Manifest)),
+            (None, Some(version)) => Some((version, BuildSource::FileName)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// [`PakReaderExt::pak_metadata_with`]
+pub(crate) fn pak_metadata<P: PakReader + ?Sized>(
+    pak: &mut P,
+    manifest_paths: &[&str],
+) -> Result<PakMetadata, PakError> {
+    #[cfg(feature = "std-fs")]
+    let name_version = pak
+        .path()
+        .and_then(|path| path.file_name())
+        .and_then(|name| pak_version::parse(&name.to_string_lossy()));
+    #[cfg(not(feature = "std-fs"))]
+    let name_version = None;
+
+    let mut manifest = None;
+    for &manifest_path in manifest_paths {
+        let Some(entry_id) = pak.find_entry_by_path(manifest_path)? else {
+            continue;
+        };
+        if pak.entry_layout(entry_id)?.file_size > MAX_MANIFEST_SIZE {
+            continue;
+        }
+        let content = pak.extract_entry_to_vec(entry_id)?;
+        if let Some(version) = pak_version::find(&String::from_utf8_lossy(&content)) {
+            manifest = Some((manifest_path.to_string(), version));
+            break;
+        }
+    }
+
+    Ok(PakMetadata {
+        footer_version: pak.declared_version()?,
+        name_version,
+        manifest,
+    })
+}
+
+#[cfg(all(test, feature = "std-fs"))]
+mod tests {
+    use super::*;
+    use crate::pak_reader::implements::open_pak;
+
+    #[test]
+    fn test_pak_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut pak = open_pak("test/normal/game_patch_1.32.11.13846.pak", 10)?;
+        let metadata = pak.pak_metadata()?;
+        assert_eq!(metadata.footer_version, 10);
+        assert_eq!(metadata.manifest, None);
+        assert_eq!(
+            metadata.build(),
+            Some((
+                &pak_version::parse("1.32.11.13846").unwrap(),
+                BuildSource::FileName
+            ))
+        );
+
+        // An entry with no version in it isn't taken for a manifest, and
+        // only the entry found is read
+        let lua = "ShadowTrackerExtra/Content/Lua/common/lua_object.lua";
+        pak.reset_stats();
+        let metadata = pak.pak_metadata_with(&["missing.json", lua])?;
+        assert_eq!(metadata.manifest, None);
+        let bytes_read = pak.stats().bytes_read;
+        let entry_id = pak.find_entry_by_path(lua)?.unwrap();
+        assert!(bytes_read <= pak.entry_layout(entry_id)?.file_size + 0x1000);
+
+        let mut pak = open_pak("test/avatar/onreadypak_405399.pak", 7)?;
+        let metadata = pak.pak_metadata()?;
+        assert_eq!((metadata.footer_version, metadata.build()), (7, None));
+        Ok(())
+    }
+}
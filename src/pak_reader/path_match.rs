@@ -0,0 +1,147 @@
+use crate::error::PakError;
+use std::collections::HashMap;
+
+/// How a path looked up in a pak is compared to the entry paths.
+///
+/// Patch versions don't agree on the casing and separators of the same
+/// files, e.g. `UI/HUD/foo.png` and `ui\hud\Foo.png`, so exact lookups can
+/// miss files that are clearly present.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PathMatch {
+    /// Byte for byte.
+    #[default]
+    Exact,
+    /// Ignoring case.
+    CaseInsensitive,
+    /// Ignoring case, treating `\` as `/`, ignoring empty and `.` components
+    /// and leaving out the pak's mount point, so `ui\hud\Foo.png` finds
+    /// `ShadowTrackerExtra/Content/UI/HUD/foo.png` in a pak mounted at
+    /// `ShadowTrackerExtra/Content/`.
+    Normalized,
+}
+
+impl PathMatch {
+    /// The form of `path` that is compared under this option.
+    ///
+    /// ```rust
+    /// use gfp::pak_reader::path_match::PathMatch;
+    ///
+    /// let mount_point = "ShadowTrackerExtra/Content/";
+    /// let path = "ShadowTrackerExtra/Content/UI/HUD/foo.png";
+    /// assert_eq!(PathMatch::Exact.key(path, mount_point), path);
+    /// assert_eq!(
+    ///     PathMatch::CaseInsensitive.key(path, mount_point),
+    ///     "shadowtrackerextra/content/ui/hud/foo.png"
+    /// );
+    /// assert_eq!(PathMatch::Normalized.key(path, mount_point), "ui/hud/foo.png");
+    /// assert_eq!(PathMatch::Normalized.key("ui\\hud\\Foo.png", mount_point), "ui/hud/foo.png");
+    /// assert_eq!(
+    ///     PathMatch::Normalized.key("/shadowtrackerextra/content//UI/./HUD/foo.png", mount_point),
+    ///     "ui/hud/foo.png"
+    /// );
+    /// ```
+    pub fn key(self, path: &str, mount_point: &str) -> String {
+        match self {
+            Self::Exact => path.to_string(),
+            Self::CaseInsensitive => path.to_lowercase(),
+            Self::Normalized => {
+                let path = normalize(path);
+                let mount_point = normalize(mount_point);
+                match path.strip_prefix(&mount_point) {
+                    Some(relative_path) if mount_point.is_empty() => relative_path.to_string(),
+                    Some(relative_path) if relative_path.starts_with('/') => {
+                        relative_path[1..].to_string()
+                    }
+                    _ => path,
+                }
+            }
+        }
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+        .to_lowercase()
+}
+
+/// The lookup tables of the inexact [`PathMatch`] options, each built on
+/// first use from the entry paths and kept by the reader afterwards.
+#[derive(Debug, Default)]
+pub(crate) struct PathMatchIndexes {
+    indexes: HashMap<PathMatch, HashMap<String, Vec<u64>>>,
+}
+
+impl PathMatchIndexes {
+    /// Find the single entry matching `path`, failing with
+    /// [`PakError::AmbiguousPath`] if several entries share its key.
+    ///
+    /// `entry_paths` lists the paths by entry id and is only walked when the
+    /// table of `path_match` isn't built yet.
+    pub(crate) fn find<'a>(
+        &mut self,
+        path: &str,
+        path_match: PathMatch,
+        mount_point: &str,
+        entry_paths: impl Iterator<Item = &'a str>,
+    ) -> Result<Option<u64>, PakError> {
+        let index = self.indexes.entry(path_match).or_insert_with(|| {
+            let mut index: HashMap<String, Vec<u64>> = HashMap::new();
+            for (entry_id, entry_path) in entry_paths.enumerate() {
+                index
+                    .entry(path_match.key(entry_path, mount_point))
+                    .or_default()
+                    .push(entry_id as u64);
+            }
+            index
+        });
+
+        match index
+            .get(&path_match.key(path, mount_point))
+            .map(Vec::as_slice)
+        {
+            None | Some([]) => Ok(None),
+            Some([entry_id]) => Ok(Some(*entry_id)),
+            Some(entry_ids) => Err(PakError::AmbiguousPath {
+                path: path.to_string(),
+                entry_ids: entry_ids.to_vec(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_ambiguous() {
+        let entry_paths = [
+            "Game/UI/HUD/foo.png",
+            "Game/ui/hud/Foo.png",
+            "Game/UI/bar.png",
+        ];
+        let mut indexes = PathMatchIndexes::default();
+        let mut find =
+            |path, path_match| indexes.find(path, path_match, "Game/", entry_paths.iter().copied());
+
+        assert!(matches!(
+            find("ui/bar.PNG", PathMatch::Normalized),
+            Ok(Some(2))
+        ));
+        assert!(matches!(
+            find("game/ui/bar.png", PathMatch::CaseInsensitive),
+            Ok(Some(2))
+        ));
+        assert!(matches!(
+            find("ui/bar.png", PathMatch::CaseInsensitive),
+            Ok(None)
+        ));
+        assert!(matches!(
+            find("UI\\HUD\\FOO.png", PathMatch::Normalized),
+            Err(PakError::AmbiguousPath { entry_ids, .. }) if entry_ids == [0, 1]
+        ));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use crate::pak_reader::cancel::CancellationToken;
+use crate::pak_reader::extract::{
+    ExtractEvent, ExtractOptions, ExtractReport, ExtractedFile, ManifestRow, MtimePolicy,
+    extract_entry_atomic, join_inside, slash_path,
+};
+use crate::utils::file_time::set_file_mtime;
+use crate::utils::hex;
+use std::path::{Path, PathBuf};
+
+/// An unpack worked out ahead of time: every file it will write, and the
+/// paks as they were when it was planned, so it can be reviewed before
+/// [`apply_planned_pak`] carries it out, possibly elsewhere and later.
+///
+/// Plans are written as JSON:
+///
+/// ```rust
+/// use gfp::pak_reader::extract::ExtractOptions;
+/// use gfp::pak_reader::implements::open_pak;
+/// use gfp::pak_reader::plan::{ExtractPlan, PlannedPak};
+/// use std::path::Path;
+///
+/// let pak_path = Path::new("test/normal/game_patch_1.32.11.13846.pak");
+/// let options = ExtractOptions::default().with_strip_components(2);
+/// let mut pak = open_pak(pak_path, 10)?;
+/// let plan = ExtractPlan {
+///     mtime: options.mtime,
+///     paks: vec![PlannedPak::plan(pak.as_mut(), pak_path, &options)?],
+/// };
+/// let entry = &plan.paks[0].entries[3];
+/// assert_eq!(entry.entry_path, "ShadowTrackerExtra/Content/Lua/common/lua_object.lua");
+/// assert_eq!(entry.output_path, "Lua/common/lua_object.lua");
+/// assert_eq!(entry.size, 4853);
+/// assert_eq!(ExtractPlan::from_json(&plan.to_json())?, plan);
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractPlan {
+    /// The time to give the files, which [`MtimePolicy::Pak`] takes from
+    /// the paks when the plan is applied.
+    pub mtime: MtimePolicy,
+    pub paks: Vec<PlannedPak>,
+}
+
+impl ExtractPlan {
+    pub fn from_json(json: &str) -> Result<Self, PakError> {
+        serde_json::from_str(json)
+            .map_err(|e| PakError::invalid_data(format!("Invalid extract plan: {}", e)))
+    }
+
+    /// [`Self::from_json`] of the file at `path`.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self, PakError> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(PakError::from)
+            .and_then(|json| Self::from_json(&json))
+            .map_err(|e| e.context(format!("reading {}", path.display())))
+    }
+
+    /// The plan as indented JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("extract plans are plain values")
+    }
+
+    /// Every file of the plan.
+    pub fn entries(&self) -> impl Iterator<Item = &PlannedEntry> {
+        self.paks.iter().flat_map(|pak| &pak.entries)
+    }
+}
+
+/// The files a plan takes from one pak.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedPak {
+    pub pak_path: PathBuf,
+    /// The hash of the index recorded in the footer when the plan was made,
+    /// see [`Self::check`].
+    #[serde(with = "sha1_hex")]
+    pub footer_hash: [u8; 20],
+    pub entries: Vec<PlannedEntry>,
+}
+
+/// A file a plan writes.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedEntry {
+    pub entry_id: u64,
+    pub entry_path: String,
+    /// Path of the file relative to the output directory, with `/` between
+    /// components on every platform.
+    pub output_path: String,
+    /// Size of the file, that is of the decompressed entry.
+    pub size: u64,
+    /// The hash recorded for the entry in the index.
+    #[serde(with = "sha1_hex")]
+    pub hash: [u8; 20],
+}
+
+impl PlannedPak {
+    /// The files [`extract_pak`](crate::pak_reader::extract::extract_pak)
+    /// would write for `pak` with `options`. Entries it would skip are left
+    /// out, and [`ExtractOptions::resume`] is ignored.
+    pub fn plan(
+        pak: &mut dyn PakReader,
+        pak_path: &Path,
+        options: &ExtractOptions,
+    ) -> Result<Self, PakError> {
+        let output_dir = options.pak_output_dir(Path::new(""), pak_path);
+        let mut entries = vec![];
+        for entry_id in options.select_entries(pak)? {
+            let entry_path = pak.get_entry_path(entry_id)?;
+            let Some(relative_path) = options.entry_output_path(&entry_path)?.0 else {
+                continue;
+            };
+            let Ok(output_path) = join_inside(&output_dir, &relative_path) else {
+                continue;
+            };
+            entries.push(PlannedEntry {
+                entry_id,
+                output_path: slash_path(&output_path),
+                size: pak.entry_layout(entry_id)?.file_size,
+                hash: pak.entry_hash(entry_id)?,
+                entry_path,
+            });
+        }
+        Ok(Self {
+            pak_path: pak_path.to_path_buf(),
+            footer_hash: pak.index_stats()?.footer_hash,
+            entries,
+        })
+    }
+
+    /// Check that `pak` has the index it had when the plan was made.
+    pub fn check(&self, pak: &mut dyn PakReader) -> Result<(), PakError> {
+        let footer_hash = pak.index_stats()?.footer_hash;
+        if footer_hash != self.footer_hash {
+            return Err(PakError::invalid_data(format!(
+                "Pak changed since it was planned: footer hash {} is now {}",
+                hex::encode(self.footer_hash),
+                hex::encode(footer_hash)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Write the files `planned` takes from `pak` below `output_root`, calling
+/// `on_event` for each, after checking with [`PlannedPak::check`] that the
+/// pak is still the one planned, and with [`PakReader::check_unchanged`]
+/// before each entry that it stays the one opened.
+///
+/// Like [`extract_pak`](crate::pak_reader::extract::extract_pak), the
+/// first entry that fails stops the pak.
+pub fn apply_planned_pak(
+    pak: &mut dyn PakReader,
+    planned: &PlannedPak,
+    output_root: &Path,
+    mtime: &MtimePolicy,
+    cancel: &CancellationToken,
+    mut on_event: impl FnMut(ExtractEvent),
+) -> Result<ExtractReport, PakError> {
+    let mut report = ExtractReport::default();
+    let pak_path = planned.pak_path.as_path();
+    let mut on_event = |event: ExtractEvent| {
+        report.rows.push(ManifestRow::from_event(pak_path, &event));
+        on_event(event);
+    };
+
+    planned.check(pak)?;
+    let mtime = mtime.resolve(pak_path)?;
+    for entry in &planned.entries {
+        cancel.check()?;
+        pak.check_unchanged()?;
+        let result = (|| {
+            // An edited plan could have a path leaving the output directory
+            let output_path = join_inside(output_root, &entry.output_path)?;
+            if pak.get_entry_path(entry.entry_id)? != entry.entry_path
+                || pak.entry_hash(entry.entry_id)? != entry.hash
+                || pak.entry_layout(entry.entry_id)?.file_size != entry.size
+            {
+                return Err(PakError::invalid_data(format!(
+                    "Entry {} doesn't match the plan",
+                    entry.entry_id
+                )));
+            }
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let written = extract_entry_atomic(pak, entry.entry_id, &output_path, cancel)?;
+            if let Some(mtime) = mtime {
+                set_file_mtime(&output_path, mtime)?;
+            }
+            Ok(written)
+        })();
+        let written = match result {
+            Ok(written) => written,
+            Err(error) => {
+                on_event(ExtractEvent::Failed {
+                    entry_id: entry.entry_id,
+                    entry_path: &entry.entry_path,
+                    error: &error,
+                });
+                return Err(error);
+            }
+        };
+        on_event(ExtractEvent::Extracted(&ExtractedFile {
+            path: PathBuf::from(&entry.output_path),
+            pak_path: pak_path.to_path_buf(),
+            entry_id: entry.entry_id,
+            entry_path: entry.entry_path.clone(),
+            hash: entry.hash,
+            resumed: false,
+            renamed: false,
+            written: Some(written),
+        }));
+    }
+    Ok(report)
+}
+
+/// Serde of SHA-1 hashes as hex
+mod sha1_hex {
+    use crate::utils::hex;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(hash: &[u8; 20], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 20], D::Error> {
+        hex::decode(&String::deserialize(deserializer)?)
+            .map_err(D::Error::custom)?
+            .try_into()
+            .map_err(|_| D::Error::custom("expected a SHA-1 hash of 40 hex digits"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::extract::{ExtractTotals, OutputLayout, extract_pak};
+    use crate::pak_reader::implements::open_pak;
+    use tempfile::TempDir;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+
+    #[test]
+    fn test_plan_and_apply() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("planned.pak");
+        std::fs::copy(PAK_1, &pak_path)?;
+        let options = ExtractOptions::default()
+            .with_layout(OutputLayout::PerPak)
+            .with_strip_components(5)
+            .with_entry_ids([0, 2, 3]);
+
+        let mut pak = open_pak(&pak_path, 10)?;
+        let plan = ExtractPlan {
+            mtime: MtimePolicy::Pak,
+            paks: vec![PlannedPak::plan(pak.as_mut(), &pak_path, &options)?],
+        };
+        // Entry 3 has too few components for --strip-components 5
+        let output_paths: Vec<_> = plan.entries().map(|entry| &entry.output_path).collect();
+        assert_eq!(
+            output_paths,
+            [
+                "planned/Maps/PUBG_Baltic/Weather/Baltic_Weather_Sunnyday.uexp",
+                "planned/login/logic_pakversion.lua"
+            ]
+        );
+        let plan = ExtractPlan::from_json(&plan.to_json())?;
+
+        let applied_root = temp_dir.path().join("applied");
+        let mut totals = ExtractTotals::default();
+        let report = apply_planned_pak(
+            pak.as_mut(),
+            &plan.paks[0],
+            &applied_root,
+            &plan.mtime,
+            &CancellationToken::new(),
+            |event| totals.record(&event),
+        )?;
+        assert_eq!(report.rows.len(), 2);
+        assert_eq!(totals.extracted, 2);
+        assert_eq!(totals.bytes_written, 50813 + 181);
+
+        // The same files as unpacking right away
+        let extracted_root = temp_dir.path().join("extracted");
+        extract_pak(
+            pak.as_mut(),
+            &pak_path,
+            &extracted_root,
+            &options,
+            &CancellationToken::new(),
+            |_| {},
+        )?;
+        for entry in plan.entries() {
+            let applied_path = applied_root.join(&entry.output_path);
+            assert_eq!(
+                std::fs::read(&applied_path)?,
+                std::fs::read(extracted_root.join(&entry.output_path))?
+            );
+            assert_eq!(
+                std::fs::metadata(&applied_path)?.modified()?,
+                std::fs::metadata(&pak_path)?.modified()?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_refuses_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let pak_path = temp_dir.path().join("changed.pak");
+        let mut data = std::fs::read(PAK_1)?;
+        std::fs::write(&pak_path, &data)?;
+        let mut pak = open_pak(&pak_path, 10)?;
+        let mut planned = PlannedPak::plan(pak.as_mut(), &pak_path, &ExtractOptions::default())?;
+        let output_root = temp_dir.path().join("out");
+        let apply = |planned: &PlannedPak| {
+            let mut pak = open_pak(&pak_path, 10)?;
+            apply_planned_pak(
+                pak.as_mut(),
+                planned,
+                &output_root,
+                &MtimePolicy::Now,
+                &CancellationToken::new(),
+                |_| {},
+            )
+        };
+
+        // An edited plan can't write outside the output directory
+        for output_path in ["../escaped.lua", "/escaped.lua", "a//b.lua", "./a.lua", ""] {
+            let mut edited = planned.clone();
+            edited.entries[0].output_path = output_path.to_string();
+            let error = apply(&edited).unwrap_err();
+            assert!(error.to_string().contains("leaves the output"), "{}", error);
+        }
+        assert!(!output_root.exists());
+        planned.entries[1].hash = [0; 20];
+        let error = apply(&planned).unwrap_err();
+        assert!(error.to_string().contains("Entry 1"), "{}", error);
+
+        // A new index means a new footer hash, which stops the pak before
+        // anything is written
+        let footer_hash_offset = data.len() - 45 + 9;
+        data[footer_hash_offset] ^= 0xFF;
+        std::fs::write(&pak_path, &data)?;
+        std::fs::remove_dir_all(&output_root)?;
+        let error = apply(&planned).unwrap_err();
+        assert!(error.to_string().contains("changed since"), "{}", error);
+        assert!(!output_root.exists());
+
+        assert!(ExtractPlan::from_json("{\"mtime\":\"now\",\"paks\":[],\"extra\":1}").is_err());
+        Ok(())
+    }
+}
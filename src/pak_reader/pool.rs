@@ -0,0 +1,211 @@
+use crate::error::PakError;
+use crate::pak_reader::PakReader;
+use crate::pak_reader::implements::open_pak;
+use std::path::{Path, PathBuf};
+
+/// Readers of many paks that keep at most a few of the pak files open.
+///
+/// Each pak's index is parsed when it's added and stays in memory, but once
+/// `max_open` files are open the least recently used reader is suspended,
+/// see [`PakReader::suspend`], and resumed when it's needed again. Resuming
+/// only costs an `open` and a footer read, so tools holding a whole Paks
+/// directory stay well below the file descriptor limit.
+///
+/// ```rust
+/// use gfp::pak_reader::pool::PakPool;
+///
+/// let mut pool = PakPool::new(1);
+/// let first = pool.add("test/normal/game_patch_1.32.11.13846.pak", 10)?;
+/// let second = pool.add("test/normal/game_patch_1.32.11.13992.pak", 10)?;
+///
+/// let mut content = Vec::new();
+/// pool.get_mut(first)?.unwrap().extract_entry_to_writer(0, &mut content)?;
+/// pool.get_mut(second)?.unwrap().extract_entry_to_writer(0, &mut content)?;
+/// assert_eq!(pool.open_files(), 1);
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+pub struct PakPool {
+    max_open: usize,
+    paks: Vec<(PathBuf, Box<dyn PakReader>)>,
+    /// Indexes of the paks whose file is open, least recently used first
+    open: Vec<usize>,
+    opened: u64,
+}
+
+impl PakPool {
+    /// How many files [`PakPool::default`] keeps open.
+    pub const DEFAULT_MAX_OPEN: usize = 64;
+
+    /// A pool keeping at most `max_open` files open. A `max_open` of 0 is
+    /// taken as 1.
+    pub fn new(max_open: usize) -> Self {
+        Self {
+            max_open: max_open.max(1),
+            paks: vec![],
+            open: vec![],
+            opened: 0,
+        }
+    }
+
+    /// Add the pak at `path`, read with the given varient, and return its
+    /// index in the pool. Its index is parsed right away, so a pak that
+    /// can't be read fails here and isn't added.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P, varient: i32) -> Result<usize, PakError> {
+        let path = path.as_ref().to_path_buf();
+        let mut pak = open_pak(&path, varient)?;
+        self.opened += 1;
+        pak.entries_count()?;
+        self.paks.push((path, pak));
+        let index = self.paks.len() - 1;
+        self.touch(index);
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.paks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paks.is_empty()
+    }
+
+    /// Path of the pak at `index`.
+    pub fn path(&self, index: usize) -> Option<&Path> {
+        self.paks.get(index).map(|(path, _)| path.as_path())
+    }
+
+    /// Reader of the pak at `index`, resumed if the pool suspended it, which
+    /// fails with [`PakError::PakChanged`] if the file was replaced since.
+    pub fn get_mut(&mut self, index: usize) -> Result<Option<&mut dyn PakReader>, PakError> {
+        let Some((_, pak)) = self.paks.get_mut(index) else {
+            return Ok(None);
+        };
+        if pak.is_suspended() {
+            pak.resume()?;
+            self.opened += 1;
+        }
+        self.touch(index);
+        Ok(Some(self.paks[index].1.as_mut()))
+    }
+
+    /// Call `f` with every pak in the order they were added, each resumed
+    /// like [`Self::get_mut`] does, stopping at the first error.
+    pub fn try_for_each<F>(&mut self, mut f: F) -> Result<(), PakError>
+    where
+        F: FnMut(&Path, &mut dyn PakReader) -> Result<(), PakError>,
+    {
+        for index in 0..self.paks.len() {
+            self.get_mut(index)?;
+            let (path, pak) = &mut self.paks[index];
+            f(path, pak.as_mut())?;
+        }
+        Ok(())
+    }
+
+    pub fn max_open(&self) -> usize {
+        self.max_open
+    }
+
+    /// How many pak files are open right now.
+    pub fn open_files(&self) -> usize {
+        self.open.len()
+    }
+
+    /// How many times a pak file was opened, resuming included.
+    pub fn files_opened(&self) -> u64 {
+        self.opened
+    }
+
+    /// Mark the pak at `index` as the most recently used one, suspending the
+    /// least recently used ones past `max_open`
+    fn touch(&mut self, index: usize) {
+        self.open.retain(|&open| open != index);
+        self.open.push(index);
+        while self.open.len() > self.max_open {
+            let oldest = self.open.remove(0);
+            self.paks[oldest].1.suspend();
+        }
+    }
+}
+
+impl Default for PakPool {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_OPEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::ext::PakReaderExt;
+    use crate::pak_reader::implements::open_pak;
+
+    #[test]
+    fn test_low_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let paks = [
+            ("test/normal/game_patch_1.32.11.13846.pak", 10),
+            ("test/normal/game_patch_1.32.11.13992.pak", 10),
+            ("test/avatar/onreadypak_405399.pak", 7),
+            ("test/avatar/onreadypak_101005004.pak", 7),
+        ];
+        let mut pool = PakPool::new(2);
+        for (pak_path, version) in paks {
+            pool.add(pak_path, version)?;
+            assert!(pool.open_files() <= 2);
+        }
+        assert_eq!(pool.len(), 4);
+        assert_eq!(pool.files_opened(), 4);
+
+        // Going round the paks twice reopens each of them every time
+        for round in 0..2 {
+            for (index, (pak_path, version)) in paks.iter().enumerate() {
+                let mut expected_pak = open_pak(pak_path, *version)?;
+                let pak = pool.get_mut(index)?.unwrap();
+                for entry_id in (0..pak.entries_count()?).step_by(10) {
+                    let mut content = vec![];
+                    pak.extract_entry_to_writer(entry_id, &mut content)?;
+                    let mut expected = vec![];
+                    expected_pak.extract_entry_to_writer(entry_id, &mut expected)?;
+                    assert!(content == expected, "{} entry {}", pak_path, entry_id);
+                }
+                assert!(pool.open_files() <= 2);
+                assert_eq!(pool.files_opened(), 4 + (round * 4 + index + 1) as u64);
+            }
+        }
+
+        assert!(pool.add("test/missing.pak", 10).is_err());
+        assert_eq!(pool.len(), 4);
+        assert!(pool.get_mut(4)?.is_none());
+
+        let mut counts = vec![];
+        pool.try_for_each(|_, pak| {
+            counts.push(pak.entries_count()?);
+            Ok(())
+        })?;
+        assert_eq!(counts.len(), 4);
+        assert!(pool.open_files() <= 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replaced_while_suspended() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let pak_path = temp_dir.path().join("a.pak");
+        std::fs::copy("test/normal/game_patch_1.32.11.13846.pak", &pak_path)?;
+        let mut pool = PakPool::new(1);
+        let replaced = pool.add(&pak_path, 10)?;
+        let other = pool.add("test/normal/game_patch_1.32.11.13992.pak", 10)?;
+        assert_eq!((pool.open_files(), pool.files_opened()), (1, 2));
+
+        std::fs::copy("test/normal/game_patch_1.32.11.13992.pak", &pak_path)?;
+        let error = pool.get_mut(replaced).err().unwrap();
+        assert!(matches!(error.root_cause(), PakError::PakChanged { .. }));
+        assert!(
+            pool.get_mut(other)?
+                .unwrap()
+                .extract_entry_to_vec(0)
+                .is_ok()
+        );
+        Ok(())
+    }
+}
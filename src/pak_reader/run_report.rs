@@ -0,0 +1,297 @@
+use crate::error::PakError;
+use crate::pak_reader::extract::{ExtractOptions, ExtractTotals, with_suffix};
+use crate::utils::file_time::format_rfc3339;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// A record of one unpack run: when and with what it ran, and how each pak
+/// went, kept up to date by a [`ReportFile`] as paks complete.
+///
+/// ```rust
+/// use gfp::pak_reader::extract::{ExtractOptions, ExtractTotals};
+/// use gfp::pak_reader::run_report::{PakRunReport, UnpackReport};
+/// use std::path::PathBuf;
+/// use std::time::Duration;
+///
+/// let mut report = UnpackReport::new(
+///     vec!["gfp".into(), "unpack".into(), "*.pak".into()],
+///     ExtractOptions::default().with_strip_components(2),
+/// );
+/// report.include_from.push(PathBuf::from("lua.txt"));
+/// report.add_pak(PakRunReport {
+///     pak_path: PathBuf::from("game_patch_1.32.11.13846.pak"),
+///     totals: ExtractTotals { extracted: 7, bytes_written: 68_000, ..Default::default() },
+///     duration_ms: 12,
+///     error: None,
+/// });
+/// report.finish(Duration::from_millis(20));
+/// assert_eq!(report.totals.extracted, 7);
+/// assert_eq!(report.failed_paks, 0);
+/// assert_eq!(report.gfp_version, env!("CARGO_PKG_VERSION"));
+///
+/// let parsed = UnpackReport::from_json(&report.to_json())?;
+/// assert_eq!(parsed.paks, report.paks);
+/// assert_eq!(parsed.options.strip_components, 2);
+/// assert_eq!(parsed.include_from, [PathBuf::from("lua.txt")]);
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UnpackReport {
+    /// Version of gfp that ran the unpack.
+    pub gfp_version: String,
+    /// The command line of the run.
+    pub args: Vec<String>,
+    /// When the run started, in RFC 3339.
+    pub started_at: String,
+    /// Milliseconds the run took, up to the last pak recorded until it
+    /// finishes.
+    pub duration_ms: u64,
+    /// Whether the run got to its end. A report left by an interrupted run
+    /// has every pak completed before the interruption.
+    pub finished: bool,
+    /// The options every pak was extracted with, once the options file and
+    /// the command line are combined. Their filter isn't serialized, see
+    /// [`Self::include_from`].
+    pub options: ExtractOptions,
+    /// The include lists the filter of [`Self::options`] was made of,
+    /// matching entries listed in any of them.
+    pub include_from: Vec<PathBuf>,
+    /// The exclude lists the filter of [`Self::options`] was made of, which
+    /// take precedence over the include lists.
+    pub exclude_from: Vec<PathBuf>,
+    pub paks: Vec<PakRunReport>,
+    /// The totals of [`Self::paks`].
+    pub totals: ExtractTotals,
+    /// How many of [`Self::paks`] have an error but no failed entry. The
+    /// failed entries of the others are in [`ExtractTotals::failed`], as in
+    /// the summary of `gfp unpack`.
+    pub failed_paks: u64,
+}
+
+/// How one pak of an [`UnpackReport`] went.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PakRunReport {
+    pub pak_path: PathBuf,
+    pub totals: ExtractTotals,
+    pub duration_ms: u64,
+    /// What stopped the pak, if anything did.
+    pub error: Option<String>,
+}
+
+impl UnpackReport {
+    /// An unfinished report with no paks yet, started now.
+    pub fn new(args: Vec<String>, options: ExtractOptions) -> Self {
+        Self {
+            gfp_version: env!("CARGO_PKG_VERSION").to_string(),
+            args,
+            started_at: format_rfc3339(SystemTime::now()),
+            duration_ms: 0,
+            finished: false,
+            options,
+            include_from: vec![],
+            exclude_from: vec![],
+            paks: vec![],
+            totals: ExtractTotals::default(),
+            failed_paks: 0,
+        }
+    }
+
+    pub fn add_pak(&mut self, pak: PakRunReport) {
+        let totals = &mut self.totals;
+        totals.extracted += pak.totals.extracted;
+        totals.resumed += pak.totals.resumed;
+        totals.skipped += pak.totals.skipped;
+        totals.failed += pak.totals.failed;
+        totals.bytes_written += pak.totals.bytes_written;
+        self.failed_paks += (pak.error.is_some() && pak.totals.failed == 0) as u64;
+        self.paks.push(pak);
+    }
+
+    /// Mark the run finished after `elapsed`.
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.finished = true;
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, PakError> {
+        serde_json::from_str(json)
+            .map_err(|e| PakError::invalid_data(format!("Invalid unpack report: {}", e)))
+    }
+
+    /// The report as indented JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("unpack reports are plain values")
+    }
+
+    /// The report as a line of JSON, without the line break.
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(self).expect("unpack reports are plain values")
+    }
+}
+
+/// The file an [`UnpackReport`] is written to each time a pak completes, so
+/// that a crash leaves every pak completed before it.
+///
+/// Each write goes to `<path>.part` first and is renamed over `path`, so a
+/// crash mid-write leaves the previous write whole rather than a torn one.
+#[derive(Debug)]
+pub struct ReportFile {
+    path: PathBuf,
+    /// For a history, how many bytes of it the earlier runs wrote
+    append: Option<u64>,
+}
+
+impl ReportFile {
+    /// Write the report to `path` as indented JSON, replacing any earlier
+    /// one.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            append: None,
+        })
+    }
+
+    /// Add the report as a line to the JSON-lines history at `path`,
+    /// creating it if needed. Each write replaces that line and keeps the
+    /// lines of earlier runs as they were when the history was opened.
+    pub fn append(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let earlier = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            append: Some(earlier),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `report` in place of what the last write wrote.
+    pub fn write(&mut self, report: &UnpackReport) -> std::io::Result<()> {
+        let contents = match self.append {
+            Some(earlier) => {
+                let mut contents = vec![];
+                if earlier > 0 {
+                    contents = std::fs::read(&self.path)?;
+                    contents.truncate(earlier as usize);
+                    // Finish a last line an earlier writer left without a break
+                    if contents.last() != Some(&b'\n') {
+                        contents.push(b'\n');
+                    }
+                }
+                contents.extend_from_slice(report.to_json_line().as_bytes());
+                contents
+            }
+            None => report.to_json().into_bytes(),
+        };
+        let part_path = with_suffix(&self.path, ".part");
+        let mut part = File::create(&part_path)?;
+        part.write_all(&contents)?;
+        part.write_all(b"\n")?;
+        std::fs::rename(&part_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn pak(name: &str, error: Option<&str>) -> PakRunReport {
+        PakRunReport {
+            pak_path: PathBuf::from(name),
+            totals: ExtractTotals {
+                extracted: 2,
+                bytes_written: 10,
+                ..Default::default()
+            },
+            duration_ms: 1,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_failed_paks() {
+        let mut report = UnpackReport::new(vec![], ExtractOptions::default());
+        report.add_pak(pak("a.pak", Some("Invalid footer")));
+        let mut failed_entry = pak("b.pak", Some("Entry 3 failed"));
+        failed_entry.totals.failed = 1;
+        report.add_pak(failed_entry);
+        report.add_pak(pak("c.pak", None));
+        // Like the summary, a pak whose entries failed counts as failed entries
+        assert_eq!(report.failed_paks, 1);
+        assert_eq!(report.totals.failed, 1);
+    }
+
+    #[test]
+    fn test_report_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("reports/report.json");
+        let mut report = UnpackReport::new(vec!["gfp".into()], ExtractOptions::default());
+        let mut file = ReportFile::create(&path)?;
+        file.write(&report)?;
+        report.add_pak(pak("a.pak", None));
+        file.write(&report)?;
+        // A crash from here leaves the completed pak
+        let written = UnpackReport::from_json(&std::fs::read_to_string(&path)?)?;
+        assert!(!written.finished);
+        assert_eq!(written.paks, [pak("a.pak", None)]);
+
+        report.add_pak(pak("b.pak", Some("Invalid footer")));
+        report.finish(Duration::from_millis(5));
+        file.write(&report)?;
+        let written = UnpackReport::from_json(&std::fs::read_to_string(&path)?)?;
+        assert!(written.finished);
+        assert_eq!(written.duration_ms, 5);
+        assert_eq!(written.totals.extracted, 4);
+        assert_eq!(written.failed_paks, 1);
+        assert_eq!(std::fs::read_dir(path.parent().unwrap())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_file_append() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("history.jsonl");
+        std::fs::write(&path, "{\"earlier\":true}")?;
+
+        for run in 0..2 {
+            let mut report = UnpackReport::new(vec![run.to_string()], ExtractOptions::default());
+            let mut file = ReportFile::append(&path)?;
+            file.write(&report)?;
+            for name in ["a.pak", "b.pak"] {
+                report.add_pak(pak(name, None));
+                file.write(&report)?;
+            }
+            report.finish(Duration::ZERO);
+            file.write(&report)?;
+        }
+
+        let history = std::fs::read_to_string(&path)?;
+        let lines: Vec<_> = history.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{\"earlier\":true}");
+        for (run, line) in lines[1..].iter().enumerate() {
+            let report = UnpackReport::from_json(line)?;
+            assert_eq!(report.args, [run.to_string()]);
+            assert!(report.finished);
+            assert_eq!(report.paks.len(), 2);
+        }
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+use crate::error::PakError;
+use crate::pak_reader::entry_reader::EntryReader;
+use crate::pak_reader::{
+    BlockLayout, EntryLayout, EntryPayload, INLINE_HEADER_SIZE, payload_hash_ranges,
+};
+use crate::utils::read_at::ReadAt;
+use crate::utils::{sha1_at, sha1_of_ranges, to_usize, xor_each_byte, zlib_decompress_bounded};
+
+/// Payloads are XORed with the same key in v7 and v10 paks
+const XOR_KEY: u8 = 0x79;
+/// Bytes of the source scanned at a time
+const WINDOW_SIZE: usize = 1 << 20;
+
+/// An entry found by [`salvage_scan`] from the record in front of its
+/// payload, without the index. Its path is lost with the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SalvagedEntry {
+    /// Where the record starts.
+    pub offset: u64,
+    /// The hash recorded for the entry.
+    pub hash: [u8; 20],
+    pub layout: EntryLayout,
+    /// Whether the payload matches [`Self::hash`], `None` when the hash is
+    /// zeroed as some packers leave it. Compressed entries whose first
+    /// block inflates are salvaged even if it doesn't match.
+    pub hash_matches: Option<bool>,
+}
+
+impl SalvagedEntry {
+    /// A file name for the entry, `<offset>_<first 8 hex digits of the
+    /// hash>.<extension>`, with `bin` when there is no better extension.
+    ///
+    /// ```rust
+    /// # use gfp::pak_reader::{EntryLayout, EntryPayload};
+    /// # use gfp::pak_reader::salvage::SalvagedEntry;
+    /// # let layout = EntryLayout {
+    /// #     compression_method: 0,
+    /// #     encrypted: false,
+    /// #     xor_key: 0x79,
+    /// #     file_size: 1,
+    /// #     payload: EntryPayload::Stored { offset: 0x2676, length: 1 },
+    /// # };
+    /// let entry = SalvagedEntry {
+    ///     offset: 0x262C,
+    ///     hash: [0x85, 0xDE, 0xED, 0x02, 0xA4, 0x2D, 0x8B, 0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    ///     layout,
+    ///     hash_matches: Some(true),
+    /// };
+    /// assert_eq!(entry.file_name(Some("lua")), "000000262c_85deed02.lua");
+    /// assert_eq!(entry.file_name(None), "000000262c_85deed02.bin");
+    /// ```
+    pub fn file_name(&self, extension: Option<&str>) -> String {
+        format!(
+            "{:010x}_{}.{}",
+            self.offset,
+            crate::utils::hex::encode(&self.hash[..4]),
+            extension.unwrap_or("bin")
+        )
+    }
+
+    /// A reader of the content of the entry in `source`, see [`EntryReader`].
+    pub fn reader<'a, R: ReadAt + ?Sized>(
+        &self,
+        source: &'a R,
+        buffer_cap: usize,
+    ) -> EntryReader<'a, R> {
+        EntryReader::new(source, self.layout.clone(), buffer_cap)
+    }
+}
+
+/// Why a candidate record was rejected by [`salvage_scan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rejection {
+    /// A compression method other than none or zlib.
+    UnknownCompression,
+    /// The payload would run past the end of the source, as the last entry
+    /// of a truncated pak does.
+    PastEnd,
+    /// No blocks, or blocks outside the payload after the record.
+    BadBlocks,
+    /// A stored entry whose size differs from its stored length.
+    SizeMismatch,
+    /// The first block doesn't inflate to the block size.
+    Inflate,
+    /// A stored payload that doesn't match its hash.
+    HashMismatch,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UnknownCompression => "unknown compression method",
+            Self::PastEnd => "payload past the end of the file",
+            Self::BadBlocks => "blocks outside the payload",
+            Self::SizeMismatch => "stored size differs from the length",
+            Self::Inflate => "first block doesn't inflate",
+            Self::HashMismatch => "stored payload doesn't match its hash",
+        })
+    }
+}
+
+/// What [`salvage_scan`] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    pub entries: Vec<SalvagedEntry>,
+    /// Offsets of the candidate records that didn't check out, and why.
+    pub rejected: Vec<(u64, Rejection)>,
+}
+
+/// Find the entries of a pak whose index is lost, e.g. cut off with the
+/// footer by an interrupted download, from the records in front of their
+/// payloads.
+///
+/// Packers write a copy of each entry's index record in front of its
+/// payload, with the offset zeroed. Every place where such a record could
+/// start, with the offset and the reserved bytes after the compressed length
+/// zeroed and a non-zero size, is a candidate. A candidate is kept if its
+/// payload fits in `source` right after it, a stored payload matches its
+/// hash, and the first block of a compressed one inflates; the scan then
+/// goes on after the payload. Records of empty entries aren't told apart
+/// from zeroed padding and are skipped.
+///
+/// This is best effort: records in what is left of the index can be
+/// candidates too, and are rejected since their blocks aren't after them.
+pub fn salvage_scan<R: ReadAt + ?Sized>(source: &R) -> Result<SalvageReport, PakError> {
+    let source_size = source.size()?;
+    let mut window = Window::new(source, source_size);
+    let mut report = SalvageReport::default();
+    let mut offset = 0;
+    while let Some(record) = window.get(offset, INLINE_HEADER_SIZE as usize)? {
+        if !is_candidate(record) {
+            offset += 1;
+            continue;
+        }
+        match check_record(source, source_size, offset, record.try_into().unwrap())? {
+            Ok((entry, payload_end)) => {
+                report.entries.push(entry);
+                offset = payload_end;
+            }
+            Err(rejection) => {
+                report.rejected.push((offset, rejection));
+                offset += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Whether `record` has the zeroed offset and reserved bytes of an inline
+/// record, and a size
+fn is_candidate(record: &[u8]) -> bool {
+    record[20..28].iter().all(|&byte| byte == 0)
+        && record[28..36].iter().any(|&byte| byte != 0)
+        && record[48..69].iter().all(|&byte| byte == 0)
+}
+
+/// The entry of the candidate record at `offset` and where its payload
+/// ends, or why it was rejected
+fn check_record<R: ReadAt + ?Sized>(
+    source: &R,
+    source_size: u64,
+    offset: u64,
+    record: &[u8; INLINE_HEADER_SIZE as usize],
+) -> Result<Result<(SalvagedEntry, u64), Rejection>, PakError> {
+    let u64_at = |at: usize| u64::from_le_bytes(record[at..at + 8].try_into().unwrap());
+    let hash: [u8; 20] = record[..20].try_into().unwrap();
+    let file_size = u64_at(28);
+    let compression_method = u32::from_le_bytes(record[36..40].try_into().unwrap());
+    let compressed_length = u64_at(40);
+
+    let num_of_blocks = match compression_method {
+        0 => 0,
+        1 => u32::from_le_bytes(record[69..73].try_into().unwrap()),
+        _ => return Ok(Err(Rejection::UnknownCompression)),
+    };
+    let header_size = match compression_method {
+        0 => INLINE_HEADER_SIZE,
+        _ => INLINE_HEADER_SIZE + 4 + u64::from(num_of_blocks) * 16,
+    };
+    let payload_offset = offset + header_size;
+    let Some(payload_end) = payload_offset
+        .checked_add(compressed_length)
+        .filter(|&end| end <= source_size)
+    else {
+        return Ok(Err(Rejection::PastEnd));
+    };
+
+    if compression_method == 0 {
+        if compressed_length != file_size {
+            return Ok(Err(Rejection::SizeMismatch));
+        }
+        let encrypted = record[73] != 0;
+        let hash_matches = check_hash(&hash, || sha1_at(source, payload_offset, file_size))?;
+        if hash_matches == Some(false) {
+            return Ok(Err(Rejection::HashMismatch));
+        }
+        let entry = SalvagedEntry {
+            offset,
+            hash,
+            layout: EntryLayout {
+                compression_method,
+                encrypted,
+                xor_key: XOR_KEY,
+                file_size,
+                payload: EntryPayload::Stored {
+                    offset: payload_offset,
+                    length: file_size,
+                },
+            },
+            hash_matches,
+        };
+        return Ok(Ok((entry, payload_end)));
+    }
+
+    if num_of_blocks == 0 || u64::from(num_of_blocks) > compressed_length {
+        return Ok(Err(Rejection::BadBlocks));
+    }
+    // The block list is after the block count, and before the block size
+    // and the encrypted flag that end the record
+    let mut tail = vec![0u8; to_usize(header_size - 73)?];
+    read_exact_at(source, &mut tail, offset + 73)?;
+    let mut blocks = Vec::with_capacity(num_of_blocks as usize);
+    for block in tail[..num_of_blocks as usize * 16].chunks_exact(16) {
+        let start = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let end = u64::from_le_bytes(block[8..].try_into().unwrap());
+        if start < payload_offset || end <= start || end > payload_end {
+            return Ok(Err(Rejection::BadBlocks));
+        }
+        blocks.push((start, end - start));
+    }
+    let compressed_block_size =
+        u32::from_le_bytes(tail[tail.len() - 5..tail.len() - 1].try_into().unwrap());
+    let encrypted = tail[tail.len() - 1] != 0;
+    let blocks = BlockLayout::split(file_size, compressed_block_size, blocks.into_iter());
+
+    let first = blocks[0];
+    let mut compressed = vec![0u8; to_usize(first.compressed_length)?];
+    read_exact_at(source, &mut compressed, first.offset)?;
+    if encrypted {
+        xor_each_byte(&mut compressed, XOR_KEY);
+    }
+    match zlib_decompress_bounded(&compressed, first.decompressed_length) {
+        Ok(data) if data.len() as u64 == first.decompressed_length => {}
+        _ => return Ok(Err(Rejection::Inflate)),
+    }
+
+    let ranges: Vec<_> = blocks
+        .iter()
+        .map(|block| (block.offset, block.compressed_length))
+        .collect();
+    let hash_matches = check_hash(&hash, || {
+        sha1_of_ranges(source, &payload_hash_ranges(&ranges, compressed_length))
+    })?;
+    let entry = SalvagedEntry {
+        offset,
+        hash,
+        layout: EntryLayout {
+            compression_method,
+            encrypted,
+            xor_key: XOR_KEY,
+            file_size,
+            payload: EntryPayload::Blocks(blocks),
+        },
+        hash_matches,
+    };
+    Ok(Ok((entry, payload_end)))
+}
+
+/// Whether the payload hashed by `sha1` matches `hash`, `None` for a
+/// zeroed hash
+fn check_hash(
+    hash: &[u8; 20],
+    sha1: impl FnOnce() -> Result<[u8; 20], PakError>,
+) -> Result<Option<bool>, PakError> {
+    if hash.iter().all(|&byte| byte == 0) {
+        return Ok(None);
+    }
+    Ok(Some(sha1()? == *hash))
+}
+
+/// Buffered reads of a source scanned byte by byte
+struct Window<'a, R: ReadAt + ?Sized> {
+    source: &'a R,
+    source_size: u64,
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl<'a, R: ReadAt + ?Sized> Window<'a, R> {
+    fn new(source: &'a R, source_size: u64) -> Self {
+        Self {
+            source,
+            source_size,
+            start: 0,
+            data: vec![],
+        }
+    }
+
+    /// `length` bytes at `offset`, `None` past the end of the source
+    fn get(&mut self, offset: u64, length: usize) -> Result<Option<&[u8]>, PakError> {
+        let end = offset + length as u64;
+        if end > self.source_size {
+            return Ok(None);
+        }
+        if offset < self.start || end > self.start + self.data.len() as u64 {
+            // Files fail reads that would run past their end instead of
+            // reading less, so only ask for what is there
+            let available = to_usize(self.source_size - offset).unwrap_or(usize::MAX);
+            self.data.resize(WINDOW_SIZE.max(length).min(available), 0);
+            read_exact_at(self.source, &mut self.data, offset)?;
+            self.start = offset;
+        }
+        let at = (offset - self.start) as usize;
+        Ok(Some(&self.data[at..at + length]))
+    }
+}
+
+/// Fill as much of `buf` as the source has from `offset`
+fn read_full<R: ReadAt + ?Sized>(
+    source: &R,
+    buf: &mut [u8],
+    offset: u64,
+) -> Result<usize, PakError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match source.read_at(&mut buf[read..], offset + read as u64)? {
+            0 => break,
+            count => read += count,
+        }
+    }
+    Ok(read)
+}
+
+fn read_exact_at<R: ReadAt + ?Sized>(
+    source: &R,
+    buf: &mut [u8],
+    offset: u64,
+) -> Result<(), PakError> {
+    if read_full(source, buf, offset)? != buf.len() {
+        return Err(PakError::invalid_data(format!(
+            "Unexpected end of data at {:08X}",
+            offset
+        )));
+    }
+    Ok(())
+}
+
+/// A file extension for content starting with `head`, from the signatures
+/// of the kinds of files found in paks.
+///
+/// ```rust
+/// use gfp::pak_reader::salvage::detect_extension;
+///
+/// assert_eq!(detect_extension(&[0xC1, 0x83, 0x2A, 0x9E, 0xF9, 0xFF]), Some("uasset"));
+/// assert_eq!(detect_extension(b"\x1bLuaS\x00"), Some("lua"));
+/// assert_eq!(detect_extension(b"\x89PNG\r\n\x1a\n"), Some("png"));
+/// assert_eq!(detect_extension(b"plain text"), None);
+/// ```
+pub fn detect_extension(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: [(&[u8], &str); 9] = [
+        (&[0xC1, 0x83, 0x2A, 0x9E], "uasset"),
+        (b"\x1bLua", "lua"),
+        (b"\x1bLJ", "lua"),
+        (b"\x89PNG\r\n\x1a\n", "png"),
+        (b"DDS ", "dds"),
+        (b"BKHD", "bnk"),
+        (b"RIFF", "wav"),
+        (b"OggS", "ogg"),
+        (b"PK\x03\x04", "zip"),
+    ];
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+        .map(|&(_, extension)| extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::gfp_v7::GfpPakReaderV7;
+    use crate::pak_reader::gfp_v10::GfpPakReaderV10;
+    use crate::pak_reader::{EntryPayload, PakReader};
+    use std::collections::BTreeMap;
+
+    const PAK_1: &str = "test/normal/game_patch_1.32.11.13846.pak";
+
+    /// Contents of the salvaged entries, by hash
+    fn salvaged_contents(
+        data: &[u8],
+        report: &SalvageReport,
+    ) -> Result<BTreeMap<[u8; 20], Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut contents = BTreeMap::new();
+        for entry in &report.entries {
+            let mut content = vec![];
+            entry.reader(data, 512).copy_to(&mut content)?;
+            contents.insert(entry.hash, content);
+        }
+        Ok(contents)
+    }
+
+    #[test]
+    fn test_salvage_truncated_pak() -> Result<(), Box<dyn std::error::Error>> {
+        let data = std::fs::read(PAK_1)?;
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        let mut expected = BTreeMap::new();
+        for entry_id in 0..pak.entries_count()? {
+            let mut content = vec![];
+            pak.extract_entry_to_writer(entry_id, &mut content)?;
+            expected.insert(pak.entry_hash(entry_id)?, content);
+        }
+        let index_offset = pak.index_stats()?.index_offset as usize;
+
+        // Cut in the middle of the index
+        let truncated = &data[..index_offset + 200];
+        let report = salvage_scan(truncated)?;
+        assert_eq!(report.entries.len(), 7);
+        assert!(
+            report
+                .entries
+                .iter()
+                .all(|entry| entry.hash_matches == Some(true))
+        );
+        assert_eq!(salvaged_contents(truncated, &report)?, expected);
+        assert_eq!(
+            report.entries[2].file_name(detect_extension(&expected[&report.entries[2].hash])),
+            "000000262c_85deed02.lua"
+        );
+        // The record of entry 0 in the index has offset 0 too, after the mount point, but its
+        // payload would run past the cut
+        let mount_point_length =
+            u32::from_le_bytes(data[index_offset..index_offset + 4].try_into()?) as u64;
+        assert_eq!(
+            report.rejected,
+            [(
+                index_offset as u64 + 4 + mount_point_length + 4,
+                Rejection::PastEnd
+            )]
+        );
+
+        // Cut in the middle of the last payload
+
+        let last = report.entries.last().unwrap();
+        let cut_short = &data[..last.offset as usize + 100];
+        let report = salvage_scan(cut_short)?;
+        assert_eq!(report.entries.len(), 6);
+        assert_eq!(report.rejected, [(last.offset, Rejection::PastEnd)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_salvage_rejects_broken_payloads() -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = std::fs::read(PAK_1)?;
+        let mut pak = GfpPakReaderV10::new(data.as_slice());
+        let index_offset = pak.index_stats()?.index_offset as usize;
+        let EntryPayload::Blocks(blocks) = pak.entry_layout(0)?.payload else {
+            panic!("entry 0 is compressed");
+        };
+        let EntryPayload::Stored { offset, .. } = pak.entry_layout(2)?.payload else {
+            panic!("entry 2 is stored");
+        };
+        let record_offsets: Vec<u64> = salvage_scan(data.as_slice())?
+            .entries
+            .iter()
+            .map(|entry| entry.offset)
+            .collect();
+        data.truncate(index_offset);
+        data[blocks[0].offset as usize + 2..][..16].fill(0xFF);
+        data[offset as usize] ^= 0xFF;
+
+        let report = salvage_scan(data.as_slice())?;
+        let found: Vec<u64> = report.entries.iter().map(|entry| entry.offset).collect();
+        assert_eq!(found, [1, 3, 4, 5, 6].map(|index| record_offsets[index]));
+        assert_eq!(
+            report.rejected,
+            [
+                (record_offsets[0], Rejection::Inflate),
+                (record_offsets[2], Rejection::HashMismatch)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_salvage_padded_pak() -> Result<(), Box<dyn std::error::Error>> {
+        // Avatar paks pad their entries, and have empty ones
+        for pak_path in [
+            "test/avatar/onreadypak_405399.pak",
+            "test/avatar/onreadypak_101005004.pak",
+        ] {
+            let data = std::fs::read(pak_path)?;
+            let mut pak = GfpPakReaderV7::new(data.as_slice());
+            let mut expected = BTreeMap::new();
+            for entry_id in 0..pak.entries_count()? {
+                if pak.entry_layout(entry_id)?.file_size > 0 {
+                    let mut content = vec![];
+                    pak.extract_entry_to_writer(entry_id, &mut content)?;
+                    expected.insert(pak.entry_hash(entry_id)?, content);
+                }
+            }
+            let index_offset = pak.index_stats()?.index_offset as usize;
+
+            let report = salvage_scan(&data[..index_offset])?;
+            assert_eq!(salvaged_contents(&data, &report)?, expected, "{}", pak_path);
+            assert!(report.rejected.is_empty(), "{:?}", report.rejected);
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,26 @@
-use flate2::read::ZlibDecoder;
-use std::fs::File;
-use std::io;
+use crate::error::PakError;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
 use std::io::Read;
 
+#[cfg(feature = "std-fs")]
 pub mod cli;
+#[cfg(feature = "std-fs")]
+pub mod direct_io;
+#[cfg(feature = "std-fs")]
+pub mod file_time;
+#[cfg(feature = "std-fs")]
 pub mod glob_ext;
+pub mod hex;
+#[cfg(feature = "std-fs")]
+pub mod mmap;
+pub mod pak_version;
+pub mod read_at;
+#[cfg(feature = "std-fs")]
+pub mod shared_read;
+#[cfg(feature = "std-fs")]
+pub mod walk;
+#[cfg(feature = "std-fs")]
+pub mod write_at;
 
 pub fn xor_each_byte(data: &mut [u8], key: u8) {
     for byte in data.iter_mut() {
@@ -12,46 +28,185 @@ pub fn xor_each_byte(data: &mut [u8], key: u8) {
     }
 }
 
-pub fn read_file_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
-    #[cfg(target_os = "linux")]
-    {
-        use std::os::unix::fs::FileExt;
-        return file.read_exact_at(buf, offset).map(|_| buf.len());
+#[cfg(feature = "std-fs")]
+pub fn read_file_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    read_at::ReadAt::read_at(file, buf, offset)
+}
+
+/// Convert a size or offset read from a pak into a `usize`, failing instead of
+/// truncating on platforms where `usize` is narrower than 64 bits.
+pub fn to_usize(value: u64) -> Result<usize, PakError> {
+    usize::try_from(value).map_err(|_| {
+        PakError::invalid_data(format!("Value too large for this platform: {}", value))
+    })
+}
+
+/// Format a byte count with binary units.
+///
+/// ```rust
+/// use gfp::utils::human_size;
+///
+/// assert_eq!(human_size(512), "512 B");
+/// assert_eq!(human_size(23820), "23.3 KiB");
+/// assert_eq!(human_size(34324702), "32.7 MiB");
+/// ```
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
     }
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::fs::FileExt;
-        file.seek_read(buf, offset)
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// SHA-1 of `length` bytes of `source` starting at `offset`, read in chunks.
+pub fn sha1_at<R: read_at::ReadAt + ?Sized>(
+    source: &R,
+    offset: u64,
+    length: u64,
+) -> Result<[u8; 20], PakError> {
+    sha1_of_ranges(source, &[(offset, length)])
 }
 
-pub fn zlib_decompress(in_data: &[u8], out_size: usize) -> Option<Vec<u8>> {
-    let mut decoder = ZlibDecoder::new(in_data);
+/// SHA-1 of the `(offset, length)` ranges of `source` one after the other,
+/// in the order given, see [`sha1_at`].
+pub fn sha1_of_ranges<R: read_at::ReadAt + ?Sized>(
+    source: &R,
+    ranges: &[(u64, u64)],
+) -> Result<[u8; 20], PakError> {
+    const CHUNK_SIZE: u64 = 65536;
+
+    let mut hasher = sha1_smol::Sha1::new();
+    let largest = ranges.iter().map(|&(_, length)| length).max().unwrap_or(0);
+    let mut buffer = vec![0u8; to_usize(largest.min(CHUNK_SIZE))?];
+    for &(offset, length) in ranges {
+        let mut done = 0;
+        while done < length {
+            let chunk = &mut buffer[..to_usize((length - done).min(CHUNK_SIZE))?];
+            if source.read_at(chunk, offset + done)? != chunk.len() {
+                return Err(PakError::invalid_data(format!(
+                    "Unexpected end of data at {:08X}",
+                    offset + done
+                )));
+            }
+            hasher.update(chunk);
+            done += chunk.len() as u64;
+        }
+    }
+    Ok(hasher.digest().bytes())
+}
+
+/// Check that `header`, the first two bytes of a zlib stream, is plausible:
+/// deflate with a window of at most 32 KiB, no preset dictionary and the
+/// check bits of RFC 1950 right.
+///
+/// ```rust
+/// use gfp::utils::check_zlib_header;
+///
+/// assert!(check_zlib_header([0x78, 0x9C]).is_ok());
+/// assert!(check_zlib_header([0x78, 0x9D]).is_err());
+/// assert!(check_zlib_header([0xFF, 0xFF]).is_err());
+/// ```
+pub fn check_zlib_header(header: [u8; 2]) -> std::io::Result<()> {
+    let [cmf, flg] = header;
+    let is_deflate = cmf & 0x0F == 8 && cmf >> 4 <= 7;
+    let has_dictionary = flg & 0x20 != 0;
+    if is_deflate && !has_dictionary && u16::from_be_bytes(header).is_multiple_of(31) {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Implausible zlib header {:02X} {:02X}", cmf, flg),
+        ))
+    }
+}
+
+/// Decompress a zlib stream, or a raw deflate stream if `in_data` doesn't
+/// start with a plausible zlib header (see [`check_zlib_header`]). The index
+/// records the same compression method for both framings.
+///
+/// Readers wrap the error in a [`PakError::Decompress`] with the entry and
+/// block they were extracting.
+pub fn zlib_decompress(in_data: &[u8], out_size: usize) -> std::io::Result<Vec<u8>> {
     let mut output = Vec::with_capacity(out_size);
+    decoder(in_data).read_to_end(&mut output)?;
+    Ok(output)
+}
 
-    decoder
-        .read_to_end(&mut output)
-        .map_or(None, |_| Some(output))
+/// [`zlib_decompress`] that fails instead of producing more than `max_size`
+/// bytes, so a block can't inflate past the size the index gives it.
+///
+/// ```rust
+/// use gfp::utils::zlib_decompress_bounded;
+///
+/// let compressed = [0x78, 0x9C, 0x4B, 0x4C, 0x4C, 0x4C, 0x04, 0x00, 0x03, 0xCE, 0x01, 0x85];
+/// assert_eq!(zlib_decompress_bounded(&compressed, 4).unwrap(), b"aaaa");
+/// assert!(zlib_decompress_bounded(&compressed, 3).is_err());
+/// ```
+pub fn zlib_decompress_bounded(in_data: &[u8], max_size: u64) -> std::io::Result<Vec<u8>> {
+    let capacity = usize::try_from(max_size)
+        .unwrap_or(usize::MAX)
+        .min(in_data.len().saturating_mul(4));
+    let mut output = Vec::with_capacity(capacity);
+    decoder(in_data)
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut output)?;
+    if output.len() as u64 > max_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Decompresses to more than {} bytes", max_size),
+        ));
+    }
+    Ok(output)
+}
+
+/// A zlib decoder for `in_data`, or a raw deflate one without a zlib header
+fn decoder(in_data: &[u8]) -> Box<dyn Read + '_> {
+    let is_zlib = in_data
+        .first_chunk::<2>()
+        .is_some_and(|header| check_zlib_header(*header).is_ok());
+    if is_zlib {
+        Box::new(ZlibDecoder::new(in_data))
+    } else {
+        Box::new(DeflateDecoder::new(in_data))
+    }
 }
 
 /// ```rust
+/// use gfp::error::PakError;
 /// use gfp::utils::utf16le_to_utf8_arr_inplace;
 ///
 /// let mut buff = [0x41, 0x00, 0x2D, 0x4E]; // 'A' 和 '中'
 /// let result = utf16le_to_utf8_arr_inplace(&mut buff);
 /// println!("Result: {:?}", result);
 /// println!("{:?}", buff);
-/// assert_eq!(result, Ok(4));
+/// assert_eq!(result.unwrap(), 4);
 /// assert_eq!(buff[0..4], [0x41, 0xE4, 0xB8, 0xAD]);
+///
+/// let mut buff = [0x41, 0x00, 0x42];
+/// let result = utf16le_to_utf8_arr_inplace(&mut buff);
+/// assert!(matches!(
+///     result,
+///     Err(PakError::Encoding { offset: 2, what: "Incomplete UTF-16 sequence" })
+/// ));
 /// ```
-pub fn utf16le_to_utf8_arr_inplace(buff: &mut [u8]) -> Result<usize, &'static str> {
+pub fn utf16le_to_utf8_arr_inplace(buff: &mut [u8]) -> Result<usize, PakError> {
     let mut i = 0;
     let mut j = 0;
     let len = buff.len();
 
     while i < len {
         if i + 1 >= len {
-            return Err("Incomplete UTF-16 sequence");
+            return Err(PakError::Encoding {
+                offset: i,
+                what: "Incomplete UTF-16 sequence",
+            });
         }
 
         // 读取UTF-16LE字符
@@ -64,14 +219,20 @@ pub fn utf16le_to_utf8_arr_inplace(buff: &mut [u8]) -> Result<usize, &'static st
             j += 1;
         } else if unicode_char <= 0x7FF {
             if j + 1 >= len {
-                return Err("Output buffer too small");
+                return Err(PakError::Encoding {
+                    offset: i - 2,
+                    what: "UTF-16 string too long to convert in place",
+                });
             }
             buff[j] = 0xC0 | ((unicode_char >> 6) as u8);
             buff[j + 1] = 0x80 | ((unicode_char & 0x3F) as u8);
             j += 2;
         } else {
             if j + 2 >= len {
-                return Err("Output buffer too small");
+                return Err(PakError::Encoding {
+                    offset: i - 2,
+                    what: "UTF-16 string too long to convert in place",
+                });
             }
             buff[j] = 0xE0 | ((unicode_char >> 12) as u8);
             buff[j + 1] = 0x80 | (((unicode_char >> 6) & 0x3F) as u8);
@@ -81,73 +242,142 @@ pub fn utf16le_to_utf8_arr_inplace(buff: &mut [u8]) -> Result<usize, &'static st
     }
 
     if i < len {
-        return Err("Input buffer not fully consumed");
+        return Err(PakError::Encoding {
+            offset: i,
+            what: "UTF-16 input not fully consumed",
+        });
     }
 
     Ok(j)
 }
 
-pub fn utf16le_to_utf8_inplace(utf16le: &mut Vec<u8>) {
+/// [`utf16le_to_utf8_arr_inplace`] for a string read at `offset`, which is
+/// added to the offset of a [`PakError::Encoding`].
+pub fn utf16le_to_utf8_inplace(utf16le: &mut Vec<u8>, offset: usize) -> Result<(), PakError> {
     match utf16le_to_utf8_arr_inplace(utf16le) {
-        Ok(len) => utf16le.truncate(len),
-        Err(e) => panic!("{}", e),
+        Ok(len) => {
+            utf16le.truncate(len);
+            Ok(())
+        }
+        Err(PakError::Encoding {
+            offset: error_offset,
+            what,
+        }) => Err(PakError::Encoding {
+            offset: offset + error_offset,
+            what,
+        }),
+        Err(error) => Err(error),
     }
 }
 
 pub mod file_reader {
+    /// A read position over a borrowed slice, e.g. a whole index buffer or
+    /// just a part of a larger one.
+    ///
+    /// ```rust
+    /// use gfp::utils::file_reader::VecCursor;
+    ///
+    /// let data = [0u8, 1, 2, 3, 4, 5, 6, 7];
+    /// let mut cursor = VecCursor::new(&data[2..6]);
+    /// assert_eq!(cursor.read::<2>().unwrap(), &[2, 3]);
+    /// assert_eq!(cursor.read_dyn(2).unwrap(), vec![4, 5]);
+    /// assert_eq!(cursor.remaining(), 0);
+    /// assert!(cursor.read::<1>().is_err());
+    /// assert!(cursor.skip(usize::MAX).is_err());
+    /// ```
     pub struct VecCursor<'a, T> {
-        pub buffer: &'a Vec<T>,
+        pub buffer: &'a [T],
         pub offset: usize,
     }
 
     impl<T: Clone> VecCursor<'_, T> {
-        pub fn new(data: &'_ Vec<T>) -> VecCursor<'_, T> {
+        pub fn new(data: &'_ [T]) -> VecCursor<'_, T> {
             VecCursor::<'_, T> {
                 buffer: data,
                 offset: 0,
             }
         }
-        pub fn new_with_offset(data: &'_ Vec<T>, offset: usize) -> VecCursor<'_, T> {
+        pub fn new_with_offset(data: &'_ [T], offset: usize) -> VecCursor<'_, T> {
             VecCursor::<'_, T> {
                 buffer: data,
                 offset,
             }
         }
 
+        /// Read `N` items without checking they're there, see [`Self::read`].
         pub fn read_nocheck<const N: usize>(&mut self) -> &[T; N] {
+            debug_assert!(
+                self.offset + N <= self.buffer.len(),
+                "Read of {} past end of buffer at offset {} (length {})",
+                N,
+                self.offset,
+                self.buffer.len()
+            );
             let slice = &self.buffer[self.offset..(self.offset + N)];
             self.move_by(N);
             slice.try_into().unwrap()
         }
 
         pub fn read<const N: usize>(&mut self) -> Result<&[T; N], std::io::Error> {
-            if self.offset + N > self.buffer.len() {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Read past end of buffer",
-                ))
+            if N > self.remaining() {
+                Err(self.past_end(N))
             } else {
                 Ok(self.read_nocheck::<N>())
             }
         }
 
+        /// Read `length` items without checking they're there, see
+        /// [`Self::read_dyn`].
         pub fn read_nocheck_dyn(&mut self, length: usize) -> Vec<T> {
+            debug_assert!(
+                self.offset + length <= self.buffer.len(),
+                "Read of {} past end of buffer at offset {} (length {})",
+                length,
+                self.offset,
+                self.buffer.len()
+            );
             let slice = &self.buffer[self.offset..(self.offset + length)];
             self.move_by(length);
             slice.to_vec()
         }
 
         pub fn read_dyn(&mut self, length: usize) -> Result<Vec<T>, std::io::Error> {
-            if self.offset + length > self.buffer.len() {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Read past end of buffer",
-                ))
+            if length > self.remaining() {
+                Err(self.past_end(length))
             } else {
                 Ok(self.read_nocheck_dyn(length))
             }
         }
 
+        /// Move past `length` items without copying them, returning the
+        /// offset they start at.
+        pub fn skip(&mut self, length: usize) -> Result<usize, std::io::Error> {
+            if length > self.remaining() {
+                Err(self.past_end(length))
+            } else {
+                let offset = self.offset;
+                self.move_by(length);
+                Ok(offset)
+            }
+        }
+
+        /// Items left after the cursor, none once it's past the end.
+        pub fn remaining(&self) -> usize {
+            self.buffer.len().saturating_sub(self.offset)
+        }
+
+        fn past_end(&self, length: usize) -> std::io::Error {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Read past end of buffer: {} at offset {:#X}, {} left",
+                    length,
+                    self.offset,
+                    self.remaining()
+                ),
+            )
+        }
+
         pub fn move_to(&mut self, offset: usize) {
             self.offset = offset;
         }
@@ -156,4 +386,52 @@ pub mod file_reader {
             self.offset += offset;
         }
     }
+
+    #[cfg(all(test, debug_assertions))]
+    mod tests {
+        use super::*;
+
+        #[test]
+        #[should_panic(expected = "past end of buffer at offset 6")]
+        fn test_read_nocheck_past_end() {
+            let data = [0u8; 8];
+            let mut cursor = VecCursor::new_with_offset(&data, 6);
+            cursor.read_nocheck::<4>();
+        }
+
+        #[test]
+        #[should_panic(expected = "past end of buffer at offset 2")]
+        fn test_read_nocheck_dyn_past_end() {
+            let data = [0u8; 8];
+            let mut cursor = VecCursor::new_with_offset(&data, 2);
+            cursor.read_nocheck_dyn(7);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::{DeflateEncoder, ZlibEncoder};
+    use std::io::Write;
+
+    #[test]
+    fn test_zlib_decompress_both_framings() -> std::io::Result<()> {
+        let data = b"ShadowTrackerExtra/Content/".repeat(100);
+
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&data)?;
+        let zlib = encoder.finish()?;
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&data)?;
+        let deflate = encoder.finish()?;
+
+        assert!(check_zlib_header([zlib[0], zlib[1]]).is_ok());
+        assert!(check_zlib_header([deflate[0], deflate[1]]).is_err());
+        assert_eq!(zlib_decompress(&zlib, data.len())?, data);
+        assert_eq!(zlib_decompress(&deflate, data.len())?, data);
+        assert!(zlib_decompress(&[0xFF; 8], data.len()).is_err());
+        Ok(())
+    }
 }
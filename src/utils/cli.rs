@@ -1,3 +1,13 @@
+use crate::pak_reader::EntryLayout;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Turn a command line path argument into a glob pattern.
+///
+/// Directories are expanded to match every pak under them, and paths that
+/// exist on disk are escaped so that glob metacharacters in them (e.g. `[`)
+/// are matched literally.
+///
 /// ```rust
 /// use std::path::PathBuf;
 /// use gfp::utils::cli::prepare_file_pattern;
@@ -8,15 +18,429 @@
 /// assert_eq!(prepare_file_pattern("**/*.pak"), "**/*.pak".to_string());
 /// assert_eq!(prepare_file_pattern("./Paks/**/*.pak"), "./Paks/**/*.pak".to_string());
 /// assert_eq!(prepare_file_pattern("./Paks/abc.pak"), "./Paks/abc.pak".to_string());
+/// assert_eq!(prepare_file_pattern("./Paks/*.PAK"), "./Paks/*.PAK".to_string());
+///
+/// let temp_dir = tempfile::TempDir::new().unwrap();
+/// let pak_dir = temp_dir.path().join("[2002291]");
+/// std::fs::create_dir(&pak_dir).unwrap();
+/// let pattern = prepare_file_pattern(pak_dir.to_string_lossy());
+/// assert!(pattern.ends_with("/[[]2002291[]]/**/*.pak"));
 /// ```
 pub fn prepare_file_pattern(file_pattern: impl AsRef<str>) -> String {
     let mut file_pattern = file_pattern.as_ref().to_string();
-    if file_pattern.ends_with(".pak") {
+    if Path::new(&file_pattern).exists() {
+        file_pattern = glob::Pattern::escape(&file_pattern);
+    }
+    if file_pattern.to_ascii_lowercase().ends_with(".pak") {
         file_pattern
     } else {
-        if !file_pattern.ends_with(|c| c == '/' || c == '\\') {
+        if !file_pattern.ends_with(['/', '\\']) {
             file_pattern += "/";
         }
         file_pattern + "**/*.pak"
     }
 }
+
+/// Entry field an [`EntryFilter`] compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    /// [`EntryLayout::compression_method`]
+    Compression,
+    /// [`EntryLayout::encrypted`], compared as `0` or `1`
+    Encrypted,
+    /// [`EntryLayout::file_size`]
+    Size,
+}
+
+/// Comparison of an [`EntryFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A `--where` filter such as `compression=0`, `compression!=1`,
+/// `encrypted=true` or `size>10M`, matched against an entry's
+/// [`EntryLayout`] so that no entry data has to be read.
+///
+/// Sizes take an optional binary suffix `K`, `M` or `G`, optionally
+/// followed by `B` or `iB`.
+///
+/// ```rust
+/// use gfp::utils::cli::{EntryFilter, FilterField, FilterOp};
+///
+/// let filter: EntryFilter = "size>10M".parse()?;
+/// assert_eq!(filter.field, FilterField::Size);
+/// assert_eq!(filter.op, FilterOp::Gt);
+/// assert_eq!(filter.value, 10 << 20);
+/// assert_eq!("encrypted=true".parse::<EntryFilter>()?.value, 1);
+/// assert!("encrypted>0".parse::<EntryFilter>().is_err());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryFilter {
+    pub field: FilterField,
+    pub op: FilterOp,
+    pub value: u64,
+}
+
+impl EntryFilter {
+    /// Whether the entry laid out as `layout` passes the filter.
+    pub fn matches(&self, layout: &EntryLayout) -> bool {
+        let actual = match self.field {
+            FilterField::Compression => layout.compression_method as u64,
+            FilterField::Encrypted => layout.encrypted as u64,
+            FilterField::Size => layout.file_size,
+        };
+        match self.op {
+            FilterOp::Eq => actual == self.value,
+            FilterOp::Ne => actual != self.value,
+            FilterOp::Lt => actual < self.value,
+            FilterOp::Le => actual <= self.value,
+            FilterOp::Gt => actual > self.value,
+            FilterOp::Ge => actual >= self.value,
+        }
+    }
+
+    /// Whether the entry passes every filter in `filters`.
+    pub fn matches_all(filters: &[EntryFilter], layout: &EntryLayout) -> bool {
+        filters.iter().all(|filter| filter.matches(layout))
+    }
+}
+
+impl FromStr for EntryFilter {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let start = expression
+            .find(['=', '!', '<', '>'])
+            .ok_or_else(|| format!("Expected FIELD<op>VALUE, got '{}'", expression))?;
+        let (field, rest) = expression.split_at(start);
+        let (op, value) = [
+            ("!=", FilterOp::Ne),
+            (">=", FilterOp::Ge),
+            ("<=", FilterOp::Le),
+            ("=", FilterOp::Eq),
+            (">", FilterOp::Gt),
+            ("<", FilterOp::Lt),
+        ]
+        .into_iter()
+        .find_map(|(token, op)| rest.strip_prefix(token).map(|value| (op, value.trim())))
+        .ok_or_else(|| format!("Unknown operator in '{}'", expression))?;
+
+        let field = match field.trim().to_ascii_lowercase().as_str() {
+            "compression" => FilterField::Compression,
+            "encrypted" => FilterField::Encrypted,
+            "size" => FilterField::Size,
+            other => {
+                return Err(format!(
+                    "Unknown field '{}', expected compression, encrypted or size",
+                    other
+                ));
+            }
+        };
+        let value = match field {
+            FilterField::Encrypted => {
+                if !matches!(op, FilterOp::Eq | FilterOp::Ne) {
+                    return Err(format!(
+                        "encrypted only supports = and !=, got '{}'",
+                        expression
+                    ));
+                }
+                match value.to_ascii_lowercase().as_str() {
+                    "true" | "1" => 1,
+                    "false" | "0" => 0,
+                    _ => return Err(format!("Expected true or false, got '{}'", value)),
+                }
+            }
+            FilterField::Compression => value
+                .parse()
+                .map_err(|e| format!("Invalid compression method '{}': {}", value, e))?,
+            FilterField::Size => parse_size(value)?,
+        };
+        Ok(Self { field, op, value })
+    }
+}
+
+/// Parse a thread count: a number of at least 1, or `auto` for
+/// [`std::thread::available_parallelism`], falling back to 1 where it
+/// can't be found out.
+pub fn parse_threads(threads: &str) -> Result<usize, String> {
+    if threads.eq_ignore_ascii_case("auto") {
+        return Ok(std::thread::available_parallelism().map_or(1, |count| count.get()));
+    }
+    match threads.parse::<usize>() {
+        Ok(0) => Err("Thread count must be at least 1".to_string()),
+        Ok(count) => Ok(count),
+        Err(_) => Err(format!(
+            "Invalid thread count '{}', expected a number or auto",
+            threads
+        )),
+    }
+}
+
+/// Parse a byte count with an optional binary `K`, `M` or `G` suffix, e.g.
+/// `512`, `64K`, `10MiB`.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let upper = size.to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (digits, shift) = match number.as_bytes().last() {
+        Some(b'K') => (&number[..number.len() - 1], 10),
+        Some(b'M') => (&number[..number.len() - 1], 20),
+        Some(b'G') => (&number[..number.len() - 1], 30),
+        _ => (number, 0),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Invalid size '{}'", size))
+}
+
+/// An `ls --format` template such as `{id}\t{size}\t{path}`.
+///
+/// Each `{name}` is replaced with a field of the entry: `{id}`, `{path}`,
+/// `{size}` (decompressed), `{compressed}` (bytes in the pak), `{method}`
+/// (`0` stored, `1` zlib) or `{enc}` (`true` or `false`). `{{` and `}}` are
+/// literal braces, and `\t`, `\n` and `\\` are a tab, a newline and a
+/// backslash, since shells pass them through unchanged in quotes.
+///
+/// ```rust
+/// use gfp::pak_reader::{EntryLayout, EntryPayload};
+/// use gfp::utils::cli::EntryFormat;
+///
+/// let layout = EntryLayout {
+///     compression_method: 1,
+///     encrypted: false,
+///     xor_key: 0x79,
+///     file_size: 70000,
+///     payload: EntryPayload::Blocks(vec![]),
+/// };
+/// let format: EntryFormat = r"{id}\t{size} {{{method}}} {path}".parse()?;
+/// assert_eq!(format.render(7, "Lua/a.lua", &layout), "7\t70000 {1} Lua/a.lua");
+/// assert!("{name}".parse::<EntryFormat>().is_err());
+/// # Ok::<(), String>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryFormat {
+    tokens: Vec<FormatToken>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormatToken {
+    Literal(String),
+    Id,
+    Path,
+    Size,
+    Compressed,
+    Method,
+    Enc,
+}
+
+impl EntryFormat {
+    /// The line for the entry `entry_id` at `entry_path`, laid out as
+    /// `layout`.
+    pub fn render(&self, entry_id: u64, entry_path: &str, layout: &EntryLayout) -> String {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match token {
+                FormatToken::Literal(text) => line.push_str(text),
+                FormatToken::Id => line.push_str(&entry_id.to_string()),
+                FormatToken::Path => line.push_str(entry_path),
+                FormatToken::Size => line.push_str(&layout.file_size.to_string()),
+                FormatToken::Compressed => line.push_str(&layout.stored_size().to_string()),
+                FormatToken::Method => line.push_str(&layout.compression_method.to_string()),
+                FormatToken::Enc => line.push_str(&layout.encrypted.to_string()),
+            }
+        }
+        line
+    }
+}
+
+impl FromStr for EntryFormat {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut tokens = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    other => {
+                        literal.push('\\');
+                        literal.extend(other);
+                    }
+                },
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed {{ in '{}'", template))?;
+                    let token = match &rest[..end] {
+                        "id" => FormatToken::Id,
+                        "path" => FormatToken::Path,
+                        "size" => FormatToken::Size,
+                        "compressed" => FormatToken::Compressed,
+                        "method" => FormatToken::Method,
+                        "enc" => FormatToken::Enc,
+                        name => {
+                            return Err(format!(
+                                "Unknown field {{{}}}, expected id, path, size, compressed, method or enc",
+                                name
+                            ));
+                        }
+                    };
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        tokens.push(FormatToken::Literal(std::mem::take(&mut literal)));
+                    }
+                    tokens.push(token);
+                }
+                '}' => {
+                    return Err(format!(
+                        "Unmatched }} in '{}', write }}}} for a brace",
+                        template
+                    ));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(FormatToken::Literal(literal));
+        }
+        Ok(Self { tokens })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_reader::EntryPayload;
+
+    fn layout(compression_method: u32, encrypted: bool, file_size: u64) -> EntryLayout {
+        EntryLayout {
+            compression_method,
+            encrypted,
+            xor_key: 0x79,
+            file_size,
+            payload: EntryPayload::Stored {
+                offset: 0,
+                length: file_size,
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let parse = |expression: &str| expression.parse::<EntryFilter>();
+        assert_eq!(
+            parse("compression=0"),
+            Ok(EntryFilter {
+                field: FilterField::Compression,
+                op: FilterOp::Eq,
+                value: 0
+            })
+        );
+        assert_eq!(parse("Compression != 1").map(|f| f.op), Ok(FilterOp::Ne));
+        assert_eq!(parse("encrypted=false").map(|f| f.value), Ok(0));
+        assert_eq!(
+            parse("size>=64K").map(|f| (f.op, f.value)),
+            Ok((FilterOp::Ge, 64 << 10))
+        );
+        assert_eq!(parse("size<2GiB").map(|f| f.value), Ok(2 << 30));
+        assert_eq!(parse("size<=100b").map(|f| f.value), Ok(100));
+
+        for invalid in [
+            "size",
+            "name=foo",
+            "size=ten",
+            "size>99999999999G",
+            "compression=-1",
+            "encrypted<1",
+            "encrypted=maybe",
+            "size=>1",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_entry_format() {
+        let render = |template: &str| {
+            template
+                .parse::<EntryFormat>()
+                .map(|format| format.render(3, "Lua/a.lua", &layout(0, true, 1024)))
+        };
+        assert_eq!(
+            render("{id} {path} {size} {compressed} {method} {enc}"),
+            Ok("3 Lua/a.lua 1024 1024 0 true".to_string())
+        );
+        assert_eq!(
+            render(r"{size}\t{path}\n"),
+            Ok("1024\tLua/a.lua\n".to_string())
+        );
+        assert_eq!(render(r"{{id}} \\ \x"), Ok(r"{id} \ \x".to_string()));
+        assert_eq!(render("plain"), Ok("plain".to_string()));
+        assert_eq!(render(""), Ok(String::new()));
+
+        for invalid in ["{id", "{nope}", "{ID}", "id}", "{}"] {
+            assert!(render(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_threads() {
+        let available = std::thread::available_parallelism().map_or(1, |count| count.get());
+        assert_eq!(parse_threads("auto"), Ok(available));
+        assert_eq!(parse_threads("AUTO"), Ok(available));
+        assert_eq!(parse_threads("4"), Ok(4));
+        assert!(parse_threads("0").is_err());
+        assert!(parse_threads("-1").is_err());
+        assert!(parse_threads("many").is_err());
+    }
+
+    #[test]
+    fn test_filter_matches() {
+        let filters: Vec<EntryFilter> = ["compression!=1", "encrypted=true", "size>10M"]
+            .into_iter()
+            .map(|expression| expression.parse().unwrap())
+            .collect();
+        assert!(EntryFilter::matches_all(
+            &filters,
+            &layout(0, true, 11 << 20)
+        ));
+        assert!(!EntryFilter::matches_all(
+            &filters,
+            &layout(1, true, 11 << 20)
+        ));
+        assert!(!EntryFilter::matches_all(
+            &filters,
+            &layout(0, false, 11 << 20)
+        ));
+        assert!(!EntryFilter::matches_all(
+            &filters,
+            &layout(0, true, 10 << 20)
+        ));
+        assert!(EntryFilter::matches_all(&[], &layout(1, false, 0)));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::utils::read_at::ReadAt;
+use crate::utils::shared_read::open_shared_read;
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::Path;
+
+/// A file read without going through the OS page cache, so extracting tens
+/// of gigabytes doesn't evict everything else from it.
+///
+/// On Linux the file is opened with `O_DIRECT` and on Windows with
+/// `FILE_FLAG_NO_BUFFERING`. Both only accept reads whose offset, length and
+/// buffer are aligned to the device block size, so every read is widened to
+/// [`Self::ALIGNMENT`] and copied out of an aligned buffer. Where unbuffered
+/// IO isn't available (other platforms, tmpfs, ...) the file is read normally,
+/// see [`Self::is_direct`].
+pub struct DirectFile {
+    file: File,
+    is_direct: bool,
+}
+
+impl DirectFile {
+    /// Alignment of unbuffered reads. 4 KiB is a multiple of the logical
+    /// block size of virtually every device.
+    pub const ALIGNMENT: usize = 4096;
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match open_unbuffered(path.as_ref()) {
+            Ok(file) => Ok(Self {
+                file,
+                is_direct: true,
+            }),
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(path = %path.as_ref().display(), error = %_e, "falling back to buffered reads");
+                Ok(Self {
+                    file: open_shared_read(path)?,
+                    is_direct: false,
+                })
+            }
+        }
+    }
+
+    /// Whether reads actually bypass the page cache.
+    pub fn is_direct(&self) -> bool {
+        self.is_direct
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.file.metadata()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_unbuffered(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    File::options()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(windows)]
+fn open_unbuffered(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_FLAG_NO_BUFFERING: u32 = 0x2000_0000;
+    File::options()
+        .read(true)
+        .share_mode(crate::utils::shared_read::SHARE_ALL)
+        .custom_flags(FILE_FLAG_NO_BUFFERING)
+        .open(path)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn open_unbuffered(_path: &Path) -> io::Result<File> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// One positioned read, which may be short.
+fn read_once(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        FileExt::read_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        file.seek_read(buf, offset)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (file, buf, offset);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// Fill `buf` from `offset` until the end of the file.
+///
+/// A read ending off `alignment` can only be the end of the file, and the
+/// next offset wouldn't be aligned anymore, so reading stops there.
+fn read_full(file: &File, buf: &mut [u8], offset: u64, alignment: usize) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match read_once(file, &mut buf[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(count) => {
+                filled += count;
+                if count % alignment != 0 {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl ReadAt for DirectFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if !self.is_direct {
+            return read_full(&self.file, buf, offset, 1);
+        }
+
+        let alignment = Self::ALIGNMENT as u64;
+        let start = offset / alignment * alignment;
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .and_then(|end| end.checked_next_multiple_of(alignment))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let length = usize::try_from(end - start)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        // Over-allocate so that an aligned window of `length` bytes fits
+        let mut storage = vec![0u8; length + Self::ALIGNMENT];
+        let padding = storage.as_ptr().align_offset(Self::ALIGNMENT);
+        let aligned = &mut storage[padding..padding + length];
+
+        let filled = read_full(&self.file, aligned, start, Self::ALIGNMENT)?;
+        let skip = (offset - start) as usize;
+        let count = filled.saturating_sub(skip).min(buf.len());
+        buf[..count].copy_from_slice(&aligned[skip..skip + count]);
+        Ok(count)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn open_path(path: &Path) -> io::Result<Self> {
+        Self::open(path)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unaligned_reads() -> Result<(), Box<dyn std::error::Error>> {
+        let pak_path = "test/avatar/onreadypak_405399.pak";
+        let data = std::fs::read(pak_path)?;
+        let file = DirectFile::open(pak_path)?;
+        println!("is_direct: {}", file.is_direct());
+        assert_eq!(file.size()?, data.len() as u64);
+
+        let end = data.len() as u64;
+        for (offset, length) in [
+            (0, 45),
+            (1, 4095),
+            (4095, 2),
+            (4096, 8192),
+            (12345, 100000),
+            (end - 45, 45),
+            (end - 10, 100),
+            (end, 10),
+            (end + 5000, 10),
+        ] {
+            let mut buf = vec![0u8; length];
+            let count = file.read_at(&mut buf, offset)?;
+            let start = (offset as usize).min(data.len());
+            let expected = &data[start..(start + length).min(data.len())];
+            assert_eq!(
+                &buf[..count],
+                expected,
+                "offset {} length {}",
+                offset,
+                length
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Set the modification time of the file at `path`.
+pub fn set_file_mtime(path: impl AsRef<Path>, mtime: SystemTime) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_modified(mtime)
+}
+
+/// Parse an RFC 3339 timestamp, e.g. `2024-05-01T12:00:00Z` or
+/// `2024-05-01T20:00:00.5+08:00`.
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use gfp::utils::file_time::parse_rfc3339;
+///
+/// let noon = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+/// assert_eq!(parse_rfc3339("2024-05-01T12:00:00Z"), Ok(noon));
+/// assert_eq!(parse_rfc3339("2024-05-01 20:00:00+08:00"), Ok(noon));
+/// assert_eq!(
+///     parse_rfc3339("2024-05-01T12:00:00.25z"),
+///     Ok(noon + Duration::from_millis(250))
+/// );
+/// assert_eq!(
+///     parse_rfc3339("1969-12-31T23:59:59Z"),
+///     Ok(UNIX_EPOCH - Duration::from_secs(1))
+/// );
+/// assert!(parse_rfc3339("2024-05-01T12:00:00").is_err());
+/// assert!(parse_rfc3339("2024-02-30T12:00:00Z").is_err());
+/// ```
+pub fn parse_rfc3339(timestamp: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("Invalid RFC 3339 timestamp '{}'", timestamp);
+    let bytes = timestamp.as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        let digits = bytes.get(range).ok_or_else(invalid)?;
+        if digits.iter().all(u8::is_ascii_digit) {
+            Ok(digits
+                .iter()
+                .fold(0, |value, digit| value * 10 + (digit - b'0') as i64))
+        } else {
+            Err(invalid())
+        }
+    };
+    let separator = |index: usize, allowed: &[u8]| -> Result<(), String> {
+        match bytes.get(index) {
+            Some(byte) if allowed.contains(byte) => Ok(()),
+            _ => Err(invalid()),
+        }
+    };
+
+    separator(4, b"-")?;
+    separator(7, b"-")?;
+    separator(10, b"Tt ")?;
+    separator(13, b":")?;
+    separator(16, b":")?;
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => 0,
+    };
+    if day < 1 || day > days_in_month || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    let mut index = 19;
+    let mut nanos = 0;
+    if bytes.get(index) == Some(&b'.') {
+        let digits = bytes[index + 1..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return Err(invalid());
+        }
+        for position in 0..9 {
+            let digit = if position < digits {
+                (bytes[index + 1 + position] - b'0') as u32
+            } else {
+                0
+            };
+            nanos = nanos * 10 + digit;
+        }
+        index += 1 + digits;
+    }
+
+    let offset = match &bytes[index..] {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (hours, minutes) = (number(index + 1..index + 3)?, number(index + 4..index + 6)?);
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' { -offset } else { offset }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let time = if seconds >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    };
+    Ok(time + Duration::from_nanos(nanos as u64))
+}
+
+/// `time` as an RFC 3339 timestamp in UTC, which [`parse_rfc3339`] reads
+/// back to the same time. Fractions of a second are written only when there
+/// are any.
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use gfp::utils::file_time::format_rfc3339;
+///
+/// let noon = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+/// assert_eq!(format_rfc3339(noon), "2024-05-01T12:00:00Z");
+/// assert_eq!(
+///     format_rfc3339(noon + Duration::from_millis(250)),
+///     "2024-05-01T12:00:00.25Z"
+/// );
+/// assert_eq!(
+///     format_rfc3339(UNIX_EPOCH - Duration::from_millis(500)),
+///     "1969-12-31T23:59:59.5Z"
+/// );
+/// ```
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (seconds, nanos) = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    let mut timestamp = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    );
+    if nanos != 0 {
+        let fraction = format!("{:09}", nanos);
+        timestamp.push('.');
+        timestamp.push_str(fraction.trim_end_matches('0'));
+    }
+    timestamp.push('Z');
+    timestamp
+}
+
+/// The date of the proleptic Gregorian calendar `days` after 1970-01-01,
+/// the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
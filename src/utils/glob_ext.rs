@@ -1,28 +1,98 @@
-use glob::{GlobResult, MatchOptions, Paths, glob, glob_with};
+use crate::utils::walk::{PakWalker, WalkError};
+use glob::{GlobResult, MatchOptions, Paths, Pattern, glob_with};
+use std::path::{Component, Path, PathBuf};
 
 /// A wrapper around glob `Paths` iterator that applies a mapping function
 /// to each result, transforming them into a different type.
 ///
-/// This struct should be created by [`glob_mapper`] and [`glob_mapper_with`].
+/// This struct should be created by [`GlobMapper::new`], or by [`glob_mapper`]
+/// and [`glob_mapper_with`].
+///
+/// `Paths` can only be walked forwards, so this is not a [`DoubleEndedIterator`].
+///
+/// ## Example
+///
+/// ```rust
+/// use glob::MatchOptions;
+/// use gfp::utils::glob_ext::GlobMapper;
+///
+/// let mut errors = vec![];
+/// let options = MatchOptions {
+///     case_sensitive: false,
+///     ..MatchOptions::new()
+/// };
+///
+/// for item in GlobMapper::new("**/*.RS")?.with_options(options).map(|result| {
+///     result.map_err(|e| errors.push(e)).ok()
+/// }) {
+///     // item: PathBuf
+///     println!("{:?}", item);
+/// }
+/// # Ok::<(), glob::PatternError>(())
+/// ```
 pub struct GlobMapper<F> {
     paths: Paths,
     mapper: F,
 }
 
+/// Builder returned by [`GlobMapper::new`], see [`GlobMapper`].
+pub struct GlobMapperBuilder {
+    pattern: String,
+    options: MatchOptions,
+}
+
+impl GlobMapper<()> {
+    /// Start building a [`GlobMapper`] for `pattern`, which is validated here.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(pattern: &str) -> Result<GlobMapperBuilder, glob::PatternError> {
+        glob_with(pattern, MatchOptions::new())?;
+        Ok(GlobMapperBuilder {
+            pattern: pattern.to_string(),
+            options: MatchOptions::new(),
+        })
+    }
+}
+
+impl GlobMapperBuilder {
+    /// Use custom [`MatchOptions`] instead of the default ones.
+    pub fn with_options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Finish building with a mapping function, see [`glob_mapper`] for its meaning.
+    pub fn map<T, F>(self, mapper: F) -> GlobMapper<F>
+    where
+        F: FnMut(GlobResult) -> Option<T>,
+    {
+        GlobMapper {
+            // The options don't take part in parsing, so the pattern can't
+            // fail here after being accepted by `GlobMapper::new`.
+            paths: glob_with(&self.pattern, self.options)
+                .expect("pattern is validated by GlobMapper::new"),
+            mapper,
+        }
+    }
+}
+
 impl<T, F> Iterator for GlobMapper<F>
 where
-    F: Fn(GlobResult) -> Option<T>,
+    F: FnMut(GlobResult) -> Option<T>,
 {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(next) = self.paths.next() {
+        for next in self.paths.by_ref() {
             if let Some(item) = (self.mapper)(next) {
                 return Some(item);
             }
         }
         None
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.paths.size_hint().1)
+    }
 }
 
 /// Create a function that return a [`GlobMapper`] with default options.
@@ -61,14 +131,9 @@ pub fn glob_mapper<'a, T, F>(
     mapper: F,
 ) -> impl Fn(&str) -> Result<GlobMapper<F>, glob::PatternError>
 where
-    F: Fn(GlobResult) -> Option<T> + 'a + Copy,
+    F: FnMut(GlobResult) -> Option<T> + 'a + Clone,
 {
-    move |pattern| {
-        Ok(GlobMapper {
-            paths: glob(pattern)?,
-            mapper,
-        })
-    }
+    move |pattern| Ok(GlobMapper::new(pattern)?.map(mapper.clone()))
 }
 
 /// Create a function that returns a [`GlobMapper`] with custom options.
@@ -103,14 +168,147 @@ pub fn glob_mapper_with<'a, T, F>(
     mapper: F,
 ) -> impl Fn(&str, MatchOptions) -> Result<GlobMapper<F>, glob::PatternError>
 where
-    F: Fn(GlobResult) -> Option<T> + 'a + Copy,
+    F: FnMut(GlobResult) -> Option<T> + 'a + Clone,
 {
     move |pattern, options| {
-        Ok(GlobMapper {
-            paths: glob_with(pattern, options)?,
-            mapper,
-        })
+        Ok(GlobMapper::new(pattern)?
+            .with_options(options)
+            .map(mapper.clone()))
+    }
+}
+
+/// Match `pattern` against the files found by a [`PakWalker`] instead of
+/// expanding it with [`glob_with`].
+///
+/// The walk starts at the longest leading part of the pattern without
+/// metacharacters and, unlike [`glob_with`], never descends into symlinked
+/// directories, so cyclic links can't make it loop. Symlinked files are still
+/// matched. As with [`glob_with`], `*` and `?` never match a path separator.
+///
+/// `max_depth` additionally limits how deep below that leading part files are
+/// matched, see [`pattern_depth`].
+///
+/// ## Example
+///
+/// ```rust
+/// use glob::MatchOptions;
+/// use gfp::utils::glob_ext::glob_no_follow;
+///
+/// for pak_path in glob_no_follow("test/**/*.pak", MatchOptions::new(), None)?.filter_map(Result::ok) {
+///     println!("{:?}", pak_path);
+/// }
+/// # Ok::<(), glob::PatternError>(())
+/// ```
+pub fn glob_no_follow(
+    pattern: &str,
+    options: MatchOptions,
+    max_depth: Option<usize>,
+) -> Result<impl Iterator<Item = Result<PathBuf, WalkError>> + use<>, glob::PatternError> {
+    let compiled = Pattern::new(pattern)?;
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..options
+    };
+
+    let (base_dir, pattern_max_depth) = literal_base_dir(pattern);
+    let max_depth = match (pattern_max_depth, max_depth) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let is_relative_to_cwd = base_dir.as_os_str().is_empty();
+    let walk_root = if is_relative_to_cwd {
+        PathBuf::from(".")
+    } else {
+        base_dir
+    };
+
+    Ok(PakWalker::new(walk_root)
+        .any_extension()
+        .max_depth(max_depth)
+        .filter_map(move |result| {
+            let path = match result {
+                Ok(path) if is_relative_to_cwd => path
+                    .strip_prefix(".")
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path),
+                Ok(path) => path,
+                Err(e) => return Some(Err(e)),
+            };
+            compiled
+                .matches_path_with(&path, options)
+                .then_some(Ok(path))
+        }))
+}
+
+/// How many levels below the leading part of `pattern` without wildcards
+/// `path` is, e.g. 2 for `Paks/a/b.pak` matched by `Paks/**/*.pak`.
+///
+/// Returns `None` if `path` isn't under that leading part.
+///
+/// ```rust
+/// use std::path::Path;
+/// use gfp::utils::glob_ext::pattern_depth;
+///
+/// assert_eq!(pattern_depth("Paks/**/*.pak", Path::new("Paks/a/b.pak")), Some(2));
+/// assert_eq!(pattern_depth("**/*.pak", Path::new("b.pak")), Some(1));
+/// assert_eq!(pattern_depth("Paks/**/*.pak", Path::new("Other/b.pak")), None);
+/// ```
+pub fn pattern_depth(pattern: &str, path: &Path) -> Option<usize> {
+    let (base_dir, _) = literal_base_dir(pattern);
+    let relative_path = path.strip_prefix(&base_dir).ok()?;
+    Some(
+        relative_path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .count(),
+    )
+}
+
+/// The directory made of the components of `pattern` before the first one
+/// with a metacharacter, with escapes such as `[[]` turned back into literals,
+/// and how deep below it the pattern can match, unless it contains `**`.
+fn literal_base_dir(pattern: &str) -> (PathBuf, Option<usize>) {
+    let components: Vec<_> = Path::new(pattern).components().collect();
+    let mut base_dir = PathBuf::new();
+    let mut literal_count = 0;
+
+    // The last component names files, so it never belongs to the base directory
+    for component in components.iter().take(components.len().saturating_sub(1)) {
+        match component {
+            Component::Normal(name) => match name.to_str().and_then(unescape_literal) {
+                Some(name) => base_dir.push(name),
+                None => break,
+            },
+            component => base_dir.push(component),
+        }
+        literal_count += 1;
     }
+
+    let rest = &components[literal_count..];
+    let max_depth =
+        (!rest.iter().any(|component| component.as_os_str() == "**")).then_some(rest.len());
+    (base_dir, max_depth)
+}
+
+/// Undo [`Pattern::escape`], or return `None` if `text` contains an actual
+/// wildcard.
+fn unescape_literal(text: &str) -> Option<String> {
+    let mut literal = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return None,
+            '[' => {
+                let escaped = chars.next()?;
+                if chars.next()? != ']' {
+                    return None;
+                }
+                literal.push(escaped);
+            }
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
 }
 
 #[cfg(test)]
@@ -137,7 +335,7 @@ mod test {
 
         let my_iter = glob_mapper(|result: GlobResult| match result {
             Ok(entry) => {
-                if !entry.extension().map_or(false, |ext| ext == "pak") {
+                if entry.extension().is_none_or(|ext| ext != "pak") {
                     None
                 } else {
                     File::open(&entry).ok()
@@ -229,6 +427,90 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_glob_mapper_stateful() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+
+        fs::write(temp_path.join("test1.PAK"), b"test pak content 1")?;
+        fs::write(temp_path.join("test2.pak"), b"test pak content 2")?;
+        fs::write(temp_path.join("test3.txt"), b"not a pak file")?;
+
+        let pattern_str = temp_path.join("*.pak").to_string_lossy().to_string();
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+
+        let mut seen = 0;
+        let mut names = vec![];
+        for name in
+            GlobMapper::new(&pattern_str)?
+                .with_options(options)
+                .map(|result: GlobResult| {
+                    seen += 1;
+                    result.ok()?.file_name().map(|name| name.to_owned())
+                })
+        {
+            names.push(name);
+        }
+
+        assert_eq!(seen, 2);
+        assert_eq!(names, vec!["test1.PAK", "test2.pak"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_no_follow() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("[2002291]");
+        fs::create_dir_all(root.join("sub"))?;
+        fs::write(root.join("a.pak"), b"")?;
+        fs::write(root.join("sub").join("b.pak"), b"")?;
+        fs::write(root.join("sub").join("c.txt"), b"")?;
+
+        let pattern = Pattern::escape(&root.to_string_lossy()) + "/**/*.pak";
+        let paths = glob_no_follow(&pattern, MatchOptions::new(), None)?
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![root.join("a.pak"), root.join("sub").join("b.pak")]
+        );
+
+        let pattern = Pattern::escape(&root.to_string_lossy()) + "/*.pak";
+        let paths = glob_no_follow(&pattern, MatchOptions::new(), None)?
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(paths, vec![root.join("a.pak")]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_glob_no_follow_cyclic_symlink() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        fs::write(temp_dir.path().join("a.pak"), b"")?;
+        std::os::unix::fs::symlink(temp_dir.path(), temp_dir.path().join("loop"))?;
+
+        let pattern = temp_dir
+            .path()
+            .join("**/*.pak")
+            .to_string_lossy()
+            .to_string();
+        let paths = glob_no_follow(&pattern, MatchOptions::new(), None)?
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(paths, vec![temp_dir.path().join("a.pak")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_mapper_invalid_pattern() {
+        assert!(GlobMapper::new("[").is_err());
+    }
+
     #[test]
     fn test_glob_mapper_error_handling() -> Result<(), Box<dyn std::error::Error>> {
         let pattern_str = "nonexistent_directory_0ds9fas0930i0kbdofgids/*.pak";
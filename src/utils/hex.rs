@@ -0,0 +1,87 @@
+use crate::error::PakError;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Lowercase hex of `bytes`, two digits per byte.
+///
+/// ```rust
+/// use gfp::utils::hex;
+///
+/// assert_eq!(hex::encode([0x00, 0x7F, 0xAB]), "007fab");
+/// assert_eq!(hex::encode([]), "");
+/// ```
+pub fn encode<T: AsRef<[u8]>>(bytes: T) -> String {
+    let bytes = bytes.as_ref();
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for &byte in bytes {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0xF) as usize] as char);
+    }
+    hex
+}
+
+/// The bytes written as hex in `hex`, in either case.
+///
+/// ```rust
+/// use gfp::utils::hex;
+///
+/// assert_eq!(hex::decode("007fAB")?, [0x00, 0x7F, 0xAB]);
+/// assert!(hex::decode("7fa").is_err());
+/// assert!(hex::decode("7g").is_err());
+/// # Ok::<(), gfp::error::PakError>(())
+/// ```
+pub fn decode(hex: &str) -> Result<Vec<u8>, PakError> {
+    let digits = hex.as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return Err(PakError::invalid_data(format!(
+            "Odd number of hex digits: {}",
+            digits.len()
+        )));
+    }
+    let digit = |index: usize| {
+        let value = match digits[index] {
+            digit @ b'0'..=b'9' => digit - b'0',
+            digit @ b'a'..=b'f' => digit - b'a' + 10,
+            digit @ b'A'..=b'F' => digit - b'A' + 10,
+            _ => {
+                return Err(PakError::invalid_data(format!(
+                    "Invalid hex digit at {}: {:?}",
+                    index,
+                    hex[index..].chars().next().unwrap_or_default()
+                )));
+            }
+        };
+        Ok(value)
+    };
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| Ok(digit(index)? << 4 | digit(index + 1)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<(), PakError> {
+        let hash: [u8; 20] = std::array::from_fn(|index| (index * 13 + 7) as u8);
+        let hex = encode(hash);
+        assert_eq!(hex, "0714212e3b4855626f7c8996a3b0bdcad7e4f1fe");
+        assert_eq!(decode(&hex)?, hash);
+        assert_eq!(decode(&hex.to_uppercase())?, hash);
+        assert_eq!(decode("")?, [0u8; 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        for hex in ["0", "abc", "0g", "g0", " 0", "0x00", "é0"] {
+            assert!(
+                matches!(decode(hex), Err(PakError::InvalidData(_))),
+                "{}",
+                hex
+            );
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::utils::read_at::ReadAt;
+use crate::utils::shared_read::open_shared_read;
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::Path;
+
+/// A file mapped into memory, so reads are copies out of the page cache
+/// without a system call each.
+///
+/// Only Linux maps the file; elsewhere, and for empty files, which can't be
+/// mapped, the file is read normally, see [`Self::is_mapped`]. A mapped file
+/// that shrinks while it's open makes reads past its new end crash the
+/// process, so only map paks nothing writes to.
+pub struct MappedFile {
+    file: File,
+    #[cfg(target_os = "linux")]
+    map: Option<Mapping>,
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = open_shared_read(path.as_ref())?;
+        #[cfg(target_os = "linux")]
+        {
+            let map = Mapping::new(&file)
+                .inspect_err(|_e| {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(path = %path.as_ref().display(), error = %_e, "falling back to buffered reads");
+                })
+                .unwrap_or_default();
+            Ok(Self { file, map })
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(Self { file })
+    }
+
+    /// Whether reads actually come from a mapping.
+    pub fn is_mapped(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            self.map.is_some()
+        }
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.file.metadata()
+    }
+}
+
+/// A read-only `mmap` of a whole file
+#[cfg(target_os = "linux")]
+struct Mapping {
+    address: *mut libc::c_void,
+    length: usize,
+}
+
+// The mapping is read-only and only unmapped on drop
+#[cfg(target_os = "linux")]
+unsafe impl Send for Mapping {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for Mapping {}
+
+#[cfg(target_os = "linux")]
+impl Mapping {
+    /// Map `file`, or `None` if it's empty
+    fn new(file: &File) -> io::Result<Option<Self>> {
+        use std::os::unix::io::AsRawFd;
+        let length = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::FileTooLarge))?;
+        if length == 0 {
+            return Ok(None);
+        }
+        let address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                length,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(Self { address, length }))
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.address as *const u8, self.length) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.address, self.length);
+        }
+    }
+}
+
+impl ReadAt for MappedFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        if let Some(map) = &self.map {
+            return map.as_slice().read_at(buf, offset);
+        }
+        self.file.read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        #[cfg(target_os = "linux")]
+        if let Some(map) = &self.map {
+            return Ok(map.length as u64);
+        }
+        self.file.size()
+    }
+
+    /// `madvise(MADV_WILLNEED)` on the mapped pages, or the hint of a plain
+    /// [`File`] when the file isn't mapped.
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(map) = &self.map {
+            // madvise wants a page aligned start, and only the mapped part
+            // of the range can be hinted
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+            let start = offset / page_size * page_size;
+            let end = offset.saturating_add(length).min(map.length as u64);
+            if start >= end {
+                return Ok(());
+            }
+            return match unsafe {
+                libc::madvise(
+                    map.address.add(start as usize),
+                    (end - start) as usize,
+                    libc::MADV_WILLNEED,
+                )
+            } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            };
+        }
+        self.file.prefetch(offset, length)
+    }
+
+    fn open_path(path: &Path) -> io::Result<Self> {
+        Self::open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_reads() -> io::Result<()> {
+        let path = "test/normal/game_patch_1.32.11.13846.pak";
+        let expected = std::fs::read(path)?;
+        let file = MappedFile::open(path)?;
+        assert_eq!(file.is_mapped(), cfg!(target_os = "linux"));
+        assert_eq!(file.size()?, expected.len() as u64);
+
+        let mut buf = [0u8; 64];
+        assert_eq!(file.read_at(&mut buf, 22333)?, 64);
+        assert_eq!(buf, expected[22333..22333 + 64]);
+        let end = expected.len() as u64;
+        if file.is_mapped() {
+            assert_eq!(file.read_at(&mut buf, end - 10)?, 10);
+            assert_eq!(buf[..10], expected[expected.len() - 10..]);
+            assert_eq!(file.read_at(&mut buf, end + 1)?, 0);
+        }
+
+        file.prefetch(0, end)?;
+        file.prefetch(end - 1, 4096)?;
+        file.prefetch(end + 4096, 4096)?;
+        Ok(())
+    }
+}
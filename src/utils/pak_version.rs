@@ -0,0 +1,213 @@
+#[cfg(feature = "std-fs")]
+use std::cmp::Ordering;
+#[cfg(feature = "std-fs")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std-fs")]
+use std::time::SystemTime;
+
+/// The version encoded in a pak name, e.g. `1.32.11.13846` in
+/// `game_patch_1.32.11.13846.pak`.
+///
+/// Versions compare component by component, numerically, so the build number
+/// comes last and `1.9` sorts before `1.32`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PakVersion {
+    pub components: Vec<u64>,
+}
+
+impl std::fmt::Display for PakVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, component) in self.components.iter().enumerate() {
+            if index > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{}", component)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the version at the end of a pak file name.
+///
+/// The version is the run of dot-separated numbers closing the file stem, and
+/// needs at least two components, so ids like `onreadypak_405399.pak` aren't
+/// taken for versions.
+///
+/// ```rust
+/// use gfp::utils::pak_version::parse;
+///
+/// let version = parse("game_patch_1.32.11.13846.pak").unwrap();
+/// assert_eq!(version.components, vec![1, 32, 11, 13846]);
+/// assert!(parse("game_patch_1.9.0.100.pak").unwrap() < version);
+/// assert_eq!(parse("onreadypak_405399.pak"), None);
+/// ```
+pub fn parse(name: &str) -> Option<PakVersion> {
+    let lowercase_name = name.to_ascii_lowercase();
+    let stem = match lowercase_name.strip_suffix(".pak") {
+        Some(stem) => &name[..stem.len()],
+        None => name,
+    };
+
+    let start = stem
+        .rfind(|c: char| !c.is_ascii_digit() && c != '.')
+        .map_or(0, |separator| separator + 1);
+    let version = stem[start..].trim_start_matches('.');
+
+    let components = version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    (components.len() >= 2).then_some(PakVersion { components })
+}
+
+/// Find a version anywhere in `text`, like the build a version manifest
+/// names, e.g. `1.32.11.13846` in `{"version": "1.32.11.13846"}`.
+///
+/// Versions are runs of at least two dot-separated numbers; when there are
+/// several, the one with the most components is taken, the first of them on
+/// ties, so a build number wins over a `1.0` schema version.
+///
+/// ```rust
+/// use gfp::utils::pak_version::find;
+///
+/// let version = find("format=1.0\nbuild=1.32.11.13846\n").unwrap();
+/// assert_eq!(version.to_string(), "1.32.11.13846");
+/// assert_eq!(find("build 13846"), None);
+/// ```
+pub fn find(text: &str) -> Option<PakVersion> {
+    text.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .filter_map(|run| {
+            let components = run
+                .trim_matches('.')
+                .split('.')
+                .map(|component| component.parse().ok())
+                .collect::<Option<Vec<u64>>>()?;
+            (components.len() >= 2).then_some(PakVersion { components })
+        })
+        .fold(None, |found: Option<PakVersion>, version| match found {
+            Some(found) if found.components.len() >= version.components.len() => Some(found),
+            _ => Some(version),
+        })
+}
+
+/// Sort paks from the oldest to the newest.
+///
+/// Paks with a version in their name come first, ordered by [`PakVersion`].
+/// The others follow, ordered by modification time; paths are the final
+/// tie-breaker so the order is always the same.
+#[cfg(feature = "std-fs")]
+pub fn sort_paks_by_version(paks: &mut [PathBuf]) {
+    paks.sort_by_cached_key(|pak_path| (SortKey::of(pak_path), pak_path.clone()));
+}
+
+#[cfg(feature = "std-fs")]
+#[derive(PartialEq, Eq)]
+enum SortKey {
+    Versioned(PakVersion),
+    Unversioned(Option<SystemTime>),
+}
+
+#[cfg(feature = "std-fs")]
+impl SortKey {
+    fn of(pak_path: &Path) -> Self {
+        match pak_path
+            .file_name()
+            .and_then(|name| parse(&name.to_string_lossy()))
+        {
+            Some(version) => Self::Versioned(version),
+            None => Self::Unversioned(
+                std::fs::metadata(pak_path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Versioned(a), Self::Versioned(b)) => a.cmp(b),
+            (Self::Versioned(_), Self::Unversioned(_)) => Ordering::Less,
+            (Self::Unversioned(_), Self::Versioned(_)) => Ordering::Greater,
+            // Paks whose mtime can't be read go last
+            (Self::Unversioned(a), Self::Unversioned(b)) => match (a, b) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let components = |name| parse(name).map(|version| version.components);
+        assert_eq!(
+            components("game_patch_1.32.11.13846.pak"),
+            Some(vec![1, 32, 11, 13846])
+        );
+        assert_eq!(
+            components("Paks/game_patch_1.32.11.13992.PAK"),
+            Some(vec![1, 32, 11, 13992])
+        );
+        assert_eq!(components("res_patch_2.1.0.5"), Some(vec![2, 1, 0, 5]));
+        assert_eq!(components("onreadypak_405399.pak"), None);
+        assert_eq!(components("map_en.pak"), None);
+        assert_eq!(components("game_patch_1..2.pak"), None);
+    }
+
+    #[cfg(feature = "std-fs")]
+    #[test]
+    fn test_sort_paks_by_version() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let older = temp_dir.path().join("onreadypak_405399.pak");
+        let newer = temp_dir.path().join("map_en.pak");
+        for (pak_path, seconds) in [(&older, 1), (&newer, 2)] {
+            std::fs::File::create(pak_path)?
+                .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds))?;
+        }
+
+        let mut paks: Vec<PathBuf> = [
+            "game_patch_1.32.11.13992.pak",
+            "game_patch_1.9.0.100.pak",
+            "missing.pak",
+            "game_patch_1.32.11.13846.pak",
+            "game_patch_1.32.10.20000.pak",
+            "game_patch_1.32.11.13846.1.pak",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .chain([newer.clone(), older.clone()])
+        .collect();
+        sort_paks_by_version(&mut paks);
+
+        assert_eq!(
+            paks,
+            vec![
+                PathBuf::from("game_patch_1.9.0.100.pak"),
+                PathBuf::from("game_patch_1.32.10.20000.pak"),
+                PathBuf::from("game_patch_1.32.11.13846.pak"),
+                PathBuf::from("game_patch_1.32.11.13846.1.pak"),
+                PathBuf::from("game_patch_1.32.11.13992.pak"),
+                older,
+                newer,
+                PathBuf::from("missing.pak"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+use std::io;
+
+/// A data source that supports positioned reads.
+///
+/// This is the only I/O the pak readers need, so anything that can hand out
+/// bytes at an offset (a file, an in-memory buffer, a JS `ArrayBuffer` copied
+/// into wasm memory, ...) can back a reader.
+///
+/// ## Example
+///
+/// ```rust
+/// use gfp::utils::read_at::ReadAt;
+///
+/// let data: &[u8] = b"hello world";
+/// let mut buf = [0u8; 5];
+/// assert_eq!(data.read_at(&mut buf, 6).unwrap(), 5);
+/// assert_eq!(&buf, b"world");
+/// assert_eq!(data.size().unwrap(), 11);
+/// ```
+pub trait ReadAt {
+    /// Fill `buf` with the bytes starting at `offset`.
+    ///
+    /// Returns the number of bytes read, which may be less than `buf.len()`
+    /// when the source ends early.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Total size of the source in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Hint that the `length` bytes at `offset` are about to be read, so the
+    /// OS can start reading them ahead. Sources that can't act on the hint,
+    /// like in-memory buffers, ignore it, which is what this default does.
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        let _ = (offset, length);
+        Ok(())
+    }
+
+    /// A new source of this kind reading the file at `path`, see
+    /// [`PakReader::reopen`](crate::pak_reader::PakReader::reopen). Sources
+    /// that don't come from a file, like slices, can't, which is what this
+    /// default reports.
+    #[cfg(feature = "std-fs")]
+    fn open_path(path: &std::path::Path) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        let _ = path;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "This source can't be reopened from a path",
+        ))
+    }
+
+    /// The file the source reads, for sources that remember it, like
+    /// [`PathSource`].
+    #[cfg(feature = "std-fs")]
+    fn path(&self) -> Option<&std::path::Path> {
+        None
+    }
+
+    /// Close the file until [`Self::resume`], if the source can open it
+    /// again; reads fail in between. Others ignore it, which is what this
+    /// default does.
+    #[cfg(feature = "std-fs")]
+    fn suspend(&mut self) {}
+
+    /// Open the file closed by [`Self::suspend`] again. Returns whether it
+    /// was closed.
+    #[cfg(feature = "std-fs")]
+    fn resume(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn is_suspended(&self) -> bool {
+        false
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let start = match usize::try_from(offset) {
+            Ok(start) if start <= self.len() => start,
+            _ => return Ok(0),
+        };
+        let count = buf.len().min(self.len() - start);
+        buf[..count].copy_from_slice(&self[start..start + count]);
+        Ok(count)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.as_slice().size()
+    }
+
+    /// The whole file at `path`, read into memory.
+    #[cfg(feature = "std-fs")]
+    fn open_path(path: &std::path::Path) -> io::Result<Self> {
+        std::fs::read(path)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        (**self).prefetch(offset, length)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Box<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        (**self).prefetch(offset, length)
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn path(&self) -> Option<&std::path::Path> {
+        (**self).path()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn suspend(&mut self) {
+        (**self).suspend()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn resume(&mut self) -> io::Result<bool> {
+        (**self).resume()
+    }
+
+    #[cfg(feature = "std-fs")]
+    fn is_suspended(&self) -> bool {
+        (**self).is_suspended()
+    }
+}
+
+/// The first `end` bytes of another source, for sources holding more than
+/// one thing, like an executable with a pak appended to it.
+///
+/// Offsets aren't moved: byte `n` is still byte `n` of the inner source, only
+/// nothing at or past `end` can be read.
+///
+/// ```rust
+/// use gfp::utils::read_at::{ReadAt, Truncated};
+///
+/// let data: &[u8] = b"hello world";
+/// let hello = Truncated::new(data, 5);
+/// let mut buf = [0u8; 8];
+/// assert_eq!(hello.read_at(&mut buf, 2).unwrap(), 3);
+/// assert_eq!(&buf[..3], b"llo");
+/// assert_eq!(hello.size().unwrap(), 5);
+/// ```
+pub struct Truncated<R> {
+    inner: R,
+    end: u64,
+}
+
+impl<R: ReadAt> Truncated<R> {
+    pub fn new(inner: R, end: u64) -> Self {
+        Self { inner, end }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: ReadAt> ReadAt for Truncated<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let available = self.end.saturating_sub(offset);
+        let count = buf
+            .len()
+            .min(usize::try_from(available).unwrap_or(usize::MAX));
+        if count == 0 {
+            return Ok(0);
+        }
+        self.inner.read_at(&mut buf[..count], offset)
+    }
+
+    /// `end`, or the size of the inner source if it's shorter.
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.inner.size()?.min(self.end))
+    }
+
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        let length = length.min(self.end.saturating_sub(offset));
+        if length == 0 {
+            return Ok(());
+        }
+        self.inner.prefetch(offset, length)
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileExt;
+            self.read_exact_at(buf, offset).map(|_| buf.len())
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileExt;
+            self.seek_read(buf, offset)
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = (buf, offset);
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn open_path(path: &std::path::Path) -> io::Result<Self> {
+        crate::utils::shared_read::open_shared_read(path)
+    }
+
+    /// `posix_fadvise(POSIX_FADV_WILLNEED)` on Linux, nothing elsewhere.
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            // Ranges past what off_t can hold can't be in the file anyway
+            let (Ok(offset), Ok(length)) = (i64::try_from(offset), i64::try_from(length)) else {
+                return Ok(());
+            };
+            // posix_fadvise returns the error number instead of setting errno
+            match unsafe {
+                libc::posix_fadvise(self.as_raw_fd(), offset, length, libc::POSIX_FADV_WILLNEED)
+            } {
+                0 => Ok(()),
+                error => Err(io::Error::from_raw_os_error(error)),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (offset, length);
+            Ok(())
+        }
+    }
+}
+
+/// A file source that remembers the path it was opened from, so it can close
+/// the file and open it again later, see
+/// [`PakReader::suspend`](crate::pak_reader::PakReader::suspend).
+///
+/// Reads fail while the file is closed.
+///
+/// ```rust
+/// use gfp::utils::read_at::{PathSource, ReadAt};
+/// use std::fs::File;
+///
+/// let mut source = PathSource::<File>::open("test/normal/game_patch_1.32.11.13846.pak")?;
+/// source.suspend();
+/// assert!(source.size().is_err());
+/// assert!(source.resume()?);
+/// assert_eq!(source.size()?, 23820);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "std-fs")]
+#[derive(Debug)]
+pub struct PathSource<R> {
+    path: std::path::PathBuf,
+    inner: Option<R>,
+}
+
+#[cfg(feature = "std-fs")]
+impl<R: ReadAt> PathSource<R> {
+    /// `inner`, which was opened from `path`.
+    pub fn new<P: Into<std::path::PathBuf>>(path: P, inner: R) -> Self {
+        Self {
+            path: path.into(),
+            inner: Some(inner),
+        }
+    }
+
+    /// Open the file at `path` with [`ReadAt::open_path`].
+    pub fn open<P: Into<std::path::PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let inner = R::open_path(&path)?;
+        Ok(Self::new(path, inner))
+    }
+
+    /// The open file, if it isn't suspended.
+    pub fn get_ref(&self) -> Option<&R> {
+        self.inner.as_ref()
+    }
+
+    fn inner(&self) -> io::Result<&R> {
+        self.inner.as_ref().ok_or_else(|| {
+            io::Error::other(format!(
+                "{} is suspended, resume it first",
+                self.path.display()
+            ))
+        })
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl<R: ReadAt> ReadAt for PathSource<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.inner()?.read_at(buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.inner()?.size()
+    }
+
+    fn prefetch(&self, offset: u64, length: u64) -> io::Result<()> {
+        self.inner()?.prefetch(offset, length)
+    }
+
+    fn open_path(path: &std::path::Path) -> io::Result<Self> {
+        Self::open(path)
+    }
+
+    fn path(&self) -> Option<&std::path::Path> {
+        Some(&self.path)
+    }
+
+    fn suspend(&mut self) {
+        self.inner = None;
+    }
+
+    fn resume(&mut self) -> io::Result<bool> {
+        if self.inner.is_some() {
+            return Ok(false);
+        }
+        self.inner = Some(R::open_path(&self.path)?);
+        Ok(true)
+    }
+
+    fn is_suspended(&self) -> bool {
+        self.inner.is_none()
+    }
+}
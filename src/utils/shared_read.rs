@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Open `path` for reading, letting other programs read, write and delete
+/// the file meanwhile.
+///
+/// On Windows this is what lets gfp read paks while the game has them open.
+/// A program that opened the file without sharing still makes this fail,
+/// see [`is_sharing_violation`].
+pub fn open_shared_read<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let mut options = File::options();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.share_mode(SHARE_ALL);
+    }
+    options.open(path)
+}
+
+/// `FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE`
+#[cfg(windows)]
+pub(crate) const SHARE_ALL: u32 = 0x1 | 0x2 | 0x4;
+
+/// Whether opening or reading a file failed because another program holds
+/// it without sharing it, which is Windows' `ERROR_SHARING_VIOLATION` or
+/// `ERROR_LOCK_VIOLATION`. Always `false` elsewhere.
+pub fn is_sharing_violation(error: &io::Error) -> bool {
+    cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33))
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use std::os::windows::fs::OpenOptionsExt;
+
+    #[test]
+    fn test_sharing_violation() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::TempDir::new()?;
+        let path = temp_dir.path().join("a.pak");
+        std::fs::write(&path, b"pak")?;
+
+        // Other readers are fine
+        let reader = open_shared_read(&path)?;
+        drop(open_shared_read(&path)?);
+        drop(reader);
+
+        let _exclusive = File::options().read(true).share_mode(0).open(&path)?;
+        let error = open_shared_read(&path).unwrap_err();
+        assert!(is_sharing_violation(&error), "{:?}", error);
+        Ok(())
+    }
+}
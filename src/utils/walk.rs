@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An error encountered while reading a directory during a [`PakWalker`] walk.
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+/// Recursively walk a directory, yielding every file with a `.pak` extension.
+///
+/// Unlike glob patterns, the root path is used literally, so directories whose
+/// names contain glob metacharacters such as `[` and `]` are handled correctly.
+/// Directories are read lazily as the iterator advances; entries of each
+/// directory are visited in file name order.
+///
+/// Symlinked directories are skipped unless [`Self::follow_symlinks`] is set,
+/// in which case every directory is only entered once, so link cycles can't
+/// make the walk loop.
+///
+/// ## Example
+///
+/// ```rust
+/// use gfp::utils::walk::PakWalker;
+///
+/// for pak_path in PakWalker::new("test").max_depth(Some(2)).filter_map(Result::ok) {
+///     println!("{:?}", pak_path);
+/// }
+/// ```
+pub struct PakWalker {
+    /// Directories to read, with their depth below the root
+    pending: Vec<(PathBuf, usize)>,
+    files: std::vec::IntoIter<PathBuf>,
+    extensions: Vec<String>,
+    max_depth: Option<usize>,
+    is_following_symlinks: bool,
+    visited: HashSet<DirKey>,
+}
+
+impl PakWalker {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            pending: vec![(root.as_ref().to_path_buf(), 0)],
+            files: Vec::new().into_iter(),
+            extensions: vec!["pak".to_string()],
+            max_depth: None,
+            is_following_symlinks: false,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Yield every file instead of only those with a `.pak` extension, for
+    /// callers that recognize paks by content.
+    pub fn any_extension(self) -> Self {
+        self.extensions(Vec::<String>::new())
+    }
+
+    /// Yield the files with one of `extensions`, compared case-insensitively,
+    /// instead of `.pak` files. An empty list accepts every file.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only yield files at most `max_depth` levels below the root, like
+    /// `find -maxdepth`: `Some(1)` means only the files directly in the root.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Descend into symlinked directories too.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.is_following_symlinks = follow_symlinks;
+        self
+    }
+
+    fn read_dir(&mut self, dir: &Path, depth: usize) -> io::Result<()> {
+        if self.is_following_symlinks && !self.visited.insert(DirKey::of(dir)?) {
+            return Ok(());
+        }
+
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut files = vec![];
+        let mut dirs = vec![];
+        for entry in entries {
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let is_dir = if file_type.is_symlink() && self.is_following_symlinks {
+                // A dangling link is reported as a file, and fails when opened
+                fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir())
+            } else {
+                file_type.is_dir()
+            };
+
+            if is_dir {
+                if self.max_depth.is_none_or(|max_depth| depth + 1 < max_depth) {
+                    dirs.push((path, depth + 1));
+                }
+            } else if self.has_wanted_extension(&path) {
+                files.push(path);
+            }
+        }
+
+        // `pending` is a stack, push in reverse to visit subdirectories in order
+        self.pending.extend(dirs.into_iter().rev());
+        self.files = files.into_iter();
+        Ok(())
+    }
+
+    fn has_wanted_extension(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || path.extension().is_some_and(|ext| {
+                self.extensions
+                    .iter()
+                    .any(|wanted| ext.eq_ignore_ascii_case(wanted))
+            })
+    }
+}
+
+impl Iterator for PakWalker {
+    type Item = Result<PathBuf, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = self.files.next() {
+                return Some(Ok(file));
+            }
+            let (dir, depth) = self.pending.pop()?;
+            if self.max_depth == Some(0) {
+                continue;
+            }
+            if let Err(error) = self.read_dir(&dir, depth) {
+                return Some(Err(WalkError { path: dir, error }));
+            }
+        }
+    }
+}
+
+/// Identity of a directory, the same whichever link it's reached through.
+#[derive(PartialEq, Eq, Hash)]
+enum DirKey {
+    #[cfg(unix)]
+    Inode(u64, u64),
+    #[cfg(not(unix))]
+    Canonical(PathBuf),
+}
+
+impl DirKey {
+    fn of(dir: &Path) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(dir)?;
+            Ok(Self::Inode(metadata.dev(), metadata.ino()))
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(Self::Canonical(fs::canonicalize(dir)?))
+        }
+    }
+}
+
+/// Sort `paths` by their canonical form, keeping only the first path seen
+/// for each file.
+///
+/// Paths that can't be canonicalized are sorted as they are. Errors are kept
+/// in their original order, ahead of the paths.
+///
+/// ## Example
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use gfp::utils::walk::sort_unique_paths;
+///
+/// let paths = vec![
+///     Ok::<_, ()>(PathBuf::from("test/normal")),
+///     Ok(PathBuf::from("test/avatar")),
+///     Ok(PathBuf::from("test/avatar/../avatar")),
+/// ];
+/// assert_eq!(
+///     sort_unique_paths(paths),
+///     vec![Ok(PathBuf::from("test/avatar")), Ok(PathBuf::from("test/normal"))]
+/// );
+/// ```
+pub fn sort_unique_paths<E>(
+    paths: impl IntoIterator<Item = Result<PathBuf, E>>,
+) -> Vec<Result<PathBuf, E>> {
+    let mut errors = vec![];
+    let mut paths_by_key = BTreeMap::new();
+    for result in paths {
+        match result {
+            Ok(path) => {
+                let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                paths_by_key.entry(key).or_insert(path);
+            }
+            Err(e) => errors.push(Err(e)),
+        }
+    }
+    errors
+        .into_iter()
+        .chain(paths_by_key.into_values().map(Ok))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_walk_bracketed_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path().join("和平精英[2002291]");
+        fs::create_dir_all(root.join("sub"))?;
+        fs::write(root.join("b.pak"), b"")?;
+        fs::write(root.join("a.pak"), b"")?;
+        fs::write(root.join("notes.txt"), b"")?;
+        fs::write(root.join("sub").join("c.PAK"), b"")?;
+
+        let paths = PakWalker::new(&root)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                root.join("a.pak"),
+                root.join("b.pak"),
+                root.join("sub").join("c.PAK"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_any_extension() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        fs::write(temp_dir.path().join("a.pak"), b"")?;
+        fs::write(temp_dir.path().join("b.pak.bak"), b"")?;
+
+        let paths = PakWalker::new(temp_dir.path())
+            .any_extension()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                temp_dir.path().join("a.pak"),
+                temp_dir.path().join("b.pak.bak")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_max_depth() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("a").join("b"))?;
+        fs::write(root.join("0.pak"), b"")?;
+        fs::write(root.join("a").join("1.pak"), b"")?;
+        fs::write(root.join("a").join("b").join("2.pak"), b"")?;
+
+        let walk = |max_depth| {
+            PakWalker::new(root)
+                .max_depth(max_depth)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(walk(Some(0)), Vec::<PathBuf>::new());
+        assert_eq!(walk(Some(1)), vec![root.join("0.pak")]);
+        assert_eq!(
+            walk(Some(2)),
+            vec![root.join("0.pak"), root.join("a").join("1.pak")]
+        );
+        assert_eq!(walk(None).len(), 3);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_symlink_cycle() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub"))?;
+        fs::write(root.join("sub").join("a.pak"), b"")?;
+        std::os::unix::fs::symlink(root, root.join("sub").join("loop"))?;
+
+        let paths = PakWalker::new(root).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(paths, vec![root.join("sub").join("a.pak")]);
+
+        // The root is reached again through `loop`, but only read once
+        let paths = PakWalker::new(root)
+            .follow_symlinks(true)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(paths, vec![root.join("sub").join("a.pak")]);
+
+        let linked = TempDir::new()?;
+        fs::write(linked.path().join("b.pak"), b"")?;
+        std::os::unix::fs::symlink(linked.path(), root.join("linked"))?;
+        let paths = PakWalker::new(root)
+            .follow_symlinks(true)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                root.join("linked").join("b.pak"),
+                root.join("sub").join("a.pak")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_missing_dir() {
+        let mut walker = PakWalker::new("nonexistent_directory_0ds9fas0930i0kbdofgids");
+        assert!(matches!(walker.next(), Some(Err(_))));
+        assert!(walker.next().is_none());
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::{self, Write};
+
+/// A [`Write`] that puts the bytes into a file from a fixed offset onwards
+/// with positioned writes (`pwrite` on Unix), so neither the file's length
+/// nor, on Unix, its cursor matter.
+///
+/// Writers on clones of one file (see [`File::try_clone`]) can fill
+/// disjoint ranges of it from different threads. On Windows each write
+/// moves the shared cursor, which positioned writes ignore anyway.
+///
+/// ```rust
+/// use std::io::Write;
+/// use gfp::utils::write_at::FileWriterAt;
+///
+/// let file = tempfile::tempfile().unwrap();
+/// let mut writer = FileWriterAt::new(&file, 4);
+/// writer.write_all(b"world").unwrap();
+/// FileWriterAt::new(&file, 0).write_all(b"hey ").unwrap();
+/// assert_eq!(writer.written(), 5);
+///
+/// let mut content = String::new();
+/// std::io::Read::read_to_string(&mut &file, &mut content).unwrap();
+/// assert_eq!(content, "hey world");
+/// ```
+pub struct FileWriterAt<'a> {
+    file: &'a File,
+    offset: u64,
+    written: u64,
+}
+
+impl<'a> FileWriterAt<'a> {
+    pub fn new(file: &'a File, offset: u64) -> Self {
+        Self {
+            file,
+            offset,
+            written: 0,
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Write for FileWriterAt<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = self.offset + self.written;
+        #[cfg(unix)]
+        let count = {
+            use std::os::unix::fs::FileExt;
+            self.file.write_at(buf, offset)?
+        };
+        #[cfg(windows)]
+        let count = {
+            use std::os::windows::fs::FileExt;
+            self.file.seek_write(buf, offset)?
+        };
+        #[cfg(not(any(unix, windows)))]
+        let count = {
+            let _ = (buf, offset);
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        };
+        self.written += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}